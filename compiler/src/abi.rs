@@ -1,4 +1,6 @@
-use crate::{Block, EventDef, Function, Item, Parameter, Program, Statement, Type};
+use crate::ir::{compute_error_selector, compute_event_topic, compute_selector, function_signature};
+use std::collections::BTreeMap;
+use crate::{Block, ErrorDef, EventDef, Function, Item, Parameter, Program, Statement, Type};
 
 #[derive(thiserror::Error, Debug)]
 pub enum AbiError {
@@ -7,13 +9,41 @@ pub enum AbiError {
 }
 
 pub fn program_to_abi_json(program: &Program) -> Result<String, AbiError> {
+    abi_json(program, false)
+}
+
+pub fn program_to_sorted_abi_json(program: &Program) -> Result<String, AbiError> {
+    abi_json(program, true)
+}
+
+/// Each ABI function's signature mapped to its selector in hex, as in
+/// solc's `evm.methodIdentifiers`.
+pub fn method_identifiers(program: &Program) -> BTreeMap<String, String> {
+    program
+        .items
+        .iter()
+        .filter_map(|item| match item {
+            Item::Function(f) if f.name != "init" && !f.internal => {
+                Some((function_signature(f), hex::encode(compute_selector(f))))
+            }
+            _ => None,
+        })
+        .collect()
+}
+
+fn abi_json(program: &Program, sorted: bool) -> Result<String, AbiError> {
     let mut out = String::with_capacity(1024);
     out.push('[');
     let mut first = true;
 
-    for item in &program.items {
+    let mut items: Vec<&Item> = program.items.iter().collect();
+    if sorted {
+        items.sort_by_cached_key(|item| sort_key(item));
+    }
+
+    for item in items {
         match item {
-            Item::Function(func) => {
+            Item::Function(func) if !func.internal => {
                 if !first { out.push(','); }
                 first = false;
                 if func.name == "init" {
@@ -27,6 +57,11 @@ pub fn program_to_abi_json(program: &Program) -> Result<String, AbiError> {
                 first = false;
                 emit_event(&mut out, event)?;
             }
+            Item::Error(error) => {
+                if !first { out.push(','); }
+                first = false;
+                emit_error(&mut out, error)?;
+            }
             _ => {}
         }
     }
@@ -35,6 +70,16 @@ pub fn program_to_abi_json(program: &Program) -> Result<String, AbiError> {
     Ok(out)
 }
 
+fn sort_key(item: &Item) -> (String, Vec<u8>) {
+    match item {
+        Item::Function(func) if func.name == "init" => (String::new(), Vec::new()),
+        Item::Function(func) => (func.name.clone(), compute_selector(func).to_vec()),
+        Item::Event(event) => (event.name.clone(), compute_event_topic(event).to_vec()),
+        Item::Error(error) => (error.name.clone(), compute_error_selector(error).to_vec()),
+        _ => (String::new(), Vec::new()),
+    }
+}
+
 fn emit_function(out: &mut String, func: &Function) -> Result<(), AbiError> {
     out.push('{');
     out.push_str("\"type\":\"function\"");
@@ -45,7 +90,7 @@ fn emit_function(out: &mut String, func: &Function) -> Result<(), AbiError> {
     out.push_str(detect_mutability(func));
     out.push('"');
     emit_inputs(out, &func.params)?;
-    emit_outputs(out, &func.return_type)?;
+    emit_outputs(out, &func.return_type, &func.return_names)?;
     out.push('}');
     Ok(())
 }
@@ -77,7 +122,8 @@ fn emit_event(out: &mut String, event: &EventDef) -> Result<(), AbiError> {
         out.push_str(",\"type\":\"");
         out.push_str(&abi_type(&field.type_)?);
         out.push('"');
-        out.push_str(",\"indexed\":false");
+        out.push_str(",\"indexed\":");
+        out.push_str(if field.indexed { "true" } else { "false" });
         out.push('}');
     }
     out.push(']');
@@ -85,6 +131,17 @@ fn emit_event(out: &mut String, event: &EventDef) -> Result<(), AbiError> {
     Ok(())
 }
 
+fn emit_error(out: &mut String, error: &ErrorDef) -> Result<(), AbiError> {
+    out.push('{');
+    out.push_str("\"type\":\"error\"");
+    out.push_str(",\"name\":\"");
+    push_escaped(out, &error.name);
+    out.push('"');
+    emit_inputs(out, &error.params)?;
+    out.push('}');
+    Ok(())
+}
+
 fn emit_inputs(out: &mut String, params: &[Parameter]) -> Result<(), AbiError> {
     out.push_str(",\"inputs\":[");
     let mut first = true;
@@ -104,11 +161,21 @@ fn emit_inputs(out: &mut String, params: &[Parameter]) -> Result<(), AbiError> {
     Ok(())
 }
 
-fn emit_outputs(out: &mut String, ret: &Option<Type>) -> Result<(), AbiError> {
+fn emit_outputs(out: &mut String, ret: &Option<Type>, names: &[String]) -> Result<(), AbiError> {
     out.push_str(",\"outputs\":[");
-    if let Some(ty) = ret {
+    let types = match ret {
+        Some(Type::Tuple(items)) => items.as_slice(),
+        Some(ty) => std::slice::from_ref(ty),
+        None => &[],
+    };
+    let mut first = true;
+    for (i, ty) in types.iter().enumerate() {
+        if !first { out.push(','); }
+        first = false;
         out.push('{');
-        out.push_str("\"name\":\"\"");
+        out.push_str("\"name\":\"");
+        push_escaped(out, names.get(i).map_or("", String::as_str));
+        out.push('"');
         out.push_str(",\"type\":\"");
         out.push_str(&abi_output_type(ty)?);
         out.push('"');
@@ -120,17 +187,20 @@ fn emit_outputs(out: &mut String, ret: &Option<Type>) -> Result<(), AbiError> {
 
 fn abi_type(ty: &Type) -> Result<String, AbiError> {
     match ty {
-        Type::Uint8 => Ok("uint8".to_string()),
-        Type::Uint256 => Ok("uint256".to_string()),
-        Type::Int256 => Ok("int256".to_string()),
+        Type::Uint(n) => Ok(format!("uint{n}")),
+        Type::Int(n) => Ok(format!("int{n}")),
         Type::Bool => Ok("bool".to_string()),
         Type::Address => Ok("address".to_string()),
         Type::Bytes => Ok("bytes".to_string()),
+        Type::FixedBytes(n) => Ok(format!("bytes{n}")),
         Type::String => Ok("string".to_string()),
         Type::Custom(name) => Err(AbiError::UnsupportedType(name.clone())),
-        Type::Vec(_) => Err(AbiError::UnsupportedType("Vec".to_string())),
+        Type::Enum(_) => Ok("uint8".to_string()),
+        Type::Vec(inner) => Ok(format!("{}[]", abi_type(inner)?)),
+        Type::Array(inner, len) => Ok(format!("{}[{len}]", abi_type(inner)?)),
         Type::Map(_, _) => Err(AbiError::UnsupportedType("Map".to_string())),
         Type::Generic(name, _) => Err(AbiError::UnsupportedType(name.clone())),
+        Type::Tuple(_) => Err(AbiError::UnsupportedType("Tuple".to_string())),
     }
 }
 
@@ -141,8 +211,11 @@ fn abi_output_type(ty: &Type) -> Result<String, AbiError> {
     }
 }
 
-fn detect_mutability(func: &Function) -> &'static str {
-    if body_has_writes(&func.body) {
+fn detect_mutability(func: &Function) -> &str {
+    if let Some(declared) = func.mutability_decorators().first() {
+        return declared;
+    }
+    if body_has_writes(&func.expanded_body()) {
         "nonpayable"
     } else {
         "view"
@@ -151,10 +224,10 @@ fn detect_mutability(func: &Function) -> &'static str {
 
 fn body_has_writes(block: &Block) -> bool {
     block.statements.iter().any(|s| match s {
-        Statement::Assign(_) | Statement::Emit(_) => true,
+        Statement::Assign(_) | Statement::Emit(_) | Statement::Asm(_) => true,
         Statement::If(if_stmt) => {
             body_has_writes(&if_stmt.then_branch)
-                || if_stmt.else_branch.as_ref().map_or(false, body_has_writes)
+                || if_stmt.else_branch.as_ref().is_some_and(body_has_writes)
         }
         Statement::For(for_stmt) => body_has_writes(&for_stmt.body),
         Statement::While(while_stmt) => body_has_writes(&while_stmt.body),
@@ -184,6 +257,17 @@ mod tests {
     use super::*;
     use crate::parser::parse_from_source;
 
+    #[test]
+    fn method_identifiers_skip_the_constructor() {
+        let program = parse_from_source(
+            "def init(a: uint256):\n    pass\n\ndef transfer(to: address, amount: uint256) -> bool:\n    return true\n",
+        )
+        .unwrap();
+        let ids = method_identifiers(&program);
+        assert_eq!(ids.len(), 1);
+        assert_eq!(ids["transfer(address,uint256)"], "a9059cbb");
+    }
+
     #[test]
     fn abi_json_for_view_function() {
         let program = parse_from_source("def t(a: uint256) -> bool: return true").unwrap();
@@ -198,6 +282,15 @@ mod tests {
         assert!(abi.contains("\"stateMutability\":\"nonpayable\""));
     }
 
+    #[test]
+    fn abi_json_uses_declared_mutability() {
+        let src = "@payable\ndef deposit():\n    x = 1\n\n@pure\ndef one() -> uint256: return 1\n";
+        let program = parse_from_source(src).unwrap();
+        let abi = program_to_abi_json(&program).unwrap();
+        assert!(abi.contains("\"name\":\"deposit\",\"stateMutability\":\"payable\""));
+        assert!(abi.contains("\"name\":\"one\",\"stateMutability\":\"pure\""));
+    }
+
     #[test]
     fn abi_json_for_constructor() {
         let program = parse_from_source("def init(supply: uint256) -> bool: return true").unwrap();
@@ -216,6 +309,106 @@ mod tests {
         assert!(abi.contains("\"indexed\":false"));
     }
 
+    #[test]
+    fn abi_json_marks_indexed_event_fields() {
+        let source = "event Transfer(from: indexed(address), amount: uint256)\n";
+        let program = parse_from_source(source).unwrap();
+        let abi = program_to_abi_json(&program).unwrap();
+        assert!(abi.contains("{\"name\":\"from\",\"type\":\"address\",\"indexed\":true}"));
+        assert!(abi.contains("{\"name\":\"amount\",\"type\":\"uint256\",\"indexed\":false}"));
+    }
+
+    #[test]
+    fn abi_json_encodes_enums_as_uint8() {
+        let source = "enum Status: Pending, Active\n\n@view\ndef get(s: Status) -> Status: return s\n";
+        let program = parse_from_source(source).unwrap();
+        let abi = program_to_abi_json(&program).unwrap();
+        assert!(abi.contains("\"inputs\":[{\"name\":\"s\",\"type\":\"uint8\"}]"), "{abi}");
+        assert!(abi.contains("\"outputs\":[{\"name\":\"\",\"type\":\"uint8\"}]"), "{abi}");
+    }
+
+    #[test]
+    fn abi_json_for_custom_error() {
+        let source = "error InsufficientBalance(needed: uint256, available: uint256)\n";
+        let program = parse_from_source(source).unwrap();
+        let abi = program_to_abi_json(&program).unwrap();
+        assert_eq!(
+            abi,
+            "[{\"type\":\"error\",\"name\":\"InsufficientBalance\",\"inputs\":[{\"name\":\"needed\",\"type\":\"uint256\"},{\"name\":\"available\",\"type\":\"uint256\"}]}]"
+        );
+    }
+
+    fn entry_names(abi: &str) -> Vec<&str> {
+        abi.split("\"name\":\"")
+            .skip(1)
+            .map(|rest| &rest[..rest.find('"').unwrap()])
+            .filter(|name| !name.is_empty() && *name != "a")
+            .collect()
+    }
+
+    #[test]
+    fn abi_preserves_source_order_by_default() {
+        let src = "def zeta() -> bool: return true
+
+event Alpha(a: uint256)
+
+def beta() -> bool: return true
+";
+        let program = parse_from_source(src).unwrap();
+        let abi = program_to_abi_json(&program).unwrap();
+        assert_eq!(entry_names(&abi), ["zeta", "Alpha", "beta"]);
+    }
+
+    #[test]
+    fn sorted_abi_orders_by_name() {
+        let src = "def zeta() -> bool: return true
+
+event Alpha(a: uint256)
+
+def beta() -> bool: return true
+
+def init():
+    x = 1
+";
+        let program = parse_from_source(src).unwrap();
+        let abi = program_to_sorted_abi_json(&program).unwrap();
+        assert!(abi.starts_with("[{\"type\":\"constructor\""));
+        assert_eq!(entry_names(&abi), ["Alpha", "beta", "zeta"]);
+    }
+
+    #[test]
+    fn abi_json_for_tuple_outputs() {
+        let src = "def divmod(a: uint256, b: uint256) -> (uint256, bool): return a / b, true";
+        let program = parse_from_source(src).unwrap();
+        let abi = program_to_abi_json(&program).unwrap();
+        assert!(abi.contains("\"outputs\":[{\"name\":\"\",\"type\":\"uint256\"},{\"name\":\"\",\"type\":\"bool\"}]"));
+    }
+
+    #[test]
+    fn abi_json_for_array_params() {
+        let src = "def f(ids: uint256[], pair: address[2]) -> uint256: return ids[0]";
+        let program = parse_from_source(src).unwrap();
+        let abi = program_to_abi_json(&program).unwrap();
+        assert!(abi.contains("\"inputs\":[{\"name\":\"ids\",\"type\":\"uint256[]\"},{\"name\":\"pair\",\"type\":\"address[2]\"}]"));
+    }
+
+    #[test]
+    fn abi_json_for_fixed_bytes() {
+        let src = "def f(sig: bytes4, h: bytes32) -> bytes1: return sig[0:1]";
+        let program = parse_from_source(src).unwrap();
+        let abi = program_to_abi_json(&program).unwrap();
+        assert!(abi.contains("\"inputs\":[{\"name\":\"sig\",\"type\":\"bytes4\"},{\"name\":\"h\",\"type\":\"bytes32\"}]"));
+        assert!(abi.contains("\"outputs\":[{\"name\":\"\",\"type\":\"bytes1\"}]"));
+    }
+
+    #[test]
+    fn abi_json_uses_named_outputs() {
+        let src = "def f() -> (q: uint256, r: uint256):\n    q = 1\n";
+        let program = parse_from_source(src).unwrap();
+        let abi = program_to_abi_json(&program).unwrap();
+        assert!(abi.contains("\"outputs\":[{\"name\":\"q\",\"type\":\"uint256\"},{\"name\":\"r\",\"type\":\"uint256\"}]"));
+    }
+
     #[test]
     fn abi_rejects_unknown_type() {
         let program = parse_from_source("def t(a: Foo) -> bool: return true").unwrap();
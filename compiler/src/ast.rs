@@ -1,79 +1,192 @@
 use num_bigint::BigUint;
+use serde::{Serialize, Serializer};
+use std::borrow::Cow;
+use std::path::PathBuf;
 
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Serialize)]
 pub struct Span {
     pub start: usize,
     pub end: usize,
 }
 
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Serialize)]
 pub struct Program {
     pub items: Vec<Item>,
     pub span: Span,
+    /// Files the items were loaded from, the entry file first. Empty for a
+    /// program parsed from one source.
+    #[serde(skip)]
+    pub files: Vec<PathBuf>,
+    /// Index into `files` of each item's file, in item order.
+    #[serde(skip)]
+    pub item_files: Vec<usize>,
 }
 
-#[derive(Debug, Clone, PartialEq)]
+impl Program {
+    /// Index into `files` of the file that declared the item at `index`.
+    pub fn item_file(&self, index: usize) -> usize {
+        self.item_files.get(index).copied().unwrap_or(0)
+    }
+
+    /// The file of the item at `index` when an import brought it in.
+    pub fn imported_file(&self, index: usize) -> Option<&PathBuf> {
+        match self.item_file(index) {
+            0 => None,
+            file => self.files.get(file),
+        }
+    }
+}
+
+/// The program as JSON, for linters and editor tooling. Numbers are decimal
+/// strings, since JSON readers lose precision past 2^53, and bytes are hex.
+pub fn program_to_ast_json(program: &Program) -> serde_json::Result<String> {
+    serde_json::to_string_pretty(program)
+}
+
+fn decimal<S: Serializer>(n: &BigUint, s: S) -> Result<S::Ok, S::Error> {
+    s.collect_str(n)
+}
+
+fn optional_decimal<S: Serializer>(n: &Option<BigUint>, s: S) -> Result<S::Ok, S::Error> {
+    match n {
+        Some(n) => s.collect_str(n),
+        None => s.serialize_none(),
+    }
+}
+
+fn hex_bytes<S: Serializer>(bytes: &[u8], s: S) -> Result<S::Ok, S::Error> {
+    s.serialize_str(&format!("0x{}", hex::encode(bytes)))
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
 pub enum Item {
     Function(Function),
     Struct(StructDef),
+    Enum(EnumDef),
     Const(ConstDecl),
     Event(EventDef),
+    Error(ErrorDef),
+    Interface(InterfaceDef),
+    Modifier(ModifierDef),
+    State(StateVar),
+    Import(ImportDecl),
 }
 
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Serialize)]
 pub struct Function {
+    pub decorators: Vec<Decorator>,
     pub name: String,
+    pub name_span: Span,
     pub params: Vec<Parameter>,
     pub return_type: Option<Type>,
+    pub return_names: Vec<String>,
     pub body: Block,
+    /// Modifiers named by the decorators, outermost first. The body stays
+    /// the function's own; modifiers are wrapped around it when lowering.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub modifiers: Vec<ModifierDef>,
+    /// Brought in by a `from` import: callable from the importer's code but
+    /// left out of its ABI and dispatcher.
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub internal: bool,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Decorator {
+    pub name: String,
+    pub args: Vec<String>,
     pub span: Span,
 }
 
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Serialize)]
 pub struct Parameter {
     pub name: String,
+    #[serde(rename = "type")]
     pub type_: Type,
     pub span: Span,
 }
 
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Serialize)]
 pub enum Type {
-    Uint8,
-    Uint256,
-    Int256,
+    /// `uint8` through `uint256`, in steps of 8 bits.
+    Uint(u16),
+    /// `int8` through `int256`, in steps of 8 bits.
+    Int(u16),
     Bool,
     Address,
     Bytes,
+    /// `bytes1` through `bytes32`, held left-aligned in a word.
+    FixedBytes(u8),
     String,
 
+    /// Dynamic array, written `T[]`.
     Vec(Box<Type>),
+    /// Fixed-size array, written `T[N]`.
+    Array(Box<Type>, usize),
     Map(Box<Type>, Box<Type>),
 
     Custom(String),
+    /// A declared enum, held as its `uint8` variant index.
+    Enum(String),
 
     Generic(String, Vec<Type>),
+
+    Tuple(Vec<Type>),
 }
 
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Serialize)]
 pub struct Block {
     pub statements: Vec<Statement>,
     pub span: Span,
 }
 
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Serialize)]
 pub enum Statement {
     Let(LetStatement),
     Assign(AssignStatement),
-    Expression(Expression),
+    Expression(Expression, Span),
     If(IfStatement),
     For(ForStatement),
     While(WhileStatement),
-    Return(Option<Expression>),
-    Require(Expression),
+    Return(Option<Expression>, Span),
+    Require(RequireStatement),
     Emit(EmitStatement),
+    Revert(RevertStatement),
+    Asm(AsmBlock),
+    Break(Span),
+    Continue(Span),
+}
+
+impl Statement {
+    /// Source range of the statement.
+    pub fn span(&self) -> &Span {
+        match self {
+            Statement::Let(s) => &s.span,
+            Statement::Assign(s) => &s.span,
+            Statement::If(s) => &s.span,
+            Statement::For(s) => &s.span,
+            Statement::While(s) => &s.span,
+            Statement::Require(s) => &s.span,
+            Statement::Emit(s) => &s.span,
+            Statement::Revert(s) => &s.span,
+            Statement::Asm(s) => &s.span,
+            Statement::Expression(_, span)
+            | Statement::Return(_, span)
+            | Statement::Break(span)
+            | Statement::Continue(span) => span,
+        }
+    }
 }
 
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct RequireStatement {
+    pub condition: Expression,
+    pub message: Option<String>,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
 pub struct LetStatement {
     pub name: String,
     pub type_: Option<Type>,
@@ -82,13 +195,15 @@ pub struct LetStatement {
     pub span: Span,
 }
 
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Serialize)]
 pub enum Expression {
-    Number(BigUint),
-    HexNumber(BigUint),
+    Number(#[serde(serialize_with = "decimal")] BigUint),
+    HexNumber(#[serde(serialize_with = "decimal")] BigUint),
+    /// A 20-byte address written as 40 hex digits, case preserved.
+    Address(String),
     String(String),
     Bool(bool),
-    Bytes(Vec<u8>),
+    Bytes(#[serde(serialize_with = "hex_bytes")] Vec<u8>),
 
     StructInit(String, Vec<(String, Expression)>),
 
@@ -101,9 +216,12 @@ pub enum Expression {
 
     Member(Box<Expression>, String),
     Index(Box<Expression>, Box<Expression>),
+    Slice(Box<Expression>, Box<Expression>, Box<Expression>),
+
+    Tuple(Vec<Expression>),
 }
 
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Serialize)]
 pub enum BinaryOp {
     Add,
     Sub,
@@ -119,44 +237,74 @@ pub enum BinaryOp {
     GreaterEqual,
     And,
     Or,
+    In,
 }
 
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Serialize)]
 pub enum UnaryOp {
     Not,
     Minus,
 }
 
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Serialize)]
 pub struct StructDef {
     pub name: String,
+    pub name_span: Span,
     pub fields: Vec<StructField>,
     pub span: Span,
 }
 
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Serialize)]
 pub struct StructField {
     pub name: String,
+    #[serde(rename = "type")]
+    pub type_: Type,
+    pub span: Span,
+}
+
+/// `enum Name: A, B, C`. Variants are numbered from zero in order.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct EnumDef {
+    pub name: String,
+    pub name_span: Span,
+    pub variants: Vec<String>,
+    pub span: Span,
+}
+
+impl EnumDef {
+    pub fn variant(&self, name: &str) -> Option<usize> {
+        self.variants.iter().position(|v| v == name)
+    }
+}
+
+/// A storage variable declared at the top level as `name: type`.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct StateVar {
+    pub name: String,
+    pub name_span: Span,
+    #[serde(rename = "type")]
     pub type_: Type,
     pub span: Span,
 }
 
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Serialize)]
 pub struct ConstDecl {
     pub name: String,
+    pub name_span: Span,
+    #[serde(rename = "type")]
     pub type_: Type,
     pub value: Expression,
     pub span: Span,
 }
 
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Serialize)]
 pub struct AssignStatement {
     pub target: Expression,
     pub value: Expression,
     pub span: Span,
 }
 
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Serialize)]
 pub struct IfStatement {
     pub condition: Expression,
     pub then_branch: Block,
@@ -164,7 +312,7 @@ pub struct IfStatement {
     pub span: Span,
 }
 
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Serialize)]
 pub struct ForStatement {
     pub var: String,
     pub iterable: Expression,
@@ -172,23 +320,264 @@ pub struct ForStatement {
     pub span: Span,
 }
 
-#[derive(Debug, Clone, PartialEq)]
+impl Function {
+    /// The body with its modifiers spliced around it, for checks that look
+    /// at every statement the function runs.
+    pub fn expanded_body(&self) -> Cow<'_, Block> {
+        self.modifiers
+            .iter()
+            .rev()
+            .fold(Cow::Borrowed(&self.body), |body, m| Cow::Owned(m.wrap(&body)))
+    }
+
+    pub fn named_returns(&self) -> Vec<(&str, &Type)> {
+        let types = match &self.return_type {
+            Some(Type::Tuple(items)) if self.return_names.len() > 1 => items.iter().collect(),
+            Some(ty) => vec![ty],
+            None => Vec::new(),
+        };
+        self.return_names.iter().map(String::as_str).zip(types).collect()
+    }
+
+    pub fn named_return_value(&self) -> Option<Expression> {
+        let mut values: Vec<Expression> = self
+            .return_names
+            .iter()
+            .map(|n| Expression::Identifier(n.clone()))
+            .collect();
+        match values.len() {
+            0 => None,
+            1 => values.pop(),
+            _ => Some(Expression::Tuple(values)),
+        }
+    }
+
+    /// True when the function carries `@name`, with or without arguments.
+    pub fn has_decorator(&self, name: &str) -> bool {
+        self.decorators.iter().any(|d| d.name == name)
+    }
+
+    /// Mutability names from `@view`, `@pure` and `@payable`, in source order.
+    pub fn mutability_decorators(&self) -> Vec<&str> {
+        self.decorators
+            .iter()
+            .map(|d| d.name.as_str())
+            .filter(|n| matches!(*n, "view" | "pure" | "payable"))
+            .collect()
+    }
+
+    pub fn is_payable(&self) -> bool {
+        self.has_decorator("payable")
+    }
+
+    /// True for `@view` and `@pure` functions, which cannot modify state.
+    pub fn is_read_only(&self) -> bool {
+        self.has_decorator("view") || self.has_decorator("pure")
+    }
+
+    /// True when the function carries `@allow(lint)`.
+    pub fn allows(&self, lint: &str) -> bool {
+        self.decorators
+            .iter()
+            .any(|d| d.name == "allow" && d.args.iter().any(|a| a == lint))
+    }
+}
+
+impl ForStatement {
+    pub fn range_args(&self) -> Option<&[Expression]> {
+        match &self.iterable {
+            Expression::Call(callee, args)
+                if matches!(callee.as_ref(), Expression::Identifier(name) if name == "range") =>
+            {
+                Some(args)
+            }
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
 pub struct WhileStatement {
     pub condition: Expression,
     pub body: Block,
     pub span: Span,
 }
 
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Serialize)]
 pub struct EventDef {
     pub name: String,
-    pub fields: Vec<Parameter>,
+    pub name_span: Span,
+    pub fields: Vec<EventField>,
     pub span: Span,
 }
 
-#[derive(Debug, Clone, PartialEq)]
+/// A custom error, reverted with as `selector . abi.encode(args)`.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ErrorDef {
+    pub name: String,
+    pub name_span: Span,
+    pub params: Vec<Parameter>,
+    pub span: Span,
+}
+
+/// A reusable block applied to a function by naming it as a decorator. The
+/// function body runs where the modifier has a bare `_` statement.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ModifierDef {
+    pub name: String,
+    pub name_span: Span,
+    pub body: Block,
+    pub span: Span,
+}
+
+impl ModifierDef {
+    pub fn has_placeholder(&self) -> bool {
+        self.body.statements.iter().any(holds_placeholder)
+    }
+
+    /// True when statements follow a placeholder, so they run after the
+    /// function body.
+    pub fn has_code_after_placeholder(&self) -> bool {
+        fn after(stmts: &[Statement]) -> bool {
+            stmts.iter().enumerate().any(|(i, stmt)| {
+                holds_placeholder(stmt)
+                    && (i + 1 < stmts.len()
+                        || matches!(stmt, Statement::If(if_stmt) if after(&if_stmt.then_branch.statements)
+                            || if_stmt.else_branch.as_ref().is_some_and(|eb| after(&eb.statements))))
+            })
+        }
+        after(&self.body.statements)
+    }
+
+    /// The modifier body with every placeholder replaced by `body`.
+    pub fn wrap(&self, body: &Block) -> Block {
+        Block {
+            statements: splice(&self.body.statements, body),
+            span: body.span.clone(),
+        }
+    }
+}
+
+fn is_placeholder(stmt: &Statement) -> bool {
+    matches!(stmt, Statement::Expression(Expression::Identifier(name), _) if name == "_")
+}
+
+/// True for a placeholder, or an `if` with one in either branch.
+fn holds_placeholder(stmt: &Statement) -> bool {
+    match stmt {
+        Statement::If(if_stmt) => {
+            if_stmt.then_branch.statements.iter().any(holds_placeholder)
+                || if_stmt.else_branch.as_ref().is_some_and(|eb| eb.statements.iter().any(holds_placeholder))
+        }
+        _ => is_placeholder(stmt),
+    }
+}
+
+fn splice(stmts: &[Statement], body: &Block) -> Vec<Statement> {
+    let mut out = Vec::with_capacity(stmts.len() + body.statements.len());
+    for stmt in stmts {
+        match stmt {
+            _ if is_placeholder(stmt) => out.extend(body.statements.iter().cloned()),
+            Statement::If(if_stmt) => {
+                let branch = |b: &Block| Block { statements: splice(&b.statements, body), span: b.span.clone() };
+                out.push(Statement::If(IfStatement {
+                    condition: if_stmt.condition.clone(),
+                    then_branch: branch(&if_stmt.then_branch),
+                    else_branch: if_stmt.else_branch.as_ref().map(branch),
+                    span: if_stmt.span.clone(),
+                }));
+            }
+            _ => out.push(stmt.clone()),
+        }
+    }
+    out
+}
+
+/// An external contract's functions. Members are bodiless `Function`s so
+/// selectors and mutability come from the same helpers as local functions.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct InterfaceDef {
+    pub name: String,
+    pub name_span: Span,
+    pub functions: Vec<Function>,
+    pub span: Span,
+}
+
+impl InterfaceDef {
+    pub fn function(&self, name: &str) -> Option<&Function> {
+        self.functions.iter().find(|f| f.name == name)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct EventField {
+    pub name: String,
+    #[serde(rename = "type")]
+    pub type_: Type,
+    pub indexed: bool,
+    pub span: Span,
+}
+
+impl EventDef {
+    pub fn indexed_count(&self) -> usize {
+        self.fields.iter().filter(|f| f.indexed).count()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
 pub struct EmitStatement {
     pub name: String,
     pub args: Vec<Expression>,
     pub span: Span,
 }
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct RevertStatement {
+    pub name: String,
+    pub args: Vec<Expression>,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct AsmInstruction {
+    pub mnemonic: String,
+    #[serde(serialize_with = "optional_decimal")]
+    pub immediate: Option<BigUint>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct AsmBlock {
+    pub instructions: Vec<AsmInstruction>,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ImportDecl {
+    pub path: String,
+    /// The items a `from` import names; empty for `import "path"`, which
+    /// brings in everything.
+    pub names: Vec<String>,
+    pub span: Span,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::parse_from_source;
+
+    #[test]
+    fn ast_json_keeps_large_numbers_exact() {
+        let src = "const BIG: uint256 = 100000000000000000000\n\ndef f() -> bytes:\n    return b'0102'\n";
+        let json = program_to_ast_json(&parse_from_source(src).unwrap()).unwrap();
+        let value: serde_json::Value = serde_json::from_str(&json).unwrap();
+        let item = &value["items"][0]["Const"];
+        assert_eq!(item["name"], "BIG");
+        assert_eq!(item["type"]["Uint"], 256);
+        assert_eq!(item["value"]["Number"], "100000000000000000000");
+        assert_eq!(item["span"]["start"], 0);
+
+        let body = &value["items"][1]["Function"]["body"]["statements"];
+        assert_eq!(body[0]["Return"][0]["Bytes"], "0x0102");
+        assert_eq!(body[0]["Return"][1]["start"], 66);
+    }
+}
@@ -1,63 +1,831 @@
-use clap::{Parser, Subcommand};
-use std::path::PathBuf;
+use clap::{Args, CommandFactory, Parser, Subcommand, ValueEnum};
+use std::collections::{BTreeMap, HashSet};
+use std::io::IsTerminal;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant, SystemTime};
 
-use pyra_compiler::{compile_file_to_abi_and_bin, compile_file, GasReport};
-use pyra_compiler::ir::lower_program;
-use pyra_compiler::{harden, add_reentrancy_guard, StorageLayout};
+use pyra_compiler::{
+    check_warnings, compile_file_to_artifacts, compile_file_with_options, module_to_deploy_bytecode,
+    module_to_eof_bytecode, module_to_runtime_bytecode, verify_file,
+    format_source, disassembly_listing, compile_standard_json, init_project, run_test_file, CompileError,
+    CompileOptions, Diagnostic, GasReport, OutputFormat,
+};
+use pyra_compiler::diagnostics::render;
+use pyra_compiler::ir::IrModule;
+use pyra_compiler::{hardened_module, EvmVersion, GuardMode, HardenConfig, OptLevel, Program, StorageLayout};
+use pyra_compiler::project::{find_root, pyra_files, ArtifactFormat, Project, MANIFEST};
+use pyra_compiler::{BuildCache, CACHE_DIR};
 
 #[derive(Parser)]
 #[command(name = "pyra", version, about = "Pyra compiler")]
 struct Cli {
+    #[arg(long = "no-color", global = true)]
+    no_color: bool,
+    /// Read solc standard JSON input on stdin and print standard JSON output.
+    #[arg(long = "standard-json", exclusive = true)]
+    standard_json: bool,
     #[command(subcommand)]
-    command: Command,
+    command: Option<Command>,
 }
 
 #[derive(Subcommand)]
 enum Command {
-    Build {
+    /// Compile files, or every `.pyra` file under a directory, in
+    /// parallel. With no input, build the project around the current
+    /// directory as its `pyra.toml` sets out; flags given here override
+    /// the manifest.
+    Build(BuildArgs),
+    /// Run the `test_` functions of test files on the built-in EVM. With
+    /// no input, run every file under the project's `tests/` folder.
+    Test {
+        inputs: Vec<PathBuf>,
+        #[arg(short = 'I', long = "include")]
+        include: Vec<PathBuf>,
+    },
+    Verify {
+        input: PathBuf,
+        #[arg(short = 'I', long = "include")]
+        include: Vec<PathBuf>,
+    },
+    /// Parse and type-check without generating code.
+    Check {
         input: PathBuf,
-        #[arg(short = 'o', long = "out-dir")]
-        out_dir: Option<PathBuf>,
-        #[arg(long = "gas-report")]
-        gas_report: bool,
+        #[arg(short = 'I', long = "include")]
+        include: Vec<PathBuf>,
+        #[arg(long = "strict")]
+        strict: bool,
+    },
+    /// Rewrite sources in canonical layout.
+    Fmt {
+        #[arg(required = true)]
+        inputs: Vec<PathBuf>,
+        /// List files that are not formatted instead of rewriting them.
+        #[arg(long = "check")]
+        check: bool,
+    },
+    /// Create a project with a manifest, a starter contract and a tests folder.
+    Init {
+        /// The project directory, created if missing; defaults to the current one.
+        path: Option<PathBuf>,
     },
+    /// Print the instructions of compiled bytecode.
+    Disasm {
+        /// A `.bin` file of hex bytecode; reads stdin when omitted or `-`.
+        input: Option<PathBuf>,
+    },
+}
+
+#[derive(Args)]
+struct BuildArgs {
+    inputs: Vec<PathBuf>,
+    /// Contracts compiled at once; defaults to the number of cores.
+    #[arg(short = 'j', long = "jobs", value_parser = clap::value_parser!(u16).range(1..))]
+    jobs: Option<u16>,
+    #[arg(short = 'o', long = "out-dir")]
+    out_dir: Option<PathBuf>,
+    #[command(flatten)]
+    reports: ReportFlags,
+    #[arg(short = 'I', long = "include")]
+    include: Vec<PathBuf>,
+    #[arg(long = "sort-abi")]
+    sort_abi: bool,
+    #[arg(long = "strict")]
+    strict: bool,
+    #[arg(
+        long = "constructor-args",
+        value_parser = parse_constructor_args,
+        conflicts_with = "artifact_format",
+        requires = "inputs"
+    )]
+    constructor_args: Option<ConstructorArgs>,
+    /// Bytecode container [default: legacy].
+    #[arg(long = "format", value_enum)]
+    format: Option<Format>,
+    /// Layout of the written files [default: pyra]; `foundry` writes
+    /// `<out>/<file>/<name>.json` with `out` as the default directory,
+    /// `hardhat` writes `<out>/<path>/<name>.json` under `artifacts`.
+    #[arg(long = "artifact-format", value_enum)]
+    artifact_format: Option<Artifact>,
+    /// Optimizer level [default: 2].
+    #[arg(short = 'O', long = "opt-level", value_enum)]
+    opt_level: Option<Opt>,
+    /// Fork to target [default: paris].
+    #[arg(long = "evm-version", value_enum)]
+    evm_version: Option<Evm>,
+    /// Also write solc-style source maps for the deploy and runtime code.
+    #[arg(long = "source-map")]
+    source_map: bool,
+    /// Extra outputs to write next to the .bin.
+    #[arg(long = "emit", value_enum)]
+    emit: Vec<Emit>,
+    /// Rebuild every contract of the project instead of reusing
+    /// unchanged ones from `.pyra-cache`.
+    #[arg(long = "no-cache", conflicts_with = "inputs")]
+    no_cache: bool,
+    /// Keep running and rebuild whenever a source or the manifest changes.
+    #[arg(short = 'w', long = "watch")]
+    watch: bool,
+    #[command(flatten)]
+    checks: CheckFlags,
+}
+
+#[derive(Args)]
+struct CheckFlags {
+    #[arg(long = "no-check-add")]
+    no_check_add: bool,
+    #[arg(long = "no-check-sub")]
+    no_check_sub: bool,
+    #[arg(long = "no-check-mul")]
+    no_check_mul: bool,
+    #[arg(long = "check-exp")]
+    check_exp: bool,
+    #[arg(long = "check-div")]
+    check_div: bool,
+    #[arg(long = "strict-calldata")]
+    strict_calldata: bool,
+    /// Lock only the functions marked `@reentrancy_guard` instead of every
+    /// public function.
+    #[arg(long = "explicit-guards")]
+    explicit_guards: bool,
+}
+
+impl CheckFlags {
+    /// Turns off the checks named on the command line and turns on the
+    /// opt-in ones if asked; everything else is left as configured.
+    fn apply(&self, config: &mut HardenConfig) {
+        config.add &= !self.no_check_add;
+        config.sub &= !self.no_check_sub;
+        config.mul &= !self.no_check_mul;
+        config.exp |= self.check_exp;
+        config.div |= self.check_div;
+        config.strict_calldata |= self.strict_calldata;
+        if self.explicit_guards {
+            config.guard_mode = GuardMode::Explicit;
+        }
+    }
+}
+
+#[derive(Args)]
+struct ReportFlags {
+    #[arg(long = "gas-report")]
+    gas_report: bool,
+    #[arg(long = "op-histogram")]
+    op_histogram: bool,
+    #[arg(long = "print-slot-count")]
+    print_slot_count: bool,
+    #[arg(long = "summary-json")]
+    summary_json: bool,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum Format {
+    Legacy,
+    Eof,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum Artifact {
+    /// `.abi`, `.bin` and `.meta.json` next to the input.
+    Pyra,
+    /// One Foundry-style JSON artifact per contract.
+    Foundry,
+    /// One Hardhat `hh-sol-artifact-1` JSON artifact per contract.
+    Hardhat,
+}
+
+#[derive(Clone, Copy, PartialEq, ValueEnum)]
+enum Emit {
+    /// The input file's AST as `<name>.ast.json`.
+    AstJson,
+    /// The hardened IR the bytecode comes from, as `<name>.ir`.
+    Ir,
+    /// `<name>.ir` with the IR before optimization, after it and after hardening.
+    IrStages,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum Opt {
+    #[value(name = "0")]
+    O0,
+    #[value(name = "1")]
+    O1,
+    #[value(name = "2")]
+    O2,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum Evm {
+    Paris,
+    Shanghai,
+    Cancun,
 }
 
 fn main() {
     let cli = Cli::parse();
+    let color = use_color(cli.no_color);
+
+    if cli.standard_json {
+        let input = std::io::read_to_string(std::io::stdin()).unwrap_or_default();
+        println!("{}", compile_standard_json(&input));
+        std::process::exit(0)
+    }
+    let Some(command) = cli.command else {
+        Cli::command().print_help().ok();
+        std::process::exit(2)
+    };
 
-    match cli.command {
-        Command::Build { input, out_dir, gas_report } => {
-            match compile_file_to_abi_and_bin(&input, out_dir.as_deref()) {
-                Ok(_) => {
-                    if gas_report {
-                        if let Ok(program) = compile_file(&input) {
-                            let mut module = lower_program(&program);
-                            harden(&mut module);
-                            let layout = StorageLayout::from_program(&program);
-                            add_reentrancy_guard(&mut module, layout.slot_count());
-                            let report = GasReport::from_module(&module);
-                            println!("Gas Report");
-                            println!("{}", "=".repeat(50));
-                            for f in &report.functions {
-                                println!(
-                                    "  {} (0x{})  ~{} gas",
-                                    f.name,
-                                    hex::encode(f.selector),
-                                    f.estimated_gas
-                                );
-                            }
-                            println!("  constructor            ~{} gas", report.constructor_gas);
-                            println!("  dispatch overhead      ~{} gas", report.dispatch_overhead);
-                        }
+    match command {
+        Command::Build(args) => {
+            if args.watch {
+                watch(&args, color)
+            }
+            std::process::exit(i32::from(!build(&args, color)))
+        }
+        Command::Test { inputs, include } => std::process::exit(i32::from(!test(inputs, include, color))),
+        Command::Check { input, include, strict } => {
+            let options = CompileOptions {
+                include_paths: include,
+                strict,
+                ..CompileOptions::default()
+            };
+            match compile_file_with_options(&input, &options) {
+                Ok(program) => {
+                    report_warnings(&program, &input, color);
+                    println!("{}: ok", input.display());
+                    std::process::exit(0)
+                }
+                Err(err) => {
+                    report_error(&err, &input, color);
+                    std::process::exit(1)
+                }
+            }
+        }
+        Command::Verify { input, include } => {
+            let options = CompileOptions {
+                include_paths: include,
+                ..CompileOptions::default()
+            };
+            match verify_file(&input, &options) {
+                Ok(program) => {
+                    report_warnings(&program, &input, color);
+                    println!("{}: ok", input.display());
+                    std::process::exit(0)
+                }
+                Err(err) => {
+                    report_error(&err, &input, color);
+                    std::process::exit(1)
+                }
+            }
+        }
+        Command::Fmt { inputs, check } => {
+            let mut failed = false;
+            for input in inputs {
+                match format_file(&input, check) {
+                    Ok(true) if check => {
+                        println!("{}: would reformat", input.display());
+                        failed = true;
+                    }
+                    Ok(_) => {}
+                    Err(err) => {
+                        report_error(&err, &input, color);
+                        failed = true;
+                    }
+                }
+            }
+            std::process::exit(i32::from(failed))
+        }
+        Command::Init { path } => {
+            let dir = path.unwrap_or_else(|| PathBuf::from("."));
+            match init_project(&dir) {
+                Ok(created) => {
+                    for file in created {
+                        println!("created {}", file.display());
                     }
                     std::process::exit(0)
                 }
                 Err(err) => {
-                    eprintln!("{err}");
+                    report_error(&CompileError::Io(err), &dir, color);
                     std::process::exit(1)
                 }
             }
         }
+        Command::Disasm { input } => {
+            let input = input.filter(|p| p.as_os_str() != "-");
+            let name = input.clone().unwrap_or_else(|| PathBuf::from("<stdin>"));
+            match read_bytecode(input.as_deref()) {
+                Ok(code) => print!("{}", disassembly_listing(&code)),
+                Err(diag) => {
+                    eprint!("{}", render(&diag, &name, None, color));
+                    std::process::exit(1)
+                }
+            }
+        }
+    }
+}
+
+/// Loads the project around the current directory with its sources and
+/// the options they compile with. Errors come with the manifest to report
+/// them against.
+fn open_project() -> Result<(Project, Vec<PathBuf>, CompileOptions), (CompileError, PathBuf)> {
+    let cwd = std::env::current_dir().map_err(|e| (CompileError::Io(e), PathBuf::from(".")))?;
+    let Some(root) = find_root(&cwd) else {
+        let message = format!("no {MANIFEST} in this directory or any parent; pass a file to build");
+        let err = std::io::Error::new(std::io::ErrorKind::NotFound, message);
+        return Err((CompileError::Io(err), PathBuf::from(".")));
+    };
+    let manifest = root.join(MANIFEST);
+    let load = || {
+        let project = Project::load(&root)?;
+        let sources = project.sources()?;
+        let options = project.compile_options()?;
+        Ok((project, sources, options))
+    };
+    load().map_err(|err| (err, manifest))
+}
+
+/// Runs the tests in `inputs`, or in the project's test folder, and prints
+/// one line per test. Returns whether every test passed.
+fn test(inputs: Vec<PathBuf>, include: Vec<PathBuf>, color: bool) -> bool {
+    let plan = if inputs.is_empty() {
+        open_project().and_then(|(project, _, options)| {
+            let dir = project.tests_dir();
+            let files = pyra_files(&dir).map_err(|e| (CompileError::Io(e), dir))?;
+            Ok((files, options))
+        })
+    } else {
+        expand_inputs(inputs).map(|files| (files, CompileOptions::default()))
+    };
+    let (files, mut options) = match plan {
+        Ok(plan) => plan,
+        Err((err, path)) => {
+            report_error(&err, &path, color);
+            return false;
+        }
+    };
+    options.include_paths.extend(include);
+
+    let (mut passed, mut failed) = (0, 0);
+    for file in &files {
+        let results = match run_test_file(file, &options) {
+            Ok(results) => results,
+            Err(err) => {
+                report_error(&err, file, color);
+                failed += 1;
+                continue;
+            }
+        };
+        println!("running {} tests in {}", results.len(), file.display());
+        for result in results {
+            let status = if result.passed { "ok" } else { "FAILED" };
+            println!("test {} ... {status} ({} gas)", result.name, result.gas);
+            match result.reason {
+                Some(reason) if !result.passed => println!("    {reason}"),
+                _ => {}
+            }
+            if result.passed {
+                passed += 1;
+            } else {
+                failed += 1;
+            }
+        }
+    }
+    let status = if failed == 0 { "ok" } else { "FAILED" };
+    println!("\ntest result: {status}. {passed} passed; {failed} failed");
+    failed == 0
+}
+
+/// What one `pyra build` run compiles and how.
+struct BuildPlan {
+    inputs: Vec<PathBuf>,
+    options: CompileOptions,
+    out_dir: Option<PathBuf>,
+    artifact: ArtifactFormat,
+    /// Set for project builds, which also list each contract they compile.
+    cache: Option<BuildCache>,
+}
+
+/// Works out the inputs and settings from the command line, or from the
+/// manifest when no input is given. Errors come with the file to report
+/// them against.
+fn plan_build(args: &BuildArgs) -> Result<BuildPlan, (CompileError, PathBuf)> {
+    let mut plan = if !args.inputs.is_empty() {
+        BuildPlan {
+            inputs: expand_inputs(args.inputs.clone())?,
+            options: CompileOptions::default(),
+            out_dir: args.out_dir.clone(),
+            artifact: ArtifactFormat::Pyra,
+            cache: None,
+        }
+    } else {
+        let (project, inputs, options) = open_project()?;
+        let cache_dir = project.root.join(CACHE_DIR);
+        let cache = BuildCache::new(&cache_dir);
+        if args.no_cache {
+            cache.clear().map_err(|e| (CompileError::Io(e), cache_dir))?;
+        }
+        BuildPlan {
+            inputs,
+            options,
+            out_dir: args.out_dir.clone().or_else(|| project.out_dir()),
+            artifact: project.manifest.build.artifact_format,
+            cache: Some(cache),
+        }
+    };
+
+    let options = &mut plan.options;
+    options.include_paths.extend(args.include.iter().cloned());
+    options.sort_abi = args.sort_abi;
+    options.strict |= args.strict;
+    if let Some(format) = args.format {
+        options.format = match format {
+            Format::Legacy => OutputFormat::Legacy,
+            Format::Eof => OutputFormat::Eof,
+        };
+    }
+    if let Some(opt_level) = args.opt_level {
+        options.harden.opt_level = match opt_level {
+            Opt::O0 => OptLevel::O0,
+            Opt::O1 => OptLevel::O1,
+            Opt::O2 => OptLevel::O2,
+        };
+    }
+    if let Some(evm_version) = args.evm_version {
+        options.harden.evm_version = match evm_version {
+            Evm::Paris => EvmVersion::Paris,
+            Evm::Shanghai => EvmVersion::Shanghai,
+            Evm::Cancun => EvmVersion::Cancun,
+        };
+    }
+    args.checks.apply(&mut options.harden);
+    options.constructor_args = args.constructor_args.clone().map(|a| a.0).unwrap_or_default();
+    options.source_map = args.source_map;
+    options.ast_json = args.emit.contains(&Emit::AstJson);
+    options.ir = args.emit.contains(&Emit::Ir);
+    options.ir_stages = args.emit.contains(&Emit::IrStages);
+
+    if let Some(artifact) = args.artifact_format {
+        plan.artifact = match artifact {
+            Artifact::Pyra => ArtifactFormat::Pyra,
+            Artifact::Foundry => ArtifactFormat::Foundry,
+            Artifact::Hardhat => ArtifactFormat::Hardhat,
+        };
+    }
+    Ok(plan)
+}
+
+/// One `pyra build` run. Returns whether every contract built.
+fn build(args: &BuildArgs, color: bool) -> bool {
+    let plan = match plan_build(args) {
+        Ok(plan) => plan,
+        Err((err, path)) => {
+            report_error(&err, &path, color);
+            return false;
+        }
+    };
+    let jobs = args.jobs.map_or_else(
+        || std::thread::available_parallelism().map_or(1, |n| n.get()),
+        usize::from,
+    );
+    let built = parallel_map(&plan.inputs, jobs, |input| compile_target(input, &plan));
+    let mut failed = false;
+    for (input, result) in plan.inputs.iter().zip(built) {
+        failed |= !report_build(input, result, &plan, &args.reports, color);
+    }
+    !failed
+}
+
+/// How often `--watch` looks for changes.
+const WATCH_INTERVAL: Duration = Duration::from_millis(250);
+
+/// Builds, then builds again each time a watched file changes, printing
+/// how long every build took. Runs until interrupted.
+fn watch(args: &BuildArgs, color: bool) -> ! {
+    loop {
+        let roots = watch_roots(args);
+        let before = snapshot(&roots);
+        let started = Instant::now();
+        let ok = build(args, color);
+        eprintln!(
+            "[watch] {} in {:.2?}; waiting for changes",
+            if ok { "built" } else { "failed" },
+            started.elapsed()
+        );
+        let changed = loop {
+            std::thread::sleep(WATCH_INTERVAL);
+            let now = snapshot(&roots);
+            if now != before {
+                break now
+                    .iter()
+                    .find(|(file, stamp)| before.get(*file) != Some(stamp))
+                    .or_else(|| before.iter().find(|(file, _)| !now.contains_key(*file)))
+                    .map(|(file, _)| file.clone());
+            }
+        };
+        if let Some(file) = changed {
+            let file = file.strip_prefix(".").unwrap_or(&file);
+            eprintln!("[watch] {} changed, rebuilding", file.display());
+        }
+    }
+}
+
+/// The files and directories whose `.pyra` files a build may read: the
+/// inputs, the directories of input files, include directories and, for
+/// a project, its root and dependencies.
+fn watch_roots(args: &BuildArgs) -> Vec<PathBuf> {
+    let mut roots = args.include.clone();
+    for input in &args.inputs {
+        roots.push(input.clone());
+        if input.is_file() {
+            roots.push(input.parent().map_or_else(|| PathBuf::from("."), Path::to_path_buf));
+        }
+    }
+    if args.inputs.is_empty() {
+        let root = match std::env::current_dir().ok().and_then(|cwd| find_root(&cwd)) {
+            Some(root) if !root.as_os_str().is_empty() => root,
+            _ => PathBuf::from("."),
+        };
+        if let Ok(options) = Project::load(&root).and_then(|project| project.compile_options()) {
+            roots.extend(options.include_paths);
+            roots.extend(options.dependencies.into_iter().map(|(_, dir)| dir));
+        }
+        roots.push(root);
+    }
+    roots
+}
+
+/// The modification time and size of every `.pyra` file and manifest
+/// under `roots`.
+fn snapshot(roots: &[PathBuf]) -> BTreeMap<PathBuf, (Option<SystemTime>, u64)> {
+    let mut files: Vec<PathBuf> = Vec::new();
+    for root in roots {
+        if root.is_dir() {
+            files.extend(pyra_files(root).unwrap_or_default());
+            files.push(root.join(MANIFEST));
+        } else {
+            files.push(root.clone());
+        }
+    }
+    files
+        .into_iter()
+        .filter_map(|file| {
+            let meta = std::fs::metadata(&file).ok()?;
+            Some((file, (meta.modified().ok(), meta.len())))
+        })
+        .collect()
+}
+
+/// Files named on the command line, with each directory replaced by the
+/// `.pyra` files under it. A file named twice is built once.
+fn expand_inputs(inputs: Vec<PathBuf>) -> Result<Vec<PathBuf>, (CompileError, PathBuf)> {
+    let mut files = Vec::new();
+    for input in inputs {
+        if input.is_dir() {
+            files.extend(pyra_files(&input).map_err(|e| (CompileError::Io(e), input.clone()))?);
+        } else {
+            files.push(input);
+        }
+    }
+    let mut seen = HashSet::new();
+    files.retain(|file| seen.insert(file.clone()));
+    Ok(files)
+}
+
+/// Runs `f` on every item with up to `jobs` threads and returns the
+/// results in item order, however the work was scheduled.
+fn parallel_map<T: Sync, R: Send>(items: &[T], jobs: usize, f: impl Fn(&T) -> R + Sync) -> Vec<R> {
+    let next = AtomicUsize::new(0);
+    let mut results: Vec<(usize, R)> = std::thread::scope(|scope| {
+        let workers: Vec<_> = (0..jobs.clamp(1, items.len().max(1)))
+            .map(|_| {
+                scope.spawn(|| {
+                    let mut done = Vec::new();
+                    loop {
+                        let index = next.fetch_add(1, Ordering::Relaxed);
+                        let Some(item) = items.get(index) else { break };
+                        done.push((index, f(item)));
+                    }
+                    done
+                })
+            })
+            .collect();
+        workers
+            .into_iter()
+            .flat_map(|worker| worker.join().expect("build thread panicked"))
+            .collect()
+    });
+    results.sort_by_key(|(index, _)| *index);
+    results.into_iter().map(|(_, result)| result).collect()
+}
+
+/// Compiles `input` and writes its artifacts. The program is `None` when
+/// the cache already had it.
+fn compile_target(input: &Path, plan: &BuildPlan) -> Result<Option<Program>, CompileError> {
+    let out_dir = plan.out_dir.as_deref();
+    match &plan.cache {
+        Some(cache) => cache
+            .build(input, out_dir, plan.artifact, &plan.options)
+            .map(|(_, program)| program),
+        None => compile_file_to_artifacts(input, out_dir, plan.artifact, &plan.options)
+            .map(|(program, _)| Some(program)),
+    }
+}
+
+/// Prints the outcome of building `input`: its errors, or its warnings
+/// and whatever `reports` asks for. A contract reused from the cache
+/// prints no warnings. Returns whether the build succeeded.
+fn report_build(
+    input: &Path,
+    built: Result<Option<Program>, CompileError>,
+    plan: &BuildPlan,
+    reports: &ReportFlags,
+    color: bool,
+) -> bool {
+    let options = &plan.options;
+    let wants_module = reports.gas_report || reports.op_histogram || reports.summary_json;
+    let program = match built {
+        Ok(Some(program)) => {
+            if plan.cache.is_some() {
+                println!("compiled {}", input.display());
+            }
+            report_warnings(&program, input, color);
+            program
+        }
+        Ok(None) if reports.print_slot_count || wants_module => {
+            match compile_file_with_options(input, options) {
+                Ok(program) => program,
+                Err(_) => return true,
+            }
+        }
+        Ok(None) => return true,
+        Err(err) => {
+            report_error(&err, input, color);
+            return false;
+        }
+    };
+    if reports.print_slot_count {
+        print_storage_layout(&program);
+    }
+    if wants_module {
+        let module = hardened_module(&program, &options.harden);
+        if reports.gas_report {
+            print_gas_report(&module);
+        }
+        if reports.op_histogram {
+            print_op_histogram(&module);
+        }
+        if reports.summary_json {
+            print_summary_json(input, &program, &module, options);
+        }
+    }
+    true
+}
+
+/// Reads hex bytecode from `input` or stdin. Surrounding whitespace and a
+/// `0x` prefix are ignored.
+fn read_bytecode(input: Option<&Path>) -> Result<Vec<u8>, Diagnostic> {
+    let text = match input {
+        Some(path) => std::fs::read_to_string(path),
+        None => std::io::read_to_string(std::io::stdin()),
+    }
+    .map_err(|e| Diagnostic::error(e.to_string()))?;
+    let text = text.trim();
+    let digits = text.strip_prefix("0x").unwrap_or(text);
+    hex::decode(digits).map_err(|e| Diagnostic::error(format!("invalid hex bytecode: {e}")))
+}
+
+/// Formats one file in place, or only compares it when `check` is set.
+/// Returns whether the file was not already formatted.
+fn format_file(input: &Path, check: bool) -> Result<bool, CompileError> {
+    let source = std::fs::read_to_string(input)?;
+    let formatted = format_source(&source).map_err(|errors| CompileError::Parse {
+        path: input.to_path_buf(),
+        errors,
+    })?;
+    let changed = formatted != source;
+    if changed && !check {
+        std::fs::write(input, formatted)?;
+    }
+    Ok(changed)
+}
+
+#[derive(Clone)]
+struct ConstructorArgs(Vec<u8>);
+
+fn parse_constructor_args(s: &str) -> Result<ConstructorArgs, String> {
+    let digits = s.strip_prefix("0x").unwrap_or(s);
+    let bytes = hex::decode(digits).map_err(|e| format!("invalid hex: {e}"))?;
+    if bytes.len() % 32 != 0 {
+        return Err(format!(
+            "ABI-encoded arguments must be a multiple of 32 bytes, got {}",
+            bytes.len()
+        ));
+    }
+    Ok(ConstructorArgs(bytes))
+}
+
+fn use_color(no_color: bool) -> bool {
+    if no_color || std::env::var_os("NO_COLOR").is_some() {
+        return false;
+    }
+    if std::env::var("CLICOLOR_FORCE").is_ok_and(|v| v != "0") {
+        return true;
+    }
+    std::io::stderr().is_terminal()
+}
+
+fn report_error(err: &CompileError, input: &Path, color: bool) {
+    if let CompileError::Type(errors) = err {
+        for e in errors {
+            let path = e.file().unwrap_or(input);
+            let source = std::fs::read_to_string(path).ok();
+            eprint!("{}", render(&Diagnostic::from_type_error(e), path, source.as_deref(), color));
+        }
+        return;
+    }
+    let path = match err {
+        CompileError::Parse { path, .. }
+        | CompileError::Manifest { path, .. }
+        | CompileError::ImportNotFound { path, .. }
+        | CompileError::ImportedNameNotFound { path, .. } => path.as_path(),
+        _ => input,
+    };
+    let source = std::fs::read_to_string(path).ok();
+    for diag in err.diagnostics(source.as_deref()) {
+        eprint!("{}", render(&diag, path, source.as_deref(), color));
+    }
+}
+
+fn report_warnings(program: &Program, input: &Path, color: bool) {
+    for w in check_warnings(program) {
+        let path = w.file().unwrap_or(input);
+        let source = std::fs::read_to_string(path).ok();
+        eprint!("{}", render(&Diagnostic::from_warning(&w), path, source.as_deref(), color));
+    }
+}
+
+fn print_gas_report(module: &IrModule) {
+    let report = GasReport::from_module(module);
+    println!("Gas Report");
+    println!("{}", "=".repeat(50));
+    for f in &report.functions {
+        println!(
+            "  {} (0x{})  ~{} gas",
+            f.name,
+            hex::encode(f.selector),
+            f.estimated_gas
+        );
+    }
+    println!("  constructor            ~{} gas", report.constructor_gas);
+    println!(
+        "  dispatch overhead      ~{} gas (linear ~{} gas)",
+        report.dispatch_overhead, report.linear_dispatch_overhead
+    );
+}
+
+fn print_storage_layout(program: &Program) {
+    let layout = StorageLayout::from_program(program);
+    let mut slots: Vec<_> = layout.iter().collect();
+    slots.sort_by_key(|(_, slot)| slot.slot);
+    println!("Storage Layout");
+    println!("{}", "=".repeat(50));
+    for (name, slot) in slots {
+        println!("  {name:<22} slot {}", slot.slot);
+    }
+    println!("  slot count             {}", layout.slot_count());
+}
+
+/// Prints one line of JSON about the contract. The sizes come from
+/// emitting `module`, the hardened IR the reports already share.
+fn print_summary_json(input: &Path, program: &Program, module: &IrModule, options: &CompileOptions) {
+    let name = input.file_stem().and_then(|s| s.to_str()).unwrap_or("");
+    let evm = options.harden.evm_version;
+    let runtime = module_to_runtime_bytecode(module, evm);
+    let deploy = match options.format {
+        OutputFormat::Legacy => module_to_deploy_bytecode(module, evm),
+        OutputFormat::Eof => module_to_eof_bytecode(module, evm),
+    };
+    let summary = serde_json::json!({
+        "contract": name,
+        "functions": module.functions.len(),
+        "runtime_size": runtime.map_or(0, |b| b.len()),
+        "deploy_size": deploy.map_or(0, |b| b.len()),
+        "estimated_gas": GasReport::from_module(module).total(),
+        "storage_slots": StorageLayout::from_program(program).slot_count(),
+        "opt_level": options.harden.opt_level.name(),
+    });
+    println!("{summary}");
+}
+
+fn print_op_histogram(module: &IrModule) {
+    println!("Opcode Histogram");
+    println!("{}", "=".repeat(50));
+    for f in &module.functions {
+        println!("  {} (0x{})", f.name, hex::encode(f.selector));
+        let mut counts: Vec<(&str, usize)> = f.op_histogram().into_iter().collect();
+        counts.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(b.0)));
+        for (name, count) in counts {
+            println!("    {name:<14} {count}");
+        }
     }
 }
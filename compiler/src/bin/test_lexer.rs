@@ -6,11 +6,14 @@ fn main() {
     let mut args = std::env::args().skip(1);
     if let Some(path) = args.next() {
         let source = std::fs::read_to_string(&path).expect("read file");
-        let lexer = PyraLexer::new(&source);
-        let tokens: Vec<Token> = lexer.collect();
+        let mut lexer = PyraLexer::new(&source);
+        let tokens: Vec<Token> = lexer.by_ref().collect();
         for (i, token) in tokens.iter().enumerate() {
             println!("{i}: {token}");
         }
+        for diagnostic in lexer.diagnostics() {
+            println!("error {path}:{diagnostic}");
+        }
         return;
     }
 
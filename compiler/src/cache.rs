@@ -0,0 +1,166 @@
+//! Incremental builds. Each compiled contract leaves an entry in
+//! `.pyra-cache` keyed by its path and every setting that affects the
+//! output. The entry lists the files the compile read with their content
+//! hashes, so a later build can tell the contract is unchanged without
+//! parsing it, and holds the merged AST, the IR and the artifact files.
+
+use crate::compiler::{artifact_outputs, check_loaded, write_outputs, CompileError, CompileOptions, Output};
+use crate::project::ArtifactFormat;
+use crate::resolver::{resolve_program, DiskSources, SourceTree};
+use crate::{hardened_module, program_to_ast_json, Program};
+use serde::{Deserialize, Serialize};
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use tiny_keccak::{Hasher, Keccak};
+
+/// The cache directory, kept at the project root.
+pub const CACHE_DIR: &str = ".pyra-cache";
+
+/// A cache directory. Nothing is created until the first entry is stored.
+pub struct BuildCache {
+    dir: PathBuf,
+}
+
+/// What compiling one contract produced.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CachedBuild {
+    /// Every file read, the entry and its imports, with its content hash.
+    pub sources: BTreeMap<PathBuf, String>,
+    /// The program as compiled, imports merged, as `--emit ast-json` writes it.
+    pub ast: String,
+    /// The hardened IR the bytecode came from.
+    pub ir: String,
+    /// The artifact files written.
+    pub outputs: Vec<Output>,
+}
+
+impl BuildCache {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        BuildCache { dir: dir.into() }
+    }
+
+    /// The entry for `path` built with these settings, if no file it read
+    /// has changed since.
+    pub fn get(
+        &self,
+        path: &Path,
+        out_dir: Option<&Path>,
+        format: ArtifactFormat,
+        options: &CompileOptions,
+    ) -> Option<CachedBuild> {
+        let text = std::fs::read_to_string(self.entry_path(path, out_dir, format, options)).ok()?;
+        let entry: CachedBuild = serde_json::from_str(&text).ok()?;
+        let fresh = entry
+            .sources
+            .iter()
+            .all(|(file, hash)| std::fs::read(file).is_ok_and(|content| content_hash(&content) == *hash));
+        fresh.then_some(entry)
+    }
+
+    /// Builds `path` as [`compile_file_to_artifacts`] does, unless the cache
+    /// holds an up-to-date entry; then only artifact files that were
+    /// deleted or edited are written back. The program is returned when it
+    /// had to be compiled. Failed builds are not cached.
+    ///
+    /// [`compile_file_to_artifacts`]: crate::compile_file_to_artifacts
+    pub fn build(
+        &self,
+        path: &Path,
+        out_dir: Option<&Path>,
+        format: ArtifactFormat,
+        options: &CompileOptions,
+    ) -> Result<(CachedBuild, Option<Program>), CompileError> {
+        if let Some(entry) = self.get(path, out_dir, format, options) {
+            let stale: Vec<Output> = entry
+                .outputs
+                .iter()
+                .filter(|(file, contents)| std::fs::read_to_string(file).ok().as_ref() != Some(contents))
+                .cloned()
+                .collect();
+            write_outputs(&stale)?;
+            return Ok((entry, None));
+        }
+
+        let tree = Recording {
+            disk: DiskSources {
+                include_paths: &options.include_paths,
+                dependencies: &options.dependencies,
+            },
+            read: RefCell::new(BTreeMap::new()),
+        };
+        let program = check_loaded(resolve_program(&tree, path)?, options)?;
+        let outputs = artifact_outputs(path, &program, out_dir, format, options)?;
+        write_outputs(&outputs)?;
+
+        let entry = CachedBuild {
+            sources: tree.read.into_inner(),
+            ast: program_to_ast_json(&program).map_err(std::io::Error::from)?,
+            ir: hardened_module(&program, &options.harden).to_string(),
+            outputs,
+        };
+        std::fs::create_dir_all(&self.dir)?;
+        let ignore = self.dir.join(".gitignore");
+        if !ignore.exists() {
+            std::fs::write(ignore, "*\n")?;
+        }
+        let json = serde_json::to_string(&entry).map_err(std::io::Error::from)?;
+        std::fs::write(self.entry_path(path, out_dir, format, options), json)?;
+        Ok((entry, Some(program)))
+    }
+
+    /// Removes every entry.
+    pub fn clear(&self) -> std::io::Result<()> {
+        match std::fs::remove_dir_all(&self.dir) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e),
+            _ => Ok(()),
+        }
+    }
+
+    fn entry_path(
+        &self,
+        path: &Path,
+        out_dir: Option<&Path>,
+        format: ArtifactFormat,
+        options: &CompileOptions,
+    ) -> PathBuf {
+        let key = format!(
+            "pyra {}\n{}\n{out_dir:?}\n{format:?}\n{options:?}",
+            env!("CARGO_PKG_VERSION"),
+            path.display()
+        );
+        self.dir.join(format!("{}.json", content_hash(key.as_bytes())))
+    }
+}
+
+/// Files on disk, noting the hash of every file read.
+struct Recording<'a> {
+    disk: DiskSources<'a>,
+    read: RefCell<BTreeMap<PathBuf, String>>,
+}
+
+impl SourceTree for Recording<'_> {
+    fn identity(&self, path: &Path) -> std::io::Result<PathBuf> {
+        self.disk.identity(path)
+    }
+
+    fn read(&self, path: &Path) -> std::io::Result<String> {
+        let text = self.disk.read(path)?;
+        self.read
+            .borrow_mut()
+            .insert(path.to_path_buf(), content_hash(text.as_bytes()));
+        Ok(text)
+    }
+
+    fn resolve(&self, importer: &Path, target: &str) -> Option<PathBuf> {
+        self.disk.resolve(importer, target)
+    }
+}
+
+fn content_hash(data: &[u8]) -> String {
+    let mut hasher = Keccak::v256();
+    hasher.update(data);
+    let mut out = [0u8; 32];
+    hasher.finalize(&mut out);
+    hex::encode(out)
+}
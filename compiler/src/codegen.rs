@@ -1,7 +1,10 @@
-use crate::ir::{lower_program, IrModule, IrOp};
-use crate::security::{harden, add_reentrancy_guard};
+use crate::evm::disassemble;
+use crate::ir::{lower_program, lower_program_with_opt_level, lower_program_with_spans, IrModule, IrOp};
+use crate::security::{
+    add_calldata_size_guard, add_callvalue_guard, add_reentrancy_guard_with_mode, harden, HardenConfig,
+};
 use crate::storage::StorageLayout;
-use crate::Program;
+use crate::{Program, Span};
 use std::collections::HashMap;
 
 #[derive(thiserror::Error, Debug)]
@@ -20,20 +23,138 @@ pub enum CodegenError {
 
     #[error("underflow")]
     Underflow,
+
+    #[error("code section of {0} bytes exceeds the EOF limit")]
+    CodeTooLarge(usize),
+
+    #[error("{op} requires evm version {needs}, but the target is {target}")]
+    UnsupportedOpcode {
+        op: &'static str,
+        needs: &'static str,
+        target: &'static str,
+        /// The statement that asked for the opcode, when the module carries
+        /// `Loc` markers.
+        span: Option<Span>,
+    },
 }
 
+impl CodegenError {
+    pub fn code(&self) -> &'static str {
+        match self {
+            CodegenError::NoFunction => "E3001",
+            CodegenError::NoReturn => "E3002",
+            CodegenError::UnsupportedExpression => "E3003",
+            CodegenError::DivisionByZero => "E3004",
+            CodegenError::Underflow => "E3005",
+            CodegenError::CodeTooLarge(_) => "E3006",
+            CodegenError::UnsupportedOpcode { .. } => "E3007",
+        }
+    }
+
+    /// Source range the error points at, if it is known.
+    pub fn span(&self) -> Option<&Span> {
+        match self {
+            CodegenError::UnsupportedOpcode { span, .. } => span.as_ref(),
+            _ => None,
+        }
+    }
+}
+
+/// Hard fork the bytecode targets. Each version may use every opcode of the
+/// ones before it.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum EvmVersion {
+    #[default]
+    Paris,
+    /// Adds `PUSH0`.
+    Shanghai,
+    /// Adds `TLOAD`, `TSTORE` and `MCOPY`.
+    Cancun,
+}
+
+impl EvmVersion {
+    pub fn name(self) -> &'static str {
+        match self {
+            EvmVersion::Paris => "paris",
+            EvmVersion::Shanghai => "shanghai",
+            EvmVersion::Cancun => "cancun",
+        }
+    }
+
+    pub fn has_push0(self) -> bool {
+        self >= EvmVersion::Shanghai
+    }
+
+    /// The first version that has `op`.
+    fn introducing(op: &IrOp) -> EvmVersion {
+        match op {
+            IrOp::TLoad | IrOp::TStore | IrOp::MCopy => EvmVersion::Cancun,
+            _ => EvmVersion::Paris,
+        }
+    }
+}
+
+fn check_opcodes<'a>(
+    ops: impl IntoIterator<Item = &'a IrOp>,
+    evm: EvmVersion,
+) -> Result<(), CodegenError> {
+    let mut span = None;
+    for op in ops {
+        if let IrOp::Loc(_, at) = op {
+            span = Some(at);
+        }
+        let needs = EvmVersion::introducing(op);
+        if needs > evm {
+            return Err(CodegenError::UnsupportedOpcode {
+                op: op.name(),
+                needs: needs.name(),
+                target: evm.name(),
+                span: span.cloned(),
+            });
+        }
+    }
+    Ok(())
+}
+
+/// Points an unlocated opcode error at its statement by lowering the program
+/// again with `Loc` markers. Emission stops at the first error, so the
+/// plain lowering is kept for the common path.
+fn locate(error: CodegenError, program: &Program, config: &HardenConfig) -> CodegenError {
+    if !matches!(error, CodegenError::UnsupportedOpcode { span: None, .. }) {
+        return error;
+    }
+    let module = harden_module(lower_program_with_spans(program, config.opt_level), program, config);
+    let ops = module.constructor_ops.iter().chain(module.functions.iter().flat_map(|f| &f.ops));
+    check_opcodes(ops, config.evm_version).err().unwrap_or(error)
+}
+
+/// Code offsets where the source file and span change; `None` is generated
+/// code.
+type SpanMarks = Vec<(usize, Option<(usize, Span)>)>;
+
 struct Emitter {
     code: Vec<u8>,
     labels: HashMap<usize, usize>,
     patches: Vec<(usize, usize)>,
+    push0: bool,
+    spans: SpanMarks,
 }
 
 impl Emitter {
-    fn new() -> Self {
+    fn new(evm: EvmVersion) -> Self {
         Self {
             code: Vec::with_capacity(4096),
             labels: HashMap::new(),
             patches: Vec::new(),
+            push0: evm.has_push0(),
+            spans: Vec::new(),
+        }
+    }
+
+    fn locate(&mut self, span: Option<(usize, Span)>) {
+        if self.spans.last().map(|(_, s)| s) != Some(&span) {
+            self.spans.push((self.code.len(), span));
         }
     }
 
@@ -44,6 +165,10 @@ impl Emitter {
     fn push_data(&mut self, data: &[u8]) {
         let n = data.len();
         debug_assert!(n > 0 && n <= 32);
+        if self.push0 && data.iter().all(|&b| b == 0) {
+            self.code.push(0x5f);
+            return;
+        }
         self.code.push(0x5f + n as u8);
         self.code.extend_from_slice(data);
     }
@@ -91,11 +216,17 @@ impl Emitter {
             IrOp::And => self.byte(0x16),
             IrOp::Or => self.byte(0x17),
             IrOp::Not => self.byte(0x19),
+            IrOp::Byte => self.byte(0x1a),
+            IrOp::SignExtend => self.byte(0x0b),
+            IrOp::Shl => self.byte(0x1b),
             IrOp::Shr => self.byte(0x1c),
             IrOp::MLoad => self.byte(0x51),
             IrOp::MStore => self.byte(0x52),
             IrOp::SLoad => self.byte(0x54),
             IrOp::SStore => self.byte(0x55),
+            IrOp::TLoad => self.byte(0x5c),
+            IrOp::TStore => self.byte(0x5d),
+            IrOp::MCopy => self.byte(0x5e),
             IrOp::Jump(label) => {
                 self.label_ref(*label);
                 self.byte(0x56);
@@ -107,75 +238,294 @@ impl Emitter {
             IrOp::JumpDest(label) => {
                 self.mark_label(*label);
             }
+            IrOp::PushLabel(label) => self.label_ref(*label),
+            IrOp::JumpBack => self.byte(0x56),
+            IrOp::Narrow { bits, signed: false } => {
+                self.push_data(&vec![0xff; usize::from(*bits / 8)]);
+                self.byte(0x16);
+            }
+            IrOp::Narrow { bits, signed: true } => {
+                self.push_data(&[(*bits / 8 - 1) as u8]);
+                self.byte(0x0b);
+            }
             IrOp::Caller => self.byte(0x33),
             IrOp::CallValue => self.byte(0x34),
+            IrOp::Timestamp => self.byte(0x42),
+            IrOp::Number => self.byte(0x43),
             IrOp::CallDataLoad => self.byte(0x35),
             IrOp::CallDataSize => self.byte(0x36),
+            IrOp::CallDataCopy => self.byte(0x37),
+            IrOp::CodeSize => self.byte(0x38),
+            IrOp::CodeCopy => self.byte(0x39),
+            IrOp::ExtCodeSize => self.byte(0x3b),
+            IrOp::ReturnDataSize => self.byte(0x3d),
+            IrOp::ReturnDataCopy => self.byte(0x3e),
+            IrOp::Gas => self.byte(0x5a),
+            IrOp::Call => self.byte(0xf1),
+            IrOp::StaticCall => self.byte(0xfa),
             IrOp::Keccak256 => self.byte(0x20),
             IrOp::Return => self.byte(0xf3),
             IrOp::Revert => self.byte(0xfd),
             IrOp::Log(n) => self.byte(0xa0 + n),
             IrOp::Stop => self.byte(0x00),
             IrOp::Invalid => self.byte(0xfe),
+            IrOp::Loc(file, span) => self.locate(Some((*file, span.clone()))),
         }
     }
 
-    fn into_bytes(mut self) -> Vec<u8> {
+    fn finish(mut self) -> (Vec<u8>, SpanMarks) {
         self.resolve();
-        self.code
+        (self.code, self.spans)
+    }
+}
+
+/// Bytecode with its source map in the `s:l:f:j` format solc uses: one
+/// `;`-separated entry per instruction giving the byte offset and length of
+/// the source it came from, the file index and the jump type. A field equal
+/// to the one before it is left empty. Code the compiler adds on its own,
+/// such as the dispatcher, maps to `-1:-1:-1`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MappedBytecode {
+    pub code: Vec<u8>,
+    pub source_map: String,
+}
+
+fn source_map(code: &[u8], spans: &SpanMarks) -> String {
+    let mut entries = Vec::new();
+    let mut prev: Option<[String; 4]> = None;
+    let mut next = spans.iter().peekable();
+    let mut current = None;
+    for ins in disassemble(code) {
+        while let Some((_, span)) = next.next_if(|(at, _)| *at <= ins.offset) {
+            current = span.as_ref();
+        }
+        let fields = match current {
+            Some((file, span)) => {
+                [span.start.to_string(), (span.end - span.start).to_string(), file.to_string(), "-".into()]
+            }
+            None => ["-1".into(), "-1".into(), "-1".into(), "-".into()],
+        };
+        let mut entry: Vec<&str> = fields
+            .iter()
+            .zip(prev.iter().flatten().map(Some).chain(std::iter::repeat(None)))
+            .map(|(field, before)| if before == Some(field) { "" } else { field.as_str() })
+            .collect();
+        while entry.last() == Some(&"") {
+            entry.pop();
+        }
+        entries.push(entry.join(":"));
+        prev = Some(fields);
     }
+    entries.join(";")
+}
+
+pub fn hardened_module(program: &Program, config: &HardenConfig) -> IrModule {
+    harden_module(lower_program_with_opt_level(program, config.opt_level), program, config)
+}
+
+fn harden_module(mut module: IrModule, program: &Program, config: &HardenConfig) -> IrModule {
+    harden(&mut module, config);
+    let layout = StorageLayout::from_program(program);
+    add_reentrancy_guard_with_mode(&mut module, layout.slot_count(), config.guard_mode);
+    if config.strict_calldata {
+        add_calldata_size_guard(&mut module);
+    }
+    add_callvalue_guard(&mut module);
+    module
 }
 
 pub fn program_to_runtime_bytecode(program: &Program) -> Result<Vec<u8>, CodegenError> {
+    program_to_runtime_bytecode_with_config(program, &HardenConfig::default())
+}
+
+pub fn program_to_runtime_bytecode_with_config(
+    program: &Program,
+    config: &HardenConfig,
+) -> Result<Vec<u8>, CodegenError> {
+    module_to_runtime(&hardened_module(program, config), config.evm_version).map_err(|e| locate(e, program, config))
+}
+
+/// The runtime bytecode of a module that is already hardened, such as one
+/// from [`hardened_module`].
+pub fn module_to_runtime_bytecode(module: &IrModule, evm: EvmVersion) -> Result<Vec<u8>, CodegenError> {
+    module_to_runtime(module, evm)
+}
+
+/// The deploy bytecode of a module that is already hardened.
+pub fn module_to_deploy_bytecode(module: &IrModule, evm: EvmVersion) -> Result<Vec<u8>, CodegenError> {
+    module_to_deploy(module, evm).map(|deploy| deploy.code)
+}
+
+/// The EOF container of a module that is already hardened.
+pub fn module_to_eof_bytecode(module: &IrModule, evm: EvmVersion) -> Result<Vec<u8>, CodegenError> {
+    let runtime = module_to_runtime(module, evm)?;
+    if runtime.len() > u16::MAX as usize {
+        return Err(CodegenError::CodeTooLarge(runtime.len()));
+    }
+    Ok(build_eof_container(&runtime, &[]))
+}
+
+/// Lowers `program` and runs each pass over the module in order before
+/// emission. No hardening is applied unless one of the passes does it, so
+/// callers compose `harden`, `add_reentrancy_guard` and their own passes.
+pub fn program_to_runtime_bytecode_with_passes(
+    program: &Program,
+    passes: &[&dyn Fn(&mut IrModule)],
+) -> Result<Vec<u8>, CodegenError> {
     let mut module = lower_program(program);
-    harden(&mut module);
-    let layout = StorageLayout::from_program(program);
-    add_reentrancy_guard(&mut module, layout.slot_count());
-    module_to_runtime(&module)
+    for pass in passes {
+        pass(&mut module);
+    }
+    module_to_runtime(&module, EvmVersion::default())
 }
 
 pub fn program_to_deploy_bytecode(program: &Program) -> Result<Vec<u8>, CodegenError> {
-    let mut module = lower_program(program);
-    harden(&mut module);
-    let layout = StorageLayout::from_program(program);
-    add_reentrancy_guard(&mut module, layout.slot_count());
+    program_to_deploy_bytecode_with_config(program, &HardenConfig::default())
+}
+
+pub fn program_to_deploy_bytecode_with_config(
+    program: &Program,
+    config: &HardenConfig,
+) -> Result<Vec<u8>, CodegenError> {
+    module_to_deploy_bytecode(&hardened_module(program, config), config.evm_version)
+        .map_err(|e| locate(e, program, config))
+}
+
+/// The runtime bytecode and its source map. The code is the same as
+/// `program_to_runtime_bytecode_with_config` produces.
+pub fn program_to_runtime_bytecode_with_source_map(
+    program: &Program,
+    config: &HardenConfig,
+) -> Result<MappedBytecode, CodegenError> {
+    let module = harden_module(lower_program_with_spans(program, config.opt_level), program, config);
+    let (code, spans) = emit_runtime(&module, config.evm_version)?;
+    let source_map = source_map(&code, &spans);
+    Ok(MappedBytecode { code, source_map })
+}
+
+/// The deploy bytecode and the source map of the constructor and the code
+/// that copies out the runtime. The runtime after them is data to the
+/// deploy step; its map comes from `program_to_runtime_bytecode_with_source_map`.
+pub fn program_to_deploy_bytecode_with_source_map(
+    program: &Program,
+    config: &HardenConfig,
+) -> Result<MappedBytecode, CodegenError> {
+    let module = harden_module(lower_program_with_spans(program, config.opt_level), program, config);
+    let deploy = module_to_deploy(&module, config.evm_version)?;
+    let source_map = source_map(&deploy.code[..deploy.init_len], &deploy.spans);
+    Ok(MappedBytecode { code: deploy.code, source_map })
+}
+
+struct Deploy {
+    code: Vec<u8>,
+    /// Bytes before the runtime: the constructor and the copy-out code.
+    init_len: usize,
+    spans: SpanMarks,
+}
+
+fn module_to_deploy(module: &IrModule, evm: EvmVersion) -> Result<Deploy, CodegenError> {
+    check_opcodes(&module.constructor_ops, evm)?;
 
-    let mut ctor_em = Emitter::new();
+    let mut ctor_em = Emitter::new(evm);
+    let ctor_end = module.label_count;
+    let mut returns_early = false;
     for op in &module.constructor_ops {
         match op {
-            IrOp::Return | IrOp::Stop => {}
+            IrOp::Return | IrOp::Stop => {
+                ctor_em.emit_op(&IrOp::Jump(ctor_end));
+                returns_early = true;
+            }
             _ => ctor_em.emit_op(op),
         }
     }
-    let ctor_bytes = ctor_em.into_bytes();
+    if returns_early {
+        ctor_em.locate(None);
+        ctor_em.emit_op(&IrOp::JumpDest(ctor_end));
+    }
+    let (ctor_bytes, mut spans) = ctor_em.finish();
+    spans.push((ctor_bytes.len(), None));
+
+    let runtime = module_to_runtime(module, evm)?;
+    let code = build_deploy(&ctor_bytes, &runtime, evm);
+    let init_len = code.len() - runtime.len();
+    Ok(Deploy { code, init_len, spans })
+}
+
+const EOF_MAGIC: [u8; 2] = [0xef, 0x00];
+const EOF_VERSION: u8 = 0x01;
+const EOF_KIND_TYPE: u8 = 0x01;
+const EOF_KIND_CODE: u8 = 0x02;
+const EOF_KIND_DATA: u8 = 0xff;
+const EOF_NON_RETURNING: u8 = 0x80;
+const EOF_MAX_STACK_HEIGHT: u16 = 0x03ff;
+
+pub fn program_to_eof_bytecode(program: &Program) -> Result<Vec<u8>, CodegenError> {
+    program_to_eof_bytecode_with_config(program, &HardenConfig::default())
+}
+
+pub fn program_to_eof_bytecode_with_config(
+    program: &Program,
+    config: &HardenConfig,
+) -> Result<Vec<u8>, CodegenError> {
+    module_to_eof_bytecode(&hardened_module(program, config), config.evm_version).map_err(|e| locate(e, program, config))
+}
 
-    let runtime = module_to_runtime(&module)?;
-    Ok(build_deploy(&ctor_bytes, &runtime))
+fn build_eof_container(code: &[u8], data: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(code.len() + data.len() + 19);
+    out.extend_from_slice(&EOF_MAGIC);
+    out.push(EOF_VERSION);
+    out.push(EOF_KIND_TYPE);
+    out.extend_from_slice(&4u16.to_be_bytes());
+    out.push(EOF_KIND_CODE);
+    out.extend_from_slice(&1u16.to_be_bytes());
+    out.extend_from_slice(&(code.len() as u16).to_be_bytes());
+    out.push(EOF_KIND_DATA);
+    out.extend_from_slice(&(data.len() as u16).to_be_bytes());
+    out.push(0x00);
+
+    out.push(0x00);
+    out.push(EOF_NON_RETURNING);
+    out.extend_from_slice(&EOF_MAX_STACK_HEIGHT.to_be_bytes());
+
+    out.extend_from_slice(code);
+    out.extend_from_slice(data);
+    out
+}
+
+/// Contracts with at least this many functions dispatch by binary search
+/// over sorted selectors; smaller ones compare each selector in turn.
+pub(crate) const BINARY_DISPATCH_MIN: usize = 4;
+
+fn module_to_runtime(module: &IrModule, evm: EvmVersion) -> Result<Vec<u8>, CodegenError> {
+    emit_runtime(module, evm).map(|(code, _)| code)
 }
 
-fn module_to_runtime(module: &IrModule) -> Result<Vec<u8>, CodegenError> {
-    let mut em = Emitter::new();
+fn emit_runtime(
+    module: &IrModule,
+    evm: EvmVersion,
+) -> Result<(Vec<u8>, SpanMarks), CodegenError> {
+    check_opcodes(module.functions.iter().flat_map(|f| &f.ops), evm)?;
+    let mut em = Emitter::new(evm);
 
-    if !module.functions.is_empty() {
+    if module.functions.is_empty() {
+        emit_revert(&mut em);
+    } else {
         em.push_data(&[0x00]);
         em.byte(0x35);
         em.push_data(&[0xe0]);
         em.byte(0x1c);
 
-        for func in &module.functions {
-            em.byte(0x80);
-            em.push_data(&func.selector);
-            em.byte(0x14);
-            em.label_ref(func.label);
-            em.byte(0x57);
+        let mut entries: Vec<([u8; 4], usize)> =
+            module.functions.iter().map(|f| (f.selector, f.label)).collect();
+        if entries.len() >= BINARY_DISPATCH_MIN {
+            entries.sort();
         }
+        let mut next_label = module.label_count;
+        emit_dispatch(&mut em, &entries, &mut next_label);
     }
 
-    em.push_data(&[0x00]);
-    em.push_data(&[0x00]);
-    em.byte(0xfd);
-
     for func in &module.functions {
+        em.locate(None);
         for (i, op) in func.ops.iter().enumerate() {
             em.emit_op(op);
             if i == 0 && matches!(op, IrOp::JumpDest(_)) {
@@ -184,10 +534,47 @@ fn module_to_runtime(module: &IrModule) -> Result<Vec<u8>, CodegenError> {
         }
     }
 
-    Ok(em.into_bytes())
+    Ok(em.finish())
+}
+
+/// Emits the dispatch for `entries`, leaving the selector on the stack for
+/// the function's entry `POP`. Each range splits on the first selector of
+/// its upper half until it is short enough to compare linearly; every leaf
+/// ends in its own revert.
+fn emit_dispatch(em: &mut Emitter, entries: &[([u8; 4], usize)], next_label: &mut usize) {
+    if entries.len() < BINARY_DISPATCH_MIN {
+        for (selector, label) in entries {
+            em.byte(0x80);
+            em.push_data(selector);
+            em.byte(0x14);
+            em.label_ref(*label);
+            em.byte(0x57);
+        }
+        emit_revert(em);
+        return;
+    }
+
+    let (lower, upper) = entries.split_at(entries.len() / 2);
+    let lower_label = *next_label;
+    *next_label += 1;
+    em.byte(0x80);
+    em.push_data(&upper[0].0);
+    em.byte(0x11);
+    em.label_ref(lower_label);
+    em.byte(0x57);
+    emit_dispatch(em, upper, next_label);
+    em.mark_label(lower_label);
+    emit_dispatch(em, lower, next_label);
+}
+
+fn emit_revert(em: &mut Emitter) {
+    em.push_data(&[0x00]);
+    em.push_data(&[0x00]);
+    em.byte(0xfd);
 }
 
-fn build_deploy(constructor: &[u8], runtime: &[u8]) -> Vec<u8> {
+fn build_deploy(constructor: &[u8], runtime: &[u8], evm: EvmVersion) -> Vec<u8> {
+    let push_usize = |value| push_usize(value, evm);
     let mut cr_len = 0usize;
     for _ in 0..8 {
         let total_prefix = constructor.len() + cr_len;
@@ -224,9 +611,9 @@ fn build_deploy(constructor: &[u8], runtime: &[u8]) -> Vec<u8> {
     out
 }
 
-fn push_usize(value: usize) -> Vec<u8> {
+fn push_usize(value: usize, evm: EvmVersion) -> Vec<u8> {
     if value == 0 {
-        return vec![0x60, 0x00];
+        return if evm.has_push0() { vec![0x5f] } else { vec![0x60, 0x00] };
     }
     let mut buf = [0u8; 32];
     let mut v = value;
@@ -246,7 +633,30 @@ fn push_usize(value: usize) -> Vec<u8> {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::evm::{disassemble, Instruction};
+    use crate::ir::IrFunction;
     use crate::parser::parse_from_source;
+    use crate::runtime::{self, CallResult, ContractHandle, Outcome};
+
+    #[test]
+    fn custom_passes_run_in_order_before_emission() {
+        let program = parse_from_source("def t() -> uint256: return 1").unwrap();
+        let plain = program_to_runtime_bytecode_with_passes(&program, &[]).unwrap();
+        let ran = std::cell::Cell::new(0);
+        let harden_pass = |m: &mut IrModule| {
+            harden(m, &HardenConfig::default());
+            ran.set(ran.get() * 10 + 1);
+        };
+        let noop_pass = |m: &mut IrModule| {
+            m.functions[0].ops.push(IrOp::JumpDest(m.label_count));
+            m.label_count += 1;
+            ran.set(ran.get() * 10 + 2);
+        };
+        let code = program_to_runtime_bytecode_with_passes(&program, &[&harden_pass, &noop_pass]).unwrap();
+        assert_eq!(ran.get(), 12);
+        assert_eq!(code.len(), plain.len() + 1);
+        assert_eq!(code.last(), Some(&0x5b));
+    }
 
     #[test]
     fn runtime_starts_with_dispatcher() {
@@ -261,6 +671,59 @@ mod tests {
         assert_eq!(code[5], 0x1c);
     }
 
+    #[test]
+    fn many_functions_dispatch_by_binary_search() {
+        let src: String = (0..9).map(|i| format!("def f{i}() -> uint256: return {i}\n\n")).collect();
+        let program = parse_from_source(&src).unwrap();
+        let code = program_to_runtime_bytecode(&program).unwrap();
+        let ins = disassemble(&code);
+        assert!(ins.iter().any(|i| i.opcode == 0x11));
+
+        let mut vm = crate::vm::Vm::from_source(&src);
+        for i in 0..9u8 {
+            let out = vm.call_function(&format!("f{i}"), &[]);
+            assert!(out.is_success(), "f{i}: {out:?}");
+            assert_eq!(out.word(0), i.into());
+        }
+        assert!(!vm.call(&[0xff, 0xff, 0xff, 0xff]).is_success());
+        assert!(!vm.call(&[0x00, 0x00, 0x00, 0x00]).is_success());
+    }
+
+    #[test]
+    fn few_functions_keep_linear_dispatch() {
+        let program = parse_from_source("def a() -> uint256: return 1\n\ndef b() -> uint256: return 2\n").unwrap();
+        let code = program_to_runtime_bytecode(&program).unwrap();
+        assert!(!disassemble(&code).iter().any(|i| i.opcode == 0x11));
+    }
+
+    #[test]
+    fn shanghai_pushes_zero_with_push0() {
+        let program = parse_from_source("def t(x: uint256) -> uint256: return x").unwrap();
+        let shanghai = HardenConfig { evm_version: EvmVersion::Shanghai, ..HardenConfig::default() };
+        let paris = program_to_deploy_bytecode(&program).unwrap();
+        let deploy = program_to_deploy_bytecode_with_config(&program, &shanghai).unwrap();
+        assert!(deploy.len() < paris.len());
+        assert!(disassemble(&deploy).iter().any(|i| i.opcode == 0x5f));
+        assert!(!disassemble(&paris).iter().any(|i| i.opcode == 0x5f));
+
+        let mut contract = runtime::deploy(&deploy).unwrap();
+        let out = contract.call(runtime::function_selector(&program, "t").unwrap(), &[7u8.into()]);
+        assert_eq!(out.word(0), 7u8.into());
+    }
+
+    #[test]
+    fn cancun_opcodes_need_cancun() {
+        let src = "def t():\n    asm: PUSH1 0x01 PUSH1 0x00 TSTORE\n";
+        let program = parse_from_source(src).unwrap();
+        let target = |evm_version| HardenConfig { evm_version, ..HardenConfig::default() };
+        let err = program_to_runtime_bytecode_with_config(&program, &target(EvmVersion::Shanghai)).unwrap_err();
+        assert_eq!(err.to_string(), "TSTORE requires evm version cancun, but the target is shanghai");
+        let span = err.span().unwrap();
+        assert_eq!(&src[span.start..span.end], "asm: PUSH1 0x01 PUSH1 0x00 TSTORE");
+        let code = program_to_runtime_bytecode_with_config(&program, &target(EvmVersion::Cancun)).unwrap();
+        assert!(disassemble(&code).iter().any(|i| i.opcode == 0x5d));
+    }
+
     #[test]
     fn deploy_ends_with_runtime() {
         let program = parse_from_source("def t() -> uint256: return 1").unwrap();
@@ -270,43 +733,302 @@ mod tests {
         assert!(deploy.len() > runtime.len());
     }
 
+    /// Deploys `program` with one constructor argument word per entry.
+    fn deploy_with(program: &Program, args: &[u8]) -> Result<ContractHandle, CallResult> {
+        let mut init = program_to_deploy_bytecode(program).unwrap();
+        for &arg in args {
+            init.extend([0; 31]);
+            init.push(arg);
+        }
+        runtime::deploy(&init)
+    }
+
     #[test]
-    fn deploy_has_codecopy() {
+    fn deploy_installs_runtime() {
         let program = parse_from_source("def t() -> uint256: return 1").unwrap();
-        let deploy = program_to_deploy_bytecode(&program).unwrap();
-        assert!(deploy.contains(&0x39));
+        let contract = deploy_with(&program, &[]).unwrap();
+        assert_eq!(contract.code(), program_to_runtime_bytecode(&program).unwrap());
     }
 
     #[test]
-    fn runtime_contains_push_42() {
+    fn runtime_returns_42() {
         let program = parse_from_source("def t() -> uint256: return 42").unwrap();
-        let code = program_to_runtime_bytecode(&program).unwrap();
-        let found = code.windows(2).any(|w| w[0] == 0x60 && w[1] == 0x2a);
-        assert!(found);
+        let mut contract = deploy_with(&program, &[]).unwrap();
+        let out = contract.call(runtime::function_selector(&program, "t").unwrap(), &[]);
+        assert!(out.is_success(), "{out:?}");
+        assert_eq!(out.output().len(), 32);
+        assert_eq!(out.word(0), 42u8.into());
     }
 
     #[test]
-    fn runtime_has_jumpdest() {
+    fn runtime_rejects_unknown_selectors() {
         let program = parse_from_source("def t() -> uint256: return 1").unwrap();
-        let code = program_to_runtime_bytecode(&program).unwrap();
-        assert!(code.contains(&0x5b));
+        let mut contract = deploy_with(&program, &[]).unwrap();
+        assert!(!contract.call(0xdeadbeefu32.to_be_bytes(), &[]).is_success());
+        assert!(!contract.call_raw(&[]).is_success());
     }
 
     #[test]
     fn constructor_stores_constant() {
         let src = "const supply: uint256 = 100\n\ndef t() -> uint256: return supply\n";
         let program = parse_from_source(src).unwrap();
-        let deploy = program_to_deploy_bytecode(&program).unwrap();
+        let contract = deploy_with(&program, &[]).unwrap();
+        assert_eq!(contract.storage(&0u8.into()), 100u8.into());
+    }
+
+    #[test]
+    fn constructor_require_reverts_deployment() {
+        let src = "def init(x: uint256):\n    require x > 0\n\ndef t() -> uint256: return 1\n";
+        let program = parse_from_source(src).unwrap();
+        let failed = deploy_with(&program, &[0]).err().expect("x = 0 is rejected");
+        assert_eq!(failed.outcome, Outcome::Revert(Vec::new()));
+        assert!(deploy_with(&program, &[1]).is_ok());
+    }
+
+    #[test]
+    fn constructor_early_return_jumps_to_epilogue() {
+        let src = "def init(x: uint256):\n    if x == 0:\n        return\n    y = 1\n\ndef t() -> uint256: return 1\n";
+        let program = parse_from_source(src).unwrap();
+        let runtime = program_to_runtime_bytecode(&program).unwrap();
+        let returned = deploy_with(&program, &[0]).unwrap();
+        assert_eq!(returned.code(), runtime);
+        assert_eq!(returned.storage(&0u8.into()), 0u8.into());
+        let ran = deploy_with(&program, &[7]).unwrap();
+        assert_eq!(ran.code(), runtime);
+        assert_eq!(ran.storage(&0u8.into()), 1u8.into());
+    }
+
+    #[test]
+    fn eof_container_wraps_runtime() {
+        let program = parse_from_source("def t() -> uint256: return 1").unwrap();
         let runtime = program_to_runtime_bytecode(&program).unwrap();
-        let ctor_region = &deploy[..deploy.len() - runtime.len()];
-        assert!(ctor_region.contains(&0x55));
+        let eof = program_to_eof_bytecode(&program).unwrap();
+        assert_eq!(&eof[..3], &[0xef, 0x00, 0x01]);
+        assert_eq!(&eof[3..6], &[0x01, 0x00, 0x04]);
+        assert_eq!(&eof[6..9], &[0x02, 0x00, 0x01]);
+        assert_eq!(u16::from_be_bytes([eof[9], eof[10]]) as usize, runtime.len());
+        assert_eq!(&eof[11..15], &[0xff, 0x00, 0x00, 0x00]);
+        assert_eq!(&eof[15..19], &[0x00, 0x80, 0x03, 0xff]);
+        assert_eq!(&eof[19..], runtime.as_slice());
+    }
+
+    /// Expands a compressed source map into `(start, length, file)` per instruction.
+    fn decode_source_map(map: &str) -> Vec<(i64, i64, i64)> {
+        let mut prev = (0, 0, 0);
+        map.split(';')
+            .map(|entry| {
+                let fields: Vec<&str> = entry.split(':').collect();
+                let field = |i: usize, before: i64| match fields.get(i) {
+                    Some(f) if !f.is_empty() => f.parse().unwrap(),
+                    _ => before,
+                };
+                prev = (field(0, prev.0), field(1, prev.1), field(2, prev.2));
+                prev
+            })
+            .collect()
+    }
+
+    #[test]
+    fn source_map_does_not_change_the_code() {
+        for name in ["ERC20", "Vault"] {
+            let path = format!("{}/../contracts/{name}.pyra", env!("CARGO_MANIFEST_DIR"));
+            let program = parse_from_source(&std::fs::read_to_string(path).unwrap()).unwrap();
+            let config = HardenConfig::default();
+            let runtime = program_to_runtime_bytecode_with_source_map(&program, &config).unwrap();
+            assert_eq!(runtime.code, program_to_runtime_bytecode_with_config(&program, &config).unwrap());
+            assert_eq!(decode_source_map(&runtime.source_map).len(), disassemble(&runtime.code).len());
+
+            let deploy = program_to_deploy_bytecode_with_source_map(&program, &config).unwrap();
+            assert_eq!(deploy.code, program_to_deploy_bytecode_with_config(&program, &config).unwrap());
+            let init = &deploy.code[..deploy.code.len() - runtime.code.len()];
+            assert_eq!(decode_source_map(&deploy.source_map).len(), disassemble(init).len());
+        }
+    }
+
+    #[test]
+    fn source_map_points_at_statements() {
+        let src = "total: uint256\n\ndef add(a: uint256):\n    total = a\n    if a > 1:\n        total = a * 2\n    require a < 9\n";
+        let program = parse_from_source(src).unwrap();
+        let mapped = program_to_runtime_bytecode_with_source_map(&program, &HardenConfig::default()).unwrap();
+        let ins = disassemble(&mapped.code);
+        let map = decode_source_map(&mapped.source_map);
+        let text = |i: usize| {
+            let (start, len, _) = map[i];
+            &src[start as usize..(start + len) as usize]
+        };
+
+        assert_eq!(map[0], (-1, -1, -1));
+        // The reentrancy lock is taken before the body and released after it.
+        let stores: Vec<&str> = (0..ins.len())
+            .filter(|&i| ins[i].opcode == 0x55)
+            .map(|i| if map[i].0 < 0 { "" } else { text(i) })
+            .collect();
+        assert_eq!(stores, ["", "total = a", "total = a * 2", "require a < 9"]);
+        let mul = ins.iter().position(|i| i.opcode == 0x02).unwrap();
+        assert_eq!(text(mul), "total = a * 2");
+        let revert = ins.iter().rposition(|i| i.opcode == 0xfd).unwrap();
+        assert_eq!(text(revert), "require a < 9");
+        assert!(map.iter().all(|&(_, _, file)| file <= 0));
+    }
+
+    #[test]
+    fn deploy_source_map_covers_the_constructor() {
+        let src = "owner: address\n\ndef init():\n    owner = msg.sender\n\ndef get() -> address:\n    return owner\n";
+        let program = parse_from_source(src).unwrap();
+        let mapped = program_to_deploy_bytecode_with_source_map(&program, &HardenConfig::default()).unwrap();
+        let map = decode_source_map(&mapped.source_map);
+        let caller = disassemble(&mapped.code).iter().position(|i| i.opcode == 0x33).unwrap();
+        let (start, len, file) = map[caller];
+        assert_eq!(&src[start as usize..(start + len) as usize], "owner = msg.sender");
+        assert_eq!(file, 0);
+        assert_eq!(map.last(), Some(&(-1, -1, -1)));
     }
 
     #[test]
     fn runtime_reads_state_variable() {
         let src = "const supply: uint256 = 100\n\ndef t() -> uint256: return supply\n";
         let program = parse_from_source(src).unwrap();
-        let code = program_to_runtime_bytecode(&program).unwrap();
-        assert!(code.contains(&0x54));
+        let mut contract = deploy_with(&program, &[]).unwrap();
+        let out = contract.call(runtime::function_selector(&program, "t").unwrap(), &[]);
+        assert_eq!(out.word(0), 100u8.into());
+        assert!(out.gas_used > 21_000 + 2_100, "reads a cold slot: {}", out.gas_used);
+    }
+
+    struct Rng(u64);
+
+    impl Rng {
+        fn next(&mut self) -> u64 {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            self.0
+        }
+
+        fn below(&mut self, n: usize) -> usize {
+            (self.next() % n as u64) as usize
+        }
+    }
+
+    fn random_ops(rng: &mut Rng, next_label: &mut usize, entry: usize) -> Vec<IrOp> {
+        const SIMPLE: [IrOp; 20] = [
+            IrOp::Pop, IrOp::Add, IrOp::Sub, IrOp::Mul, IrOp::Div, IrOp::Mod, IrOp::Exp,
+            IrOp::Lt, IrOp::Gt, IrOp::Eq, IrOp::IsZero, IrOp::And, IrOp::Shl, IrOp::MLoad,
+            IrOp::SStore, IrOp::Caller, IrOp::CallDataLoad, IrOp::Keccak256, IrOp::Return,
+            IrOp::Invalid,
+        ];
+        let mut ops = vec![IrOp::JumpDest(entry)];
+        let mut pending = Vec::new();
+        for _ in 0..rng.below(48) {
+            let op = match rng.below(8) {
+                0 | 1 => {
+                    let data = (0..1 + rng.below(32)).map(|_| rng.next() as u8).collect();
+                    IrOp::Push(data)
+                }
+                2 => IrOp::Dup(1 + rng.below(16) as u8),
+                3 => IrOp::Swap(1 + rng.below(16) as u8),
+                4 => {
+                    let label = *next_label;
+                    *next_label += 1;
+                    pending.push(label);
+                    if rng.below(2) == 0 { IrOp::Jump(label) } else { IrOp::JumpI(label) }
+                }
+                5 if !pending.is_empty() => {
+                    IrOp::JumpDest(pending.swap_remove(rng.below(pending.len())))
+                }
+                6 => IrOp::Log(rng.below(5) as u8),
+                _ => SIMPLE[rng.below(SIMPLE.len())].clone(),
+            };
+            ops.push(op);
+        }
+        ops.extend(pending.into_iter().map(IrOp::JumpDest));
+        ops
+    }
+
+    fn random_module(seed: u64) -> IrModule {
+        let mut rng = Rng(seed.wrapping_mul(0x9e37_79b9_7f4a_7c15) | 1);
+        let count = 1 + rng.below(4);
+        let mut next_label = count;
+        let functions = (0..count)
+            .map(|label| IrFunction {
+                name: format!("f{label}"),
+                selector: (rng.next() as u32).to_be_bytes(),
+                ops: random_ops(&mut rng, &mut next_label, label),
+                label,
+                guarded: false,
+                payable: false,
+                read_only: false,
+                calldata_size: 4,
+            })
+            .collect();
+        IrModule {
+            functions,
+            constructor_ops: Vec::new(),
+            label_count: next_label,
+        }
+    }
+
+    fn opcode_of(op: &IrOp) -> u8 {
+        let mut em = Emitter::new(EvmVersion::default());
+        em.emit_op(op);
+        *em.code.last().unwrap()
+    }
+
+    #[test]
+    fn random_modules_round_trip_through_disassembler() {
+        for seed in 0..256 {
+            let module = random_module(seed);
+            let code = module_to_runtime(&module, EvmVersion::default()).unwrap();
+            let ins = disassemble(&code);
+            assert_eq!(ins.iter().map(|i| i.size()).sum::<usize>(), code.len(), "seed {seed}");
+            let by_offset: HashMap<usize, &Instruction> = ins.iter().map(|i| (i.offset, i)).collect();
+
+            for pair in ins.windows(2) {
+                if pair[0].opcode == 0x61 && matches!(pair[1].opcode, 0x56 | 0x57) {
+                    let target = u16::from_be_bytes([pair[0].immediate[0], pair[0].immediate[1]]);
+                    let dest = by_offset.get(&(target as usize));
+                    assert_eq!(dest.map(|d| d.opcode), Some(0x5b), "seed {seed}");
+                }
+            }
+
+            let expected: usize = module
+                .functions
+                .iter()
+                .flat_map(|f| &f.ops)
+                .map(|op| if matches!(op, IrOp::Jump(_) | IrOp::JumpI(_)) { 2 } else { 1 })
+                .sum::<usize>()
+                + module.functions.len();
+            let mut body = ins[ins.len() - expected..].iter();
+            let mut labels = HashMap::new();
+            let mut jumps = Vec::new();
+            for func in &module.functions {
+                for (i, op) in func.ops.iter().enumerate() {
+                    let at = body.next().unwrap();
+                    match op {
+                        IrOp::Push(data) => {
+                            assert_eq!(at.opcode, 0x5f + data.len() as u8, "seed {seed}");
+                            assert_eq!(&at.immediate, data, "seed {seed}");
+                        }
+                        IrOp::JumpDest(label) => {
+                            assert_eq!(at.opcode, 0x5b, "seed {seed}");
+                            labels.insert(*label, at.offset);
+                        }
+                        IrOp::Jump(label) | IrOp::JumpI(label) => {
+                            assert_eq!(at.opcode, 0x61, "seed {seed}");
+                            jumps.push((*label, at.immediate.clone()));
+                            assert_eq!(body.next().unwrap().opcode, opcode_of(op), "seed {seed}");
+                        }
+                        _ => assert_eq!(at.opcode, opcode_of(op), "seed {seed}"),
+                    }
+                    if i == 0 {
+                        assert_eq!(body.next().unwrap().opcode, 0x50, "seed {seed}");
+                    }
+                }
+            }
+            assert!(body.next().is_none());
+            for (label, immediate) in jumps {
+                let offset = (labels[&label] as u16).to_be_bytes();
+                assert_eq!(immediate, offset.to_vec(), "seed {seed}");
+            }
+        }
     }
 }
@@ -1,18 +1,29 @@
 use crate::parser::{parse_from_source, ParseError};
-use crate::typer::{check_program, TypeError};
-use crate::{program_to_abi_json, AbiError};
-use crate::{program_to_deploy_bytecode, CodegenError};
-use crate::Program;
-use std::path::Path;
-use std::path::PathBuf;
+use crate::typer::{check_program_with_options, CheckOptions, TypeError};
+use crate::{method_identifiers, program_to_abi_json, program_to_sorted_abi_json, AbiError};
+use crate::{program_to_deploy_bytecode_with_config, program_to_eof_bytecode_with_config};
+use crate::{program_to_deploy_bytecode_with_source_map, program_to_runtime_bytecode_with_source_map};
+use crate::{CodegenError, HardenConfig, MappedBytecode};
+use crate::verifier::{verify_module, VerifyError};
+use crate::diagnostics::{Diagnostic, SYNTAX_ERROR};
+use crate::{hardened_module, lower_program_with_opt_level, OptLevel};
+use crate::resolver::{normalize, resolve_program, DiskSources, MemorySources};
+use crate::{program_to_ast_json, Program};
+use crate::project::ArtifactFormat;
+use std::collections::BTreeMap;
+use std::ops::Range;
+use std::path::{Component, Path, PathBuf};
 
 #[derive(thiserror::Error, Debug)]
 pub enum CompileError {
     #[error("{0}")]
     Io(#[from] std::io::Error),
 
-    #[error("parse failed: {0:?}")]
-    Parse(Vec<ParseError>),
+    #[error("parse failed: {errors:?}")]
+    Parse {
+        path: PathBuf,
+        errors: Vec<ParseError>,
+    },
 
     #[error("type errors: {0:?}")]
     Type(Vec<TypeError>),
@@ -22,18 +33,192 @@ pub enum CompileError {
 
     #[error("codegen failed: {0}")]
     Codegen(#[from] CodegenError),
+
+    #[error("cannot resolve import \"{import}\"")]
+    ImportNotFound {
+        import: String,
+        /// The importing file and the import's position in it.
+        path: PathBuf,
+        span: Range<usize>,
+    },
+
+    #[error("import cycle detected at {0}")]
+    ImportCycle(String),
+
+    #[error("\"{module}\" has no item named `{name}`")]
+    ImportedNameNotFound {
+        name: String,
+        module: String,
+        path: PathBuf,
+        span: Range<usize>,
+    },
+
+    #[error("{}: {message}", path.display())]
+    Manifest {
+        path: PathBuf,
+        message: String,
+        span: Option<Range<usize>>,
+    },
+
+    #[error("cannot fetch dependency `{name}`: {message}")]
+    Dependency { name: String, message: String },
+
+    #[error("verification failed: {0:?}")]
+    Verify(Vec<VerifyError>),
+}
+
+impl CompileError {
+    /// Every problem in the error as a diagnostic. `source` is the text of
+    /// the file that failed to parse, quoted in parse diagnostics.
+    pub fn diagnostics(&self, source: Option<&str>) -> Vec<Diagnostic> {
+        match self {
+            CompileError::Parse { errors, .. } => errors
+                .iter()
+                .map(|e| match source {
+                    Some(src) => Diagnostic::from_parse_error(e, src),
+                    None => Diagnostic::error(format!("parse failed: {e:?}")).with_code(SYNTAX_ERROR),
+                })
+                .collect(),
+            CompileError::Type(errors) => errors.iter().map(Diagnostic::from_type_error).collect(),
+            CompileError::Verify(errors) => errors
+                .iter()
+                .map(|e| Diagnostic::error(e.to_string()).with_code(e.code()))
+                .collect(),
+            CompileError::Codegen(e) => {
+                let diag = Diagnostic::error(self.to_string()).with_code(e.code());
+                vec![match e.span() {
+                    Some(span) => diag.with_span(span.start..span.end),
+                    None => diag,
+                }]
+            }
+            CompileError::Manifest { message, span, .. } => {
+                let diag = Diagnostic::error(message.clone()).with_code("E5006");
+                vec![match span {
+                    Some(span) => diag.with_span(span.clone()),
+                    None => diag,
+                }]
+            }
+            CompileError::ImportNotFound { span, .. } => {
+                vec![Diagnostic::error(self.to_string()).with_code("E5002").with_span(span.clone())]
+            }
+            CompileError::ImportedNameNotFound { span, .. } => {
+                vec![Diagnostic::error(self.to_string()).with_code("E5005").with_span(span.clone())]
+            }
+            other => {
+                let code = match other {
+                    CompileError::Io(_) => "E5001",
+                    CompileError::ImportCycle(_) => "E5003",
+                    CompileError::Dependency { .. } => "E5007",
+                    _ => "E5004",
+                };
+                vec![Diagnostic::error(other.to_string()).with_code(code)]
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OutputFormat {
+    #[default]
+    Legacy,
+    Eof,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct CompileOptions {
+    pub include_paths: Vec<PathBuf>,
+    /// Import prefixes and the directories they stand for: with
+    /// `("math", dir)`, `from math.fixed import mul` reads
+    /// `dir/fixed.pyra`. A name may map to several directories, tried in
+    /// order.
+    pub dependencies: Vec<(String, PathBuf)>,
+    pub sort_abi: bool,
+    pub format: OutputFormat,
+    pub harden: HardenConfig,
+    pub strict: bool,
+    /// ABI-encoded constructor arguments appended to the deploy bytecode.
+    pub constructor_args: Vec<u8>,
+    /// Also write `.srcmap` for the deploy code (legacy format only) and
+    /// `.runtime.srcmap` for the deployed code.
+    pub source_map: bool,
+    /// Also write `.ast.json` with the input file's AST, imports unresolved.
+    pub ast_json: bool,
+    /// Also write `.ir` with the IR the bytecode is emitted from.
+    pub ir: bool,
+    /// Write `.ir` with the IR after each stage: lowered without
+    /// optimization, optimized and hardened.
+    pub ir_stages: bool,
 }
 
 pub fn compile_file(path: &Path) -> Result<Program, CompileError> {
-    let source = std::fs::read_to_string(path)?;
-    let program = parse_from_source(&source).map_err(CompileError::Parse)?;
-    let errors = check_program(&program);
+    compile_file_with_options(path, &CompileOptions::default())
+}
+
+pub fn compile_file_with_options(
+    path: &Path,
+    options: &CompileOptions,
+) -> Result<Program, CompileError> {
+    let program = load_program(path, options)?;
+    check_loaded(program, options)
+}
+
+/// Compiles `entry` from in-memory sources keyed by path, as given in
+/// standard JSON input. An import is looked up relative to the importing
+/// source, then as a key of its own; the disk is never read.
+pub fn compile_sources(
+    entry: &str,
+    sources: &BTreeMap<String, String>,
+    options: &CompileOptions,
+) -> Result<Program, CompileError> {
+    let program = resolve_program(&MemorySources::new(sources), Path::new(entry))?;
+    check_loaded(program, options)
+}
+
+pub(crate) fn check_loaded(program: Program, options: &CompileOptions) -> Result<Program, CompileError> {
+    let errors = check_program_with_options(&program, &CheckOptions { strict: options.strict });
     if !errors.is_empty() {
         return Err(CompileError::Type(errors));
     }
     Ok(program)
 }
 
+pub fn load_program(path: &Path, options: &CompileOptions) -> Result<Program, CompileError> {
+    let tree = DiskSources {
+        include_paths: &options.include_paths,
+        dependencies: &options.dependencies,
+    };
+    resolve_program(&tree, path)
+}
+
+fn ir_listing(program: &Program, options: &CompileOptions) -> String {
+    let hardened = hardened_module(program, &options.harden);
+    if !options.ir_stages {
+        return hardened.to_string();
+    }
+    let opt = options.harden.opt_level;
+    let mut stages = vec![("unoptimized", lower_program_with_opt_level(program, OptLevel::O0))];
+    if opt != OptLevel::O0 {
+        stages.push(("optimized", lower_program_with_opt_level(program, opt)));
+    }
+    stages.push(("hardened", hardened));
+    stages
+        .iter()
+        .map(|(stage, module)| format!("; ==== {stage} ====\n{module}"))
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+pub fn verify_file(path: &Path, options: &CompileOptions) -> Result<Program, CompileError> {
+    let program = compile_file_with_options(path, options)?;
+    let module = hardened_module(&program, &options.harden);
+    let errors = verify_module(&module);
+    if !errors.is_empty() {
+        return Err(CompileError::Verify(errors));
+    }
+    Ok(program)
+}
+
 pub fn compile_file_to_abi(path: &Path, out_dir: Option<&Path>) -> Result<PathBuf, CompileError> {
     let program = compile_file(path)?;
     let abi = program_to_abi_json(&program)?;
@@ -61,9 +246,79 @@ pub fn compile_file_to_abi_and_bin(
     path: &Path,
     out_dir: Option<&Path>,
 ) -> Result<(PathBuf, PathBuf), CompileError> {
-    let program = compile_file(path)?;
-    let abi = program_to_abi_json(&program)?;
-    let bin = program_to_deploy_bytecode(&program)?;
+    compile_file_to_abi_and_bin_with_options(path, out_dir, &CompileOptions::default())
+}
+
+pub fn compile_file_to_abi_and_bin_with_options(
+    path: &Path,
+    out_dir: Option<&Path>,
+    options: &CompileOptions,
+) -> Result<(PathBuf, PathBuf), CompileError> {
+    let program = compile_file_with_options(path, options)?;
+    let outputs = pyra_outputs(path, &program, out_dir, options)?;
+    write_outputs(&outputs)?;
+    Ok((outputs[0].0.clone(), outputs[1].0.clone()))
+}
+
+/// A file a build writes, with its contents.
+pub type Output = (PathBuf, String);
+
+/// Compiles `path` and writes its artifacts in `format`. Returns the
+/// program along with every file written.
+pub fn compile_file_to_artifacts(
+    path: &Path,
+    out_dir: Option<&Path>,
+    format: ArtifactFormat,
+    options: &CompileOptions,
+) -> Result<(Program, Vec<Output>), CompileError> {
+    let program = compile_file_with_options(path, options)?;
+    let outputs = artifact_outputs(path, &program, out_dir, format, options)?;
+    write_outputs(&outputs)?;
+    Ok((program, outputs))
+}
+
+/// The files `program`, compiled from `path`, is written as in `format`.
+pub(crate) fn artifact_outputs(
+    path: &Path,
+    program: &Program,
+    out_dir: Option<&Path>,
+    format: ArtifactFormat,
+    options: &CompileOptions,
+) -> Result<Vec<Output>, CompileError> {
+    match format {
+        ArtifactFormat::Pyra => pyra_outputs(path, program, out_dir, options),
+        ArtifactFormat::Foundry => foundry_outputs(path, program, out_dir, options),
+        ArtifactFormat::Hardhat => hardhat_outputs(path, program, out_dir, options),
+    }
+}
+
+pub(crate) fn write_outputs(outputs: &[Output]) -> std::io::Result<()> {
+    for (path, contents) in outputs {
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        std::fs::write(path, contents)?;
+    }
+    Ok(())
+}
+
+/// `.abi`, `.bin`, in that order, then the optional outputs and `.meta.json`.
+fn pyra_outputs(
+    path: &Path,
+    program: &Program,
+    out_dir: Option<&Path>,
+    options: &CompileOptions,
+) -> Result<Vec<Output>, CompileError> {
+    let abi = if options.sort_abi {
+        program_to_sorted_abi_json(program)?
+    } else {
+        program_to_abi_json(program)?
+    };
+    let mut bin = match options.format {
+        OutputFormat::Legacy => program_to_deploy_bytecode_with_config(program, &options.harden)?,
+        OutputFormat::Eof => program_to_eof_bytecode_with_config(program, &options.harden)?,
+    };
+    bin.extend_from_slice(&options.constructor_args);
 
     let stem = path
         .file_stem()
@@ -78,13 +333,211 @@ pub fn compile_file_to_abi_and_bin(
             .unwrap_or_else(|| PathBuf::from(".")),
     };
 
-    std::fs::create_dir_all(&dir)?;
+    let mut outputs = vec![
+        (dir.join(format!("{stem}.abi")), abi),
+        (dir.join(format!("{stem}.bin")), hex::encode(bin)),
+    ];
+
+    if options.source_map {
+        let runtime = program_to_runtime_bytecode_with_source_map(program, &options.harden)?;
+        outputs.push((dir.join(format!("{stem}.runtime.srcmap")), runtime.source_map));
+        if options.format == OutputFormat::Legacy {
+            let deploy = program_to_deploy_bytecode_with_source_map(program, &options.harden)?;
+            outputs.push((dir.join(format!("{stem}.srcmap")), deploy.source_map));
+        }
+    }
 
-    let abi_path = dir.join(format!("{stem}.abi"));
-    std::fs::write(&abi_path, abi)?;
+    outputs.extend(emitted_outputs(path, program, &dir, stem, options)?);
 
-    let bin_path = dir.join(format!("{stem}.bin"));
-    std::fs::write(&bin_path, hex::encode(bin))?;
+    outputs.push((
+        dir.join(format!("{stem}.meta.json")),
+        format!(
+            "{{\"compiler\":\"pyra {}\",\"opt_level\":\"{}\",\"evm_version\":\"{}\"}}\n",
+            env!("CARGO_PKG_VERSION"),
+            options.harden.opt_level.name(),
+            options.harden.evm_version.name()
+        ),
+    ));
+    Ok(outputs)
+}
 
-    Ok((abi_path, bin_path))
+/// Writes a Foundry-style artifact to `<out>/<file name>/<stem>.json`,
+/// where `<out>` defaults to `out`, so `forge` can load the contract as
+/// `Token.pyra:Token`. Constructor arguments are left out, since tools
+/// append their own, and source maps are included for legacy bytecode.
+pub fn compile_file_to_foundry_artifact(
+    path: &Path,
+    out_dir: Option<&Path>,
+    options: &CompileOptions,
+) -> Result<PathBuf, CompileError> {
+    compile_file_to_artifacts(path, out_dir, ArtifactFormat::Foundry, options)
+        .map(|(_, outputs)| outputs[0].0.clone())
+}
+
+/// The Foundry artifact, then any `--emit` outputs beside it.
+fn foundry_outputs(
+    path: &Path,
+    program: &Program,
+    out_dir: Option<&Path>,
+    options: &CompileOptions,
+) -> Result<Vec<Output>, CompileError> {
+    let parts = artifact_parts(program, options)?;
+    let bytecode = |code: &MappedBytecode| {
+        let mut json = serde_json::json!({
+            "object": format!("0x{}", hex::encode(&code.code)),
+            "linkReferences": {},
+        });
+        if !code.source_map.is_empty() {
+            json["sourceMap"] = code.source_map.clone().into();
+        }
+        json
+    };
+    let mut deployed_bytecode = bytecode(&parts.runtime);
+    deployed_bytecode["immutableReferences"] = serde_json::json!({});
+    let artifact = serde_json::json!({
+        "abi": parts.abi,
+        "bytecode": bytecode(&parts.deploy),
+        "deployedBytecode": deployed_bytecode,
+        "methodIdentifiers": method_identifiers(program),
+    });
+
+    let file_name = path.file_name().ok_or_else(invalid_path)?;
+    let dir = out_dir.unwrap_or(Path::new("out")).join(file_name);
+    artifact_files(path, &dir, &artifact, program, options)
+}
+
+/// Writes a Hardhat artifact (`hh-sol-artifact-1`) to
+/// `<out>/<source name>/<stem>.json`, where `<out>` defaults to
+/// `artifacts` and the source name is the input path as given, so
+/// `hre.artifacts.readArtifact` and hardhat-deploy find the contract by
+/// its stem. Constructor arguments are left out, as for Foundry.
+pub fn compile_file_to_hardhat_artifact(
+    path: &Path,
+    out_dir: Option<&Path>,
+    options: &CompileOptions,
+) -> Result<PathBuf, CompileError> {
+    compile_file_to_artifacts(path, out_dir, ArtifactFormat::Hardhat, options)
+        .map(|(_, outputs)| outputs[0].0.clone())
+}
+
+/// The Hardhat artifact, then any `--emit` outputs beside it.
+fn hardhat_outputs(
+    path: &Path,
+    program: &Program,
+    out_dir: Option<&Path>,
+    options: &CompileOptions,
+) -> Result<Vec<Output>, CompileError> {
+    let parts = artifact_parts(program, options)?;
+    let stem = path.file_stem().and_then(|s| s.to_str()).ok_or_else(invalid_path)?;
+    let source_name = hardhat_source_name(path).ok_or_else(invalid_path)?;
+    let artifact = serde_json::json!({
+        "_format": "hh-sol-artifact-1",
+        "contractName": stem,
+        "sourceName": source_name,
+        "abi": parts.abi,
+        "bytecode": format!("0x{}", hex::encode(&parts.deploy.code)),
+        "deployedBytecode": format!("0x{}", hex::encode(&parts.runtime.code)),
+        "linkReferences": {},
+        "deployedLinkReferences": {},
+    });
+
+    let dir = out_dir.unwrap_or(Path::new("artifacts")).join(&source_name);
+    artifact_files(path, &dir, &artifact, program, options)
+}
+
+/// The input as Hardhat names sources: its path relative to the project
+/// with `/` separators. A path that leaves the working directory is
+/// reduced to its file name.
+fn hardhat_source_name(path: &Path) -> Option<String> {
+    let inside = path.is_relative()
+        && path
+            .components()
+            .all(|c| matches!(c, Component::Normal(_) | Component::CurDir));
+    if !inside {
+        return path.file_name()?.to_str().map(str::to_string);
+    }
+    let path = normalize(path);
+    let parts: Option<Vec<&str>> = path.iter().map(|c| c.to_str()).collect();
+    Some(parts?.join("/"))
+}
+
+/// What the JSON artifact formats are built from.
+struct ArtifactParts {
+    abi: serde_json::Value,
+    /// EOF output has no separate runtime or source maps, so its container
+    /// serves as both codes and both maps are empty.
+    deploy: MappedBytecode,
+    runtime: MappedBytecode,
+}
+
+fn artifact_parts(program: &Program, options: &CompileOptions) -> Result<ArtifactParts, CompileError> {
+    let abi = if options.sort_abi {
+        program_to_sorted_abi_json(program)?
+    } else {
+        program_to_abi_json(program)?
+    };
+    let abi = serde_json::from_str(&abi).map_err(std::io::Error::from)?;
+    let (deploy, runtime) = match options.format {
+        OutputFormat::Legacy => (
+            program_to_deploy_bytecode_with_source_map(program, &options.harden)?,
+            program_to_runtime_bytecode_with_source_map(program, &options.harden)?,
+        ),
+        OutputFormat::Eof => {
+            let code = program_to_eof_bytecode_with_config(program, &options.harden)?;
+            let mapped = MappedBytecode {
+                code,
+                source_map: String::new(),
+            };
+            (mapped.clone(), mapped)
+        }
+    };
+    Ok(ArtifactParts {
+        abi,
+        deploy,
+        runtime,
+    })
+}
+
+/// `artifact` as `<dir>/<stem>.json`, with any `--emit` outputs beside it.
+fn artifact_files(
+    path: &Path,
+    dir: &Path,
+    artifact: &serde_json::Value,
+    program: &Program,
+    options: &CompileOptions,
+) -> Result<Vec<Output>, CompileError> {
+    let stem = path.file_stem().and_then(|s| s.to_str()).ok_or_else(invalid_path)?;
+    let json = serde_json::to_string_pretty(artifact).map_err(std::io::Error::from)?;
+    let mut outputs = vec![(dir.join(format!("{stem}.json")), json)];
+    outputs.extend(emitted_outputs(path, program, dir, stem, options)?);
+    Ok(outputs)
+}
+
+fn invalid_path() -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::InvalidInput, "invalid input path")
+}
+
+/// The extra outputs asked for with `--emit`, in `dir`.
+fn emitted_outputs(
+    path: &Path,
+    program: &Program,
+    dir: &Path,
+    stem: &str,
+    options: &CompileOptions,
+) -> Result<Vec<Output>, CompileError> {
+    let mut outputs = Vec::new();
+    if options.ast_json {
+        let source = std::fs::read_to_string(path)?;
+        let ast = parse_from_source(&source).map_err(|errors| CompileError::Parse {
+            path: path.to_path_buf(),
+            errors,
+        })?;
+        let json = program_to_ast_json(&ast).map_err(std::io::Error::from)?;
+        outputs.push((dir.join(format!("{stem}.ast.json")), json));
+    }
+
+    if options.ir || options.ir_stages {
+        outputs.push((dir.join(format!("{stem}.ir")), ir_listing(program, options)));
+    }
+    Ok(outputs)
 }
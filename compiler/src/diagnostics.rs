@@ -0,0 +1,255 @@
+use crate::lexer::LexDiagnostic;
+use crate::parser::ParseError;
+use crate::typer::{TypeError, TypeWarning};
+use chumsky::error::SimpleReason;
+use std::ops::Range;
+use std::path::Path;
+
+const RED: &str = "\x1b[31m";
+const YELLOW: &str = "\x1b[33m";
+const BLUE: &str = "\x1b[34m";
+const BOLD: &str = "\x1b[1m";
+const RESET: &str = "\x1b[0m";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+/// One problem found in a program. Every stage reports through this type;
+/// `code` identifies the kind of problem so tools can filter on it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Diagnostic {
+    pub code: Option<&'static str>,
+    pub severity: Severity,
+    pub message: String,
+    pub span: Option<Range<usize>>,
+    pub notes: Vec<String>,
+}
+
+/// Code of a syntax error the lexer did not already classify.
+pub const SYNTAX_ERROR: &str = "E1001";
+
+impl Diagnostic {
+    pub fn error(message: impl Into<String>) -> Self {
+        Self {
+            code: None,
+            severity: Severity::Error,
+            message: message.into(),
+            span: None,
+            notes: Vec::new(),
+        }
+    }
+
+    pub fn warning(message: impl Into<String>) -> Self {
+        Self {
+            severity: Severity::Warning,
+            ..Self::error(message)
+        }
+    }
+
+    pub fn with_span(mut self, span: Range<usize>) -> Self {
+        self.span = Some(span);
+        self
+    }
+
+    pub fn with_code(mut self, code: &'static str) -> Self {
+        self.code = Some(code);
+        self
+    }
+
+    pub fn with_note(mut self, note: impl Into<String>) -> Self {
+        self.notes.push(note.into());
+        self
+    }
+
+    pub fn from_lex(diag: &LexDiagnostic) -> Self {
+        Self::error(&diag.message)
+            .with_code(diag.code)
+            .with_span(diag.span.clone())
+    }
+
+    pub fn from_type_error(err: &TypeError) -> Self {
+        let diag = Self::error(err.to_string()).with_code(err.code());
+        let diag = match err.kind() {
+            TypeError::Warning(w) => diag.with_note(allow_note(w)),
+            _ => diag,
+        };
+        match err.span() {
+            Some(span) => diag.with_span(span.start..span.end),
+            None => diag,
+        }
+    }
+
+    pub fn from_warning(warning: &TypeWarning) -> Self {
+        let span = warning.span();
+        Self::warning(warning.to_string())
+            .with_code(warning.code())
+            .with_span(span.start..span.end)
+            .with_note(allow_note(warning))
+    }
+
+    pub fn from_parse_error(err: &ParseError, source: &str) -> Self {
+        let span = err.span();
+        let message = match err.reason() {
+            SimpleReason::Custom(msg) => msg.clone(),
+            _ => match source.get(span.clone()).filter(|s| !s.trim().is_empty()) {
+                Some(text) => format!("unexpected `{}`", text.trim()),
+                None if span.start >= source.len() => "unexpected end of input".to_string(),
+                None => "unexpected token".to_string(),
+            },
+        };
+        Self::error(format!("parse failed: {message}"))
+            .with_code(err.label().unwrap_or(SYNTAX_ERROR))
+            .with_span(span)
+    }
+}
+
+fn allow_note(warning: &TypeWarning) -> String {
+    format!("silence this with `@allow({})` on the function", warning.lint())
+}
+
+struct Painter {
+    color: bool,
+}
+
+impl Painter {
+    fn paint(&self, style: &str, text: &str) -> String {
+        if self.color {
+            format!("{style}{text}{RESET}")
+        } else {
+            text.to_string()
+        }
+    }
+}
+
+pub fn line_col(source: &str, offset: usize) -> (usize, usize) {
+    let before = &source[..offset.min(source.len())];
+    let line = before.matches('\n').count() + 1;
+    let col = before.rsplit('\n').next().map_or(0, |l| l.chars().count()) + 1;
+    (line, col)
+}
+
+pub fn render(diag: &Diagnostic, path: &Path, source: Option<&str>, color: bool) -> String {
+    let p = Painter { color };
+    let (label, accent) = match diag.severity {
+        Severity::Error => ("error", RED),
+        Severity::Warning => ("warning", YELLOW),
+    };
+    let label = match diag.code {
+        Some(code) => format!("{label}[{code}]"),
+        None => label.to_string(),
+    };
+    let mut out = format!(
+        "{}{}\n",
+        p.paint(&format!("{BOLD}{accent}"), &label),
+        p.paint(BOLD, &format!(": {}", diag.message))
+    );
+
+    let (Some(span), Some(source)) = (diag.span.clone(), source) else {
+        out.push_str(&format!("{} {}\n", p.paint(BLUE, "-->"), path.display()));
+        for note in &diag.notes {
+            out.push_str(&format!(" {} note: {note}\n", p.paint(BLUE, "=")));
+        }
+        return out;
+    };
+
+    let (line, col) = line_col(source, span.start);
+    let text = source.lines().nth(line - 1).unwrap_or("");
+    let gutter = " ".repeat(line.to_string().len());
+    let width = source
+        .get(span.clone())
+        .map_or(1, |s| s.lines().next().unwrap_or("").chars().count().max(1));
+
+    out.push_str(&format!(
+        "{gutter}{} {}:{line}:{col}\n",
+        p.paint(BLUE, "-->"),
+        path.display()
+    ));
+    out.push_str(&format!("{gutter} {}\n", p.paint(BLUE, "|")));
+    out.push_str(&format!("{} {text}\n", p.paint(BLUE, &format!("{line} |"))));
+    out.push_str(&format!(
+        "{gutter} {} {}{}\n",
+        p.paint(BLUE, "|"),
+        " ".repeat(col - 1),
+        p.paint(accent, &"^".repeat(width))
+    ));
+    for note in &diag.notes {
+        out.push_str(&format!("{gutter} {} note: {note}\n", p.paint(BLUE, "=")));
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::parse_from_source;
+
+    #[test]
+    fn line_col_counts_from_one() {
+        let src = "def t():\n    return 1\n";
+        assert_eq!(line_col(src, 0), (1, 1));
+        assert_eq!(line_col(src, 13), (2, 5));
+    }
+
+    #[test]
+    fn renders_caret_under_span() {
+        let src = "def t( -> bool: return true";
+        let errors = parse_from_source(src).unwrap_err();
+        let diag = Diagnostic::from_parse_error(&errors[0], src);
+        let out = render(&diag, Path::new("t.pyra"), Some(src), false);
+        assert!(out.starts_with("error[E1001]: parse failed: unexpected `->`"));
+        assert!(out.contains("--> t.pyra:1:8"));
+        assert!(out.contains("1 | def t( -> bool"));
+        assert!(out.contains("|        ^^\n"));
+        assert!(!out.contains('\x1b'));
+    }
+
+    #[test]
+    fn renders_lexer_error_location() {
+        let src = "def t() -> uint256:\n    return 1 $ 2\n";
+        let errors = parse_from_source(src).unwrap_err();
+        let diag = Diagnostic::from_parse_error(&errors[0], src);
+        let out = render(&diag, Path::new("t.pyra"), Some(src), false);
+        assert!(out.starts_with("error[E0001]: parse failed: unexpected character `$`"));
+        assert!(out.contains("--> t.pyra:2:14"));
+    }
+
+    #[test]
+    fn renders_code_and_notes() {
+        let src = "def t(a: uint256):\n    let b: bool = a\n";
+        let errors = crate::check_program(&parse_from_source(src).unwrap());
+        let diag = Diagnostic::from_type_error(&errors[0]).with_note("declared here");
+        assert_eq!(diag.code, Some("E2002"));
+        let out = render(&diag, Path::new("t.pyra"), Some(src), false);
+        assert!(out.starts_with("error[E2002]: type mismatch"));
+        assert!(out.contains("--> t.pyra:2:5"));
+        assert!(out.ends_with("  = note: declared here\n"));
+    }
+
+    #[test]
+    fn lexer_codes_survive_parsing() {
+        let src = "def t() -> uint256:\n    return \"open\n";
+        let errors = parse_from_source(src).unwrap_err();
+        assert_eq!(Diagnostic::from_parse_error(&errors[0], src).code, Some("E0002"));
+    }
+
+    #[test]
+    fn colored_render_uses_ansi() {
+        let diag = Diagnostic::error("boom");
+        let out = render(&diag, Path::new("t.pyra"), None, true);
+        assert!(out.contains(RED));
+        assert!(out.contains(RESET));
+    }
+
+    #[test]
+    fn renders_warning_label() {
+        let src = "def t(x: uint256):\n    require x > 0\n";
+        let diag = Diagnostic::warning("bare revert").with_span(23..36);
+        let out = render(&diag, Path::new("t.pyra"), Some(src), false);
+        assert!(out.starts_with("warning: bare revert"));
+        assert!(out.contains("--> t.pyra:2:5"));
+        assert!(out.contains("|     ^^^^^^^^^^^^^\n"));
+    }
+}
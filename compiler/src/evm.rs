@@ -48,6 +48,252 @@ pub fn init_return_runtime(runtime: &[u8]) -> Vec<u8> {
     prefix
 }
 
+#[derive(Debug, Clone, PartialEq)]
+pub struct Instruction {
+    pub offset: usize,
+    pub opcode: u8,
+    pub immediate: Vec<u8>,
+}
+
+impl Instruction {
+    pub fn size(&self) -> usize {
+        1 + self.immediate.len()
+    }
+}
+
+impl std::fmt::Display for Instruction {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match mnemonic(self.opcode) {
+            Some(name) => f.write_str(name)?,
+            None => write!(f, "UNKNOWN 0x{:02x}", self.opcode)?,
+        }
+        if !self.immediate.is_empty() {
+            write!(f, " 0x{}", hex::encode(&self.immediate))?;
+        }
+        Ok(())
+    }
+}
+
+/// Splits bytecode into instructions. A PUSH cut off by the end of the code
+/// keeps whatever immediate bytes are present.
+pub fn disassemble(code: &[u8]) -> Vec<Instruction> {
+    let mut out = Vec::with_capacity(code.len());
+    let mut pc = 0;
+    while pc < code.len() {
+        let opcode = code[pc];
+        let width = match opcode {
+            0x60..=0x7f => (opcode - 0x5f) as usize,
+            _ => 0,
+        };
+        let end = (pc + 1 + width).min(code.len());
+        out.push(Instruction {
+            offset: pc,
+            opcode,
+            immediate: code[pc + 1..end].to_vec(),
+        });
+        pc = end;
+    }
+    out
+}
+
+/// The mnemonic for `opcode` as of Cancun, or `None` for an unassigned byte.
+pub fn mnemonic(opcode: u8) -> Option<&'static str> {
+    const PUSH: [&str; 32] = [
+        "PUSH1", "PUSH2", "PUSH3", "PUSH4", "PUSH5", "PUSH6", "PUSH7", "PUSH8", "PUSH9", "PUSH10",
+        "PUSH11", "PUSH12", "PUSH13", "PUSH14", "PUSH15", "PUSH16", "PUSH17", "PUSH18", "PUSH19",
+        "PUSH20", "PUSH21", "PUSH22", "PUSH23", "PUSH24", "PUSH25", "PUSH26", "PUSH27", "PUSH28",
+        "PUSH29", "PUSH30", "PUSH31", "PUSH32",
+    ];
+    const DUP: [&str; 16] = [
+        "DUP1", "DUP2", "DUP3", "DUP4", "DUP5", "DUP6", "DUP7", "DUP8", "DUP9", "DUP10", "DUP11",
+        "DUP12", "DUP13", "DUP14", "DUP15", "DUP16",
+    ];
+    const SWAP: [&str; 16] = [
+        "SWAP1", "SWAP2", "SWAP3", "SWAP4", "SWAP5", "SWAP6", "SWAP7", "SWAP8", "SWAP9", "SWAP10",
+        "SWAP11", "SWAP12", "SWAP13", "SWAP14", "SWAP15", "SWAP16",
+    ];
+    const LOG: [&str; 5] = ["LOG0", "LOG1", "LOG2", "LOG3", "LOG4"];
+
+    Some(match opcode {
+        0x00 => "STOP",
+        0x01 => "ADD",
+        0x02 => "MUL",
+        0x03 => "SUB",
+        0x04 => "DIV",
+        0x05 => "SDIV",
+        0x06 => "MOD",
+        0x07 => "SMOD",
+        0x08 => "ADDMOD",
+        0x09 => "MULMOD",
+        0x0a => "EXP",
+        0x0b => "SIGNEXTEND",
+        0x10 => "LT",
+        0x11 => "GT",
+        0x12 => "SLT",
+        0x13 => "SGT",
+        0x14 => "EQ",
+        0x15 => "ISZERO",
+        0x16 => "AND",
+        0x17 => "OR",
+        0x18 => "XOR",
+        0x19 => "NOT",
+        0x1a => "BYTE",
+        0x1b => "SHL",
+        0x1c => "SHR",
+        0x1d => "SAR",
+        0x20 => "KECCAK256",
+        0x30 => "ADDRESS",
+        0x31 => "BALANCE",
+        0x32 => "ORIGIN",
+        0x33 => "CALLER",
+        0x34 => "CALLVALUE",
+        0x35 => "CALLDATALOAD",
+        0x36 => "CALLDATASIZE",
+        0x37 => "CALLDATACOPY",
+        0x38 => "CODESIZE",
+        0x39 => "CODECOPY",
+        0x3a => "GASPRICE",
+        0x3b => "EXTCODESIZE",
+        0x3c => "EXTCODECOPY",
+        0x3d => "RETURNDATASIZE",
+        0x3e => "RETURNDATACOPY",
+        0x3f => "EXTCODEHASH",
+        0x40 => "BLOCKHASH",
+        0x41 => "COINBASE",
+        0x42 => "TIMESTAMP",
+        0x43 => "NUMBER",
+        0x44 => "PREVRANDAO",
+        0x45 => "GASLIMIT",
+        0x46 => "CHAINID",
+        0x47 => "SELFBALANCE",
+        0x48 => "BASEFEE",
+        0x49 => "BLOBHASH",
+        0x4a => "BLOBBASEFEE",
+        0x50 => "POP",
+        0x51 => "MLOAD",
+        0x52 => "MSTORE",
+        0x53 => "MSTORE8",
+        0x54 => "SLOAD",
+        0x55 => "SSTORE",
+        0x56 => "JUMP",
+        0x57 => "JUMPI",
+        0x58 => "PC",
+        0x59 => "MSIZE",
+        0x5a => "GAS",
+        0x5b => "JUMPDEST",
+        0x5c => "TLOAD",
+        0x5d => "TSTORE",
+        0x5e => "MCOPY",
+        0x5f => "PUSH0",
+        0x60..=0x7f => PUSH[(opcode - 0x60) as usize],
+        0x80..=0x8f => DUP[(opcode - 0x80) as usize],
+        0x90..=0x9f => SWAP[(opcode - 0x90) as usize],
+        0xa0..=0xa4 => LOG[(opcode - 0xa0) as usize],
+        0xf0 => "CREATE",
+        0xf1 => "CALL",
+        0xf2 => "CALLCODE",
+        0xf3 => "RETURN",
+        0xf4 => "DELEGATECALL",
+        0xf5 => "CREATE2",
+        0xfa => "STATICCALL",
+        0xfd => "REVERT",
+        0xfe => "INVALID",
+        0xff => "SELFDESTRUCT",
+        _ => return None,
+    })
+}
+
+/// Renders bytecode one instruction per line: the hex offset, the mnemonic
+/// and any push immediate. Each JUMPDEST starts a new paragraph, and a
+/// `PUSH` that feeds a `JUMP` or `JUMPI` is annotated with its target,
+/// flagged when no JUMPDEST sits there.
+///
+/// Deploy code that ends by copying out a runtime is split in two, and the
+/// runtime is numbered from zero as it will be once deployed. Bytes after
+/// the runtime, such as constructor arguments, are printed as hex.
+pub fn disassembly_listing(code: &[u8]) -> String {
+    let Some(runtime) = runtime_range(code) else {
+        return listing(code);
+    };
+    let mut out = format!("; ==== deploy ====\n{}", listing(&code[..runtime.start]));
+    out.push_str(&format!(
+        "\n; ==== runtime (at 0x{:04x}) ====\n{}",
+        runtime.start,
+        listing(&code[runtime.clone()])
+    ));
+    if runtime.end < code.len() {
+        out.push_str(&format!(
+            "\n; ==== trailing data ====\n0x{}\n",
+            hex::encode(&code[runtime.end..])
+        ));
+    }
+    out
+}
+
+/// Finds the runtime in deploy code by the copy-out sequence
+/// `PUSH len PUSH start PUSH 0 CODECOPY PUSH len PUSH 0 RETURN` placed
+/// directly before it.
+fn runtime_range(code: &[u8]) -> Option<std::ops::Range<usize>> {
+    let ins = disassemble(code);
+    let value = |i: &Instruction| {
+        (0x5f..=0x67)
+            .contains(&i.opcode)
+            .then(|| i.immediate.iter().fold(0usize, |acc, b| acc << 8 | *b as usize))
+    };
+    ins.windows(7).find_map(|w| {
+        let len = value(&w[0])?;
+        let start = value(&w[1])?;
+        let copies = value(&w[2]) == Some(0) && w[3].opcode == 0x39;
+        let returns = value(&w[4]) == Some(len) && value(&w[5]) == Some(0) && w[6].opcode == 0xf3;
+        let end = start.checked_add(len)?;
+        (copies && returns && start == w[6].offset + 1 && len > 0 && end <= code.len())
+            .then_some(start..end)
+    })
+}
+
+fn listing(code: &[u8]) -> String {
+    let ins = disassemble(code);
+    let jumpdests: std::collections::HashSet<usize> = ins
+        .iter()
+        .filter(|i| i.opcode == 0x5b)
+        .map(|i| i.offset)
+        .collect();
+
+    let mut out = String::new();
+    for (n, i) in ins.iter().enumerate() {
+        if i.opcode == 0x5b && n > 0 {
+            out.push('\n');
+        }
+        let mut line = format!("{:04x}  {i}", i.offset);
+
+        let width = match i.opcode {
+            0x60..=0x7f => (i.opcode - 0x5f) as usize,
+            _ => 0,
+        };
+        let jumps = ins
+            .get(n + 1)
+            .is_some_and(|next| matches!(next.opcode, 0x56 | 0x57));
+        let note = if i.immediate.len() < width {
+            Some("truncated".to_string())
+        } else if jumps && width > 0 && width <= 8 {
+            let target = i.immediate.iter().fold(0usize, |acc, b| acc << 8 | *b as usize);
+            if jumpdests.contains(&target) {
+                Some(format!("-> {target:04x}"))
+            } else {
+                Some(format!("-> {target:04x} (not a JUMPDEST)"))
+            }
+        } else {
+            None
+        };
+        if let Some(note) = note {
+            line = format!("{line:<32}; {note}");
+        }
+        out.push_str(&line);
+        out.push('\n');
+    }
+    out
+}
+
 fn push_usize(value: usize) -> Vec<u8> {
     if value == 0 {
         return vec![0x60, 0x00];
@@ -103,4 +349,61 @@ mod tests {
         assert_eq!(init[runtime_start - 1], 0xf3);
         assert!(init[..runtime_start].contains(&0x39));
     }
+
+    #[test]
+    fn disassembles_push_immediates() {
+        let code = [0x61, 0x01, 0x02, 0x56, 0x5b, 0x62, 0xff];
+        let ins = disassemble(&code);
+        assert_eq!(ins.len(), 4);
+        assert_eq!(ins[0].immediate, vec![0x01, 0x02]);
+        assert_eq!(ins[1].offset, 3);
+        assert_eq!(ins[2].opcode, 0x5b);
+        assert_eq!(ins[3].immediate, vec![0xff]);
+        assert_eq!(ins.iter().map(Instruction::size).sum::<usize>(), code.len());
+    }
+
+    #[test]
+    fn names_every_assigned_opcode() {
+        assert_eq!(mnemonic(0x5f), Some("PUSH0"));
+        assert_eq!(mnemonic(0x7f), Some("PUSH32"));
+        assert_eq!(mnemonic(0x8f), Some("DUP16"));
+        assert_eq!(mnemonic(0x90), Some("SWAP1"));
+        assert_eq!(mnemonic(0xa4), Some("LOG4"));
+        assert_eq!(mnemonic(0x5d), Some("TSTORE"));
+        assert_eq!(mnemonic(0x0c), None);
+        assert_eq!(mnemonic(0xa5), None);
+    }
+
+    #[test]
+    fn listing_marks_jump_targets() {
+        // PUSH1 5, JUMP, INVALID, 0x0c, JUMPDEST, PUSH1 9, JUMPI, PUSH2 0xff..
+        let code = [0x60, 0x05, 0x56, 0xfe, 0x0c, 0x5b, 0x60, 0x09, 0x57, 0x61, 0xff];
+        let listing = disassembly_listing(&code);
+        let lines: Vec<&str> = listing.lines().collect();
+        assert_eq!(lines[0], "0000  PUSH1 0x05                ; -> 0005");
+        assert_eq!(lines[1], "0002  JUMP");
+        assert_eq!(lines[3], "0004  UNKNOWN 0x0c");
+        assert_eq!(lines[4], "");
+        assert_eq!(lines[5], "0005  JUMPDEST");
+        assert!(lines[6].ends_with("; -> 0009 (not a JUMPDEST)"));
+        assert!(lines[8].starts_with("0009  PUSH2 0xff"));
+        assert!(lines[8].ends_with("; truncated"));
+    }
+
+    #[test]
+    fn listing_numbers_runtime_from_zero() {
+        // JUMPDEST, PUSH1 0, JUMP
+        let runtime = [0x5b, 0x60, 0x00, 0x56];
+        let mut code = init_return_runtime(&runtime);
+        code.extend_from_slice(&[0xaa, 0xbb]);
+        let listing = disassembly_listing(&code);
+        let (deploy, rest) = listing.split_once("; ==== runtime (at 0x000c) ====\n").unwrap();
+        assert!(deploy.starts_with("; ==== deploy ====\n0000  PUSH1 0x04"));
+        assert!(deploy.contains("CODECOPY"));
+        assert!(rest.starts_with("0000  JUMPDEST\n0001  PUSH1 0x00"));
+        assert!(rest.contains("; -> 0000\n"));
+        assert!(rest.ends_with("; ==== trailing data ====\n0xaabb\n"));
+
+        assert!(!disassembly_listing(&runtime).contains("===="));
+    }
 }
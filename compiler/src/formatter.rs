@@ -0,0 +1,374 @@
+//! Canonical source formatting. It works on tokens rather than the AST, so
+//! comments, literal spellings such as `1e18` or `1 ether`, and `elif`
+//! chains come out as written.
+
+use crate::lexer::{PyraLexer, Token};
+use crate::parser::{parse_from_source, ParseError};
+use std::ops::Range;
+
+/// Lines longer than this have their first bracketed list split one item
+/// per line.
+pub const MAX_WIDTH: usize = 100;
+
+const INDENT: &str = "    ";
+
+type SpannedToken = (Token, Range<usize>);
+
+/// A logical line: the tokens between two newlines, which may span several
+/// physical lines inside brackets.
+struct Line {
+    depth: usize,
+    tokens: Vec<SpannedToken>,
+}
+
+impl Line {
+    fn start(&self) -> usize {
+        self.tokens[0].1.start
+    }
+
+    fn end(&self) -> usize {
+        self.tokens[self.tokens.len() - 1].1.end
+    }
+}
+
+/// Reformats `source` with 4-space indentation, one space around binary
+/// operators, at most one blank line in a row and long bracketed lists
+/// wrapped one item per line. Source that does not parse is returned as
+/// its errors, untouched.
+pub fn format_source(source: &str) -> Result<String, Vec<ParseError>> {
+    parse_from_source(source)?;
+
+    let mut lines = Vec::new();
+    let mut depth = 0usize;
+    let mut current = Line { depth: 0, tokens: Vec::new() };
+    let mut lexer = PyraLexer::new(source);
+    while let Some(token) = lexer.next_token() {
+        match token {
+            Token::Indent => depth += 1,
+            Token::Dedent => depth = depth.saturating_sub(1),
+            Token::Newline => {
+                if !current.tokens.is_empty() {
+                    lines.push(std::mem::replace(&mut current, Line { depth, tokens: Vec::new() }));
+                }
+            }
+            token => {
+                if current.tokens.is_empty() {
+                    current.depth = depth;
+                }
+                current.tokens.push((token, lexer.span()));
+            }
+        }
+    }
+    if !current.tokens.is_empty() {
+        lines.push(current);
+    }
+
+    let mut out: Vec<String> = Vec::new();
+    let mut prev: Option<&Line> = None;
+    for line in &lines {
+        let gap = &source[prev.map_or(0, Line::end)..line.start()];
+        emit_gap(source, gap, prev, Some(line), &mut out);
+        emit_line(source, line, &mut out);
+        prev = Some(line);
+    }
+    let tail = &source[prev.map_or(0, Line::end)..];
+    emit_gap(source, tail, prev, None, &mut out);
+
+    while out.last().is_some_and(|l| l.is_empty()) {
+        out.pop();
+    }
+    let mut formatted = out.join("\n");
+    formatted.push('\n');
+    Ok(formatted)
+}
+
+/// Emits the comments and blank lines between two code lines. A comment
+/// indented deeper than the next line stays with the block above it.
+fn emit_gap(source: &str, gap: &str, prev: Option<&Line>, next: Option<&Line>, out: &mut Vec<String>) {
+    let mut segments: Vec<&str> = gap.split('\n').collect();
+    if prev.is_some() {
+        let trailing = segments.remove(0).trim();
+        if trailing.starts_with('#') {
+            if let Some(last) = out.last_mut() {
+                last.push_str("  ");
+                last.push_str(trailing);
+            }
+        }
+    }
+    if next.is_some() {
+        // What is left before the next line is its indentation.
+        segments.pop();
+    }
+
+    let next_col = next.map(|n| column(source, n.start()));
+    for segment in segments {
+        let text = segment.trim();
+        if text.is_empty() {
+            if out.last().is_some_and(|l| !l.is_empty()) {
+                out.push(String::new());
+            }
+            continue;
+        }
+        let col = indent_width(segment);
+        let depth = match (next, next_col) {
+            (Some(n), Some(c)) if col <= c => n.depth,
+            _ if col == 0 => 0,
+            _ => prev.map_or(0, |p| p.depth),
+        };
+        out.push(format!("{}{text}", INDENT.repeat(depth)));
+    }
+}
+
+fn emit_line(source: &str, line: &Line, out: &mut Vec<String>) {
+    let indent = INDENT.repeat(line.depth);
+    let has_comment = line
+        .tokens
+        .windows(2)
+        .any(|w| source[w[0].1.end..w[1].1.start].contains('#'));
+    if has_comment {
+        // Comments inside brackets have nowhere else to go; keep the text.
+        let text = &source[line.start()..line.end()];
+        let mut physical = text.split('\n');
+        out.push(format!("{indent}{}", physical.next().unwrap_or_default()));
+        out.extend(physical.map(str::to_string));
+        return;
+    }
+
+    let flat = format!("{indent}{}", render(source, &line.tokens));
+    if flat.chars().count() <= MAX_WIDTH {
+        out.push(flat);
+        return;
+    }
+    match wrappable_group(&line.tokens) {
+        Some((open, close)) => {
+            out.push(format!("{indent}{}", render(source, &line.tokens[..=open])));
+            for item in split_items(&line.tokens[open + 1..close]) {
+                out.push(format!("{indent}{INDENT}{},", render(source, item)));
+            }
+            out.push(format!("{indent}{}", render(source, &line.tokens[close..])));
+        }
+        None => out.push(flat),
+    }
+}
+
+/// The first `(` or `[` list at the top of the line that has a comma in it,
+/// as the indices of its brackets.
+fn wrappable_group(tokens: &[SpannedToken]) -> Option<(usize, usize)> {
+    let mut i = 0;
+    while i < tokens.len() {
+        if matches!(tokens[i].0, Token::LParen | Token::LBracket) {
+            let close = matching_close(tokens, i)?;
+            if split_items(&tokens[i + 1..close]).nth(1).is_some() {
+                return Some((i, close));
+            }
+            i = close;
+        }
+        i += 1;
+    }
+    None
+}
+
+fn matching_close(tokens: &[SpannedToken], open: usize) -> Option<usize> {
+    let mut depth = 0usize;
+    for (i, (token, _)) in tokens.iter().enumerate().skip(open) {
+        if opens(token) {
+            depth += 1;
+        } else if closes(token) {
+            depth -= 1;
+            if depth == 0 {
+                return Some(i);
+            }
+        }
+    }
+    None
+}
+
+/// The comma-separated items of a bracket's contents, without a trailing
+/// empty item.
+fn split_items(tokens: &[SpannedToken]) -> impl Iterator<Item = &[SpannedToken]> {
+    let mut depth = 0usize;
+    tokens
+        .split(move |(token, _)| {
+            if opens(token) {
+                depth += 1;
+            } else if closes(token) {
+                depth = depth.saturating_sub(1);
+            }
+            depth == 0 && *token == Token::Comma
+        })
+        .filter(|item| !item.is_empty())
+}
+
+/// The tokens on one line with canonical spacing. A comma right before a
+/// closing bracket is dropped.
+fn render(source: &str, tokens: &[SpannedToken]) -> String {
+    let mut out = String::new();
+    let mut brackets: Vec<&Token> = Vec::new();
+    let mut last: Option<&Token> = None;
+    let mut unary = false;
+    let mut generic = false;
+    for (i, (token, span)) in tokens.iter().enumerate() {
+        if *token == Token::Comma && tokens.get(i + 1).is_some_and(|(t, _)| closes(t)) {
+            continue;
+        }
+        let in_brackets = brackets.last() == Some(&&Token::LBracket);
+        let opens_generic = *token == Token::Less && i == 2 && tokens[0].0 == Token::Struct;
+        if let Some(prev) = last {
+            let space = match (prev, token) {
+                _ if opens_generic || unary => false,
+                (Token::Less, _) if generic => false,
+                (_, Token::Greater) if generic => false,
+                (_, Token::RParen | Token::RBracket | Token::Comma | Token::Dot | Token::Colon) => false,
+                (Token::LParen | Token::LBracket | Token::Dot | Token::At, _) => false,
+                (Token::LBrace, Token::RBrace) => false,
+                (prev, Token::LParen | Token::LBracket) => !ends_operand(prev) || *prev == Token::RBrace,
+                (Token::Colon, _) => !in_brackets,
+                _ => true,
+            };
+            if space {
+                out.push(' ');
+            }
+        }
+        out.push_str(&source[span.clone()]);
+
+        unary = *token == Token::Minus && !last.is_some_and(ends_operand);
+        if opens_generic {
+            generic = true;
+        } else if generic && *token == Token::Greater {
+            generic = false;
+        }
+        if opens(token) {
+            brackets.push(token);
+        } else if closes(token) {
+            brackets.pop();
+        }
+        last = Some(token);
+    }
+    out
+}
+
+fn opens(token: &Token) -> bool {
+    matches!(token, Token::LParen | Token::LBracket | Token::LBrace)
+}
+
+fn closes(token: &Token) -> bool {
+    matches!(token, Token::RParen | Token::RBracket | Token::RBrace)
+}
+
+/// True when the token can end an operand, so a following `-` is binary and
+/// a following `(` or `[` is a call or an index.
+fn ends_operand(token: &Token) -> bool {
+    matches!(
+        token,
+        Token::Identifier(_)
+            | Token::Number(_)
+            | Token::HexNumber(_)
+            | Token::AddressLiteral(_)
+            | Token::StringLiteral(_)
+            | Token::BytesLiteral(_)
+            | Token::True
+            | Token::False
+            | Token::RParen
+            | Token::RBracket
+            | Token::RBrace
+            | Token::Uint(_)
+            | Token::Int(_)
+            | Token::Bool
+            | Token::Address
+            | Token::Bytes
+            | Token::FixedBytes(_)
+            | Token::String
+    )
+}
+
+fn column(source: &str, offset: usize) -> usize {
+    let line_start = source[..offset].rfind('\n').map_or(0, |i| i + 1);
+    indent_width(&source[line_start..offset])
+}
+
+fn indent_width(text: &str) -> usize {
+    text.chars()
+        .take_while(|c| c.is_whitespace())
+        .map(|c| if c == '\t' { 8 } else { 1 })
+        .sum()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fmt(source: &str) -> String {
+        format_source(source).unwrap()
+    }
+
+    /// Tokens that carry meaning: no layout, and no comma before a closer.
+    fn significant(source: &str) -> Vec<Token> {
+        let tokens: Vec<Token> = PyraLexer::new(source)
+            .filter(|t| !matches!(t, Token::Newline | Token::Indent | Token::Dedent))
+            .collect();
+        tokens
+            .iter()
+            .enumerate()
+            .filter(|(i, t)| !(**t == Token::Comma && tokens.get(i + 1).is_some_and(closes)))
+            .map(|(_, t)| t.clone())
+            .collect()
+    }
+
+    #[test]
+    fn normalizes_spacing_and_indentation() {
+        let src = "def  f( a:uint256,b :uint256 )->uint256 :\n  let x=a+b*-2\n  if x>=1 and not(a==b):\n      return x**2\n  return data[0 :4]\n";
+        assert_eq!(
+            fmt(src),
+            "def f(a: uint256, b: uint256) -> uint256:\n    let x = a + b * -2\n    if x >= 1 and not (a == b):\n        return x ** 2\n    return data[0:4]\n"
+        );
+    }
+
+    #[test]
+    fn keeps_comments_and_collapses_blank_lines() {
+        let src = "# header\n\n\n\ndef f():  # entry\n    # first\n    x = 1\n    # end of f\n\n\n# before g\ndef g():\n    pass_through()\n";
+        assert_eq!(
+            fmt(src),
+            "# header\n\ndef f():  # entry\n    # first\n    x = 1\n    # end of f\n\n# before g\ndef g():\n    pass_through()\n"
+        );
+    }
+
+    #[test]
+    fn keeps_literal_spelling() {
+        let src = "const A: uint256 = 1e18\nconst B: uint256 = 2   ether\nconst C: uint256 = 0xFF\n";
+        assert_eq!(fmt(src), "const A: uint256 = 1e18\nconst B: uint256 = 2 ether\nconst C: uint256 = 0xFF\n");
+    }
+
+    #[test]
+    fn wraps_long_parameter_lists() {
+        let src = "def transfer_from_with_a_long_name(sender_address: address, recipient_address: address, amount: uint256) -> bool:\n    return true\n";
+        let expected = "def transfer_from_with_a_long_name(\n    sender_address: address,\n    recipient_address: address,\n    amount: uint256,\n) -> bool:\n    return true\n";
+        assert_eq!(fmt(src), expected);
+        assert_eq!(fmt(expected), expected);
+        assert_eq!(fmt("def f(\n    a: uint256,\n) -> bool:\n    return true\n"), "def f(a: uint256) -> bool:\n    return true\n");
+    }
+
+    #[test]
+    fn formats_structs_decorators_and_generics() {
+        let src = "struct  Vault<T:Token> {\n  token: T,\n  owner:address\n}\n\n@allow( bare_revert )\ndef f(x: uint256):\n  require x>0\n  let m = P{a: 1, b: 2}\n";
+        assert_eq!(
+            fmt(src),
+            "struct Vault<T: Token> {\n    token: T,\n    owner: address\n}\n\n@allow(bare_revert)\ndef f(x: uint256):\n    require x > 0\n    let m = P { a: 1, b: 2 }\n"
+        );
+    }
+
+    #[test]
+    fn contracts_are_stable_and_keep_their_tokens() {
+        for name in ["ERC20", "Vault"] {
+            let path = format!("{}/../contracts/{name}.pyra", env!("CARGO_MANIFEST_DIR"));
+            let src = std::fs::read_to_string(path).unwrap();
+            let once = fmt(&src);
+            assert_eq!(fmt(&once), once, "{name} is not stable");
+            assert_eq!(significant(&once), significant(&src), "{name} changed meaning");
+        }
+    }
+
+    #[test]
+    fn refuses_source_that_does_not_parse() {
+        assert!(format_source("def f(:\n").is_err());
+    }
+}
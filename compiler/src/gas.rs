@@ -1,3 +1,4 @@
+use crate::codegen::BINARY_DISPATCH_MIN;
 use crate::ir::{IrModule, IrOp};
 
 #[derive(Debug, Clone)]
@@ -11,12 +12,16 @@ pub struct FunctionGas {
 pub struct GasReport {
     pub functions: Vec<FunctionGas>,
     pub constructor_gas: u64,
+    /// Worst-case cost of reaching a function through the emitted dispatcher.
     pub dispatch_overhead: u64,
+    /// What `dispatch_overhead` would be if every selector were compared in turn.
+    pub linear_dispatch_overhead: u64,
 }
 
 impl GasReport {
     pub fn from_module(module: &IrModule) -> Self {
-        let dispatch_overhead = module.functions.len() as u64 * DISPATCH_PER_BRANCH;
+        let dispatch_overhead = dispatch_cost(module.functions.len());
+        let linear_dispatch_overhead = module.functions.len() as u64 * DISPATCH_PER_BRANCH;
 
         let functions: Vec<FunctionGas> = module
             .functions
@@ -34,12 +39,26 @@ impl GasReport {
             functions,
             constructor_gas,
             dispatch_overhead,
+            linear_dispatch_overhead,
         }
     }
+
+    pub fn total(&self) -> u64 {
+        self.functions.iter().map(|f| f.estimated_gas).sum::<u64>() + self.constructor_gas
+    }
 }
 
 const DEPLOY_BASE: u64 = 32000;
 const DISPATCH_PER_BRANCH: u64 = 22;
+const DISPATCH_PER_PIVOT: u64 = 23;
+
+fn dispatch_cost(functions: usize) -> u64 {
+    if functions < BINARY_DISPATCH_MIN {
+        return functions as u64 * DISPATCH_PER_BRANCH;
+    }
+    let lower = functions / 2;
+    DISPATCH_PER_PIVOT + dispatch_cost(lower).max(dispatch_cost(functions - lower))
+}
 
 fn estimate_ops(ops: &[IrOp]) -> u64 {
     let mut total: u64 = 0;
@@ -58,30 +77,47 @@ fn op_gas(op: &IrOp) -> u64 {
         IrOp::Add | IrOp::Sub => 3,
         IrOp::Mul | IrOp::Div | IrOp::SDiv | IrOp::Mod => 5,
         IrOp::Exp => 10,
+        IrOp::SignExtend => 5,
         IrOp::Lt | IrOp::Gt | IrOp::Eq => 3,
         IrOp::IsZero => 3,
         IrOp::And | IrOp::Or | IrOp::Not => 3,
-        IrOp::Shr => 3,
+        IrOp::Byte | IrOp::Shl | IrOp::Shr => 3,
         IrOp::MLoad | IrOp::MStore => 3,
         IrOp::SLoad => 2100,
         IrOp::SStore => 5000,
+        IrOp::TLoad | IrOp::TStore => 100,
+        IrOp::MCopy => 3,
         IrOp::Jump(_) => 8,
         IrOp::JumpI(_) => 10,
         IrOp::JumpDest(_) => 1,
+        IrOp::PushLabel(_) => 3,
+        IrOp::JumpBack => 8,
+        IrOp::Narrow { .. } => 8,
         IrOp::Caller => 2,
         IrOp::CallValue => 2,
+        IrOp::Timestamp | IrOp::Number => 2,
         IrOp::CallDataLoad => 3,
         IrOp::CallDataSize => 2,
+        IrOp::CallDataCopy => 3,
+        IrOp::CodeSize => 2,
+        IrOp::CodeCopy => 3,
+        IrOp::ExtCodeSize => 2600,
+        IrOp::ReturnDataSize => 2,
+        IrOp::ReturnDataCopy => 3,
+        IrOp::Gas => 2,
+        IrOp::Call | IrOp::StaticCall => 2600,
         IrOp::Keccak256 => 30,
         IrOp::Return => 0,
         IrOp::Revert => 0,
         IrOp::Log(n) => 375 + (*n as u64) * 375,
         IrOp::Stop => 0,
         IrOp::Invalid => 0,
+        IrOp::Loc(..) => 0,
     }
 }
 
 #[cfg(test)]
+#[allow(clippy::identity_op)]
 mod tests {
     use super::*;
     use crate::ir::IrFunction;
@@ -93,6 +129,10 @@ mod tests {
                 selector: [0xa9, 0x05, 0x9c, 0xbb],
                 ops,
                 label: 0,
+                guarded: false,
+                payable: false,
+                read_only: false,
+                calldata_size: 4,
             }],
             constructor_ops,
             label_count: 1,
@@ -120,6 +160,16 @@ mod tests {
         assert_eq!(report.functions[0].estimated_gas, 3 + 2100 + 0 + DISPATCH_PER_BRANCH);
     }
 
+    #[test]
+    fn gas_extcodesize_is_cold_access() {
+        let module = make_module(
+            vec![IrOp::Caller, IrOp::ExtCodeSize, IrOp::Return],
+            vec![],
+        );
+        let report = GasReport::from_module(&module);
+        assert_eq!(report.functions[0].estimated_gas, 2 + 2600 + 0 + DISPATCH_PER_BRANCH);
+    }
+
     #[test]
     fn gas_sstore_is_5000() {
         let module = make_module(
@@ -164,9 +214,9 @@ mod tests {
     fn gas_dispatch_scales_with_functions() {
         let module = IrModule {
             functions: vec![
-                IrFunction { name: "a".into(), selector: [0; 4], ops: vec![IrOp::Stop], label: 0 },
-                IrFunction { name: "b".into(), selector: [1; 4], ops: vec![IrOp::Stop], label: 1 },
-                IrFunction { name: "c".into(), selector: [2; 4], ops: vec![IrOp::Stop], label: 2 },
+                IrFunction { name: "a".into(), selector: [0; 4], ops: vec![IrOp::Stop], label: 0, guarded: false, payable: false, read_only: false, calldata_size: 4 },
+                IrFunction { name: "b".into(), selector: [1; 4], ops: vec![IrOp::Stop], label: 1, guarded: false, payable: false, read_only: false, calldata_size: 4 },
+                IrFunction { name: "c".into(), selector: [2; 4], ops: vec![IrOp::Stop], label: 2, guarded: false, payable: false, read_only: false, calldata_size: 4 },
             ],
             constructor_ops: vec![],
             label_count: 3,
@@ -178,6 +228,27 @@ mod tests {
         }
     }
 
+    #[test]
+    fn gas_binary_dispatch_beats_linear() {
+        let functions = (0..16)
+            .map(|i| IrFunction {
+                name: format!("f{i}"),
+                selector: [i as u8; 4],
+                ops: vec![IrOp::Stop],
+                label: i,
+                guarded: false,
+                payable: false,
+                read_only: false,
+                calldata_size: 4,
+            })
+            .collect();
+        let module = IrModule { functions, constructor_ops: vec![], label_count: 16 };
+        let report = GasReport::from_module(&module);
+        assert_eq!(report.linear_dispatch_overhead, 16 * DISPATCH_PER_BRANCH);
+        assert_eq!(report.dispatch_overhead, 3 * DISPATCH_PER_PIVOT + 2 * DISPATCH_PER_BRANCH);
+        assert!(report.dispatch_overhead < report.linear_dispatch_overhead);
+    }
+
     #[test]
     fn gas_arithmetic_costs() {
         let module = make_module(
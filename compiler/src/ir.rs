@@ -1,9 +1,16 @@
-use crate::storage::{StorageKind, StorageLayout};
-use crate::{BinaryOp, Block, Expression, Function, Item, Program, Statement, UnaryOp};
+use crate::optimizer::{
+    constant_condition, fold_constants, fold_ir, repeated_subexpressions, signed_constant,
+    simplify_identity, slice_bounds, OptLevel,
+};
+use crate::storage::{StorageKind, StorageLayout, StorageSlot};
+use crate::{BinaryOp, Block, Expression, Function, Item, Program, Span, Statement, UnaryOp};
+use std::borrow::Cow;
 use std::collections::HashMap;
+use std::fmt;
+use std::rc::Rc;
 use tiny_keccak::{Hasher, Keccak};
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum IrOp {
     Push(Vec<u8>),
     Pop,
@@ -16,6 +23,7 @@ pub enum IrOp {
     SDiv,
     Mod,
     Exp,
+    SignExtend,
     Lt,
     Gt,
     Eq,
@@ -23,24 +31,138 @@ pub enum IrOp {
     And,
     Or,
     Not,
+    Byte,
+    Shl,
     Shr,
     MLoad,
     MStore,
     SLoad,
     SStore,
+    TLoad,
+    TStore,
+    MCopy,
     Jump(usize),
     JumpI(usize),
     JumpDest(usize),
+    /// Pushes the code offset of a label, as the return address of an
+    /// internal call.
+    PushLabel(usize),
+    /// Jumps to the code offset on top of the stack.
+    JumpBack,
+    /// Brings the arithmetic result on top of the stack back into an
+    /// integer type narrower than 256 bits, two's complement when `signed`.
+    /// Hardening turns it into a range check when the operation before it
+    /// is checked; otherwise the result wraps.
+    Narrow { bits: u16, signed: bool },
     Caller,
     CallValue,
+    Timestamp,
+    Number,
     CallDataLoad,
     CallDataSize,
+    CallDataCopy,
+    CodeSize,
+    CodeCopy,
+    ExtCodeSize,
+    ReturnDataSize,
+    ReturnDataCopy,
+    Gas,
+    Call,
+    StaticCall,
     Keccak256,
     Return,
     Revert,
     Log(u8),
     Stop,
     Invalid,
+    /// Emits nothing. The ops after it came from this span of the file
+    /// numbered as in `Program::files`, for the source map.
+    Loc(usize, Span),
+}
+
+impl IrOp {
+    pub fn name(&self) -> &'static str {
+        match self {
+            IrOp::Push(_) => "PUSH",
+            IrOp::Pop => "POP",
+            IrOp::Dup(_) => "DUP",
+            IrOp::Swap(_) => "SWAP",
+            IrOp::Add => "ADD",
+            IrOp::Sub => "SUB",
+            IrOp::Mul => "MUL",
+            IrOp::Div => "DIV",
+            IrOp::SDiv => "SDIV",
+            IrOp::Mod => "MOD",
+            IrOp::Exp => "EXP",
+            IrOp::SignExtend => "SIGNEXTEND",
+            IrOp::Lt => "LT",
+            IrOp::Gt => "GT",
+            IrOp::Eq => "EQ",
+            IrOp::IsZero => "ISZERO",
+            IrOp::And => "AND",
+            IrOp::Or => "OR",
+            IrOp::Not => "NOT",
+            IrOp::Byte => "BYTE",
+            IrOp::Shl => "SHL",
+            IrOp::Shr => "SHR",
+            IrOp::MLoad => "MLOAD",
+            IrOp::MStore => "MSTORE",
+            IrOp::SLoad => "SLOAD",
+            IrOp::SStore => "SSTORE",
+            IrOp::TLoad => "TLOAD",
+            IrOp::TStore => "TSTORE",
+            IrOp::MCopy => "MCOPY",
+            IrOp::Jump(_) => "JUMP",
+            IrOp::JumpI(_) => "JUMPI",
+            IrOp::JumpDest(_) => "JUMPDEST",
+            IrOp::PushLabel(_) => "PUSH",
+            IrOp::JumpBack => "JUMP",
+            IrOp::Narrow { .. } => "NARROW",
+            IrOp::Caller => "CALLER",
+            IrOp::CallValue => "CALLVALUE",
+            IrOp::Timestamp => "TIMESTAMP",
+            IrOp::Number => "NUMBER",
+            IrOp::CallDataLoad => "CALLDATALOAD",
+            IrOp::CallDataSize => "CALLDATASIZE",
+            IrOp::CallDataCopy => "CALLDATACOPY",
+            IrOp::CodeSize => "CODESIZE",
+            IrOp::CodeCopy => "CODECOPY",
+            IrOp::ExtCodeSize => "EXTCODESIZE",
+            IrOp::ReturnDataSize => "RETURNDATASIZE",
+            IrOp::ReturnDataCopy => "RETURNDATACOPY",
+            IrOp::Gas => "GAS",
+            IrOp::Call => "CALL",
+            IrOp::StaticCall => "STATICCALL",
+            IrOp::Keccak256 => "KECCAK256",
+            IrOp::Return => "RETURN",
+            IrOp::Revert => "REVERT",
+            IrOp::Log(_) => "LOG",
+            IrOp::Stop => "STOP",
+            IrOp::Invalid => "INVALID",
+            IrOp::Loc(..) => "LOC",
+        }
+    }
+}
+
+/// One op as the IR listing shows it: pushes in hex, `DUP`/`SWAP`/`LOG`
+/// with their operand, and labels as `L<n>`.
+impl fmt::Display for IrOp {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            IrOp::Push(data) => write!(f, "PUSH 0x{}", hex::encode(data)),
+            IrOp::Dup(n) => write!(f, "DUP{n}"),
+            IrOp::Swap(n) => write!(f, "SWAP{n}"),
+            IrOp::Log(n) => write!(f, "LOG{n}"),
+            IrOp::Jump(label) => write!(f, "JUMP L{label}"),
+            IrOp::JumpI(label) => write!(f, "JUMPI L{label}"),
+            IrOp::JumpDest(label) => write!(f, "L{label}:"),
+            IrOp::PushLabel(label) => write!(f, "PUSH L{label}"),
+            IrOp::Narrow { bits, signed } => write!(f, "NARROW {}{bits}", if *signed { "int" } else { "uint" }),
+            IrOp::Loc(0, span) => write!(f, "; source {}..{}", span.start, span.end),
+            IrOp::Loc(file, span) => write!(f, "; source {file}:{}..{}", span.start, span.end),
+            other => f.write_str(other.name()),
+        }
+    }
 }
 
 pub struct IrFunction {
@@ -48,6 +170,23 @@ pub struct IrFunction {
     pub selector: [u8; 4],
     pub ops: Vec<IrOp>,
     pub label: usize,
+    pub guarded: bool,
+    pub payable: bool,
+    /// `@view` or `@pure`: the function may be reached by STATICCALL, so it
+    /// must not write storage.
+    pub read_only: bool,
+    /// Bytes of calldata the selector and static parameter head take up.
+    pub calldata_size: usize,
+}
+
+impl IrFunction {
+    pub fn op_histogram(&self) -> HashMap<&'static str, usize> {
+        let mut counts = HashMap::new();
+        for op in self.ops.iter().filter(|op| !matches!(op, IrOp::Loc(..))) {
+            *counts.entry(op.name()).or_insert(0) += 1;
+        }
+        counts
+    }
 }
 
 pub struct IrModule {
@@ -56,25 +195,179 @@ pub struct IrModule {
     pub label_count: usize,
 }
 
+/// The module as a numbered listing: the constructor, then each function
+/// under a header with its selector, entry label and flags.
+impl fmt::Display for IrModule {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fn ops(f: &mut fmt::Formatter, ops: &[IrOp]) -> fmt::Result {
+            for (i, op) in ops.iter().enumerate() {
+                writeln!(f, "{i:>6}  {op}")?;
+            }
+            Ok(())
+        }
+
+        writeln!(f, "constructor")?;
+        ops(f, &self.constructor_ops)?;
+        for func in &self.functions {
+            write!(
+                f,
+                "\nfunction {} selector 0x{} entry L{} calldata {}",
+                func.name,
+                hex::encode(func.selector),
+                func.label,
+                func.calldata_size
+            )?;
+            if func.guarded {
+                f.write_str(" guarded")?;
+            }
+            if func.payable {
+                f.write_str(" payable")?;
+            }
+            writeln!(f)?;
+            ops(f, &func.ops)?;
+        }
+        Ok(())
+    }
+}
+
 struct LowerCtx {
     layout: StorageLayout,
     params: HashMap<String, usize>,
     locals: HashMap<String, usize>,
-    events: HashMap<String, Vec<crate::Type>>,
+    dynamic_params: HashMap<String, usize>,
+    /// Declared type of each calldata param, for narrowing and arrays.
+    param_types: HashMap<String, crate::Type>,
+    /// Types of locals and state variables where they are known, so hex
+    /// literals can be aligned for the `bytesN` they meet.
+    local_types: HashMap<String, crate::Type>,
+    state_types: HashMap<String, crate::Type>,
+    /// Return type of the function being lowered.
+    return_type: Option<crate::Type>,
+    events: HashMap<String, Vec<crate::EventField>>,
+    errors: HashMap<String, crate::ErrorDef>,
+    enums: HashMap<String, crate::EnumDef>,
+    functions: HashMap<String, Function>,
+    interfaces: HashMap<String, crate::InterfaceDef>,
+    string_consts: HashMap<String, String>,
+    /// Repeated subexpressions of the expression being lowered, with the
+    /// memory temp holding each once it has been computed.
+    cse: Vec<(Expression, Option<usize>)>,
+    inline_frames: Vec<InlineFrame>,
+    /// Internal functions already lowered for the function being lowered,
+    /// and their code, placed after it.
+    subroutines: HashMap<String, Subroutine>,
+    subroutine_ops: Vec<IrOp>,
+    /// `(continue, break)` targets of the enclosing loops, innermost last.
+    loops: Vec<(usize, usize)>,
+    named_return: Option<Expression>,
+    modified: Option<Modified>,
+    /// The function being lowered returns `string` or `bytes`.
+    returns_dynamic: bool,
+    /// Word holding the free memory pointer, when the function being
+    /// lowered has `string` or `bytes` params and so sets one up.
+    free_ptr: Option<usize>,
     next_mem: usize,
     label_count: usize,
+    opt: OptLevel,
+    /// Emit `Loc` markers. `span` is the innermost statement being lowered,
+    /// `file` the file it is in and `marked` the last marker in the current
+    /// op list.
+    spans: bool,
+    span: Option<Span>,
+    file: usize,
+    marked: Option<(usize, Span)>,
+    /// File of each function, modifier and constant, by name.
+    origins: HashMap<String, usize>,
+}
+
+/// An internal function being lowered: its results go to `results` and
+/// `return` jumps to `end_label`.
+struct InlineFrame {
+    name: String,
+    results: Vec<usize>,
+    end_label: usize,
+}
+
+/// A function whose modifiers are being lowered. `depth` counts the
+/// modifiers entered so far; a `_` lowers the next one in, or the body once
+/// all are entered, and the body's `return` stores `results` and jumps back
+/// to the code after that `_`.
+struct Modified {
+    function: Rc<Function>,
+    depth: usize,
+    results: Vec<usize>,
+}
+
+/// An internal function's entry label and the memory words of its frame.
+#[derive(Clone)]
+struct Subroutine {
+    entry: usize,
+    params: Vec<usize>,
+    results: Vec<usize>,
 }
 
+// Memory map: mapping keys are hashed from the two scratch words at 0x00,
+// return data is assembled from 0x40, and locals and temporaries start at 0x80.
+// Return values are all on the stack before anything is written to the return
+// region, so a mapping read inside a return cannot be clobbered by the hash.
+const HASH_SCRATCH: usize = 0x00;
+const RETURN_BASE: usize = 0x40;
+const LOCALS_BASE: usize = 0x80;
+
 impl LowerCtx {
     fn new(layout: StorageLayout) -> Self {
         Self {
             layout,
             params: HashMap::with_capacity(8),
             locals: HashMap::with_capacity(8),
+            dynamic_params: HashMap::new(),
+            param_types: HashMap::new(),
+            local_types: HashMap::new(),
+            state_types: HashMap::new(),
+            return_type: None,
             events: HashMap::new(),
-            next_mem: 0x80,
+            errors: HashMap::new(),
+            enums: HashMap::new(),
+            functions: HashMap::new(),
+            interfaces: HashMap::new(),
+            string_consts: HashMap::new(),
+            cse: Vec::new(),
+            inline_frames: Vec::new(),
+            subroutines: HashMap::new(),
+            subroutine_ops: Vec::new(),
+            loops: Vec::new(),
+            named_return: None,
+            modified: None,
+            returns_dynamic: false,
+            free_ptr: None,
+            next_mem: LOCALS_BASE,
             label_count: 0,
+            opt: OptLevel::default(),
+            spans: false,
+            span: None,
+            file: 0,
+            marked: None,
+            origins: HashMap::new(),
+        }
+    }
+
+    /// Marks the ops that follow as coming from `span`, unless the last
+    /// marker already says so.
+    fn mark_span(&mut self, ops: &mut Vec<IrOp>) {
+        let at = self.span.clone().map(|span| (self.file, span));
+        if !self.spans || at == self.marked {
+            return;
+        }
+        if let Some((file, span)) = &at {
+            ops.push(IrOp::Loc(*file, span.clone()));
         }
+        self.marked = at;
+    }
+
+    /// Moves into the file that declared `name` and returns the one left.
+    fn enter_file(&mut self, name: &str) -> usize {
+        let file = self.origins.get(name).copied().unwrap_or(0);
+        std::mem::replace(&mut self.file, file)
     }
 
     fn fresh_label(&mut self) -> usize {
@@ -84,70 +377,195 @@ impl LowerCtx {
     }
 
     fn alloc_local(&mut self, name: &str) -> usize {
+        self.local_types.remove(name);
         let off = self.next_mem;
         self.locals.insert(name.to_string(), off);
         self.next_mem += 32;
         off
     }
 
+    fn alloc_temp(&mut self) -> usize {
+        let off = self.next_mem;
+        self.next_mem += 32;
+        off
+    }
+
+    /// Resolves `Iface(address).method` to the address and the declared method.
+    fn interface_method(&self, callee: &Expression) -> Option<(Expression, Function)> {
+        let Expression::Member(target, method) = callee else { return None };
+        let Expression::Call(iface, args) = target.as_ref() else { return None };
+        let (Expression::Identifier(iface), [address]) = (iface.as_ref(), args.as_slice()) else {
+            return None;
+        };
+        let method = self.interfaces.get(iface)?.function(method)?;
+        Some((address.clone(), method.clone()))
+    }
+
+    fn string_const(&self, name: &str) -> Option<Vec<u8>> {
+        if self.params.contains_key(name) || self.locals.contains_key(name) {
+            return None;
+        }
+        self.string_consts.get(name).map(|s| string_literal_bytes(s))
+    }
+
     fn reset_for_function(&mut self) {
         self.params.clear();
         self.locals.clear();
-        self.next_mem = 0x80;
+        self.dynamic_params.clear();
+        self.param_types.clear();
+        self.local_types.clear();
+        self.named_return = None;
+        self.returns_dynamic = false;
+        self.free_ptr = None;
+        self.next_mem = LOCALS_BASE;
+        self.span = None;
+        self.marked = None;
     }
 }
 
 pub fn lower_program(program: &Program) -> IrModule {
+    lower_program_with_opt_level(program, OptLevel::default())
+}
+
+pub fn lower_program_with_opt_level(program: &Program, opt: OptLevel) -> IrModule {
+    lower_module(program, opt, false)
+}
+
+/// Lowers like `lower_program_with_opt_level`, with a `Loc` marker wherever
+/// the source span of the ops changes. The markers emit no bytecode.
+pub fn lower_program_with_spans(program: &Program, opt: OptLevel) -> IrModule {
+    lower_module(program, opt, true)
+}
+
+fn lower_module(program: &Program, opt: OptLevel, spans: bool) -> IrModule {
     let layout = StorageLayout::from_program(program);
     let mut ctx = LowerCtx::new(layout);
+    ctx.opt = opt;
+    ctx.spans = spans;
     let mut functions = Vec::new();
     let mut constructor_ops = Vec::new();
 
     for item in &program.items {
-        if let Item::Event(ev) = item {
-            ctx.events.insert(
-                ev.name.clone(),
-                ev.fields.iter().map(|f| f.type_.clone()).collect(),
-            );
+        match item {
+            Item::Event(ev) => {
+                ctx.events.insert(ev.name.clone(), ev.fields.clone());
+            }
+            Item::Error(e) => {
+                ctx.errors.insert(e.name.clone(), e.clone());
+            }
+            Item::Enum(e) => {
+                ctx.enums.insert(e.name.clone(), e.clone());
+            }
+            Item::Interface(iface) => {
+                ctx.interfaces.insert(iface.name.clone(), iface.clone());
+            }
+            Item::State(s) => {
+                ctx.state_types.insert(s.name.clone(), s.type_.clone());
+            }
+            Item::Const(c) => {
+                ctx.state_types.insert(c.name.clone(), c.type_.clone());
+            }
+            _ => {}
         }
     }
 
+    for (i, item) in program.items.iter().enumerate() {
+        let name = match item {
+            Item::Function(f) => {
+                ctx.functions.insert(f.name.clone(), f.clone());
+                &f.name
+            }
+            Item::Modifier(m) => &m.name,
+            Item::Const(c) => &c.name,
+            _ => continue,
+        };
+        ctx.origins.insert(name.clone(), program.item_file(i));
+    }
+
     for item in &program.items {
         if let Item::Const(c) = item {
+            ctx.enter_file(&c.name);
             if let Some(slot) = ctx.layout.get(&c.name) {
                 let slot_num = slot.slot;
-                let mut ops = lower_expression(&mut ctx, &c.value);
+                let value = match align_fixed_bytes(&c.type_, &c.value) {
+                    Some(aligned) => Expression::HexNumber(aligned),
+                    None => fold_constants(&c.value),
+                };
+                if let Expression::String(s) = value {
+                    ctx.string_consts.insert(c.name.clone(), s);
+                    continue;
+                }
+                ctx.span = Some(c.span.clone());
+                ctx.mark_span(&mut constructor_ops);
+                let mut ops = lower_expression(&mut ctx, &value);
                 ops.push(IrOp::Push(u64_to_bytes(slot_num)));
                 ops.push(IrOp::SStore);
                 constructor_ops.extend(ops);
+                append_constructor_subroutines(&mut ctx, &mut constructor_ops);
+            } else if let Expression::StructInit(_, fields) = &c.value {
+                ctx.span = Some(c.span.clone());
+                ctx.mark_span(&mut constructor_ops);
+                for (field, value) in fields {
+                    let Some(slot_num) = ctx.layout.field(&c.name, field).map(|s| s.slot) else {
+                        continue;
+                    };
+                    let value = fold_constants(value);
+                    let mut ops = lower_expression(&mut ctx, &value);
+                    ops.push(IrOp::Push(u64_to_bytes(slot_num)));
+                    ops.push(IrOp::SStore);
+                    constructor_ops.extend(ops);
+                    append_constructor_subroutines(&mut ctx, &mut constructor_ops);
+                }
             }
         }
     }
 
     for item in &program.items {
         if let Item::Function(f) = item {
+            if f.internal {
+                continue;
+            }
             ctx.reset_for_function();
+            ctx.span = Some(f.span.clone());
+            ctx.enter_file(&f.name);
 
             if f.name == "init" {
-                for (i, p) in f.params.iter().enumerate() {
-                    ctx.params.insert(p.name.clone(), 4 + 32 * i);
+                ctx.mark_span(&mut constructor_ops);
+                lower_constructor_args(&mut ctx, f, &mut constructor_ops);
+                if f.modifiers.is_empty() {
+                    lower_block(&mut ctx, &f.body, &mut constructor_ops);
+                } else {
+                    lower_modifiers(&mut ctx, f, &mut constructor_ops);
                 }
-                lower_block(&mut ctx, &f.body, &mut constructor_ops);
+                append_constructor_subroutines(&mut ctx, &mut constructor_ops);
                 continue;
             }
 
             let label = ctx.fresh_label();
-            for (i, p) in f.params.iter().enumerate() {
-                ctx.params.insert(p.name.clone(), 4 + 32 * i);
+            ctx.returns_dynamic = matches!(f.return_type, Some(crate::Type::String | crate::Type::Bytes));
+            // A fixed-size array param is encoded in place, one word per element.
+            let mut offset = 4;
+            for p in &f.params {
+                ctx.params.insert(p.name.clone(), offset);
+                ctx.param_types.insert(p.name.clone(), p.type_.clone());
+                offset += match p.type_ {
+                    crate::Type::Array(_, len) => 32 * len,
+                    _ => 32,
+                };
             }
 
             let mut ops = Vec::with_capacity(64);
             ops.push(IrOp::JumpDest(label));
-            lower_block(&mut ctx, &f.body, &mut ops);
+            ctx.mark_span(&mut ops);
+            lower_function_body(&mut ctx, f, &mut ops);
 
-            if !ops.iter().any(|op| matches!(op, IrOp::Return | IrOp::Revert | IrOp::Stop)) {
+            // A revert inside the body does not end it; without the STOP the
+            // last statement would fall through into the next function.
+            let last = ops.iter().rev().find(|op| !matches!(op, IrOp::Loc(..)));
+            if !matches!(last, Some(IrOp::Return | IrOp::Revert | IrOp::Stop)) {
                 ops.push(IrOp::Stop);
             }
+            ops.extend(take_subroutines(&mut ctx));
 
             let selector = compute_selector(f);
             functions.push(IrFunction {
@@ -155,6 +573,10 @@ pub fn lower_program(program: &Program) -> IrModule {
                 selector,
                 ops,
                 label,
+                guarded: f.has_decorator("reentrancy_guard"),
+                payable: f.is_payable(),
+                read_only: f.is_read_only(),
+                calldata_size: offset,
             });
         }
     }
@@ -167,6 +589,194 @@ pub fn lower_program(program: &Program) -> IrModule {
     }
 }
 
+fn lower_function_body(ctx: &mut LowerCtx, f: &Function, ops: &mut Vec<IrOp>) {
+    let dynamic: Vec<_> = f
+        .params
+        .iter()
+        .filter(|p| matches!(p.type_, crate::Type::Bytes | crate::Type::String))
+        .map(|p| (ctx.params[&p.name], ctx.alloc_temp(), p.name.clone()))
+        .collect();
+    let free = (!dynamic.is_empty()).then(|| ctx.alloc_temp());
+    for (_, ptr, name) in &dynamic {
+        ctx.dynamic_params.insert(name.clone(), *ptr);
+    }
+    if free.is_some() {
+        ctx.free_ptr = free;
+    }
+
+    ctx.return_type = f.return_type.clone();
+    let return_types = match &f.return_type {
+        Some(crate::Type::Tuple(items)) => items.clone(),
+        other => other.iter().cloned().collect(),
+    };
+    let mut body = Vec::with_capacity(64);
+    for (i, name) in f.return_names.iter().enumerate() {
+        let off = ctx.alloc_local(name);
+        if let Some(ty) = return_types.get(i) {
+            ctx.local_types.insert(name.clone(), ty.clone());
+        }
+        body.push(IrOp::Push(vec![0x00]));
+        body.push(IrOp::Push(usize_to_bytes(off)));
+        body.push(IrOp::MStore);
+    }
+    ctx.named_return = f.named_return_value();
+    let outer = ctx.modified.take();
+    if f.modifiers.is_empty() {
+        lower_own_body(ctx, f, &mut body);
+    } else {
+        lower_modifiers(ctx, f, &mut body);
+    }
+    ctx.modified = outer;
+
+    // Dynamic params are copied past every local the body uses, so the
+    // prologue is emitted last, once the function's memory high-water mark is known.
+    if let Some(free) = free {
+        ops.push(IrOp::Push(usize_to_bytes(ctx.next_mem)));
+        ops.push(IrOp::Push(usize_to_bytes(free)));
+        ops.push(IrOp::MStore);
+        for (param, ptr, _) in dynamic {
+            lower_dynamic_param(param, ptr, free, ops);
+        }
+    }
+    ops.extend(body);
+}
+
+fn lower_own_body(ctx: &mut LowerCtx, f: &Function, ops: &mut Vec<IrOp>) {
+    lower_block(ctx, &f.body, ops);
+    if let Some(value) = ctx.named_return.clone() {
+        if !matches!(f.body.statements.last(), Some(Statement::Return(_, _))) {
+            lower_statement_ops(ctx, &Statement::Return(Some(value), f.body.span.clone()), ops);
+        }
+    }
+}
+
+/// Lowers the outermost modifier, whose `_` leads to the others and the
+/// body, then returns what the body returned once the modifier code after
+/// it has run.
+fn lower_modifiers(ctx: &mut LowerCtx, f: &Function, ops: &mut Vec<IrOp>) {
+    let arity = match &f.return_type {
+        Some(crate::Type::Tuple(items)) => items.len(),
+        Some(_) => 1,
+        None => 0,
+    };
+    // Inside a subroutine the body can return straight into its frame.
+    let results = match ctx.inline_frames.last() {
+        Some(frame) => frame.results.clone(),
+        None => (0..arity).map(|_| ctx.alloc_temp()).collect(),
+    };
+    let function = Rc::new(f.clone());
+    ctx.modified = Some(Modified { function: Rc::clone(&function), depth: 0, results: results.clone() });
+    let outer = ctx.enter_file(&function.modifiers[0].name);
+    lower_block(ctx, &function.modifiers[0].body, ops);
+    ctx.file = outer;
+    ctx.modified = None;
+    if ctx.inline_frames.is_empty() && !results.is_empty() {
+        for (i, off) in results.iter().enumerate() {
+            ops.push(IrOp::Push(usize_to_bytes(*off)));
+            ops.push(IrOp::MLoad);
+            ops.push(IrOp::Push(usize_to_bytes(RETURN_BASE + 32 * i)));
+            ops.push(IrOp::MStore);
+        }
+        ops.push(IrOp::Push(usize_to_bytes(32 * results.len())));
+        ops.push(IrOp::Push(usize_to_bytes(RETURN_BASE)));
+        ops.push(IrOp::Return);
+    }
+}
+
+/// Lowers a modifier's `_`: the next modifier in, or the function body,
+/// with the body's returns sent to the code after the `_`. A `string` or
+/// `bytes` return still ends the call where it is, since its encoding is
+/// built in place.
+fn lower_placeholder(ctx: &mut LowerCtx, ops: &mut Vec<IrOp>) {
+    let Some(modified) = ctx.modified.as_mut() else { return };
+    modified.depth += 1;
+    let (function, depth, results) = (Rc::clone(&modified.function), modified.depth, modified.results.clone());
+    let end = ctx.fresh_label();
+    let framed = !ctx.returns_dynamic;
+    if framed {
+        ctx.inline_frames.push(InlineFrame { name: format!("@{}", function.name), results, end_label: end });
+    }
+    let outer = ctx.enter_file(function.modifiers.get(depth).map_or(&function.name, |m| &m.name));
+    match function.modifiers.get(depth) {
+        Some(modifier) => lower_block(ctx, &modifier.body, ops),
+        None => {
+            let saved = ctx.modified.take();
+            lower_own_body(ctx, &function, ops);
+            ctx.modified = saved;
+        }
+    }
+    ctx.file = outer;
+    if framed {
+        ctx.inline_frames.pop();
+    }
+    if let Some(modified) = ctx.modified.as_mut() {
+        modified.depth -= 1;
+    }
+    ops.push(IrOp::JumpDest(end));
+}
+
+/// Copies an ABI-encoded `bytes`/`string` param from calldata to memory as
+/// `[length][data]`, stores the region's address at `ptr` and bumps the
+/// free pointer at `free` past the word-padded data.
+fn lower_dynamic_param(param: usize, ptr: usize, free: usize, ops: &mut Vec<IrOp>) {
+    ops.push(IrOp::Push(usize_to_bytes(param)));
+    ops.push(IrOp::CallDataLoad);
+    ops.push(IrOp::Push(vec![0x04]));
+    ops.push(IrOp::Add);
+    ops.push(IrOp::Dup(1));
+    ops.push(IrOp::CallDataLoad);
+    ops.push(IrOp::Push(usize_to_bytes(free)));
+    ops.push(IrOp::MLoad);
+    ops.push(IrOp::Dup(2));
+    ops.push(IrOp::Dup(2));
+    ops.push(IrOp::MStore);
+    ops.push(IrOp::Dup(1));
+    ops.push(IrOp::Push(usize_to_bytes(ptr)));
+    ops.push(IrOp::MStore);
+    ops.push(IrOp::Dup(2));
+    ops.push(IrOp::Dup(4));
+    ops.push(IrOp::Push(vec![0x20]));
+    ops.push(IrOp::Add);
+    ops.push(IrOp::Dup(3));
+    ops.push(IrOp::Push(vec![0x20]));
+    ops.push(IrOp::Add);
+    ops.push(IrOp::CallDataCopy);
+    ops.push(IrOp::Swap(1));
+    ops.push(IrOp::Push(vec![0x1f]));
+    ops.push(IrOp::Add);
+    ops.push(IrOp::Push(vec![0x05]));
+    ops.push(IrOp::Shr);
+    ops.push(IrOp::Push(vec![0x05]));
+    ops.push(IrOp::Shl);
+    ops.push(IrOp::Add);
+    ops.push(IrOp::Push(vec![0x20]));
+    ops.push(IrOp::Add);
+    ops.push(IrOp::Push(usize_to_bytes(free)));
+    ops.push(IrOp::MStore);
+    ops.push(IrOp::Pop);
+}
+
+/// Constructor arguments are appended to the init code rather than sent as
+/// calldata, so they are the last `32 * n` bytes of the running code. They
+/// are copied into consecutive locals, one word per parameter.
+fn lower_constructor_args(ctx: &mut LowerCtx, init: &Function, ops: &mut Vec<IrOp>) {
+    if init.params.is_empty() {
+        return;
+    }
+    let size = 32 * init.params.len();
+    let dest = ctx.next_mem;
+    for p in &init.params {
+        ctx.alloc_local(&p.name);
+        ctx.local_types.insert(p.name.clone(), p.type_.clone());
+    }
+    ops.push(IrOp::Push(usize_to_bytes(size)));
+    ops.push(IrOp::Push(usize_to_bytes(size)));
+    ops.push(IrOp::CodeSize);
+    ops.push(IrOp::Sub);
+    ops.push(IrOp::Push(usize_to_bytes(dest)));
+    ops.push(IrOp::CodeCopy);
+}
+
 fn lower_block(ctx: &mut LowerCtx, block: &Block, ops: &mut Vec<IrOp>) {
     for stmt in &block.statements {
         lower_statement(ctx, stmt, ops);
@@ -174,34 +784,91 @@ fn lower_block(ctx: &mut LowerCtx, block: &Block, ops: &mut Vec<IrOp>) {
 }
 
 fn lower_statement(ctx: &mut LowerCtx, stmt: &Statement, ops: &mut Vec<IrOp>) {
+    let outer = ctx.span.clone();
+    ctx.span = Some(stmt.span().clone());
+    ctx.mark_span(ops);
+    lower_statement_ops(ctx, stmt, ops);
+    ctx.span = outer;
+}
+
+fn lower_statement_ops(ctx: &mut LowerCtx, stmt: &Statement, ops: &mut Vec<IrOp>) {
     match stmt {
-        Statement::Return(Some(e)) => {
-            lower_expression_into(ctx, e, ops);
-            ops.push(IrOp::Push(vec![0x40]));
-            ops.push(IrOp::MStore);
-            ops.push(IrOp::Push(vec![0x20]));
-            ops.push(IrOp::Push(vec![0x40]));
+        Statement::Expression(Expression::Identifier(name), _) if name == "_" && ctx.modified.is_some() => {
+            lower_placeholder(ctx, ops);
+        }
+        Statement::Return(Some(Expression::String(s)), _) if ctx.inline_frames.is_empty() => {
+            lower_string_return(&string_literal_bytes(s), ops);
+        }
+        Statement::Return(Some(Expression::Identifier(name)), _)
+            if ctx.inline_frames.is_empty() && ctx.string_const(name).is_some() =>
+        {
+            let data = ctx.string_const(name).unwrap_or_default();
+            lower_string_return(&data, ops);
+        }
+        Statement::Return(Some(e), _) => {
+            if ctx.returns_dynamic && ctx.inline_frames.is_empty() && lower_dynamic_return(ctx, e, ops) {
+                return;
+            }
+            let values = match e {
+                Expression::Tuple(items) => items.as_slice(),
+                other => std::slice::from_ref(other),
+            };
+            let types = match &ctx.return_type {
+                Some(crate::Type::Tuple(items)) => items.clone(),
+                other => other.iter().cloned().collect(),
+            };
+            for (i, v) in values.iter().enumerate() {
+                lower_expression_into(ctx, &coerce(types.get(i), v), ops);
+            }
+            if let Some(frame) = ctx.inline_frames.last() {
+                for &off in frame.results.iter().rev() {
+                    ops.push(IrOp::Push(usize_to_bytes(off)));
+                    ops.push(IrOp::MStore);
+                }
+                ops.push(IrOp::Jump(frame.end_label));
+                return;
+            }
+            for i in (0..values.len()).rev() {
+                ops.push(IrOp::Push(usize_to_bytes(RETURN_BASE + 32 * i)));
+                ops.push(IrOp::MStore);
+            }
+            ops.push(IrOp::Push(usize_to_bytes(32 * values.len())));
+            ops.push(IrOp::Push(usize_to_bytes(RETURN_BASE)));
             ops.push(IrOp::Return);
         }
-        Statement::Return(None) => {
-            ops.push(IrOp::Stop);
+        Statement::Return(None, span) if ctx.named_return.is_some() => {
+            let value = ctx.named_return.clone();
+            lower_statement_ops(ctx, &Statement::Return(value, span.clone()), ops);
         }
-        Statement::Require(e) => {
+        Statement::Return(None, _) => match ctx.inline_frames.last() {
+            Some(frame) => ops.push(IrOp::Jump(frame.end_label)),
+            None => ops.push(IrOp::Stop),
+        },
+        Statement::Require(r) => {
             let continue_label = ctx.fresh_label();
-            lower_expression_into(ctx, e, ops);
+            lower_expression_into(ctx, &r.condition, ops);
             ops.push(IrOp::JumpI(continue_label));
-            ops.push(IrOp::Push(vec![0x00]));
-            ops.push(IrOp::Push(vec![0x00]));
-            ops.push(IrOp::Revert);
+            match &r.message {
+                Some(message) => lower_revert_reason(&string_literal_bytes(message), ops),
+                None => {
+                    ops.push(IrOp::Push(vec![0x00]));
+                    ops.push(IrOp::Push(vec![0x00]));
+                    ops.push(IrOp::Revert);
+                }
+            }
             ops.push(IrOp::JumpDest(continue_label));
         }
         Statement::Let(l) => {
+            let ty = l.type_.clone().or_else(|| l.value.as_ref().and_then(|v| static_type(ctx, v)));
             let off = ctx.alloc_local(&l.name);
             if let Some(v) = &l.value {
-                lower_expression_into(ctx, v, ops);
+                lower_expression_into(ctx, &coerce(ty.as_ref(), v), ops);
                 ops.push(IrOp::Push(usize_to_bytes(off)));
                 ops.push(IrOp::MStore);
             }
+            if let Some(ty) = ty {
+                ctx.local_types.insert(l.name.clone(), ty);
+            }
         }
         Statement::Assign(a) => {
             lower_assign(ctx, &a.target, &a.value, ops);
@@ -209,8 +876,8 @@ fn lower_statement(ctx: &mut LowerCtx, stmt: &Statement, ops: &mut Vec<IrOp>) {
         Statement::If(if_stmt) => {
             lower_if(ctx, if_stmt, ops);
         }
-        Statement::For(_) => {
-            ops.push(IrOp::Stop);
+        Statement::For(for_stmt) => {
+            lower_for(ctx, for_stmt, ops);
         }
         Statement::While(while_stmt) => {
             lower_while(ctx, while_stmt, ops);
@@ -218,104 +885,900 @@ fn lower_statement(ctx: &mut LowerCtx, stmt: &Statement, ops: &mut Vec<IrOp>) {
         Statement::Emit(em) => {
             lower_emit(ctx, em, ops);
         }
-        Statement::Expression(e) => {
+        Statement::Revert(rv) => {
+            lower_revert_error(ctx, rv, ops);
+        }
+        Statement::Asm(block) => {
+            // Assembly label numbers are local to the block; each lowering
+            // of it, a subroutine copy included, gets labels of its own.
+            let mut labels = HashMap::new();
+            for ins in &block.instructions {
+                let mut op = asm_op(&ins.mnemonic, ins.immediate.as_ref()).unwrap_or(IrOp::Invalid);
+                if let IrOp::Jump(l) | IrOp::JumpI(l) | IrOp::JumpDest(l) = &mut op {
+                    *l = *labels.entry(*l).or_insert_with(|| ctx.fresh_label());
+                }
+                ops.push(op);
+            }
+        }
+        Statement::Expression(e, _) => {
             lower_expression_into(ctx, e, ops);
             ops.push(IrOp::Pop);
         }
+        Statement::Break(_) => match ctx.loops.last() {
+            Some(&(_, end)) => ops.push(IrOp::Jump(end)),
+            None => ops.push(IrOp::Invalid),
+        },
+        Statement::Continue(_) => match ctx.loops.last() {
+            Some(&(next, _)) => ops.push(IrOp::Jump(next)),
+            None => ops.push(IrOp::Invalid),
+        },
+    }
+}
+
+pub fn asm_op(mnemonic: &str, immediate: Option<&num_bigint::BigUint>) -> Option<IrOp> {
+    let numbered = |prefix: &str, max: u8| {
+        mnemonic
+            .strip_prefix(prefix)
+            .and_then(|n| n.parse::<u8>().ok())
+            .filter(|n| (1..=max).contains(n))
+    };
+
+    if let Some(n) = numbered("PUSH", 32) {
+        let value = immediate?;
+        if value.bits() > u64::from(n) * 8 {
+            return None;
+        }
+        let bytes = value.to_bytes_be();
+        let mut data = vec![0u8; usize::from(n)];
+        if value.bits() > 0 {
+            data[usize::from(n) - bytes.len()..].copy_from_slice(&bytes);
+        }
+        return Some(IrOp::Push(data));
+    }
+    if let ("JUMP" | "JUMPI" | "JUMPDEST", Some(label)) = (mnemonic, immediate) {
+        let label = usize::try_from(label).ok()?;
+        return Some(match mnemonic {
+            "JUMP" => IrOp::Jump(label),
+            "JUMPI" => IrOp::JumpI(label),
+            _ => IrOp::JumpDest(label),
+        });
+    }
+    if immediate.is_some() {
+        return None;
+    }
+    if let Some(n) = numbered("DUP", 16) {
+        return Some(IrOp::Dup(n));
+    }
+    if let Some(n) = numbered("SWAP", 16) {
+        return Some(IrOp::Swap(n));
     }
+    if let Some(n) = mnemonic.strip_prefix("LOG").and_then(|n| n.parse::<u8>().ok()) {
+        return (n <= 4).then_some(IrOp::Log(n));
+    }
+
+    let op = match mnemonic {
+        "POP" => IrOp::Pop,
+        "ADD" => IrOp::Add,
+        "MUL" => IrOp::Mul,
+        "SUB" => IrOp::Sub,
+        "DIV" => IrOp::Div,
+        "SDIV" => IrOp::SDiv,
+        "MOD" => IrOp::Mod,
+        "EXP" => IrOp::Exp,
+        "SIGNEXTEND" => IrOp::SignExtend,
+        "LT" => IrOp::Lt,
+        "GT" => IrOp::Gt,
+        "EQ" => IrOp::Eq,
+        "ISZERO" => IrOp::IsZero,
+        "AND" => IrOp::And,
+        "OR" => IrOp::Or,
+        "NOT" => IrOp::Not,
+        "BYTE" => IrOp::Byte,
+        "SHL" => IrOp::Shl,
+        "SHR" => IrOp::Shr,
+        "KECCAK256" => IrOp::Keccak256,
+        "CALLER" => IrOp::Caller,
+        "CALLVALUE" => IrOp::CallValue,
+        "TIMESTAMP" => IrOp::Timestamp,
+        "NUMBER" => IrOp::Number,
+        "CALLDATALOAD" => IrOp::CallDataLoad,
+        "CALLDATASIZE" => IrOp::CallDataSize,
+        "CALLDATACOPY" => IrOp::CallDataCopy,
+        "CODESIZE" => IrOp::CodeSize,
+        "CODECOPY" => IrOp::CodeCopy,
+        "EXTCODESIZE" => IrOp::ExtCodeSize,
+        "RETURNDATASIZE" => IrOp::ReturnDataSize,
+        "RETURNDATACOPY" => IrOp::ReturnDataCopy,
+        "GAS" => IrOp::Gas,
+        "CALL" => IrOp::Call,
+        "STATICCALL" => IrOp::StaticCall,
+        "MLOAD" => IrOp::MLoad,
+        "MSTORE" => IrOp::MStore,
+        "SLOAD" => IrOp::SLoad,
+        "SSTORE" => IrOp::SStore,
+        "TLOAD" => IrOp::TLoad,
+        "TSTORE" => IrOp::TStore,
+        "MCOPY" => IrOp::MCopy,
+        "RETURN" => IrOp::Return,
+        "REVERT" => IrOp::Revert,
+        "STOP" => IrOp::Stop,
+        "INVALID" => IrOp::Invalid,
+        _ => return None,
+    };
+    Some(op)
 }
 
 fn lower_assign(ctx: &mut LowerCtx, target: &Expression, value: &Expression, ops: &mut Vec<IrOp>) {
+    if let Some(plain) = unqualified_self(ctx, target) {
+        return lower_assign(ctx, &plain, value, ops);
+    }
+    if let Expression::Tuple(targets) = target {
+        lower_tuple_assign(ctx, targets, value, ops);
+        return;
+    }
+    if let Expression::StructInit(_, values) = value {
+        if lower_struct_assign(ctx, target, values, ops) {
+            return;
+        }
+    }
+    let ty = static_type(ctx, target);
+    lower_expression_into(ctx, &coerce(ty.as_ref(), value), ops);
+    lower_store(ctx, target, ops);
+}
+
+/// Writes every field of a struct instance or struct-valued mapping entry.
+/// Fields missing from the literal are cleared.
+fn lower_struct_assign(
+    ctx: &mut LowerCtx,
+    target: &Expression,
+    values: &[(String, Expression)],
+    ops: &mut Vec<IrOp>,
+) -> bool {
+    let field_value = |field: &str| {
+        values
+            .iter()
+            .find(|(name, _)| name == field)
+            .map_or(Expression::Number(0u8.into()), |(_, value)| value.clone())
+    };
+    match target {
+        Expression::Identifier(name) if ctx.layout.is_instance(name) && !ctx.locals.contains_key(name) => {
+            let mut fields: Vec<(String, u64)> = ctx
+                .layout
+                .instance_fields(name)
+                .map(|(field, slot)| (field.to_string(), slot.slot))
+                .collect();
+            fields.sort_by_key(|&(_, slot)| slot);
+            for (field, slot) in fields {
+                lower_expression_into(ctx, &field_value(&field), ops);
+                ops.push(IrOp::Push(u64_to_bytes(slot)));
+                ops.push(IrOp::SStore);
+            }
+            true
+        }
+        Expression::Index(inner, key) => {
+            let Some((mapping, depth)) = mapping_entry_root(ctx, target) else {
+                return false;
+            };
+            let Some(fields) = ctx.layout.entry_fields(&mapping, depth).map(<[String]>::to_vec) else {
+                return false;
+            };
+            if !lower_mapping_entry(ctx, inner, key, ops) {
+                return false;
+            }
+            let entry = ctx.alloc_temp();
+            ops.push(IrOp::Push(usize_to_bytes(entry)));
+            ops.push(IrOp::MStore);
+            for (i, field) in fields.iter().enumerate() {
+                lower_expression_into(ctx, &field_value(field), ops);
+                ops.push(IrOp::Push(usize_to_bytes(entry)));
+                ops.push(IrOp::MLoad);
+                if i > 0 {
+                    ops.push(IrOp::Push(usize_to_bytes(i)));
+                    ops.push(IrOp::Add);
+                }
+                ops.push(IrOp::SStore);
+            }
+            true
+        }
+        _ => false,
+    }
+}
+
+fn lower_tuple_assign(
+    ctx: &mut LowerCtx,
+    targets: &[Expression],
+    value: &Expression,
+    ops: &mut Vec<IrOp>,
+) {
+    match value {
+        Expression::Tuple(items) if items.len() == targets.len() => {
+            for item in items {
+                lower_expression_into(ctx, item, ops);
+            }
+            for target in targets.iter().rev() {
+                lower_store(ctx, target, ops);
+            }
+        }
+        Expression::Call(callee, args) => {
+            if let Some((address, method)) = ctx.interface_method(callee) {
+                let buf = lower_external_call_buffer(ctx, &method, &address, args, ops);
+                if return_arity(&method) != targets.len() {
+                    ops.push(IrOp::Invalid);
+                    return;
+                }
+                for i in 0..targets.len() {
+                    buf.push_at(32 * i, ops);
+                    ops.push(IrOp::MLoad);
+                }
+                for target in targets.iter().rev() {
+                    lower_store(ctx, target, ops);
+                }
+                return;
+            }
+            let results = match callee.as_ref() {
+                Expression::Identifier(name) => lower_internal_call(ctx, name, args, ops),
+                _ => None,
+            };
+            match results {
+                Some(results) if results.len() == targets.len() => {
+                    for (target, off) in targets.iter().zip(results) {
+                        ops.push(IrOp::Push(usize_to_bytes(off)));
+                        ops.push(IrOp::MLoad);
+                        lower_store(ctx, target, ops);
+                    }
+                }
+                _ => ops.push(IrOp::Invalid),
+            }
+        }
+        _ => ops.push(IrOp::Invalid),
+    }
+}
+
+fn lower_store(ctx: &mut LowerCtx, target: &Expression, ops: &mut Vec<IrOp>) {
     match target {
         Expression::Identifier(name) => {
-            lower_expression_into(ctx, value, ops);
             if let Some(&off) = ctx.locals.get(name) {
                 ops.push(IrOp::Push(usize_to_bytes(off)));
                 ops.push(IrOp::MStore);
             } else if let Some(slot) = ctx.layout.get(name) {
                 ops.push(IrOp::Push(u64_to_bytes(slot.slot)));
                 ops.push(IrOp::SStore);
+            } else {
+                ops.push(IrOp::Pop);
             }
         }
-        Expression::Index(base, key) => {
-            if let Expression::Identifier(name) = base.as_ref() {
-                if let Some(slot) = ctx.layout.get(name) {
-                    let slot_num = slot.slot;
-                    lower_expression_into(ctx, value, ops);
-                    lower_mapping_key(ctx, key, slot_num, ops);
-                    ops.push(IrOp::SStore);
-                }
+        Expression::Index(base, key) => match array_ref(ctx, base) {
+            Some(array @ ArrayRef::Storage { .. }) => {
+                lower_array_element(ctx, &array, key, ops);
+                ops.push(IrOp::SStore);
             }
+            Some(ArrayRef::Calldata { .. }) => ops.push(IrOp::Pop),
+            None if lower_mapping_entry(ctx, base, key, ops) => ops.push(IrOp::SStore),
+            None => ops.push(IrOp::Pop),
+        },
+        Expression::Member(base, field) if lower_entry_field(ctx, base, field, ops) => {
+            ops.push(IrOp::SStore);
         }
-        _ => {}
+        Expression::Member(base, field) => match field_slot(ctx, base, field) {
+            Some(slot) => {
+                ops.push(IrOp::Push(u64_to_bytes(slot.slot)));
+                ops.push(IrOp::SStore);
+            }
+            None => ops.push(IrOp::Pop),
+        },
+        _ => ops.push(IrOp::Pop),
     }
 }
 
-fn lower_mapping_key(ctx: &mut LowerCtx, key: &Expression, slot: u64, ops: &mut Vec<IrOp>) {
-    lower_expression_into(ctx, key, ops);
-    ops.push(IrOp::Push(vec![0x00]));
-    ops.push(IrOp::MStore);
-    ops.push(IrOp::Push(u64_to_bytes(slot)));
-    ops.push(IrOp::Push(vec![0x20]));
-    ops.push(IrOp::MStore);
-    ops.push(IrOp::Push(vec![0x40]));
-    ops.push(IrOp::Push(vec![0x00]));
-    ops.push(IrOp::Keccak256);
+/// Rewrites `self.name` at the root of an index, member or call chain to the
+/// bare storage name, so every storage path handles both spellings. A name
+/// shadowed by a param or local keeps its `self.` form.
+fn unqualified_self(ctx: &LowerCtx, expr: &Expression) -> Option<Expression> {
+    match expr {
+        Expression::Member(base, field) => match base.as_ref() {
+            Expression::Identifier(name) if name == "self" => (!ctx.params.contains_key(field)
+                && !ctx.locals.contains_key(field))
+            .then(|| Expression::Identifier(field.clone())),
+            _ => Some(Expression::Member(Box::new(unqualified_self(ctx, base)?), field.clone())),
+        },
+        Expression::Index(base, key) => {
+            Some(Expression::Index(Box::new(unqualified_self(ctx, base)?), key.clone()))
+        }
+        Expression::Call(callee, args) => {
+            Some(Expression::Call(Box::new(unqualified_self(ctx, callee)?), args.clone()))
+        }
+        Expression::Binary(BinaryOp::In, key, container) => Some(Expression::Binary(
+            BinaryOp::In,
+            key.clone(),
+            Box::new(unqualified_self(ctx, container)?),
+        )),
+        Expression::Tuple(items) => {
+            let plain: Vec<Option<Expression>> = items.iter().map(|e| unqualified_self(ctx, e)).collect();
+            plain.iter().any(Option::is_some).then(|| {
+                Expression::Tuple(plain.into_iter().zip(items).map(|(p, e)| p.unwrap_or_else(|| e.clone())).collect())
+            })
+        }
+        _ => None,
+    }
 }
 
-fn lower_if(ctx: &mut LowerCtx, if_stmt: &crate::IfStatement, ops: &mut Vec<IrOp>) {
-    let else_label = ctx.fresh_label();
-    let end_label = ctx.fresh_label();
-
-    lower_expression_into(ctx, &if_stmt.condition, ops);
-    ops.push(IrOp::IsZero);
-    ops.push(IrOp::JumpI(else_label));
+fn field_slot<'a>(ctx: &'a LowerCtx, base: &Expression, field: &str) -> Option<&'a StorageSlot> {
+    match base {
+        Expression::Identifier(name) if name == "self" => ctx.layout.get(field),
+        Expression::Identifier(name)
+            if !ctx.params.contains_key(name) && !ctx.locals.contains_key(name) =>
+        {
+            ctx.layout.field(name, field)
+        }
+        _ => None,
+    }
+}
 
-    lower_block(ctx, &if_stmt.then_branch, ops);
-    ops.push(IrOp::Jump(end_label));
+/// Storage name of the mapping under a chain of indexes and the number of
+/// keys applied: `users[a]` is `("users", 1)` and `bank.users[a][b]` is
+/// `("bank.users", 2)`.
+fn mapping_entry_root(ctx: &LowerCtx, expr: &Expression) -> Option<(String, usize)> {
+    let mut depth = 0;
+    let mut root = expr;
+    while let Expression::Index(inner, _) = root {
+        depth += 1;
+        root = inner;
+    }
+    let name = match root {
+        Expression::Identifier(name) if !ctx.params.contains_key(name) && !ctx.locals.contains_key(name) => {
+            name.clone()
+        }
+        Expression::Member(inner, field) => match inner.as_ref() {
+            Expression::Identifier(name) if name == "self" => field.clone(),
+            Expression::Identifier(name) if field_slot(ctx, inner, field).is_some() => format!("{name}.{field}"),
+            _ => return None,
+        },
+        _ => return None,
+    };
+    (depth > 0).then_some((name, depth))
+}
 
-    ops.push(IrOp::JumpDest(else_label));
-    if let Some(eb) = &if_stmt.else_branch {
-        lower_block(ctx, eb, ops);
+/// Pushes the slot of `field` in the struct stored at the mapping entry
+/// `base`. Emits nothing and returns false when `base` is not such an entry.
+fn lower_entry_field(ctx: &mut LowerCtx, base: &Expression, field: &str, ops: &mut Vec<IrOp>) -> bool {
+    let Expression::Index(inner, key) = base else {
+        return false;
+    };
+    let Some(offset) = mapping_entry_root(ctx, base)
+        .and_then(|(mapping, depth)| ctx.layout.entry_field(&mapping, depth, field))
+    else {
+        return false;
+    };
+    if !lower_mapping_entry(ctx, inner, key, ops) {
+        return false;
     }
+    if offset > 0 {
+        ops.push(IrOp::Push(u64_to_bytes(offset)));
+        ops.push(IrOp::Add);
+    }
+    true
+}
 
-    ops.push(IrOp::JumpDest(end_label));
+/// Where an array's elements live. `len` is set for fixed-size arrays.
+enum ArrayRef {
+    Storage { slot: u64, len: Option<u64> },
+    Calldata { offset: usize, len: Option<u64> },
 }
 
-fn lower_while(ctx: &mut LowerCtx, while_stmt: &crate::WhileStatement, ops: &mut Vec<IrOp>) {
-    let loop_label = ctx.fresh_label();
-    let end_label = ctx.fresh_label();
+fn array_ref(ctx: &LowerCtx, base: &Expression) -> Option<ArrayRef> {
+    if let Expression::Identifier(name) = base {
+        if let Some(&offset) = ctx.params.get(name) {
+            return match ctx.param_types.get(name)? {
+                crate::Type::Vec(_) => Some(ArrayRef::Calldata { offset, len: None }),
+                crate::Type::Array(_, len) => Some(ArrayRef::Calldata { offset, len: Some(*len as u64) }),
+                _ => None,
+            };
+        }
+        if ctx.locals.contains_key(name) {
+            return None;
+        }
+    }
+    let slot = match base {
+        Expression::Identifier(name) => ctx.layout.get(name)?,
+        Expression::Member(inner, field) => field_slot(ctx, inner, field)?,
+        _ => return None,
+    };
+    match slot.kind {
+        StorageKind::Array => Some(ArrayRef::Storage { slot: slot.slot, len: None }),
+        StorageKind::FixedArray(len) => Some(ArrayRef::Storage { slot: slot.slot, len: Some(len) }),
+        _ => None,
+    }
+}
 
-    ops.push(IrOp::JumpDest(loop_label));
-    lower_expression_into(ctx, &while_stmt.condition, ops);
-    ops.push(IrOp::IsZero);
-    ops.push(IrOp::JumpI(end_label));
+fn lower_array_length(array: &ArrayRef, ops: &mut Vec<IrOp>) {
+    match *array {
+        ArrayRef::Storage { len: Some(len), .. } | ArrayRef::Calldata { len: Some(len), .. } => {
+            ops.push(IrOp::Push(u64_to_bytes(len)));
+        }
+        ArrayRef::Storage { slot, len: None } => {
+            ops.push(IrOp::Push(u64_to_bytes(slot)));
+            ops.push(IrOp::SLoad);
+        }
+        ArrayRef::Calldata { offset, len: None } => {
+            lower_calldata_array_start(offset, ops);
+            ops.push(IrOp::CallDataLoad);
+        }
+    }
+}
 
-    lower_block(ctx, &while_stmt.body, ops);
-    ops.push(IrOp::Jump(loop_label));
+/// Pushes the calldata position of a dynamic array param's length word.
+/// The param's head holds that position relative to the arguments.
+fn lower_calldata_array_start(offset: usize, ops: &mut Vec<IrOp>) {
+    ops.push(IrOp::Push(usize_to_bytes(offset)));
+    ops.push(IrOp::CallDataLoad);
+    ops.push(IrOp::Push(vec![0x04]));
+    ops.push(IrOp::Add);
+}
 
+/// Pushes the storage slot or calldata offset of `array[index]`, reverting
+/// when the index is not below the array's length.
+fn lower_array_element(ctx: &mut LowerCtx, array: &ArrayRef, index: &Expression, ops: &mut Vec<IrOp>) {
+    lower_expression_into(ctx, index, ops);
+    let ok_label = ctx.fresh_label();
+    ops.push(IrOp::Dup(1));
+    lower_array_length(array, ops);
+    ops.push(IrOp::Gt);
+    ops.push(IrOp::JumpI(ok_label));
+    ops.push(IrOp::Push(vec![0]));
+    ops.push(IrOp::Push(vec![0]));
+    ops.push(IrOp::Revert);
+    ops.push(IrOp::JumpDest(ok_label));
+
+    match *array {
+        ArrayRef::Storage { slot, len: Some(_) } => {
+            ops.push(IrOp::Push(u64_to_bytes(slot)));
+            ops.push(IrOp::Add);
+        }
+        ArrayRef::Storage { slot, len: None } => {
+            lower_array_data_slot(slot, ops);
+            ops.push(IrOp::Add);
+        }
+        ArrayRef::Calldata { offset, len } => {
+            ops.push(IrOp::Push(vec![0x20]));
+            ops.push(IrOp::Mul);
+            match len {
+                Some(_) => ops.push(IrOp::Push(usize_to_bytes(offset))),
+                None => {
+                    lower_calldata_array_start(offset, ops);
+                    ops.push(IrOp::Push(vec![0x20]));
+                    ops.push(IrOp::Add);
+                }
+            }
+            ops.push(IrOp::Add);
+        }
+    }
+}
+
+/// Pushes `keccak(slot)`, where a dynamic storage array's elements start.
+fn lower_array_data_slot(slot: u64, ops: &mut Vec<IrOp>) {
+    ops.push(IrOp::Push(u64_to_bytes(slot)));
+    ops.push(IrOp::Push(usize_to_bytes(HASH_SCRATCH)));
+    ops.push(IrOp::MStore);
+    ops.push(IrOp::Push(vec![0x20]));
+    ops.push(IrOp::Push(usize_to_bytes(HASH_SCRATCH)));
+    ops.push(IrOp::Keccak256);
+}
+
+/// Appends `value` to the dynamic storage array at `slot` and bumps its
+/// length. Leaves a zero, like other calls without a result.
+fn lower_array_push(ctx: &mut LowerCtx, slot: u64, value: &Expression, ops: &mut Vec<IrOp>) {
+    lower_expression_into(ctx, value, ops);
+    ops.push(IrOp::Push(u64_to_bytes(slot)));
+    ops.push(IrOp::SLoad);
+    ops.push(IrOp::Dup(1));
+    ops.push(IrOp::Push(vec![0x01]));
+    ops.push(IrOp::Add);
+    ops.push(IrOp::Push(u64_to_bytes(slot)));
+    ops.push(IrOp::SStore);
+    lower_array_data_slot(slot, ops);
+    ops.push(IrOp::Add);
+    ops.push(IrOp::SStore);
+    ops.push(IrOp::Push(vec![0]));
+}
+
+fn mapping_slot(ctx: &LowerCtx, base: &Expression) -> Option<u64> {
+    match base {
+        Expression::Identifier(name) => ctx.layout.get(name).map(|s| s.slot),
+        Expression::Member(inner, field) => field_slot(ctx, inner, field).map(|s| s.slot),
+        _ => None,
+    }
+}
+
+/// Calls an internal function: the arguments go to the callee's parameter
+/// words, the return address is pushed and control jumps to the callee's
+/// subroutine, which jumps back once it has written its results.
+fn lower_internal_call(
+    ctx: &mut LowerCtx,
+    name: &str,
+    args: &[Expression],
+    ops: &mut Vec<IrOp>,
+) -> Option<Vec<usize>> {
+    let callee = ctx.functions.get(name)?.clone();
+    if ctx.inline_frames.iter().any(|f| f.name == name) {
+        ops.push(IrOp::Invalid);
+        return Some(Vec::new());
+    }
+
+    for (i, arg) in args.iter().enumerate() {
+        let ty = callee.params.get(i).map(|p| &p.type_);
+        lower_expression_into(ctx, &coerce(ty, arg), ops);
+    }
+    let sub = match ctx.subroutines.get(name) {
+        Some(sub) => sub.clone(),
+        None => lower_subroutine(ctx, &callee),
+    };
+    for &off in sub.params.iter().rev() {
+        ops.push(IrOp::Push(usize_to_bytes(off)));
+        ops.push(IrOp::MStore);
+    }
+    let back = ctx.fresh_label();
+    ops.push(IrOp::PushLabel(back));
+    ops.push(IrOp::Jump(sub.entry));
+    ops.push(IrOp::JumpDest(back));
+    Some(sub.results)
+}
+
+/// Lowers `callee` once for the function being lowered. Its frame is static:
+/// recursion is rejected, so no call can find its own words in use.
+fn lower_subroutine(ctx: &mut LowerCtx, callee: &Function) -> Subroutine {
+    let arity = match &callee.return_type {
+        Some(crate::Type::Tuple(items)) => items.len(),
+        Some(_) => 1,
+        None => 0,
+    };
+    let return_address = ctx.alloc_temp();
+    let sub = Subroutine {
+        entry: ctx.fresh_label(),
+        params: callee.params.iter().map(|_| ctx.alloc_temp()).collect(),
+        results: (0..arity).map(|_| ctx.alloc_temp()).collect(),
+    };
+    let exit = ctx.fresh_label();
+
+    let saved_params = std::mem::take(&mut ctx.params);
+    let saved_locals = std::mem::take(&mut ctx.locals);
+    let saved_dynamic_params = std::mem::take(&mut ctx.dynamic_params);
+    let saved_local_types = std::mem::take(&mut ctx.local_types);
+    let saved_return_type = ctx.return_type.take();
+    let saved_named_return = ctx.named_return.take();
+    let saved_cse = std::mem::take(&mut ctx.cse);
+    let saved_loops = std::mem::take(&mut ctx.loops);
+    let saved_span = ctx.span.replace(callee.span.clone());
+    let saved_marked = ctx.marked.take();
+    let saved_file = ctx.enter_file(&callee.name);
+    for (p, &off) in callee.params.iter().zip(&sub.params) {
+        ctx.locals.insert(p.name.clone(), off);
+        ctx.local_types.insert(p.name.clone(), p.type_.clone());
+    }
+    ctx.inline_frames.push(InlineFrame {
+        name: callee.name.clone(),
+        results: sub.results.clone(),
+        end_label: exit,
+    });
+
+    let mut body = vec![IrOp::JumpDest(sub.entry)];
+    ctx.mark_span(&mut body);
+    body.push(IrOp::Push(usize_to_bytes(return_address)));
+    body.push(IrOp::MStore);
+    lower_function_body(ctx, callee, &mut body);
+    body.push(IrOp::JumpDest(exit));
+    body.push(IrOp::Push(usize_to_bytes(return_address)));
+    body.push(IrOp::MLoad);
+    body.push(IrOp::JumpBack);
+
+    ctx.inline_frames.pop();
+    ctx.params = saved_params;
+    ctx.locals = saved_locals;
+    ctx.dynamic_params = saved_dynamic_params;
+    ctx.local_types = saved_local_types;
+    ctx.return_type = saved_return_type;
+    ctx.named_return = saved_named_return;
+    ctx.cse = saved_cse;
+    ctx.loops = saved_loops;
+    ctx.span = saved_span;
+    ctx.marked = saved_marked;
+    ctx.file = saved_file;
+
+    ctx.subroutine_ops.extend(body);
+    ctx.subroutines.insert(callee.name.clone(), sub.clone());
+    sub
+}
+
+/// Takes the subroutines lowered since the last call, to be placed after
+/// code that ends in a halt or a jump.
+fn take_subroutines(ctx: &mut LowerCtx) -> Vec<IrOp> {
+    ctx.subroutines.clear();
+    std::mem::take(&mut ctx.subroutine_ops)
+}
+
+/// Appends the constructor's subroutines behind a jump over them, since
+/// constructor code runs on past this point.
+fn append_constructor_subroutines(ctx: &mut LowerCtx, ops: &mut Vec<IrOp>) {
+    let subroutines = take_subroutines(ctx);
+    if subroutines.is_empty() {
+        return;
+    }
+    let skip = ctx.fresh_label();
+    ops.push(IrOp::Jump(skip));
+    ops.extend(subroutines);
+    ops.push(IrOp::JumpDest(skip));
+}
+
+/// Pushes the storage slot of `base[key]` when `base` is a mapping. For a
+/// nested mapping the slot of each level is hashed with the next key, so
+/// `m[a][b]` lives at `keccak(b . keccak(a . slot))`. Emits nothing and
+/// returns false when `base` is not a mapping.
+fn lower_mapping_entry(
+    ctx: &mut LowerCtx,
+    base: &Expression,
+    key: &Expression,
+    ops: &mut Vec<IrOp>,
+) -> bool {
+    if let Some(slot) = mapping_slot(ctx, base) {
+        lower_mapping_key(ctx, key, slot, ops);
+        return true;
+    }
+    let Expression::Index(outer, outer_key) = base else {
+        return false;
+    };
+    if !lower_mapping_entry(ctx, outer, outer_key, ops) {
+        return false;
+    }
+    lower_expression_into(ctx, key, ops);
+    ops.push(IrOp::Push(usize_to_bytes(HASH_SCRATCH)));
+    ops.push(IrOp::MStore);
+    ops.push(IrOp::Push(usize_to_bytes(HASH_SCRATCH + 0x20)));
+    ops.push(IrOp::MStore);
+    ops.push(IrOp::Push(vec![0x40]));
+    ops.push(IrOp::Push(usize_to_bytes(HASH_SCRATCH)));
+    ops.push(IrOp::Keccak256);
+    true
+}
+
+fn lower_mapping_key(ctx: &mut LowerCtx, key: &Expression, slot: u64, ops: &mut Vec<IrOp>) {
+    lower_expression_into(ctx, key, ops);
+    ops.push(IrOp::Push(usize_to_bytes(HASH_SCRATCH)));
+    ops.push(IrOp::MStore);
+    ops.push(IrOp::Push(u64_to_bytes(slot)));
+    ops.push(IrOp::Push(usize_to_bytes(HASH_SCRATCH + 0x20)));
+    ops.push(IrOp::MStore);
+    ops.push(IrOp::Push(vec![0x40]));
+    ops.push(IrOp::Push(usize_to_bytes(HASH_SCRATCH)));
+    ops.push(IrOp::Keccak256);
+}
+
+fn lower_if(ctx: &mut LowerCtx, if_stmt: &crate::IfStatement, ops: &mut Vec<IrOp>) {
+    match constant_condition(&if_stmt.condition).filter(|_| ctx.opt.folds()) {
+        Some(true) => return lower_block(ctx, &if_stmt.then_branch, ops),
+        Some(false) => {
+            if let Some(eb) = &if_stmt.else_branch {
+                lower_block(ctx, eb, ops);
+            }
+            return;
+        }
+        None => {}
+    }
+
+    let else_label = ctx.fresh_label();
+    let end_label = ctx.fresh_label();
+
+    lower_expression_into(ctx, &if_stmt.condition, ops);
+    ops.push(IrOp::IsZero);
+    ops.push(IrOp::JumpI(else_label));
+
+    lower_block(ctx, &if_stmt.then_branch, ops);
+    ctx.mark_span(ops);
+    ops.push(IrOp::Jump(end_label));
+
+    ops.push(IrOp::JumpDest(else_label));
+    if let Some(eb) = &if_stmt.else_branch {
+        lower_block(ctx, eb, ops);
+        ctx.mark_span(ops);
+    }
+
+    ops.push(IrOp::JumpDest(end_label));
+}
+
+fn lower_while(ctx: &mut LowerCtx, while_stmt: &crate::WhileStatement, ops: &mut Vec<IrOp>) {
+    let loop_label = ctx.fresh_label();
+    let end_label = ctx.fresh_label();
+
+    ops.push(IrOp::JumpDest(loop_label));
+    lower_expression_into(ctx, &while_stmt.condition, ops);
+    ops.push(IrOp::IsZero);
+    ops.push(IrOp::JumpI(end_label));
+
+    ctx.loops.push((loop_label, end_label));
+    lower_block(ctx, &while_stmt.body, ops);
+    ctx.loops.pop();
+    ctx.mark_span(ops);
+    ops.push(IrOp::Jump(loop_label));
+
+    ops.push(IrOp::JumpDest(end_label));
+}
+
+fn lower_for(ctx: &mut LowerCtx, for_stmt: &crate::ForStatement, ops: &mut Vec<IrOp>) {
+    let Some(args) = for_stmt.range_args() else {
+        ops.push(IrOp::Invalid);
+        return;
+    };
+    let zero = Expression::Number(0u8.into());
+    let (start, stop, step) = match args {
+        [stop] => (&zero, stop, None),
+        [start, stop] => (start, stop, None),
+        [start, stop, step] => (start, stop, Some(step)),
+        _ => {
+            ops.push(IrOp::Invalid);
+            return;
+        }
+    };
+    let (descending, step_size) = match step.map(signed_constant) {
+        None => (false, num_bigint::BigUint::from(1u8)),
+        Some(Some(s)) => s,
+        Some(None) => {
+            ops.push(IrOp::Invalid);
+            return;
+        }
+    };
+    let step_bytes = biguint_to_push_bytes(&step_size);
+
+    let loop_label = ctx.fresh_label();
+    let next_label = ctx.fresh_label();
+    let end_label = ctx.fresh_label();
+
+    let stop_off = ctx.alloc_temp();
+    lower_expression_into(ctx, stop, ops);
+    ops.push(IrOp::Push(usize_to_bytes(stop_off)));
+    ops.push(IrOp::MStore);
+
+    lower_expression_into(ctx, start, ops);
+    let shadowed = ctx.locals.get(&for_stmt.var).copied();
+    let var_off = ctx.alloc_local(&for_stmt.var);
+    ops.push(IrOp::Push(usize_to_bytes(var_off)));
+    ops.push(IrOp::MStore);
+
+    ops.push(IrOp::JumpDest(loop_label));
+    ops.push(IrOp::Push(usize_to_bytes(var_off)));
+    ops.push(IrOp::MLoad);
+    ops.push(IrOp::Push(usize_to_bytes(stop_off)));
+    ops.push(IrOp::MLoad);
+    ops.push(IrOp::Swap(1));
+    ops.push(if descending { IrOp::Gt } else { IrOp::Lt });
+    ops.push(IrOp::IsZero);
+    ops.push(IrOp::JumpI(end_label));
+
+    ctx.loops.push((next_label, end_label));
+    lower_block(ctx, &for_stmt.body, ops);
+    ctx.loops.pop();
+    ctx.mark_span(ops);
+
+    ops.push(IrOp::JumpDest(next_label));
+    if descending {
+        ops.push(IrOp::Push(usize_to_bytes(var_off)));
+        ops.push(IrOp::MLoad);
+        ops.push(IrOp::Push(usize_to_bytes(stop_off)));
+        ops.push(IrOp::MLoad);
+        ops.push(IrOp::Push(step_bytes.clone()));
+        ops.push(IrOp::Add);
+        ops.push(IrOp::Swap(1));
+        ops.push(IrOp::Gt);
+        ops.push(IrOp::IsZero);
+        ops.push(IrOp::JumpI(end_label));
+        ops.push(IrOp::Push(usize_to_bytes(var_off)));
+        ops.push(IrOp::MLoad);
+        ops.push(IrOp::Push(step_bytes));
+        ops.push(IrOp::Swap(1));
+        ops.push(IrOp::Sub);
+    } else {
+        ops.push(IrOp::Push(usize_to_bytes(var_off)));
+        ops.push(IrOp::MLoad);
+        ops.push(IrOp::Push(step_bytes));
+        ops.push(IrOp::Add);
+    }
+    ops.push(IrOp::Push(usize_to_bytes(var_off)));
+    ops.push(IrOp::MStore);
+    ops.push(IrOp::Jump(loop_label));
     ops.push(IrOp::JumpDest(end_label));
+
+    match shadowed {
+        Some(off) => ctx.locals.insert(for_stmt.var.clone(), off),
+        None => ctx.locals.remove(&for_stmt.var),
+    };
 }
 
 fn lower_emit(ctx: &mut LowerCtx, em: &crate::EmitStatement, ops: &mut Vec<IrOp>) {
+    let fields = ctx.events.get(&em.name).cloned().unwrap_or_default();
+    let is_indexed = |i: usize| fields.get(i).is_some_and(|f| f.indexed);
+
+    // The data words are reserved up front so temporaries used by the
+    // arguments cannot land on them.
     let mem_start = ctx.next_mem;
+    ctx.next_mem += 32 * (0..em.args.len()).filter(|&i| !is_indexed(i)).count();
+
+    // Arguments run in source order: data words are stored as they come and
+    // topics stay on the stack, the last one on top.
+    let mut data_size = 0;
+    let mut topics = 0;
     for (i, arg) in em.args.iter().enumerate() {
+        let arg = &coerce(fields.get(i).map(|f| &f.type_), arg);
+        if is_indexed(i) {
+            lower_topic(ctx, arg, ops);
+            topics += 1;
+            continue;
+        }
         lower_expression_into(ctx, arg, ops);
-        ops.push(IrOp::Push(u64_to_bytes((mem_start + i * 32) as u64)));
+        ops.push(IrOp::Push(u64_to_bytes((mem_start + data_size) as u64)));
         ops.push(IrOp::MStore);
+        data_size += 32;
     }
-    let data_size = em.args.len() * 32;
-    let sig = build_event_signature(&em.name, ctx.events.get(&em.name));
+
+    // LOGn wants the first topic just under topic0; with at most three,
+    // one swap of the outer two reverses them.
+    if topics > 1 {
+        ops.push(IrOp::Swap(topics - 1));
+    }
+    let types: Vec<crate::Type> = fields.iter().map(|f| f.type_.clone()).collect();
+    let sig = build_event_signature(&em.name, Some(&types));
     let topic = keccak256_bytes(sig.as_bytes());
     ops.push(IrOp::Push(topic.to_vec()));
     ops.push(IrOp::Push(u64_to_bytes(data_size as u64)));
     ops.push(IrOp::Push(u64_to_bytes(mem_start as u64)));
-    ops.push(IrOp::Log(1));
+    ops.push(IrOp::Log(1 + topics));
+}
+
+/// Pushes the topic of an indexed field. A `string` or `bytes` value is
+/// stored as the keccak256 of its contents, as the ABI specifies.
+fn lower_topic(ctx: &mut LowerCtx, arg: &Expression, ops: &mut Vec<IrOp>) {
+    match arg {
+        Expression::String(s) => ops.push(IrOp::Push(keccak256_bytes(&string_literal_bytes(s)).to_vec())),
+        Expression::Bytes(b) => ops.push(IrOp::Push(keccak256_bytes(b).to_vec())),
+        _ => match dynamic_param_ptr(ctx, arg) {
+            Some(ptr) => {
+                ops.push(IrOp::Push(usize_to_bytes(ptr)));
+                ops.push(IrOp::MLoad);
+                ops.push(IrOp::Dup(1));
+                ops.push(IrOp::MLoad);
+                ops.push(IrOp::Swap(1));
+                ops.push(IrOp::Push(vec![0x20]));
+                ops.push(IrOp::Add);
+                ops.push(IrOp::Keccak256);
+            }
+            None => lower_expression_into(ctx, arg, ops),
+        },
+    }
+}
+
+/// Reverts with `selector . abi.encode(args)`. The arguments are all on the
+/// stack before the selector is written, so none of them can clobber it.
+fn lower_revert_error(ctx: &mut LowerCtx, rv: &crate::RevertStatement, ops: &mut Vec<IrOp>) {
+    let params = ctx.errors.get(&rv.name).map(|def| def.params.clone()).unwrap_or_default();
+    for (i, arg) in rv.args.iter().enumerate() {
+        lower_expression_into(ctx, &coerce(params.get(i).map(|p| &p.type_), arg), ops);
+    }
+    let mut selector = match ctx.errors.get(&rv.name) {
+        Some(def) => compute_error_selector(def).to_vec(),
+        None => vec![0; 4],
+    };
+    selector.resize(32, 0);
+    ops.push(IrOp::Push(selector));
+    ops.push(IrOp::Push(vec![0x00]));
+    ops.push(IrOp::MStore);
+    for i in (0..rv.args.len()).rev() {
+        ops.push(IrOp::Push(usize_to_bytes(4 + 32 * i)));
+        ops.push(IrOp::MStore);
+    }
+    ops.push(IrOp::Push(usize_to_bytes(4 + 32 * rv.args.len())));
+    ops.push(IrOp::Push(vec![0x00]));
+    ops.push(IrOp::Revert);
 }
 
 fn build_event_signature(name: &str, types: Option<&Vec<crate::Type>>) -> String {
     let params = match types {
-        Some(ts) => ts.iter().map(|t| type_to_abi_string(t)).collect::<Vec<_>>().join(","),
+        Some(ts) => ts.iter().map(type_to_abi_string).collect::<Vec<_>>().join(","),
         None => String::new(),
     };
     format!("{name}({params})")
@@ -329,7 +1792,55 @@ fn keccak256_bytes(data: &[u8]) -> [u8; 32] {
     out
 }
 
+/// Lowers `expr` and folds its constant subexpressions. Folding is per
+/// expression rather than per function so inline `asm` is never rewritten.
 fn lower_expression_into(ctx: &mut LowerCtx, expr: &Expression, ops: &mut Vec<IrOp>) {
+    let start = ops.len();
+    lower_expression_unfolded(ctx, expr, ops);
+    if !ctx.opt.folds() {
+        return;
+    }
+    let folded = fold_ir(&ops[start..]);
+    ops.truncate(start);
+    ops.extend(folded);
+}
+
+fn lower_expression_unfolded(ctx: &mut LowerCtx, expr: &Expression, ops: &mut Vec<IrOp>) {
+    if let Some(simpler) = simplify_identity(expr).filter(|_| ctx.opt.folds()) {
+        return lower_expression_into(ctx, &simpler, ops);
+    }
+    if let Some(plain) = unqualified_self(ctx, expr) {
+        return lower_expression_into(ctx, &plain, ops);
+    }
+    if ctx.cse.is_empty() && ctx.opt.caches() {
+        let repeated = repeated_subexpressions(expr);
+        if !repeated.is_empty() {
+            ctx.cse = repeated.into_iter().map(|e| (e, None)).collect();
+            lower_expression_uncached(ctx, expr, ops);
+            ctx.cse.clear();
+            return;
+        }
+    }
+    match ctx.cse.iter().position(|(e, _)| e == expr) {
+        Some(i) => match ctx.cse[i].1 {
+            Some(off) => {
+                ops.push(IrOp::Push(usize_to_bytes(off)));
+                ops.push(IrOp::MLoad);
+            }
+            None => {
+                lower_expression_uncached(ctx, expr, ops);
+                let off = ctx.alloc_temp();
+                ops.push(IrOp::Dup(1));
+                ops.push(IrOp::Push(usize_to_bytes(off)));
+                ops.push(IrOp::MStore);
+                ctx.cse[i].1 = Some(off);
+            }
+        },
+        None => lower_expression_uncached(ctx, expr, ops),
+    }
+}
+
+fn lower_expression_uncached(ctx: &mut LowerCtx, expr: &Expression, ops: &mut Vec<IrOp>) {
     match expr {
         Expression::Number(n) => {
             ops.push(IrOp::Push(biguint_to_push_bytes(n)));
@@ -337,6 +1848,10 @@ fn lower_expression_into(ctx: &mut LowerCtx, expr: &Expression, ops: &mut Vec<Ir
         Expression::HexNumber(n) => {
             ops.push(IrOp::Push(biguint_to_push_bytes(n)));
         }
+        Expression::Address(digits) => {
+            let n = num_bigint::BigUint::parse_bytes(digits.as_bytes(), 16).unwrap_or_default();
+            ops.push(IrOp::Push(biguint_to_push_bytes(&n)));
+        }
         Expression::Bool(b) => {
             ops.push(IrOp::Push(vec![u8::from(*b)]));
         }
@@ -351,9 +1866,16 @@ fn lower_expression_into(ctx: &mut LowerCtx, expr: &Expression, ops: &mut Vec<Ir
             }
         }
         Expression::Identifier(name) => {
-            if let Some(&off) = ctx.params.get(name) {
+            if let Some(&ptr) = ctx.dynamic_params.get(name) {
+                ops.push(IrOp::Push(usize_to_bytes(ptr)));
+                ops.push(IrOp::MLoad);
+            } else if let Some(&off) = ctx.params.get(name) {
                 ops.push(IrOp::Push(usize_to_bytes(off)));
                 ops.push(IrOp::CallDataLoad);
+                if let Some(ty) = ctx.param_types.get(name) {
+                    lower_cleanup(ty, ops);
+                }
+                lower_enum_check(ctx, name, ops);
             } else if let Some(&off) = ctx.locals.get(name) {
                 ops.push(IrOp::Push(usize_to_bytes(off)));
                 ops.push(IrOp::MLoad);
@@ -365,27 +1887,111 @@ fn lower_expression_into(ctx: &mut LowerCtx, expr: &Expression, ops: &mut Vec<Ir
             }
         }
         Expression::Member(base, field) => {
-            if let Expression::Identifier(name) = base.as_ref() {
+            if let Some(array) = array_ref(ctx, base).filter(|_| field == "length") {
+                lower_array_length(&array, ops);
+            } else if lower_entry_field(ctx, base, field, ops) {
+                ops.push(IrOp::SLoad);
+            } else if let Some(slot) = field_slot(ctx, base, field) {
+                if slot.kind == StorageKind::Value {
+                    ops.push(IrOp::Push(u64_to_bytes(slot.slot)));
+                    ops.push(IrOp::SLoad);
+                }
+            } else if let Expression::Identifier(name) = base.as_ref() {
+                let variant = ctx.enums.get(name).and_then(|e| e.variant(field));
                 match (name.as_str(), field.as_str()) {
                     ("msg", "sender") => ops.push(IrOp::Caller),
                     ("msg", "value") => ops.push(IrOp::CallValue),
-                    _ => ops.push(IrOp::Push(vec![0])),
+                    ("block", "timestamp") => ops.push(IrOp::Timestamp),
+                    ("block", "number") => ops.push(IrOp::Number),
+                    _ => ops.push(IrOp::Push(usize_to_bytes(variant.unwrap_or(0)))),
                 }
             } else {
                 ops.push(IrOp::Push(vec![0]));
             }
         }
         Expression::Index(base, key) => {
-            if let Expression::Identifier(name) = base.as_ref() {
-                if let Some(slot) = ctx.layout.get(name) {
-                    lower_mapping_key(ctx, key, slot.slot, ops);
+            if let Some(array) = array_ref(ctx, base) {
+                lower_array_element(ctx, &array, key, ops);
+                match array {
+                    ArrayRef::Storage { .. } => ops.push(IrOp::SLoad),
+                    ArrayRef::Calldata { .. } => {
+                        ops.push(IrOp::CallDataLoad);
+                        if let Some(elem) = calldata_element_type(ctx, base) {
+                            lower_cleanup(&elem, ops);
+                        }
+                    }
+                }
+            } else if lower_mapping_entry(ctx, base, key, ops) {
+                ops.push(IrOp::SLoad);
+            } else if let Some(ptr) = dynamic_param_ptr(ctx, base) {
+                lower_dynamic_byte(ctx, ptr, key, ops);
+            } else {
+                lower_bytes_word(ctx, base, ops);
+                lower_expression_into(ctx, key, ops);
+                ops.push(IrOp::Byte);
+            }
+        }
+        Expression::Slice(base, start, end) => {
+            let Some((start, end)) = slice_bounds(start, end) else {
+                ops.push(IrOp::Invalid);
+                return;
+            };
+            if let Some(ptr) = dynamic_param_ptr(ctx, base) {
+                lower_length_check(ctx, ptr, end, ops);
+            }
+            lower_bytes_word(ctx, base, ops);
+            if start > 0 {
+                ops.push(IrOp::Push(usize_to_bytes(8 * start)));
+                ops.push(IrOp::Shl);
+            }
+            let cleared = 256 - 8 * (end - start);
+            if cleared > 0 {
+                ops.push(IrOp::Push(usize_to_bytes(cleared)));
+                ops.push(IrOp::Shr);
+                ops.push(IrOp::Push(usize_to_bytes(cleared)));
+                ops.push(IrOp::Shl);
+            }
+        }
+        Expression::Binary(op @ (BinaryOp::Equal | BinaryOp::NotEqual), left, right)
+            if is_empty_literal(left) || is_empty_literal(right) =>
+        {
+            let operand = if is_empty_literal(left) { right } else { left };
+            lower_dynamic_length(ctx, operand, ops);
+            ops.push(IrOp::IsZero);
+            if *op == BinaryOp::NotEqual {
+                ops.push(IrOp::IsZero);
+            }
+        }
+        Expression::Binary(BinaryOp::In, key, container) => {
+            let slot = mapping_slot(ctx, container);
+            match slot {
+                Some(slot_num) => {
+                    lower_mapping_key(ctx, key, slot_num, ops);
                     ops.push(IrOp::SLoad);
+                    ops.push(IrOp::IsZero);
+                    ops.push(IrOp::IsZero);
                 }
+                None => ops.push(IrOp::Invalid),
             }
         }
         Expression::Binary(op, left, right) => {
-            lower_expression_into(ctx, left, ops);
-            lower_expression_into(ctx, right, ops);
+            // A hex literal compared with a `bytesN` is aligned like one.
+            let comparison = matches!(
+                op,
+                BinaryOp::Equal
+                    | BinaryOp::NotEqual
+                    | BinaryOp::Less
+                    | BinaryOp::Greater
+                    | BinaryOp::LessEqual
+                    | BinaryOp::GreaterEqual
+            );
+            let (left_ty, right_ty) = if comparison {
+                (static_type(ctx, left), static_type(ctx, right))
+            } else {
+                (None, None)
+            };
+            lower_expression_into(ctx, &coerce(right_ty.as_ref(), left), ops);
+            lower_expression_into(ctx, &coerce(left_ty.as_ref(), right), ops);
             match op {
                 BinaryOp::Add => ops.push(IrOp::Add),
                 BinaryOp::Sub => {
@@ -430,6 +2036,16 @@ fn lower_expression_into(ctx: &mut LowerCtx, expr: &Expression, ops: &mut Vec<Ir
                 }
                 BinaryOp::And => ops.push(IrOp::And),
                 BinaryOp::Or => ops.push(IrOp::Or),
+                BinaryOp::In => unreachable!("membership is lowered above"),
+            }
+            // A narrow result is brought back into its type right after the
+            // operation, so hardening can turn it into a range check.
+            if matches!(op, BinaryOp::Add | BinaryOp::Sub | BinaryOp::Mul | BinaryOp::Pow) {
+                match static_type(ctx, expr) {
+                    Some(crate::Type::Uint(bits)) if bits < 256 => ops.push(IrOp::Narrow { bits, signed: false }),
+                    Some(crate::Type::Int(bits)) if bits < 256 => ops.push(IrOp::Narrow { bits, signed: true }),
+                    _ => {}
+                }
             }
         }
         Expression::Unary(op, operand) => {
@@ -443,80 +2059,724 @@ fn lower_expression_into(ctx: &mut LowerCtx, expr: &Expression, ops: &mut Vec<Ir
             }
         }
         Expression::Call(callee, args) => {
+            if let Some((address, method)) = ctx.interface_method(callee) {
+                lower_external_call(ctx, &method, &address, args, ops);
+                return;
+            }
+            if let (Expression::Member(base, method), [value]) = (callee.as_ref(), args.as_slice()) {
+                if let Some(ArrayRef::Storage { slot, len: None }) =
+                    array_ref(ctx, base).filter(|_| method == "push")
+                {
+                    lower_array_push(ctx, slot, value, ops);
+                    return;
+                }
+            }
+            if let Expression::Identifier(name) = callee.as_ref() {
+                if let ([address], true) = (args.as_slice(), ctx.interfaces.contains_key(name)) {
+                    lower_expression_into(ctx, address, ops);
+                    return;
+                }
+                if let Some(results) = lower_internal_call(ctx, name, args, ops) {
+                    match results.first() {
+                        Some(&off) => {
+                            ops.push(IrOp::Push(usize_to_bytes(off)));
+                            ops.push(IrOp::MLoad);
+                        }
+                        None => ops.push(IrOp::Push(vec![0])),
+                    }
+                    return;
+                }
+                if let ("is_contract", [addr]) = (name.as_str(), args.as_slice()) {
+                    lower_expression_into(ctx, addr, ops);
+                    ops.push(IrOp::ExtCodeSize);
+                    ops.push(IrOp::IsZero);
+                    ops.push(IrOp::IsZero);
+                    return;
+                }
+            }
             lower_expression_into(ctx, callee, ops);
             for arg in args {
                 lower_expression_into(ctx, arg, ops);
             }
         }
+        Expression::Tuple(_) => {
+            ops.push(IrOp::Invalid);
+        }
         Expression::StructInit(_, _) => {
             ops.push(IrOp::Push(vec![0]));
         }
     }
 }
 
-fn lower_expression(ctx: &mut LowerCtx, expr: &Expression) -> Vec<IrOp> {
-    let mut ops = Vec::with_capacity(8);
-    lower_expression_into(ctx, expr, &mut ops);
-    ops
+/// Calls an interface method and leaves its first return word on the stack,
+/// or zero when it returns nothing.
+fn lower_external_call(
+    ctx: &mut LowerCtx,
+    method: &Function,
+    address: &Expression,
+    args: &[Expression],
+    ops: &mut Vec<IrOp>,
+) {
+    let buf = lower_external_call_buffer(ctx, method, address, args, ops);
+    if return_arity(method) > 0 {
+        buf.push_at(0, ops);
+        ops.push(IrOp::MLoad);
+    } else {
+        ops.push(IrOp::Push(vec![0]));
+    }
 }
 
-pub fn compute_selector(func: &Function) -> [u8; 4] {
-    let mut sig = func.name.clone();
-    sig.push('(');
-    for (i, p) in func.params.iter().enumerate() {
-        if i > 0 {
-            sig.push(',');
-        }
-        sig.push_str(&type_to_abi_string(&p.type_));
+fn return_arity(f: &Function) -> usize {
+    match &f.return_type {
+        Some(crate::Type::Tuple(items)) => items.len(),
+        Some(_) => 1,
+        None => 0,
     }
-    sig.push(')');
-
-    let mut hasher = Keccak::v256();
-    let mut output = [0u8; 32];
-    hasher.update(sig.as_bytes());
-    hasher.finalize(&mut output);
+}
 
-    [output[0], output[1], output[2], output[3]]
+/// Where an external call's calldata is encoded and its return data lands:
+/// a buffer at a fixed address, or at the free memory pointer stored in the
+/// given word when an argument's length is only known at run time.
+#[derive(Clone, Copy)]
+enum CallBuffer {
+    Fixed(usize),
+    Free(usize),
 }
 
-fn type_to_abi_string(ty: &crate::Type) -> String {
-    match ty {
-        crate::Type::Uint8 => "uint8".into(),
-        crate::Type::Uint256 => "uint256".into(),
-        crate::Type::Int256 => "int256".into(),
-        crate::Type::Bool => "bool".into(),
-        crate::Type::Address => "address".into(),
-        crate::Type::Bytes => "bytes".into(),
-        crate::Type::String => "string".into(),
-        _ => "bytes".into(),
+impl CallBuffer {
+    /// Pushes the address `offset` bytes into the buffer.
+    fn push_at(self, offset: usize, ops: &mut Vec<IrOp>) {
+        match self {
+            CallBuffer::Fixed(buf) => ops.push(IrOp::Push(usize_to_bytes(buf + offset))),
+            CallBuffer::Free(free) => {
+                ops.push(IrOp::Push(usize_to_bytes(free)));
+                ops.push(IrOp::MLoad);
+                if offset > 0 {
+                    ops.push(IrOp::Push(usize_to_bytes(offset)));
+                    ops.push(IrOp::Add);
+                }
+            }
+        }
     }
 }
 
-fn biguint_to_push_bytes(n: &num_bigint::BigUint) -> Vec<u8> {
-    let bytes = n.to_bytes_be();
-    if bytes.is_empty() || (bytes.len() == 1 && bytes[0] == 0) {
-        return vec![0];
-    }
-    bytes
+/// A `string` or `bytes` argument the encoder can copy: literal contents,
+/// or a parameter, read again from calldata at its head offset.
+enum DynamicArg {
+    Literal(Vec<u8>),
+    Param(usize),
 }
 
-fn u64_to_bytes(n: u64) -> Vec<u8> {
-    if n == 0 {
-        return vec![0];
+fn dynamic_arg(ctx: &LowerCtx, arg: &Expression) -> Option<DynamicArg> {
+    match arg {
+        Expression::String(s) => Some(DynamicArg::Literal(string_literal_bytes(s))),
+        Expression::Bytes(b) => Some(DynamicArg::Literal(b.clone())),
+        Expression::Identifier(name) if ctx.dynamic_params.contains_key(name) => {
+            ctx.params.get(name).copied().map(DynamicArg::Param)
+        }
+        _ => None,
     }
-    let bytes = n.to_be_bytes();
-    let start = bytes.iter().position(|&b| b != 0).unwrap_or(7);
-    bytes[start..].to_vec()
 }
 
-fn usize_to_bytes(n: usize) -> Vec<u8> {
-    u64_to_bytes(n as u64)
-}
+/// ABI-encodes the selector and arguments, calls `address` and returns the
+/// buffer the return words were copied to. The address and the static
+/// arguments are evaluated before anything is written, so a call nested in
+/// them cannot clobber the buffer. A failed call reverts with the callee's
+/// revert data so its reason reaches the original caller, and a call that
+/// returns fewer words than declared (such as one to an address without
+/// code) reverts empty.
+fn lower_external_call_buffer(
+    ctx: &mut LowerCtx,
+    method: &Function,
+    address: &Expression,
+    args: &[Expression],
+    ops: &mut Vec<IrOp>,
+) -> CallBuffer {
+    let returns = return_arity(method);
+    let dynamic: Vec<Option<DynamicArg>> = method
+        .params
+        .iter()
+        .zip(args)
+        .map(|(p, arg)| match p.type_ {
+            crate::Type::String | crate::Type::Bytes => dynamic_arg(ctx, arg),
+            _ => None,
+        })
+        .collect();
+    let head = 4 + 32 * args.len();
+    let buf = match ctx.free_ptr {
+        Some(free) if dynamic.iter().any(|d| matches!(d, Some(DynamicArg::Param(_)))) => CallBuffer::Free(free),
+        _ => {
+            let tails: usize = dynamic
+                .iter()
+                .map(|d| match d {
+                    Some(DynamicArg::Literal(b)) => 32 + 32 * b.len().div_ceil(32),
+                    _ => 0,
+                })
+                .sum();
+            let buf = ctx.next_mem;
+            ctx.next_mem += (32 * (args.len() + 1) + tails).max(32 * returns);
+            CallBuffer::Fixed(buf)
+        }
+    };
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use crate::parser::parse_from_source;
+    let target = ctx.alloc_temp();
+    lower_expression_into(ctx, address, ops);
+    ops.push(IrOp::Push(usize_to_bytes(target)));
+    ops.push(IrOp::MStore);
+    for ((arg, d), p) in args.iter().zip(&dynamic).zip(&method.params) {
+        if d.is_none() {
+            lower_expression_into(ctx, &coerce(Some(&p.type_), arg), ops);
+        }
+    }
+    let mut selector = compute_selector(method).to_vec();
+    selector.resize(32, 0);
+    ops.push(IrOp::Push(selector));
+    buf.push_at(0, ops);
+    ops.push(IrOp::MStore);
+    for (i, d) in dynamic.iter().enumerate().rev() {
+        if d.is_none() {
+            buf.push_at(4 + 32 * i, ops);
+            ops.push(IrOp::MStore);
+        }
+    }
+
+    // Each `string`/`bytes` tail is appended at `tail` as `[length][data]`,
+    // and its head word holds the tail's offset from the first argument.
+    let tail = dynamic.iter().any(Option::is_some).then(|| ctx.alloc_temp());
+    if let Some(tail) = tail {
+        buf.push_at(head, ops);
+        ops.push(IrOp::Push(usize_to_bytes(tail)));
+        ops.push(IrOp::MStore);
+        for (i, d) in dynamic.iter().enumerate() {
+            let Some(d) = d else { continue };
+            buf.push_at(4, ops);
+            ops.push(IrOp::Push(usize_to_bytes(tail)));
+            ops.push(IrOp::MLoad);
+            ops.push(IrOp::Sub);
+            buf.push_at(4 + 32 * i, ops);
+            ops.push(IrOp::MStore);
+            match d {
+                DynamicArg::Literal(bytes) => {
+                    ops.push(IrOp::Push(usize_to_bytes(bytes.len())));
+                    ops.push(IrOp::Push(usize_to_bytes(tail)));
+                    ops.push(IrOp::MLoad);
+                    ops.push(IrOp::MStore);
+                    let words = bytes.len().div_ceil(32);
+                    for (k, chunk) in bytes.chunks(32).enumerate() {
+                        let mut word = chunk.to_vec();
+                        word.resize(32, 0);
+                        ops.push(IrOp::Push(word));
+                        ops.push(IrOp::Push(usize_to_bytes(tail)));
+                        ops.push(IrOp::MLoad);
+                        ops.push(IrOp::Push(usize_to_bytes(32 * (k + 1))));
+                        ops.push(IrOp::Add);
+                        ops.push(IrOp::MStore);
+                    }
+                    ops.push(IrOp::Push(usize_to_bytes(32 * (words + 1))));
+                }
+                DynamicArg::Param(param) => {
+                    // [length][data] is copied straight from calldata,
+                    // padded to whole words.
+                    ops.push(IrOp::Push(usize_to_bytes(*param)));
+                    ops.push(IrOp::CallDataLoad);
+                    ops.push(IrOp::Push(vec![0x04]));
+                    ops.push(IrOp::Add);
+                    ops.push(IrOp::Dup(1));
+                    ops.push(IrOp::CallDataLoad);
+                    ops.push(IrOp::Push(vec![0x1f]));
+                    ops.push(IrOp::Add);
+                    ops.push(IrOp::Push(vec![0x05]));
+                    ops.push(IrOp::Shr);
+                    ops.push(IrOp::Push(vec![0x05]));
+                    ops.push(IrOp::Shl);
+                    ops.push(IrOp::Push(vec![0x20]));
+                    ops.push(IrOp::Add);
+                    ops.push(IrOp::Dup(1));
+                    ops.push(IrOp::Swap(2));
+                    ops.push(IrOp::Push(usize_to_bytes(tail)));
+                    ops.push(IrOp::MLoad);
+                    ops.push(IrOp::CallDataCopy);
+                }
+            }
+            ops.push(IrOp::Push(usize_to_bytes(tail)));
+            ops.push(IrOp::MLoad);
+            ops.push(IrOp::Add);
+            ops.push(IrOp::Push(usize_to_bytes(tail)));
+            ops.push(IrOp::MStore);
+        }
+    }
+
+    ops.push(IrOp::Push(usize_to_bytes(32 * returns)));
+    buf.push_at(0, ops);
+    match tail {
+        Some(tail) => {
+            buf.push_at(0, ops);
+            ops.push(IrOp::Push(usize_to_bytes(tail)));
+            ops.push(IrOp::MLoad);
+            ops.push(IrOp::Sub);
+        }
+        None => ops.push(IrOp::Push(usize_to_bytes(head))),
+    }
+    buf.push_at(0, ops);
+    if !method.is_read_only() {
+        ops.push(IrOp::Push(vec![0]));
+    }
+    ops.push(IrOp::Push(usize_to_bytes(target)));
+    ops.push(IrOp::MLoad);
+    ops.push(IrOp::Gas);
+    ops.push(if method.is_read_only() { IrOp::StaticCall } else { IrOp::Call });
+
+    let ok_label = ctx.fresh_label();
+    ops.push(IrOp::JumpI(ok_label));
+    ops.push(IrOp::ReturnDataSize);
+    ops.push(IrOp::Push(vec![0]));
+    ops.push(IrOp::Push(vec![0]));
+    ops.push(IrOp::ReturnDataCopy);
+    ops.push(IrOp::ReturnDataSize);
+    ops.push(IrOp::Push(vec![0]));
+    ops.push(IrOp::Revert);
+    ops.push(IrOp::JumpDest(ok_label));
+
+    if returns > 0 {
+        let decoded_label = ctx.fresh_label();
+        ops.push(IrOp::Push(usize_to_bytes(32 * returns)));
+        ops.push(IrOp::ReturnDataSize);
+        ops.push(IrOp::Lt);
+        ops.push(IrOp::IsZero);
+        ops.push(IrOp::JumpI(decoded_label));
+        ops.push(IrOp::Push(vec![0]));
+        ops.push(IrOp::Push(vec![0]));
+        ops.push(IrOp::Revert);
+        ops.push(IrOp::JumpDest(decoded_label));
+    }
+    buf
+}
+
+/// Returns a `bytes` or `string` value as `[offset][length][data]`. Emits
+/// nothing and returns false for values whose length is not known here.
+fn lower_dynamic_return(ctx: &mut LowerCtx, value: &Expression, ops: &mut Vec<IrOp>) -> bool {
+    match value {
+        Expression::Bytes(b) => lower_string_return(b, ops),
+        Expression::Identifier(name) if ctx.dynamic_params.contains_key(name) => {
+            // The param's copy is already `[length][data]`, so the offset
+            // word goes just before it; nothing reads memory after RETURN.
+            let ptr = ctx.dynamic_params[name];
+            ops.push(IrOp::Push(vec![0x20]));
+            ops.push(IrOp::Push(usize_to_bytes(ptr)));
+            ops.push(IrOp::MLoad);
+            ops.push(IrOp::Push(vec![0x20]));
+            ops.push(IrOp::Swap(1));
+            ops.push(IrOp::Sub);
+            ops.push(IrOp::MStore);
+            ops.push(IrOp::Push(usize_to_bytes(ptr)));
+            ops.push(IrOp::MLoad);
+            ops.push(IrOp::MLoad);
+            ops.push(IrOp::Push(vec![0x1f]));
+            ops.push(IrOp::Add);
+            ops.push(IrOp::Push(vec![0x05]));
+            ops.push(IrOp::Shr);
+            ops.push(IrOp::Push(vec![0x05]));
+            ops.push(IrOp::Shl);
+            ops.push(IrOp::Push(vec![0x40]));
+            ops.push(IrOp::Add);
+            ops.push(IrOp::Push(vec![0x20]));
+            ops.push(IrOp::Push(usize_to_bytes(ptr)));
+            ops.push(IrOp::MLoad);
+            ops.push(IrOp::Sub);
+            ops.push(IrOp::Return);
+        }
+        Expression::Slice(_, start, end) => {
+            let Some((start, end)) = slice_bounds(start, end) else {
+                return false;
+            };
+            lower_expression_into(ctx, value, ops);
+            ops.push(IrOp::Push(usize_to_bytes(RETURN_BASE + 0x40)));
+            ops.push(IrOp::MStore);
+            ops.push(IrOp::Push(vec![0x20]));
+            ops.push(IrOp::Push(usize_to_bytes(RETURN_BASE)));
+            ops.push(IrOp::MStore);
+            ops.push(IrOp::Push(usize_to_bytes(end - start)));
+            ops.push(IrOp::Push(usize_to_bytes(RETURN_BASE + 0x20)));
+            ops.push(IrOp::MStore);
+            ops.push(IrOp::Push(usize_to_bytes(if end > start { 0x60 } else { 0x40 })));
+            ops.push(IrOp::Push(usize_to_bytes(RETURN_BASE)));
+            ops.push(IrOp::Return);
+        }
+        _ => return false,
+    }
+    true
+}
+
+fn lower_string_return(data: &[u8], ops: &mut Vec<IrOp>) {
+    let size = store_abi_string(data, RETURN_BASE, ops);
+    ops.push(IrOp::Push(usize_to_bytes(size)));
+    ops.push(IrOp::Push(usize_to_bytes(RETURN_BASE)));
+    ops.push(IrOp::Return);
+}
+
+/// Reverts with `Error(string)` data, the encoding Solidity tooling decodes.
+fn lower_revert_reason(data: &[u8], ops: &mut Vec<IrOp>) {
+    let mut selector = vec![0x08, 0xc3, 0x79, 0xa0];
+    selector.resize(32, 0);
+    ops.push(IrOp::Push(selector));
+    ops.push(IrOp::Push(vec![0x00]));
+    ops.push(IrOp::MStore);
+    let size = store_abi_string(data, 0x04, ops);
+    ops.push(IrOp::Push(usize_to_bytes(4 + size)));
+    ops.push(IrOp::Push(vec![0x00]));
+    ops.push(IrOp::Revert);
+}
+
+/// Writes an ABI-encoded string (offset, length, padded bytes) at `base` and
+/// returns the encoded size.
+fn store_abi_string(data: &[u8], base: usize, ops: &mut Vec<IrOp>) -> usize {
+    ops.push(IrOp::Push(vec![0x20]));
+    ops.push(IrOp::Push(usize_to_bytes(base)));
+    ops.push(IrOp::MStore);
+    ops.push(IrOp::Push(usize_to_bytes(data.len())));
+    ops.push(IrOp::Push(usize_to_bytes(base + 0x20)));
+    ops.push(IrOp::MStore);
+    for (i, chunk) in data.chunks(32).enumerate() {
+        let mut word = chunk.to_vec();
+        word.resize(32, 0);
+        ops.push(IrOp::Push(word));
+        ops.push(IrOp::Push(usize_to_bytes(base + 0x40 + 32 * i)));
+        ops.push(IrOp::MStore);
+    }
+    0x40 + data.len().div_ceil(32) * 32
+}
+
+pub fn string_literal_bytes(raw: &str) -> Vec<u8> {
+    let mut out = Vec::with_capacity(raw.len());
+    let mut chars = raw.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            let mut buf = [0u8; 4];
+            out.extend_from_slice(c.encode_utf8(&mut buf).as_bytes());
+            continue;
+        }
+        match chars.next() {
+            Some('n') => out.push(b'\n'),
+            Some('t') => out.push(b'\t'),
+            Some('r') => out.push(b'\r'),
+            Some('0') => out.push(0),
+            Some('x') => {
+                let hex: String = chars.by_ref().take(2).collect();
+                match u8::from_str_radix(&hex, 16) {
+                    Ok(b) => out.push(b),
+                    Err(_) => out.extend_from_slice(format!("\\x{hex}").as_bytes()),
+                }
+            }
+            Some(other) => {
+                let mut buf = [0u8; 4];
+                out.extend_from_slice(other.encode_utf8(&mut buf).as_bytes());
+            }
+            None => out.push(b'\\'),
+        }
+    }
+    out
+}
+
+/// Clears the bits a calldata word may carry outside its declared type:
+/// unsigned values and addresses are masked to their width, signed ones
+/// sign-extended from their top byte, `bytesN` masked to their leading N
+/// bytes, and bools collapsed to 0 or 1.
+fn lower_cleanup(ty: &crate::Type, ops: &mut Vec<IrOp>) {
+    let low_bits = |n: usize| (num_bigint::BigUint::from(1u8) << n) - 1u8;
+    match ty {
+        crate::Type::Uint(n) if *n < 256 => {
+            ops.push(IrOp::Push(low_bits(*n as usize).to_bytes_be()));
+            ops.push(IrOp::And);
+        }
+        crate::Type::Address => {
+            ops.push(IrOp::Push(low_bits(160).to_bytes_be()));
+            ops.push(IrOp::And);
+        }
+        crate::Type::FixedBytes(n) if *n < 32 => {
+            let mask = low_bits(8 * *n as usize) << (8 * (32 - *n as usize));
+            ops.push(IrOp::Push(mask.to_bytes_be()));
+            ops.push(IrOp::And);
+        }
+        crate::Type::Bool => {
+            ops.push(IrOp::IsZero);
+            ops.push(IrOp::IsZero);
+        }
+        crate::Type::Int(n) if *n < 256 => {
+            ops.push(IrOp::Push(vec![(n / 8 - 1) as u8]));
+            ops.push(IrOp::SignExtend);
+        }
+        _ => {}
+    }
+}
+
+fn calldata_element_type(ctx: &LowerCtx, base: &Expression) -> Option<crate::Type> {
+    let Expression::Identifier(name) = base else { return None };
+    match ctx.param_types.get(name)? {
+        crate::Type::Vec(elem) | crate::Type::Array(elem, _) => Some((**elem).clone()),
+        _ => None,
+    }
+}
+
+/// Reverts when an enum param is not one of its declared variants.
+fn lower_enum_check(ctx: &mut LowerCtx, param: &str, ops: &mut Vec<IrOp>) {
+    let count = match ctx.param_types.get(param) {
+        Some(crate::Type::Enum(name)) => ctx.enums.get(name).map(|e| e.variants.len()),
+        _ => None,
+    };
+    let Some(count) = count else { return };
+    let ok_label = ctx.fresh_label();
+    ops.push(IrOp::Dup(1));
+    ops.push(IrOp::Push(usize_to_bytes(count)));
+    ops.push(IrOp::Gt);
+    ops.push(IrOp::JumpI(ok_label));
+    ops.push(IrOp::Push(vec![0]));
+    ops.push(IrOp::Push(vec![0]));
+    ops.push(IrOp::Revert);
+    ops.push(IrOp::JumpDest(ok_label));
+}
+
+/// A hex literal given to a `bytesN` declaration is written as its N bytes,
+/// so it moves to the high end of the word. `None` when it does not fit.
+pub fn align_fixed_bytes(ty: &crate::Type, value: &Expression) -> Option<num_bigint::BigUint> {
+    match (ty, value) {
+        (crate::Type::FixedBytes(n), Expression::HexNumber(v)) if v.bits() <= 8 * *n as u64 => {
+            Some(v << (8 * (32 - *n as usize)))
+        }
+        _ => None,
+    }
+}
+
+/// `value` as an operand of type `ty`: a hex literal given for a `bytesN`
+/// is left-aligned, as it is in a typed `let` or a constant.
+fn coerce<'a>(ty: Option<&crate::Type>, value: &'a Expression) -> Cow<'a, Expression> {
+    match ty.and_then(|ty| align_fixed_bytes(ty, value)) {
+        Some(aligned) => Cow::Owned(Expression::HexNumber(aligned)),
+        None => Cow::Borrowed(value),
+    }
+}
+
+/// The declared type of `expr` where lowering can tell it: variables,
+/// mapping and array elements, and the results of calls.
+fn static_type(ctx: &LowerCtx, expr: &Expression) -> Option<crate::Type> {
+    match expr {
+        Expression::Identifier(name) => ctx
+            .local_types
+            .get(name)
+            .or_else(|| ctx.param_types.get(name))
+            .or_else(|| ctx.state_types.get(name).filter(|_| !ctx.locals.contains_key(name)))
+            .cloned(),
+        Expression::Member(base, field) if matches!(base.as_ref(), Expression::Identifier(s) if s == "self") => {
+            ctx.state_types.get(field).cloned()
+        }
+        Expression::Index(base, _) => match static_type(ctx, base)? {
+            crate::Type::Map(_, value) => Some(*value),
+            crate::Type::Vec(elem) | crate::Type::Array(elem, _) => Some(*elem),
+            _ => None,
+        },
+        Expression::Call(callee, _) => match callee.as_ref() {
+            Expression::Identifier(name) => ctx.functions.get(name)?.return_type.clone(),
+            _ => ctx.interface_method(callee)?.1.return_type,
+        },
+        Expression::Binary(
+            BinaryOp::Add | BinaryOp::Sub | BinaryOp::Mul | BinaryOp::Div | BinaryOp::Mod | BinaryOp::Pow,
+            left,
+            right,
+        ) => {
+            // A literal operand takes the other side's width, as in `x + 1`.
+            if is_number_literal(right) || matches!(expr, Expression::Binary(BinaryOp::Pow, ..)) {
+                return static_type(ctx, left);
+            }
+            if is_number_literal(left) {
+                return static_type(ctx, right);
+            }
+            match (static_type(ctx, left)?, static_type(ctx, right)?) {
+                (crate::Type::Uint(a), crate::Type::Uint(b)) => Some(crate::Type::Uint(a.max(b))),
+                (crate::Type::Int(a), crate::Type::Int(b)) => Some(crate::Type::Int(a.max(b))),
+                _ => None,
+            }
+        }
+        _ => None,
+    }
+}
+
+fn is_number_literal(expr: &Expression) -> bool {
+    match expr {
+        Expression::Number(_) | Expression::HexNumber(_) => true,
+        Expression::Unary(UnaryOp::Minus, operand) => is_number_literal(operand),
+        _ => false,
+    }
+}
+
+fn is_empty_literal(expr: &Expression) -> bool {
+    match expr {
+        Expression::String(s) => s.is_empty(),
+        Expression::Bytes(b) => b.is_empty(),
+        _ => false,
+    }
+}
+
+/// The memory slot holding the pointer to a `bytes` or `string` param's
+/// `[length][data]` copy, when `expr` names one.
+fn dynamic_param_ptr(ctx: &LowerCtx, expr: &Expression) -> Option<usize> {
+    match expr {
+        Expression::Identifier(name) => ctx.dynamic_params.get(name).copied(),
+        _ => None,
+    }
+}
+
+/// Pushes byte `key` of a dynamic param's data, reverting if `key` is not
+/// below its length.
+fn lower_dynamic_byte(ctx: &mut LowerCtx, ptr: usize, key: &Expression, ops: &mut Vec<IrOp>) {
+    let ok_label = ctx.fresh_label();
+    lower_expression_into(ctx, key, ops);
+    ops.push(IrOp::Dup(1));
+    ops.push(IrOp::Push(usize_to_bytes(ptr)));
+    ops.push(IrOp::MLoad);
+    ops.push(IrOp::MLoad);
+    ops.push(IrOp::Gt);
+    ops.push(IrOp::JumpI(ok_label));
+    ops.push(IrOp::Push(vec![0]));
+    ops.push(IrOp::Push(vec![0]));
+    ops.push(IrOp::Revert);
+    ops.push(IrOp::JumpDest(ok_label));
+    ops.push(IrOp::Push(usize_to_bytes(ptr)));
+    ops.push(IrOp::MLoad);
+    ops.push(IrOp::Add);
+    ops.push(IrOp::Push(vec![0x20]));
+    ops.push(IrOp::Add);
+    ops.push(IrOp::MLoad);
+    ops.push(IrOp::Push(vec![0]));
+    ops.push(IrOp::Byte);
+}
+
+/// Reverts when a dynamic param is shorter than `end` bytes.
+fn lower_length_check(ctx: &mut LowerCtx, ptr: usize, end: usize, ops: &mut Vec<IrOp>) {
+    let ok_label = ctx.fresh_label();
+    ops.push(IrOp::Push(usize_to_bytes(ptr)));
+    ops.push(IrOp::MLoad);
+    ops.push(IrOp::MLoad);
+    ops.push(IrOp::Push(usize_to_bytes(end)));
+    ops.push(IrOp::Gt);
+    ops.push(IrOp::IsZero);
+    ops.push(IrOp::JumpI(ok_label));
+    ops.push(IrOp::Push(vec![0]));
+    ops.push(IrOp::Push(vec![0]));
+    ops.push(IrOp::Revert);
+    ops.push(IrOp::JumpDest(ok_label));
+}
+
+/// Pushes the first 32 bytes of a `bytes` value, left-aligned. A dynamic
+/// param is a memory pointer, so its data word sits one word past it.
+fn lower_bytes_word(ctx: &mut LowerCtx, expr: &Expression, ops: &mut Vec<IrOp>) {
+    lower_expression_into(ctx, expr, ops);
+    if matches!(expr, Expression::Identifier(name) if ctx.dynamic_params.contains_key(name)) {
+        ops.push(IrOp::Push(vec![0x20]));
+        ops.push(IrOp::Add);
+        ops.push(IrOp::MLoad);
+    }
+}
+
+fn lower_dynamic_length(ctx: &mut LowerCtx, expr: &Expression, ops: &mut Vec<IrOp>) {
+    match expr {
+        Expression::String(s) => {
+            ops.push(IrOp::Push(usize_to_bytes(string_literal_bytes(s).len())))
+        }
+        Expression::Bytes(b) => ops.push(IrOp::Push(usize_to_bytes(b.len()))),
+        Expression::Identifier(name) if ctx.params.contains_key(name) => {
+            let off = ctx.params[name];
+            ops.push(IrOp::Push(usize_to_bytes(off)));
+            ops.push(IrOp::CallDataLoad);
+            ops.push(IrOp::Push(vec![0x04]));
+            ops.push(IrOp::Add);
+            ops.push(IrOp::CallDataLoad);
+        }
+        _ => lower_expression_into(ctx, expr, ops),
+    }
+}
+
+fn lower_expression(ctx: &mut LowerCtx, expr: &Expression) -> Vec<IrOp> {
+    let mut ops = Vec::with_capacity(8);
+    lower_expression_into(ctx, expr, &mut ops);
+    ops
+}
+
+pub fn compute_selector(func: &Function) -> [u8; 4] {
+    let sig = function_signature(func);
+    let mut hasher = Keccak::v256();
+    let mut output = [0u8; 32];
+    hasher.update(sig.as_bytes());
+    hasher.finalize(&mut output);
+
+    [output[0], output[1], output[2], output[3]]
+}
+
+/// The canonical signature the selector hashes, such as
+/// `transfer(address,uint256)`.
+pub fn function_signature(func: &Function) -> String {
+    let mut sig = func.name.clone();
+    sig.push('(');
+    for (i, p) in func.params.iter().enumerate() {
+        if i > 0 {
+            sig.push(',');
+        }
+        sig.push_str(&type_to_abi_string(&p.type_));
+    }
+    sig.push(')');
+    sig
+}
+
+pub fn compute_error_selector(error: &crate::ErrorDef) -> [u8; 4] {
+    let types: Vec<crate::Type> = error.params.iter().map(|p| p.type_.clone()).collect();
+    let hash = keccak256_bytes(build_event_signature(&error.name, Some(&types)).as_bytes());
+    [hash[0], hash[1], hash[2], hash[3]]
+}
+
+pub fn compute_event_topic(event: &crate::EventDef) -> [u8; 32] {
+    let types: Vec<crate::Type> = event.fields.iter().map(|f| f.type_.clone()).collect();
+    keccak256_bytes(build_event_signature(&event.name, Some(&types)).as_bytes())
+}
+
+fn type_to_abi_string(ty: &crate::Type) -> String {
+    match ty {
+        crate::Type::Uint(n) => format!("uint{n}"),
+        crate::Type::Int(n) => format!("int{n}"),
+        crate::Type::Bool => "bool".into(),
+        crate::Type::Address => "address".into(),
+        crate::Type::Bytes => "bytes".into(),
+        crate::Type::FixedBytes(n) => format!("bytes{n}"),
+        crate::Type::String => "string".into(),
+        crate::Type::Enum(_) => "uint8".into(),
+        crate::Type::Vec(inner) => format!("{}[]", type_to_abi_string(inner)),
+        crate::Type::Array(inner, len) => format!("{}[{len}]", type_to_abi_string(inner)),
+        _ => "bytes".into(),
+    }
+}
+
+fn biguint_to_push_bytes(n: &num_bigint::BigUint) -> Vec<u8> {
+    let bytes = n.to_bytes_be();
+    if bytes.is_empty() || (bytes.len() == 1 && bytes[0] == 0) {
+        return vec![0];
+    }
+    bytes
+}
+
+fn u64_to_bytes(n: u64) -> Vec<u8> {
+    if n == 0 {
+        return vec![0];
+    }
+    let bytes = n.to_be_bytes();
+    let start = bytes.iter().position(|&b| b != 0).unwrap_or(7);
+    bytes[start..].to_vec()
+}
+
+fn usize_to_bytes(n: usize) -> Vec<u8> {
+    u64_to_bytes(n as u64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::parse_from_source;
 
     #[test]
     fn lower_return_constant() {
@@ -529,15 +2789,83 @@ mod tests {
         assert!(matches!(ops.last().unwrap(), IrOp::Return));
     }
 
+    fn lowered_ops(src: &str) -> Vec<IrOp> {
+        let program = parse_from_source(src).unwrap();
+        lower_program(&program).functions.remove(0).ops
+    }
+
+    #[test]
+    fn lower_drops_algebraic_identities() {
+        for body in ["a + 0", "0 + a", "a - 0", "a * 1", "1 * a"] {
+            let ops = lowered_ops(&format!("def t(a: uint256) -> uint256: return {body}"));
+            let arith = ops.iter().filter(|op| matches!(op, IrOp::Add | IrOp::Sub | IrOp::Mul)).count();
+            assert_eq!(arith, 0, "{body}: {ops:?}");
+            assert!(ops.contains(&IrOp::CallDataLoad), "{body}");
+        }
+        for body in ["a * 0", "0 * a"] {
+            let ops = lowered_ops(&format!("def t(a: uint256) -> uint256: return {body}"));
+            assert!(!ops.contains(&IrOp::Mul), "{body}");
+            assert!(!ops.contains(&IrOp::CallDataLoad), "{body}");
+        }
+    }
+
+    #[test]
+    fn repeated_product_is_computed_once() {
+        let src = "def t(a: uint256, b: uint256) -> uint256: return a * b + a * b";
+        let ops = lowered_ops(src);
+        assert_eq!(ops.iter().filter(|op| matches!(op, IrOp::Mul)).count(), 1);
+        assert_eq!(ops.iter().filter(|op| matches!(op, IrOp::Add)).count(), 1);
+        let mut vm = crate::vm::Vm::from_source(src);
+        let out = vm.call_function("t", &[6u8.into(), 7u8.into()]);
+        assert_eq!(out.word(0), 84u8.into());
+    }
+
+    #[test]
+    fn opt_level_selects_lowering_passes() {
+        let at = |src: &str, opt| {
+            let program = parse_from_source(src).unwrap();
+            lower_program_with_opt_level(&program, opt).functions[0].ops.clone()
+        };
+        let count = |ops: &[IrOp], f: fn(&IrOp) -> bool| ops.iter().filter(|op| f(op)).count();
+
+        let constant = "def t() -> uint256: return 1 + 2";
+        assert_eq!(count(&at(constant, OptLevel::O0), |op| matches!(op, IrOp::Add)), 1);
+        assert_eq!(count(&at(constant, OptLevel::O1), |op| matches!(op, IrOp::Add)), 0);
+
+        let repeated = "def t(a: uint256, b: uint256) -> uint256: return a * b + a * b";
+        assert_eq!(count(&at(repeated, OptLevel::O1), |op| matches!(op, IrOp::Mul)), 2);
+        assert_eq!(count(&at(repeated, OptLevel::O2), |op| matches!(op, IrOp::Mul)), 1);
+    }
+
+    #[test]
+    fn mul_by_zero_keeps_side_effects() {
+        let src = "def bump() -> uint256:\n    count = count + 1\n    return count\n\ndef t() -> uint256: return bump() * 0\n";
+        assert!(lowered_ops(src).iter().any(|op| matches!(op, IrOp::SStore)));
+        let mut vm = crate::vm::Vm::from_source(src);
+        let out = vm.call_function("t", &[]);
+        assert!(out.is_success(), "{out:?}");
+        assert_eq!(out.word(0), 0u8.into());
+        assert_eq!(vm.storage.values().max(), Some(&1u8.into()));
+    }
+
     #[test]
     fn lower_binary_add() {
-        let program = parse_from_source("def t() -> uint256: return 1 + 2").unwrap();
+        let program = parse_from_source("def t(x: uint256) -> uint256: return x + 2").unwrap();
         let module = lower_program(&program);
         let ops = &module.functions[0].ops;
         let has_add = ops.iter().any(|op| matches!(op, IrOp::Add));
         assert!(has_add);
     }
 
+    #[test]
+    fn constant_arithmetic_is_folded_before_hardening() {
+        let program = parse_from_source("def t() -> uint256: return 1 + 2 * 3").unwrap();
+        let module = lower_program(&program);
+        let ops = &module.functions[0].ops;
+        assert!(ops.contains(&IrOp::Push(vec![7])));
+        assert!(!ops.iter().any(|op| matches!(op, IrOp::Add | IrOp::Mul)));
+    }
+
     #[test]
     fn lower_param_access() {
         let program = parse_from_source("def t(x: uint256) -> uint256: return x").unwrap();
@@ -558,6 +2886,13 @@ mod tests {
         assert!(has_revert);
     }
 
+    #[test]
+    fn body_ending_after_require_stops() {
+        let program = parse_from_source("def t(x: uint256):\n    require x > 0\n    x = 1\n").unwrap();
+        let module = lower_program(&program);
+        assert!(matches!(module.functions[0].ops.last(), Some(IrOp::Stop)));
+    }
+
     #[test]
     fn lower_state_write() {
         let program = parse_from_source("def t():\n    x = 42\n").unwrap();
@@ -579,6 +2914,66 @@ mod tests {
         assert!(has_sstore);
     }
 
+    #[test]
+    fn lower_self_qualified_mapping_write() {
+        let lower = |src: &str| lower_program(&parse_from_source(src).unwrap()).functions[0].ops.clone();
+        let qualified = lower("def t():\n    self.balances[msg.sender] = 100\n");
+        let bare = lower("def t():\n    balances[msg.sender] = 100\n");
+        assert!(qualified.iter().any(|op| matches!(op, IrOp::Keccak256)));
+        assert!(qualified.iter().any(|op| matches!(op, IrOp::SStore)));
+        assert_eq!(qualified, bare);
+    }
+
+    #[test]
+    fn nested_mapping_uses_iterated_slots() {
+        let src = "struct Token {\n    allowances: mapping[address => mapping[address => uint256]]\n}\n\nlet token: Token = Token {}\n\ndef approve(spender: address, amount: uint256):\n    token.allowances[msg.sender][spender] = amount\n\ndef allowance(owner: address, spender: address) -> uint256: return token.allowances[owner][spender]\n";
+        let mut vm = crate::vm::Vm::from_source(src);
+        assert!(vm.call_function("approve", &[5u8.into(), 300u32.into()]).is_success());
+        let caller = vm.caller.clone();
+        assert_eq!(vm.call_function("allowance", &[caller.clone(), 5u8.into()]).word(0), 300u32.into());
+        assert_eq!(vm.call_function("allowance", &[5u8.into(), caller.clone()]).word(0), 0u8.into());
+
+        let hash = |key: &num_bigint::BigUint, slot: &[u8; 32]| {
+            let mut word = [0u8; 64];
+            let key = key.to_bytes_be();
+            word[32 - key.len()..32].copy_from_slice(&key);
+            word[32..].copy_from_slice(slot);
+            keccak256_bytes(&word)
+        };
+        let slot = layout_slot(src, "token.allowances");
+        let outer = hash(&caller, &slot);
+        let inner = hash(&5u8.into(), &outer);
+        assert_eq!(vm.storage[&num_bigint::BigUint::from_bytes_be(&inner)], 300u32.into());
+    }
+
+    fn layout_slot(src: &str, name: &str) -> [u8; 32] {
+        let layout = StorageLayout::from_program(&parse_from_source(src).unwrap());
+        let mut word = [0u8; 32];
+        word[24..].copy_from_slice(&layout.get(name).unwrap().slot.to_be_bytes());
+        word
+    }
+
+    #[test]
+    fn self_qualified_mapping_round_trips() {
+        let src = "def set(k: address, v: uint256):\n    self.balances[k] = v\n\ndef get(k: address) -> uint256: return balances[k]\n";
+        let mut vm = crate::vm::Vm::from_source(src);
+        assert!(vm.call_function("set", &[7u8.into(), 42u8.into()]).is_success());
+        assert_eq!(vm.call_function("get", &[7u8.into()]).word(0), 42u8.into());
+    }
+
+    #[test]
+    fn mapping_read_returns_stored_value() {
+        let src = "def set(o: address, v: uint256):\n    balances[o] = v\n\ndef bal(o: address) -> uint256: return balances[o]\n\ndef both(a: address, b: address) -> (uint256, uint256): return balances[a], balances[b]\n";
+        let mut vm = crate::vm::Vm::from_source(src);
+        assert!(vm.call_function("set", &[1u8.into(), 500u32.into()]).is_success());
+        assert!(vm.call_function("set", &[2u8.into(), 7u8.into()]).is_success());
+        assert_eq!(vm.call_function("bal", &[1u8.into()]).word(0), 500u32.into());
+        assert_eq!(vm.call_function("bal", &[3u8.into()]).word(0), 0u8.into());
+        let out = vm.call_function("both", &[2u8.into(), 1u8.into()]);
+        assert_eq!(out.word(0), 7u8.into());
+        assert_eq!(out.word(1), 500u32.into());
+    }
+
     #[test]
     fn lower_msg_sender() {
         let program = parse_from_source("def t():\n    balances[msg.sender] = 1\n").unwrap();
@@ -588,6 +2983,34 @@ mod tests {
         assert!(has_caller);
     }
 
+    #[test]
+    fn listing_numbers_ops_and_names_labels() {
+        let program = parse_from_source("def t(a: uint256) -> uint256:\n    if a > 1:\n        return 2\n    return a\n").unwrap();
+        let listing = lower_program(&program).to_string();
+        let mut lines = listing.lines();
+        assert_eq!(lines.next(), Some("constructor"));
+        assert_eq!(lines.next(), Some(""));
+        let selector = hex::encode(compute_selector(match &program.items[0] {
+            Item::Function(f) => f,
+            _ => unreachable!(),
+        }));
+        assert_eq!(lines.next(), Some(format!("function t selector 0x{selector} entry L0 calldata 36").as_str()));
+        assert_eq!(lines.next(), Some("     0  L0:"));
+        assert!(listing.contains("  PUSH 0x02\n"));
+        assert!(listing.contains("  JUMPI L1\n"));
+        assert!(listing.contains("  L1:\n"));
+    }
+
+    #[test]
+    fn op_histogram_counts_sstores() {
+        let program = parse_from_source("def t():\n    x = 1\n    y = 2\n").unwrap();
+        let module = lower_program(&program);
+        let histogram = module.functions[0].op_histogram();
+        assert_eq!(histogram.get("SSTORE"), Some(&2));
+        assert_eq!(histogram.get("JUMPDEST"), Some(&1));
+        assert!(!histogram.contains_key("SLOAD"));
+    }
+
     #[test]
     fn selector_transfer() {
         let program =
@@ -597,6 +3020,16 @@ mod tests {
         assert_eq!(module.functions[0].selector, [0xa9, 0x05, 0x9c, 0xbb]);
     }
 
+    #[test]
+    fn selector_uses_integer_widths() {
+        let program = parse_from_source("def f(a: uint16, b: int128) -> bool: return true").unwrap();
+        let mut hasher = Keccak::v256();
+        hasher.update(b"f(uint16,int128)");
+        let mut hash = [0u8; 32];
+        hasher.finalize(&mut hash);
+        assert_eq!(lower_program(&program).functions[0].selector, hash[..4]);
+    }
+
     #[test]
     fn lower_constructor_const() {
         let src = "const supply: uint256 = 100\n\ndef t() -> uint256: return supply\n";
@@ -609,9 +3042,60 @@ mod tests {
         assert!(has_sstore);
     }
 
+    #[test]
+    fn lower_constructor_copies_args_from_code() {
+        let src = "def init(a: uint256, b: uint256):\n    x = b\n";
+        let module = lower_program(&parse_from_source(src).unwrap());
+        let ops = &module.constructor_ops;
+        assert!(matches!(&ops[..6], [
+            IrOp::Push(size), IrOp::Push(_), IrOp::CodeSize, IrOp::Sub, IrOp::Push(dest), IrOp::CodeCopy,
+        ] if size == &[64] && dest == &[0x80]));
+        assert!(!ops.contains(&IrOp::CallDataLoad));
+        assert!(ops.windows(3).any(|w| matches!(w, [IrOp::Push(v), IrOp::MLoad, _] if v == &[0xa0])));
+    }
+
+    #[test]
+    fn lower_constructor_folds_const_expression() {
+        let src = "const MAX: uint256 = 2 ** 8 - 1\n\ndef t() -> uint256: return MAX\n";
+        let program = parse_from_source(src).unwrap();
+        let module = lower_program(&program);
+        let ops = &module.constructor_ops;
+        assert_eq!(ops.len(), 3);
+        assert!(matches!(&ops[0], IrOp::Push(v) if v == &[255]));
+        assert!(matches!(ops[2], IrOp::SStore));
+    }
+
+    #[test]
+    fn lower_constructor_require() {
+        let src = "def init(x: uint256):\n    require x > 0\n\ndef t():\n    require true\n";
+        let program = parse_from_source(src).unwrap();
+        let module = lower_program(&program);
+        let ctor = &module.constructor_ops;
+        assert!(ctor.iter().any(|op| matches!(op, IrOp::JumpI(_))));
+        assert!(ctor.iter().any(|op| matches!(op, IrOp::Revert)));
+
+        let ctor_labels: Vec<usize> = ctor
+            .iter()
+            .filter_map(|op| match op {
+                IrOp::JumpDest(l) => Some(*l),
+                _ => None,
+            })
+            .collect();
+        let fn_labels: Vec<usize> = module.functions[0]
+            .ops
+            .iter()
+            .filter_map(|op| match op {
+                IrOp::JumpDest(l) => Some(*l),
+                _ => None,
+            })
+            .collect();
+        assert!(ctor_labels.iter().all(|l| !fn_labels.contains(l)));
+        assert!(crate::verify_module(&module).is_empty());
+    }
+
     #[test]
     fn lower_if_branch() {
-        let src = "def t() -> uint256:\n    if true: return 1\n    else: return 2\n";
+        let src = "def t(a: bool) -> uint256:\n    if a: return 1\n    else: return 2\n";
         let program = parse_from_source(src).unwrap();
         let module = lower_program(&program);
         let ops = &module.functions[0].ops;
@@ -624,6 +3108,495 @@ mod tests {
         assert!(jumpdest_count >= 2);
     }
 
+    #[test]
+    fn lower_asm_block() {
+        let src = "def t():\n    asm: PUSH1 0x01 PUSH1 0x02 ADD\n";
+        let program = parse_from_source(src).unwrap();
+        let module = lower_program(&program);
+        let ops = &module.functions[0].ops;
+        assert!(matches!(
+            &ops[1..4],
+            [IrOp::Push(a), IrOp::Push(b), IrOp::Add] if a == &[1] && b == &[2]
+        ));
+        assert!(crate::verify_module(&module).is_empty());
+    }
+
+    #[test]
+    fn asm_push_pads_to_width() {
+        let op = asm_op("PUSH2", Some(&1u8.into())).unwrap();
+        assert!(matches!(op, IrOp::Push(v) if v == [0, 1]));
+        assert!(asm_op("PUSH1", Some(&256u16.into())).is_none());
+        assert!(asm_op("PUSH1", None).is_none());
+        assert!(asm_op("ADD", Some(&1u8.into())).is_none());
+        assert!(asm_op("JUMPDEST", None).is_none());
+    }
+
+    #[test]
+    fn asm_labels_are_fresh_per_block() {
+        let src = "def jump_over():\n    asm: PUSH1 0x01 JUMPI 1 JUMPDEST 1\n\n\
+                   def t():\n    jump_over()\n    jump_over()\n    asm: PUSH1 0x01 JUMPI 1 JUMPDEST 1\n";
+        let program = parse_from_source(src).unwrap();
+        let module = lower_program(&program);
+        let t = module.functions.iter().find(|f| f.name == "t").unwrap();
+        let jumps: Vec<usize> = t
+            .ops
+            .iter()
+            .filter_map(|op| match op {
+                IrOp::JumpI(l) => Some(*l),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(jumps.len(), 2);
+        assert_ne!(jumps[0], jumps[1]);
+        assert!(crate::verify_module(&module).is_empty());
+    }
+
+    #[test]
+    fn lower_require_membership() {
+        let src = "def t(k: address):\n    require k in balances\n    balances[k] = 1\n";
+        let program = parse_from_source(src).unwrap();
+        let module = lower_program(&program);
+        let ops = &module.functions[0].ops;
+        let require_end = ops.iter().position(|op| matches!(op, IrOp::Revert)).unwrap();
+        let check = &ops[..require_end];
+        assert_eq!(check.iter().filter(|op| matches!(op, IrOp::Keccak256)).count(), 1);
+        assert_eq!(check.iter().filter(|op| matches!(op, IrOp::SLoad)).count(), 1);
+        assert_eq!(check.iter().filter(|op| matches!(op, IrOp::CallDataLoad)).count(), 1);
+        assert!(check
+            .windows(4)
+            .any(|w| matches!(w, [IrOp::SLoad, IrOp::IsZero, IrOp::IsZero, IrOp::JumpI(_)])));
+    }
+
+    #[test]
+    fn lower_if_true_drops_else_branch() {
+        let src = "def t() -> uint256:\n    if true: return 1\n    else: return 2\n";
+        let program = parse_from_source(src).unwrap();
+        let module = lower_program(&program);
+        let ops = &module.functions[0].ops;
+        assert!(!ops.iter().any(|op| matches!(op, IrOp::JumpI(_))));
+        assert!(ops.iter().any(|op| matches!(op, IrOp::Push(v) if v == &[1])));
+        assert!(!ops.iter().any(|op| matches!(op, IrOp::Push(v) if v == &[2])));
+        assert_eq!(ops.iter().filter(|op| matches!(op, IrOp::Return)).count(), 1);
+    }
+
+    #[test]
+    fn lower_if_folded_comparison_takes_else() {
+        let src = "def t() -> uint256:\n    if 2 < 1: return 1\n    else: return 2\n";
+        let program = parse_from_source(src).unwrap();
+        let module = lower_program(&program);
+        let ops = &module.functions[0].ops;
+        assert!(!ops.iter().any(|op| matches!(op, IrOp::JumpI(_) | IrOp::Lt)));
+        assert!(ops.iter().any(|op| matches!(op, IrOp::Push(v) if v == &[2])));
+    }
+
+    #[test]
+    fn lower_empty_string_compare_uses_length() {
+        let program =
+            parse_from_source("def t(s: string) -> bool: return s == \"\"").unwrap();
+        let module = lower_program(&program);
+        let ops = &module.functions[0].ops;
+        let has_length_check = ops.windows(6).any(|w| {
+            matches!(
+                w,
+                [
+                    IrOp::Push(_),
+                    IrOp::CallDataLoad,
+                    IrOp::Push(_),
+                    IrOp::Add,
+                    IrOp::CallDataLoad,
+                    IrOp::IsZero
+                ]
+            )
+        });
+        assert!(has_length_check);
+        assert!(!ops.iter().any(|op| matches!(op, IrOp::Eq | IrOp::Keccak256)));
+    }
+
+    #[test]
+    fn lower_empty_bytes_not_equal() {
+        let program = parse_from_source("def t(b: bytes) -> bool: return b'' != b").unwrap();
+        let module = lower_program(&program);
+        let ops = &module.functions[0].ops;
+        let zeros = ops.iter().filter(|op| matches!(op, IrOp::IsZero)).count();
+        assert_eq!(zeros, 2);
+        assert!(!ops.iter().any(|op| matches!(op, IrOp::Eq)));
+    }
+
+    fn has_backward_jump(ops: &[IrOp]) -> bool {
+        ops.iter().enumerate().any(|(i, op)| match op {
+            IrOp::Jump(l) => ops[..i].iter().any(|o| matches!(o, IrOp::JumpDest(d) if d == l)),
+            _ => false,
+        })
+    }
+
+    #[test]
+    fn helper_called_from_another_function() {
+        let src = "struct Token {\n    balances: mapping[address => uint256]\n}\n\ndef credit(to: address, amount: uint256):\n    balances[to] += amount\n\ndef fee(amount: uint256) -> uint256: return amount / 100\n\ndef transfer(to: address, amount: uint256) -> uint256:\n    credit(to, amount - fee(amount))\n    credit(msg.sender, fee(amount))\n    return balances[to]\n";
+        let program = parse_from_source(src).unwrap();
+        assert!(crate::check_program(&program).is_empty());
+        assert!(crate::verify_module(&lower_program(&program)).is_empty());
+        let mut vm = crate::vm::Vm::from_source(src);
+        let out = vm.call_function("transfer", &[9u8.into(), 1000u32.into()]);
+        assert!(out.is_success(), "{out:?}");
+        assert_eq!(out.word(0), 990u32.into());
+        let out = vm.call_function("transfer", &[9u8.into(), 1000u32.into()]);
+        assert_eq!(out.word(0), 1980u32.into());
+    }
+
+    #[test]
+    fn internal_function_is_lowered_once_and_returns_by_jump() {
+        let src = "def fee(amount: uint256) -> uint256: return amount / 100\n\n\
+                   def t(a: uint256, b: uint256) -> uint256: return fee(a) + fee(b)\n";
+        let program = parse_from_source(src).unwrap();
+        let module = lower_program(&program);
+        let t = module.functions.iter().find(|f| f.name == "t").unwrap();
+        assert_eq!(t.ops.iter().filter(|op| matches!(op, IrOp::Div)).count(), 1);
+        assert_eq!(t.ops.iter().filter(|op| matches!(op, IrOp::PushLabel(_))).count(), 2);
+        assert_eq!(t.ops.iter().filter(|op| matches!(op, IrOp::JumpBack)).count(), 1);
+        assert!(crate::verify_module(&module).is_empty());
+
+        let mut vm = crate::vm::Vm::from_source(src);
+        let out = vm.call_function("t", &[500u32.into(), 1200u32.into()]);
+        assert!(out.is_success(), "{out:?}");
+        assert_eq!(out.word(0), 17u32.into());
+    }
+
+    #[test]
+    fn constructor_jumps_over_its_subroutines() {
+        let src = "struct C {\n    total: uint256\n}\n\n\
+                   def double(x: uint256) -> uint256: return x * 2\n\n\
+                   def init():\n    total = double(3)\n    total = double(total)\n\n\
+                   def get() -> uint256: return total\n";
+        let program = parse_from_source(src).unwrap();
+        let module = lower_program(&program);
+        assert_eq!(module.constructor_ops.iter().filter(|op| matches!(op, IrOp::Mul)).count(), 1);
+        assert!(crate::verify_module(&module).is_empty());
+
+        let mut vm = crate::vm::Vm::from_source(src);
+        assert_eq!(vm.call_function("get", &[]).word(0), 12u32.into());
+    }
+
+    #[test]
+    fn interface_calls_use_call_and_staticcall() {
+        let src = "interface IERC20:\n    def transfer(to: address, amount: uint256) -> bool\n    @view\n    def balanceOf(owner: address) -> uint256\n\ndef t(token: address) -> uint256:\n    require IERC20(token).transfer(msg.sender, 1)\n    return IERC20(token).balanceOf(msg.sender)\n";
+        let program = parse_from_source(src).unwrap();
+        let module = lower_program(&program);
+        let ops = &module.functions[0].ops;
+        assert_eq!(ops.iter().filter(|op| matches!(op, IrOp::Call)).count(), 1);
+        assert_eq!(ops.iter().filter(|op| matches!(op, IrOp::StaticCall)).count(), 1);
+        assert!(ops.iter().any(|op| matches!(op, IrOp::Push(v) if v.starts_with(&[0xa9, 0x05, 0x9c, 0xbb]))));
+        assert!(crate::verify_module(&module).is_empty());
+    }
+
+    #[test]
+    fn lower_two_value_internal_call() {
+        let src = "def divmod(a: uint256, b: uint256) -> (uint256, uint256):\n    return a / b, a % b\n\ndef t(a: uint256):\n    (q, r) = divmod(a, 7)\n";
+        let program = parse_from_source(src).unwrap();
+        let module = lower_program(&program);
+
+        let divmod = &module.functions[0].ops;
+        let ret = divmod.iter().position(|op| matches!(op, IrOp::Return)).unwrap();
+        assert!(matches!(&divmod[ret - 2], IrOp::Push(v) if v == &[0x40]));
+        assert!(matches!(&divmod[ret - 4], IrOp::Push(v) if v == &[0x40]));
+        assert!(matches!(&divmod[ret - 6], IrOp::Push(v) if v == &[0x60]));
+
+        let t = &module.functions[1].ops;
+        assert!(!t.iter().any(|op| matches!(op, IrOp::Return)));
+        assert!(t.iter().any(|op| matches!(op, IrOp::Div)));
+        assert!(t.iter().any(|op| matches!(op, IrOp::Mod)));
+        assert_eq!(t.iter().filter(|op| matches!(op, IrOp::SStore)).count(), 2);
+        let end = t.iter().rposition(|op| matches!(op, IrOp::JumpDest(_))).unwrap();
+        let IrOp::JumpDest(end_label) = t[end] else { unreachable!() };
+        assert!(t.iter().any(|op| matches!(op, IrOp::Jump(l) if *l == end_label)));
+        assert!(crate::verify_module(&module).is_empty());
+    }
+
+    #[test]
+    fn lower_named_return_falls_through() {
+        let src = "def f() -> (result: uint256):\n    result = 5\n";
+        let program = parse_from_source(src).unwrap();
+        let module = lower_program(&program);
+        let ops = &module.functions[0].ops;
+        let tail = &ops[ops.len() - 10..];
+        assert!(matches!(&tail[0], IrOp::Push(v) if v == &[0x05]));
+        assert!(matches!(&tail[1], IrOp::Push(v) if v == &[0x80]));
+        assert!(matches!(tail[2], IrOp::MStore));
+        assert!(matches!(&tail[3], IrOp::Push(v) if v == &[0x80]));
+        assert!(matches!(tail[4], IrOp::MLoad));
+        assert!(matches!(&tail[5], IrOp::Push(v) if v == &[0x40]));
+        assert!(matches!(tail[6], IrOp::MStore));
+        assert!(matches!(tail[9], IrOp::Return));
+        assert!(!ops.iter().any(|op| matches!(op, IrOp::SStore)));
+        assert!(crate::verify_module(&module).is_empty());
+    }
+
+    #[test]
+    fn lower_bytes_index_uses_byte() {
+        let src = "def t(data: bytes) -> uint8: return data[0]";
+        let program = parse_from_source(src).unwrap();
+        let module = lower_program(&program);
+        let ops = &module.functions[0].ops;
+        let at = ops.iter().position(|op| matches!(op, IrOp::Byte)).unwrap();
+        assert!(matches!(&ops[at - 1], IrOp::Push(v) if v == &[0x00]));
+        assert!(matches!(ops[at - 2], IrOp::MLoad));
+        assert!(!ops.iter().any(|op| matches!(op, IrOp::SLoad)));
+    }
+
+    #[test]
+    fn lower_bytes_slice_masks_low_bytes() {
+        let src = "def t(data: bytes) -> bytes: return data[1:5]";
+        let program = parse_from_source(src).unwrap();
+        let module = lower_program(&program);
+        let ops = &module.functions[0].ops;
+        let shl = ops
+            .windows(2)
+            .position(|w| matches!(&w[0], IrOp::Push(v) if v == &[0x08]) && w[1] == IrOp::Shl)
+            .unwrap()
+            + 1;
+        assert!(matches!(ops[shl - 2], IrOp::MLoad));
+        assert!(matches!(&ops[shl + 1], IrOp::Push(v) if v == &[0xe0]));
+        assert!(matches!(ops[shl + 2], IrOp::Shr));
+        assert!(matches!(ops[shl + 4], IrOp::Shl));
+    }
+
+    #[test]
+    fn lower_bytes_param_copies_calldata() {
+        let src = "def t(data: bytes) -> uint8: return data[0]";
+        let program = parse_from_source(src).unwrap();
+        let module = lower_program(&program);
+        let ops = &module.functions[0].ops;
+        assert!(matches!(
+            &ops[4..10],
+            [IrOp::Push(off), IrOp::CallDataLoad, IrOp::Push(four), IrOp::Add, IrOp::Dup(1), IrOp::CallDataLoad]
+                if off == &[0x04] && four == &[0x04]
+        ));
+        assert_eq!(ops.iter().filter(|op| matches!(op, IrOp::CallDataCopy)).count(), 1);
+        assert!(crate::verify_module(&module).is_empty());
+    }
+
+    #[test]
+    fn bytes_param_is_read_from_its_data() {
+        use crate::vm::fixed_bytes;
+        let src = "def first(data: bytes) -> uint8: return data[1]\n\ndef head(tag: uint256, data: bytes) -> bytes: return data[0:4]\n";
+        let mut vm = crate::vm::Vm::from_source(src);
+        let out = vm.call_function("first", &[0x20u8.into(), 5u8.into(), fixed_bytes(b"hello")]);
+        assert!(out.is_success(), "{out:?}");
+        assert_eq!(out.word(0), u32::from(b'e').into());
+
+        let args = [7u8.into(), 0x40u8.into(), 5u8.into(), fixed_bytes(b"hello")];
+        let out = vm.call_function("head", &args);
+        assert_eq!(out.output().len(), 0x60);
+        assert_eq!(out.word(1), 4u8.into());
+        assert_eq!(out.word(2), fixed_bytes(b"hell"));
+    }
+
+    #[test]
+    fn bytes_param_index_reads_any_byte_and_checks_length() {
+        use crate::vm::fixed_bytes;
+        let src = "def at(data: bytes, i: uint256) -> uint8: return data[i]\n\n\
+                   def head(data: bytes) -> bytes: return data[0:4]\n";
+        let mut vm = crate::vm::Vm::from_source(src);
+        let text = b"a string longer than one word, to span two";
+        let call = |vm: &mut crate::vm::Vm, i: usize| {
+            let mut args = vec![0x40u8.into(), i.into(), text.len().into()];
+            args.extend(text.chunks(32).map(fixed_bytes));
+            vm.call_function("at", &args)
+        };
+        assert_eq!(call(&mut vm, 0).word(0), u32::from(b'a').into());
+        assert_eq!(call(&mut vm, 40).word(0), u32::from(text[40]).into());
+        assert!(!call(&mut vm, text.len()).is_success());
+        assert!(!vm.call_function("head", &[0x20u8.into(), 3u8.into(), fixed_bytes(b"abc")]).is_success());
+    }
+
+    #[test]
+    fn dynamic_returns_are_abi_encoded() {
+        use crate::vm::fixed_bytes;
+        let src = "def echo(tag: uint256, s: string) -> string: return s
+
+def raw() -> bytes: return b'cafe'
+";
+        let mut vm = crate::vm::Vm::from_source(src);
+        let text = b"a string longer than one word, to span two";
+        let mut args = vec![9u8.into(), 0x40u8.into(), text.len().into()];
+        args.extend(text.chunks(32).map(fixed_bytes));
+        let out = vm.call_function("echo", &args);
+        assert!(out.is_success(), "{out:?}");
+        assert_eq!(out.output().len(), 0x80);
+        assert_eq!(out.word(0), 0x20u8.into());
+        assert_eq!(out.word(1), text.len().into());
+        assert_eq!(&out.output()[0x40..0x40 + text.len()], text);
+
+        let out = vm.call_function("raw", &[]);
+        assert_eq!(out.output().len(), 0x60);
+        assert_eq!(out.word(1), 2u8.into());
+        assert_eq!(out.word(2), fixed_bytes(b"\xca\xfe"));
+    }
+
+    #[test]
+    fn string_literal_bytes_decodes_escapes() {
+        assert_eq!(string_literal_bytes("héllo").len(), 6);
+        assert_eq!(string_literal_bytes(r#"a\nb\"\x41"#), b"a\nb\"A");
+    }
+
+    #[test]
+    fn string_return_uses_utf8_byte_length() {
+        let src = "def name() -> string: return \"h\u{e9}llo\"";
+        let mut vm = crate::vm::Vm::from_source(src);
+        let out = vm.call_function("name", &[]);
+        assert!(out.is_success(), "{out:?}");
+        assert_eq!(out.output().len(), 96);
+        assert_eq!(out.word(0), 0x20u8.into());
+        assert_eq!(out.word(1), 6u8.into());
+        assert_eq!(&out.output()[64..70], "h\u{e9}llo".as_bytes());
+        assert!(out.output()[70..].iter().all(|&b| b == 0));
+    }
+
+    #[test]
+    fn string_return_pads_multiple_words() {
+        let text = "\u{3b1}".repeat(20);
+        let src = format!("def name() -> string: return \"{text}\"");
+        let mut vm = crate::vm::Vm::from_source(&src);
+        let out = vm.call_function("name", &[]);
+        assert_eq!(out.word(1), 40u8.into());
+        assert_eq!(out.output().len(), 64 + 64);
+        assert_eq!(&out.output()[64..104], text.as_bytes());
+        assert!(out.output()[104..].iter().all(|&b| b == 0));
+    }
+
+    #[test]
+    fn string_const_concatenation_is_inlined() {
+        let src = "const GREETING: string = \"hello\" + \" world\"\n\ndef greet() -> string: return GREETING\n";
+        let program = parse_from_source(src).unwrap();
+        assert!(!lower_program(&program).constructor_ops.contains(&IrOp::SStore));
+        let mut vm = crate::vm::Vm::from_source(src);
+        let out = vm.call_function("greet", &[]);
+        assert!(out.is_success(), "{out:?}");
+        assert_eq!(out.word(1), 11u8.into());
+        assert_eq!(&out.output()[64..75], b"hello world");
+    }
+
+    #[test]
+    fn lower_is_contract_uses_extcodesize() {
+        let src = "def t(to: address) -> bool: return is_contract(to)";
+        let program = parse_from_source(src).unwrap();
+        let module = lower_program(&program);
+        let ops = &module.functions[0].ops;
+        let at = ops.iter().position(|op| matches!(op, IrOp::ExtCodeSize)).unwrap();
+        assert!(matches!(&ops[at - 3..at], [IrOp::CallDataLoad, IrOp::Push(mask), IrOp::And] if mask.len() == 20));
+        assert!(matches!(&ops[at + 1..at + 3], [IrOp::IsZero, IrOp::IsZero]));
+    }
+
+    #[test]
+    fn is_contract_checks_code_at_address() {
+        let mut vm = crate::vm::Vm::from_source("def t(to: address) -> bool: return is_contract(to)");
+        vm.accounts.insert(0xabu8.into(), vec![0x00]);
+        assert_eq!(vm.call_function("t", &[0xabu8.into()]).word(0), 1u8.into());
+        assert_eq!(vm.call_function("t", &[0xcdu8.into()]).word(0), 0u8.into());
+    }
+
+    #[test]
+    fn require_message_reverts_with_error_string() {
+        let src = "def t(x: uint256):\n    require x > 0, \"x must be positive\"\n";
+        let mut vm = crate::vm::Vm::from_source(src);
+        let outcome = vm.call_function("t", &[0u8.into()]);
+        let crate::vm::Outcome::Revert(data) = outcome else { panic!("{outcome:?}") };
+        assert_eq!(&data[..4], &[0x08, 0xc3, 0x79, 0xa0]);
+        assert_eq!(data.len(), 4 + 32 * 3);
+        assert_eq!(data[4 + 31], 0x20);
+        assert_eq!(data[4 + 63], 18);
+        assert_eq!(&data[68..86], b"x must be positive");
+        assert!(vm.call_function("t", &[1u8.into()]).is_success());
+    }
+
+    #[test]
+    fn require_message_spans_several_words() {
+        let message = "caller is not the owner of this vault contract";
+        let src = format!("def t(x: uint256):\n    require x == 1, \"{message}\"\n");
+        let mut vm = crate::vm::Vm::from_source(&src);
+        let outcome = vm.call_function("t", &[0u8.into()]);
+        let crate::vm::Outcome::Revert(data) = outcome else { panic!("{outcome:?}") };
+        assert_eq!(data.len(), 4 + 32 * 4);
+        assert_eq!(data[4 + 63] as usize, message.len());
+        assert_eq!(&data[68..68 + message.len()], message.as_bytes());
+        assert!(data[68 + message.len()..].iter().all(|&b| b == 0));
+    }
+
+    #[test]
+    fn lower_tuple_swap_assignment() {
+        let src = "def t():\n    let a = 1\n    let b = 2\n    (a, b) = (b, a)\n";
+        let program = parse_from_source(src).unwrap();
+        let module = lower_program(&program);
+        let ops = &module.functions[0].ops;
+        let stores: Vec<&Vec<u8>> = ops
+            .windows(2)
+            .filter_map(|w| match w {
+                [IrOp::Push(off), IrOp::MStore] => Some(off),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(stores, [&vec![0x80], &vec![0xa0], &vec![0xa0], &vec![0x80]]);
+    }
+
+    #[test]
+    fn lower_for_range_ascending_step() {
+        let src = "def t():\n    for i in range(0, 10, 2):\n        x = i\n";
+        let program = parse_from_source(src).unwrap();
+        let module = lower_program(&program);
+        let ops = &module.functions[0].ops;
+        assert!(has_backward_jump(ops));
+        assert!(ops.iter().any(|op| matches!(op, IrOp::Lt)));
+        assert!(ops.windows(2).any(|w| matches!(w, [IrOp::Push(v), IrOp::Add] if v == &[2])));
+        assert!(!ops.iter().any(|op| matches!(op, IrOp::Gt)));
+        assert!(crate::verify_module(&module).is_empty());
+    }
+
+    #[test]
+    fn for_range_matches_python_bounds() {
+        let src = "def sum_to(n: uint256) -> uint256:\n    let mut total = 0\n    for i in range(n):\n        total += i\n    return total\n\ndef sum_between(a: uint256, b: uint256) -> uint256:\n    let mut total = 0\n    for i in range(a, b):\n        total += i\n    return total\n\ndef count(a: uint256, b: uint256) -> uint256:\n    let mut n = 0\n    for i in range(a, b):\n        n += 1\n    return n\n";
+        let mut vm = crate::vm::Vm::from_source(src);
+        assert_eq!(vm.call_function("sum_to", &[5u8.into()]).word(0), 10u8.into());
+        assert_eq!(vm.call_function("sum_to", &[0u8.into()]).word(0), 0u8.into());
+        assert_eq!(vm.call_function("sum_between", &[2u8.into(), 5u8.into()]).word(0), 9u8.into());
+        assert_eq!(vm.call_function("count", &[2u8.into(), 2u8.into()]).word(0), 0u8.into());
+        assert_eq!(vm.call_function("count", &[5u8.into(), 2u8.into()]).word(0), 0u8.into());
+    }
+
+    #[test]
+    fn nested_for_loops_and_require_in_body() {
+        let src = "def grid(n: uint256, m: uint256) -> uint256:\n    let mut cells = 0\n    for i in range(n):\n        for j in range(1, m):\n            cells += j\n    return cells\n\ndef capped(n: uint256) -> uint256:\n    let mut total = 0\n    for i in range(n):\n        require i < 3, \"too many\"\n        total += i\n    return total\n";
+        let program = parse_from_source(src).unwrap();
+        assert!(crate::verify_module(&lower_program(&program)).is_empty());
+        let mut vm = crate::vm::Vm::from_source(src);
+        assert_eq!(vm.call_function("grid", &[3u8.into(), 4u8.into()]).word(0), 18u8.into());
+        assert_eq!(vm.call_function("grid", &[0u8.into(), 4u8.into()]).word(0), 0u8.into());
+        assert_eq!(vm.call_function("capped", &[3u8.into()]).word(0), 3u8.into());
+        assert!(matches!(vm.call_function("capped", &[4u8.into()]), crate::vm::Outcome::Revert(_)));
+    }
+
+    #[test]
+    fn break_and_continue_jump_to_loop_labels() {
+        let src = "def first_multiple(n: uint256, k: uint256) -> uint256:\n    let mut found = 0\n    for i in range(1, n):\n        if i % k != 0:\n            continue\n        found = i\n        break\n    return found\n\ndef countdown(n: uint256) -> uint256:\n    let mut i = n\n    while true:\n        if i == 0:\n            break\n        i -= 1\n    return i\n";
+        let program = parse_from_source(src).unwrap();
+        assert!(crate::verify_module(&lower_program(&program)).is_empty());
+        let mut vm = crate::vm::Vm::from_source(src);
+        assert_eq!(vm.call_function("first_multiple", &[20u8.into(), 7u8.into()]).word(0), 7u8.into());
+        assert_eq!(vm.call_function("first_multiple", &[5u8.into(), 7u8.into()]).word(0), 0u8.into());
+        assert_eq!(vm.call_function("countdown", &[4u8.into()]).word(0), 0u8.into());
+    }
+
+    #[test]
+    fn lower_for_range_descending() {
+        let src = "def t():\n    for i in range(10, 0, -1):\n        x = i\n";
+        let program = parse_from_source(src).unwrap();
+        let module = lower_program(&program);
+        let ops = &module.functions[0].ops;
+        assert!(has_backward_jump(ops));
+        assert!(ops.iter().any(|op| matches!(op, IrOp::Gt)));
+        assert!(!ops.iter().any(|op| matches!(op, IrOp::Lt)));
+        assert!(ops.windows(3).any(|w| matches!(w, [IrOp::Push(v), IrOp::Swap(1), IrOp::Sub] if v == &[1])));
+        assert!(crate::verify_module(&module).is_empty());
+    }
+
     #[test]
     fn lower_emit_produces_log1() {
         let src = "event Transfer(from: address, to: address, amount: uint256)\n\ndef t():\n    emit Transfer(msg.sender, msg.sender, 100)\n";
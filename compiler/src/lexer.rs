@@ -27,12 +27,32 @@ pub enum Token {
     Const,
     #[token("struct")]
     Struct,
+    #[token("enum")]
+    Enum,
+    #[token("modifier")]
+    Modifier,
     #[token("require")]
     Require,
     #[token("event")]
     Event,
     #[token("emit")]
     Emit,
+    #[token("error")]
+    CustomError,
+    #[token("revert")]
+    Revert,
+    #[token("indexed")]
+    Indexed,
+    #[token("interface")]
+    Interface,
+    #[token("break")]
+    Break,
+    #[token("continue")]
+    Continue,
+    #[token("import")]
+    Import,
+    #[token("asm")]
+    Asm,
     #[token("in")]
     In,
     #[token("true")]
@@ -40,18 +60,18 @@ pub enum Token {
     #[token("false")]
     False,
 
-    #[token("uint256")]
-    Uint256,
-    #[token("uint8")]
-    Uint8,
-    #[token("int256")]
-    Int256,
+    #[regex(r"uint(8|16|24|32|40|48|56|64|72|80|88|96|104|112|120|128|136|144|152|160|168|176|184|192|200|208|216|224|232|240|248|256)", |lex| lex.slice()[4..].parse::<u16>().ok(), priority = 3)]
+    Uint(u16),
+    #[regex(r"int(8|16|24|32|40|48|56|64|72|80|88|96|104|112|120|128|136|144|152|160|168|176|184|192|200|208|216|224|232|240|248|256)", |lex| lex.slice()[3..].parse::<u16>().ok(), priority = 3)]
+    Int(u16),
     #[token("bool")]
     Bool,
     #[token("address")]
     Address,
     #[token("bytes")]
     Bytes,
+    #[regex(r"bytes([1-9]|[12][0-9]|3[0-2])", |lex| lex.slice()[5..].parse::<u8>().ok(), priority = 3)]
+    FixedBytes(u8),
     #[token("string")]
     String,
 
@@ -119,6 +139,10 @@ pub enum Token {
     Dot,
     #[token("->")]
     Arrow,
+    #[token("=>")]
+    FatArrow,
+    #[token("@")]
+    At,
 
     #[token("<", priority = 1)]
     LAngle,
@@ -128,6 +152,7 @@ pub enum Token {
     #[regex(r"[0-9]+", |lex| {
         BigUint::parse_bytes(lex.slice().as_bytes(), 10)
     })]
+    #[regex(r"[0-9]+(\.[0-9]+)?e[0-9]+", |lex| scientific(lex.slice()))]
     Number(BigUint),
 
     #[regex(r#""([^"\\]|\\.)*""#, |lex| {
@@ -155,6 +180,10 @@ pub enum Token {
     })]
     HexNumber(BigUint),
 
+    /// Exactly 40 hex digits, kept as written so the checksum can be checked.
+    #[regex(r"0x[0-9a-fA-F]{40}", |lex| lex.slice()[2..].to_string(), priority = 10)]
+    AddressLiteral(String),
+
     #[regex(r"[a-zA-Z_][a-zA-Z0-9_]*", |lex| lex.slice().to_string())]
     Identifier(String),
 
@@ -172,10 +201,12 @@ pub enum Token {
     #[regex(r"[ \t]+\n", |_| ())]
     WhitespaceOnlyLine,
 
-    IndentationError,
-    MixedIndentationError,
+    IndentationError((usize, usize)),
+    MixedIndentationError((usize, usize)),
     InvalidChar(char),
     MalformedNumber(String),
+    /// Scientific notation whose value has a fractional part, like `1.25e1`.
+    FractionalNumber(String),
     UnterminatedString,
     InvalidHexDigit(String),
     InvalidBytesLiteral(String),
@@ -189,6 +220,7 @@ impl fmt::Display for Token {
             Token::Number(n) => write!(f, "Number({})", n),
             Token::StringLiteral(s) => write!(f, "String(\"{}\")", s),
             Token::HexNumber(n) => write!(f, "Hex(0x{:x})", n),
+            Token::AddressLiteral(digits) => write!(f, "Address(0x{})", digits),
             Token::BytesLiteral(bytes) => {
                 write!(f, "Bytes(0x")?;
                 for byte in bytes {
@@ -196,11 +228,14 @@ impl fmt::Display for Token {
                 }
                 write!(f, ")")
             }
-            Token::MixedIndentationError => write!(f, "MixedIndentationError"),
-            Token::IndentationError => write!(f, "IndentationError"),
+            Token::MixedIndentationError((line, col)) => {
+                write!(f, "MixedIndentationError({line}:{col})")
+            }
+            Token::IndentationError((line, col)) => write!(f, "IndentationError({line}:{col})"),
             Token::WhitespaceOnlyLine => write!(f, "WhitespaceOnlyLine"),
             Token::InvalidChar(ch) => write!(f, "InvalidChar('{}')", ch),
             Token::MalformedNumber(s) => write!(f, "MalformedNumber(\"{}\")", s),
+            Token::FractionalNumber(s) => write!(f, "FractionalNumber(\"{}\")", s),
             Token::UnterminatedString => write!(f, "UnterminatedString"),
             Token::InvalidHexDigit(s) => write!(f, "InvalidHexDigit(\"{}\")", s),
             Token::InvalidBytesLiteral(s) => write!(f, "InvalidBytesLiteral(\"{}\")", s),
@@ -210,14 +245,94 @@ impl fmt::Display for Token {
     }
 }
 
+/// `1e18` as `1 * 10^18`. Exponents past 77 cannot fit in 256 bits.
+/// `2.5e6` is 2500000. `None` when the exponent is out of range or too
+/// small to make the value whole.
+fn scientific(text: &str) -> Option<BigUint> {
+    let (mantissa, exponent) = text.split_once('e')?;
+    let exponent: u32 = exponent.parse().ok().filter(|&e| e <= 77)?;
+    let (whole, fraction) = mantissa.split_once('.').unwrap_or((mantissa, ""));
+    let fraction = fraction.trim_end_matches('0');
+    let shift = exponent.checked_sub(fraction.len() as u32)?;
+    let digits = format!("{whole}{fraction}");
+    Some(BigUint::parse_bytes(digits.as_bytes(), 10)? * BigUint::from(10u8).pow(shift))
+}
+
+/// True for a well-formed `digits.digits e digits` literal that `scientific`
+/// rejected only because its value is not whole.
+fn is_fractional_scientific(text: &str) -> bool {
+    let all_digits = |s: &str| !s.is_empty() && s.bytes().all(|b| b.is_ascii_digit());
+    text.split_once('e').is_some_and(|(mantissa, exponent)| {
+        all_digits(exponent)
+            && exponent.parse::<u32>().is_ok_and(|e| e <= 77)
+            && mantissa
+                .split_once('.')
+                .is_some_and(|(whole, fraction)| all_digits(whole) && all_digits(fraction))
+    })
+}
+
+/// A malformed piece of source, reported alongside the error token the lexer
+/// yields in its place.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LexDiagnostic {
+    /// Stable identifier, like `E0001` for an unexpected character.
+    pub code: &'static str,
+    pub message: String,
+    pub span: std::ops::Range<usize>,
+    /// 1-based line and column of `span.start`.
+    pub line: usize,
+    pub col: usize,
+    /// The offending source text.
+    pub slice: String,
+}
+
+impl fmt::Display for LexDiagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}: {}", self.line, self.col, self.message)
+    }
+}
+
+impl Token {
+    /// True for the tokens the lexer yields in place of malformed source.
+    pub fn is_error(&self) -> bool {
+        error_message(self).is_some()
+    }
+}
+
+fn error_message(token: &Token) -> Option<(&'static str, String)> {
+    let diag = match token {
+        Token::InvalidChar(ch) => ("E0001", format!("unexpected character `{ch}`")),
+        Token::Error => ("E0001", "unexpected character".to_string()),
+        Token::UnterminatedString => ("E0002", "unterminated string literal".to_string()),
+        Token::MalformedNumber(text) => ("E0003", format!("malformed number `{text}`")),
+        Token::FractionalNumber(text) => ("E0004", format!("`{text}` is not a whole number")),
+        Token::InvalidHexDigit(text) => ("E0005", format!("invalid hex literal `{text}`")),
+        Token::InvalidBytesLiteral(text) => ("E0006", format!("invalid bytes literal `{text}`")),
+        Token::MixedIndentationError((line, _)) => {
+            ("E0007", format!("mixed tabs and spaces in indentation on line {line}"))
+        }
+        Token::IndentationError((line, _)) => {
+            ("E0008", format!("inconsistent indentation on line {line}"))
+        }
+        _ => return None,
+    };
+    Some(diag)
+}
+
 pub struct PyraLexer<'a> {
     inner: logos::Lexer<'a, Token>,
     indent_stack: Vec<usize>,
     pending_dedents: usize,
     pending_indent: bool,
+    pending_newline: bool,
     pending_token: Option<Token>,
     at_line_start: bool,
     indent_type: Option<IndentType>,
+    /// Open `(` and `[` not yet closed. Line breaks inside them are not
+    /// newlines and do not change indentation. Braces keep their newlines,
+    /// which separate struct fields.
+    bracket_depth: usize,
+    diagnostics: Vec<LexDiagnostic>,
 }
 
 #[derive(Debug, Clone, PartialEq)]
@@ -233,13 +348,46 @@ impl<'a> PyraLexer<'a> {
             indent_stack: vec![0],
             pending_dedents: 0,
             pending_indent: false,
+            pending_newline: false,
             pending_token: None,
             at_line_start: true,
             indent_type: None,
+            bracket_depth: 0,
+            diagnostics: Vec::new(),
         }
     }
 
+    /// Everything malformed seen so far, in source order.
+    pub fn diagnostics(&self) -> &[LexDiagnostic] {
+        &self.diagnostics
+    }
+
     pub fn next_token(&mut self) -> Option<Token> {
+        let token = self.lex_token()?;
+        if let Some((code, message)) = error_message(&token) {
+            let span = match token {
+                // Point at the indentation itself.
+                Token::IndentationError(_) | Token::MixedIndentationError(_) => {
+                    let start = self.inner.span().start;
+                    let source = self.inner.source();
+                    source[..start].rfind('\n').map_or(0, |i| i + 1)..start
+                }
+                _ => self.inner.span(),
+            };
+            let (line, col) = self.line_col_at(span.start);
+            self.diagnostics.push(LexDiagnostic {
+                code,
+                message,
+                slice: self.inner.source()[span.clone()].to_string(),
+                line,
+                col,
+                span,
+            });
+        }
+        Some(token)
+    }
+
+    fn lex_token(&mut self) -> Option<Token> {
         if self.pending_indent {
             self.pending_indent = false;
             return Some(Token::Indent);
@@ -250,11 +398,19 @@ impl<'a> PyraLexer<'a> {
             return Some(Token::Dedent);
         }
 
+        if self.pending_newline {
+            self.pending_newline = false;
+            return Some(Token::Newline);
+        }
+
         if let Some(tok) = self.pending_token.take() {
             return Some(tok);
         }
 
         match self.inner.next() {
+            Some(Ok(Token::Newline | Token::WhitespaceOnlyLine)) if self.bracket_depth > 0 => {
+                self.lex_token()
+            }
             Some(Ok(token)) => match token {
                 Token::Newline => {
                     self.at_line_start = true;
@@ -265,6 +421,13 @@ impl<'a> PyraLexer<'a> {
                     Some(Token::Newline)
                 }
                 _ => {
+                    match token {
+                        Token::LParen | Token::LBracket => self.bracket_depth += 1,
+                        Token::RParen | Token::RBracket => {
+                            self.bracket_depth = self.bracket_depth.saturating_sub(1)
+                        }
+                        _ => {}
+                    }
                     if self.at_line_start {
                         if let Some(error_token) = self.handle_indentation() {
                             return Some(error_token);
@@ -277,6 +440,7 @@ impl<'a> PyraLexer<'a> {
                                 Token::Indent
                             } else {
                                 self.pending_dedents -= 1;
+                                self.pending_newline = true;
                                 Token::Dedent
                             };
 
@@ -333,7 +497,7 @@ impl<'a> PyraLexer<'a> {
         }
 
         if has_spaces && has_tabs {
-            return Some(Token::MixedIndentationError);
+            return Some(self.mixed_indentation());
         }
 
         if indent > 0 {
@@ -348,7 +512,7 @@ impl<'a> PyraLexer<'a> {
                     self.indent_type = Some(current_indent_type);
                 }
                 Some(prev_type) if *prev_type != current_indent_type => {
-                    return Some(Token::MixedIndentationError);
+                    return Some(self.mixed_indentation());
                 }
                 _ => {}
             }
@@ -361,7 +525,8 @@ impl<'a> PyraLexer<'a> {
             self.pending_indent = true;
         } else if indent < current_level {
             if !self.indent_stack.contains(&indent) {
-                return Some(Token::IndentationError);
+                let (line, col) = self.line_col();
+                return Some(Token::IndentationError((line, col)));
             }
 
             while let Some(&level) = self.indent_stack.last() {
@@ -377,23 +542,35 @@ impl<'a> PyraLexer<'a> {
     }
 
     fn analyze_error(&mut self) -> Token {
-        let _current_slice = self.inner.slice();
-        let remaining = self.inner.remainder();
+        let source = self.inner.source();
+        let start = self.inner.span().start;
+        let remaining = &source[start..];
+
+        let digits = source[..start].trim_end_matches(|c: char| c.is_ascii_hexdigit());
+        if digits.ends_with("0x") {
+            if let Some(ch) = remaining.chars().next() {
+                return Token::InvalidHexDigit(format!("{}{ch}", &source[digits.len() - 2..start]));
+            }
+        }
 
         if let Some(first_char) = remaining.chars().next() {
             match first_char {
-                '@' | '#' | '$' | '`' | '~' => {
+                '#' | '$' | '`' | '~' => {
                     return Token::InvalidChar(first_char);
                 }
 
-                '"' => {
-                    if self.is_unterminated_string(remaining) {
-                        return Token::UnterminatedString;
-                    }
+                '"' if self.is_unterminated_string(remaining) => {
+                    return Token::UnterminatedString;
                 }
 
                 '0'..='9' => {
                     if let Some(malformed) = self.check_malformed_number(remaining) {
+                        // Swallow the whole literal so its tail is not lexed again.
+                        let seen = self.inner.span().len();
+                        self.inner.bump(malformed.len().saturating_sub(seen));
+                        if is_fractional_scientific(&malformed) {
+                            return Token::FractionalNumber(malformed);
+                        }
                         return Token::MalformedNumber(malformed);
                     }
                 }
@@ -523,9 +700,17 @@ impl<'a> PyraLexer<'a> {
         None
     }
 
+    fn mixed_indentation(&self) -> Token {
+        let (line, col) = self.line_col();
+        Token::MixedIndentationError((line, col))
+    }
+
     pub fn line_col(&self) -> (usize, usize) {
+        self.line_col_at(self.inner.span().start)
+    }
+
+    fn line_col_at(&self, pos: usize) -> (usize, usize) {
         let source = self.inner.source();
-        let pos = self.inner.span().start;
 
         let mut line = 1;
         let mut col = 1;
@@ -585,7 +770,7 @@ mod tests {
                 Token::Comma,
                 Token::Identifier("amount".to_string()),
                 Token::Colon,
-                Token::Uint256,
+                Token::Uint(256),
                 Token::RParen,
                 Token::Colon,
             ]
@@ -609,6 +794,50 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_scientific_numbers() {
+        let tokens: Vec<Token> = PyraLexer::new("1e18 2e3 0x1e18 e18").collect();
+        assert_eq!(
+            tokens,
+            vec![
+                Token::Number(BigUint::parse_bytes(b"1000000000000000000", 10).unwrap()),
+                Token::Number(BigUint::from(2000u64)),
+                Token::HexNumber(BigUint::from(0x1e18u64)),
+                Token::Identifier("e18".into()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_fixed_bytes_types() {
+        let tokens: Vec<Token> = PyraLexer::new("bytes bytes1 bytes4 bytes32 bytes33 bytes0").collect();
+        assert_eq!(
+            tokens,
+            vec![
+                Token::Bytes,
+                Token::FixedBytes(1),
+                Token::FixedBytes(4),
+                Token::FixedBytes(32),
+                Token::Identifier("bytes33".into()),
+                Token::Identifier("bytes0".into()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_scientific_with_fractional_mantissa() {
+        let tokens: Vec<Token> = PyraLexer::new("1.5e18 2.5e6 1.250e2 1.25e1").collect();
+        assert_eq!(
+            tokens,
+            vec![
+                Token::Number(BigUint::from(1_500_000_000_000_000_000u64)),
+                Token::Number(BigUint::from(2_500_000u64)),
+                Token::Number(BigUint::from(125u64)),
+                Token::FractionalNumber("1.25e1".into()),
+            ]
+        );
+    }
+
     #[test]
     fn test_large_numbers() {
         let source =
@@ -625,6 +854,24 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_integer_widths() {
+        let tokens: Vec<Token> = PyraLexer::new("uint8 uint16 uint128 int8 int64 int256 uint7 int264").collect();
+        assert_eq!(
+            tokens,
+            vec![
+                Token::Uint(8),
+                Token::Uint(16),
+                Token::Uint(128),
+                Token::Int(8),
+                Token::Int(64),
+                Token::Int(256),
+                Token::Identifier("uint7".into()),
+                Token::Identifier("int264".into()),
+            ]
+        );
+    }
+
     #[test]
     fn test_comparison_vs_generics() {
         let source = "a < b > c";
@@ -813,6 +1060,23 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_line_breaks_inside_brackets_are_ignored() {
+        let source = "f(a,\n      b)\nx = [1,\n\n  2]\n";
+        let tokens: Vec<Token> = PyraLexer::new(source).collect();
+        let newlines = tokens.iter().filter(|t| **t == Token::Newline).count();
+        assert_eq!(newlines, 2);
+        assert!(!tokens.iter().any(|t| matches!(t, Token::Indent | Token::Dedent)));
+    }
+
+    #[test]
+    fn test_forty_digit_hex_is_address() {
+        let source = "0x5aAeb6053F3E94C9b9A09f33669435E7Ef1BeAed 0x5aAeb6053F3E94C9b9A09f33669435E7Ef1BeAed0";
+        let tokens: Vec<Token> = PyraLexer::new(source).collect();
+        assert_eq!(tokens[0], Token::AddressLiteral("5aAeb6053F3E94C9b9A09f33669435E7Ef1BeAed".into()));
+        assert!(matches!(tokens[1], Token::HexNumber(_)));
+    }
+
     #[test]
     fn test_mixed_indentation_error() {
         let source = "def func():\n    line1\n\tline2";
@@ -822,7 +1086,45 @@ mod tests {
 
         assert!(tokens
             .iter()
-            .any(|t| matches!(t, Token::MixedIndentationError)));
+            .any(|t| matches!(t, Token::MixedIndentationError(..))));
+    }
+
+    #[test]
+    fn test_mixed_indentation_reports_line() {
+        let source = "def func():\n    a = 1\n    b = 2\n\tc = 3\n";
+        let tokens: Vec<Token> = PyraLexer::new(source).collect();
+        assert!(tokens.contains(&Token::MixedIndentationError((4, 2))));
+
+        let source = "def func():\n    if x:\n        a\n   b\n";
+        let tokens: Vec<Token> = PyraLexer::new(source).collect();
+        assert!(tokens.contains(&Token::IndentationError((4, 4))));
+    }
+
+    #[test]
+    fn test_diagnostics_locate_bad_source() {
+        let source = "let a = 1\nlet b = 1.2.3 $\n";
+        let mut lexer = PyraLexer::new(source);
+        let tokens: Vec<Token> = lexer.by_ref().collect();
+        assert!(tokens.iter().any(Token::is_error));
+        let diags = lexer.diagnostics();
+        assert_eq!(diags.len(), 2);
+        assert_eq!(diags[0].message, "malformed number `1.2.3`");
+        assert_eq!(diags[0].slice, "1.2.3");
+        assert_eq!((diags[0].line, diags[0].col), (2, 9));
+        assert_eq!(diags[1].slice, "$");
+        assert_eq!(&source[diags[1].span.clone()], "$");
+        assert_eq!(diags[1].to_string(), "2:15: unexpected character `$`");
+    }
+
+    #[test]
+    fn test_indentation_diagnostic_spans_the_indent() {
+        let source = "def func():\n    a = 1\n\tb = 2\n";
+        let mut lexer = PyraLexer::new(source);
+        lexer.by_ref().for_each(drop);
+        let diag = &lexer.diagnostics()[0];
+        assert_eq!(diag.message, "mixed tabs and spaces in indentation on line 3");
+        assert_eq!(diag.slice, "\t");
+        assert_eq!((diag.line, diag.col), (3, 1));
     }
 
     #[test]
@@ -834,7 +1136,7 @@ mod tests {
 
         assert!(!tokens
             .iter()
-            .any(|t| matches!(t, Token::MixedIndentationError)));
+            .any(|t| matches!(t, Token::MixedIndentationError(..))));
     }
 
     #[test]
@@ -846,7 +1148,16 @@ mod tests {
 
         assert!(!tokens
             .iter()
-            .any(|t| matches!(t, Token::MixedIndentationError)));
+            .any(|t| matches!(t, Token::MixedIndentationError(..))));
+    }
+
+    #[test]
+    fn test_dedent_followed_by_newline() {
+        let source = "def f():\n    if x:\n        a\n    b\n";
+        let tokens: Vec<Token> = PyraLexer::new(source).collect();
+        let dedent = tokens.iter().position(|t| matches!(t, Token::Dedent)).unwrap();
+        assert!(matches!(tokens[dedent + 1], Token::Newline));
+        assert_eq!(tokens[dedent + 2], Token::Identifier("b".to_string()));
     }
 
     #[test]
@@ -856,7 +1167,7 @@ mod tests {
 
         let tokens: Vec<Token> = lexer.collect();
 
-        assert!(tokens.iter().any(|t| matches!(t, Token::IndentationError)));
+        assert!(tokens.iter().any(|t| matches!(t, Token::IndentationError(..))));
     }
 
     #[test]
@@ -868,7 +1179,7 @@ mod tests {
 
         assert!(!tokens
             .iter()
-            .any(|t| matches!(t, Token::IndentationError | Token::MixedIndentationError)));
+            .any(|t| matches!(t, Token::IndentationError(..) | Token::MixedIndentationError(..))));
     }
 
     #[test]
@@ -1,26 +1,64 @@
 pub mod ast;
 pub mod abi;
+pub mod cache;
 pub mod compiler;
 pub mod codegen;
+pub mod diagnostics;
 pub mod evm;
+pub mod formatter;
 pub mod gas;
 pub mod ir;
 pub mod lexer;
+pub mod optimizer;
 pub mod parser;
+pub mod project;
+pub mod resolver;
+pub mod runtime;
 pub mod security;
+pub mod standard_json;
 pub mod storage;
+pub mod test_runner;
 pub mod typer;
 pub mod verifier;
+mod vm;
 
 pub use ast::*;
-pub use abi::{program_to_abi_json, AbiError};
-pub use compiler::{compile_file, compile_file_to_abi, compile_file_to_abi_and_bin, CompileError};
-pub use codegen::{program_to_deploy_bytecode, program_to_runtime_bytecode, CodegenError};
+pub use abi::{method_identifiers, program_to_abi_json, program_to_sorted_abi_json, AbiError};
+pub use cache::{BuildCache, CachedBuild, CACHE_DIR};
+pub use compiler::{
+    compile_file, compile_file_to_abi, compile_file_to_abi_and_bin,
+    compile_file_to_abi_and_bin_with_options, compile_file_with_options, load_program,
+    compile_file_to_artifacts, compile_file_to_foundry_artifact, compile_file_to_hardhat_artifact, compile_sources,
+    verify_file, CompileError, CompileOptions, Output, OutputFormat,
+};
+pub use codegen::{
+    hardened_module, module_to_deploy_bytecode, module_to_eof_bytecode, module_to_runtime_bytecode,
+    program_to_deploy_bytecode, program_to_deploy_bytecode_with_config,
+    program_to_deploy_bytecode_with_source_map, program_to_eof_bytecode,
+    program_to_eof_bytecode_with_config, program_to_runtime_bytecode,
+    program_to_runtime_bytecode_with_config, program_to_runtime_bytecode_with_passes,
+    program_to_runtime_bytecode_with_source_map, CodegenError, EvmVersion, MappedBytecode,
+};
+pub use diagnostics::{Diagnostic, Severity};
+pub use evm::{disassemble, disassembly_listing, mnemonic, Instruction};
+pub use formatter::format_source;
 pub use gas::{GasReport, FunctionGas};
-pub use ir::{lower_program, IrModule, IrFunction, IrOp};
-pub use lexer::{PyraLexer, Token};
-pub use parser::{parse_from_source, parse_program};
-pub use security::{harden, add_reentrancy_guard};
+pub use ir::{
+    lower_program, lower_program_with_opt_level, lower_program_with_spans, IrModule, IrFunction, IrOp,
+};
+pub use lexer::{LexDiagnostic, PyraLexer, Token};
+pub use optimizer::{fold_constants, OptLevel};
+pub use parser::{parse_from_source, parse_program, parse_with_recovery};
+pub use project::{find_root, init_project, pyra_files, ArtifactFormat, Manifest, Project};
+pub use runtime::{CallResult, ContractHandle};
+pub use security::{
+    add_calldata_size_guard, add_callvalue_guard, add_reentrancy_guard,
+    add_reentrancy_guard_with_mode, harden, GuardMode, HardenConfig,
+};
+pub use standard_json::compile_standard_json;
 pub use storage::{StorageLayout, StorageSlot, StorageKind};
-pub use typer::{check_program, TypeError};
+pub use test_runner::{run_test_file, run_tests, TestResult};
+pub use typer::{
+    check_program, check_program_with_options, check_warnings, CheckOptions, TypeError, TypeWarning,
+};
 pub use verifier::{verify_module, VerifyError};
@@ -0,0 +1,449 @@
+use crate::ir::IrOp;
+use crate::{BinaryOp, Expression, UnaryOp};
+use num_bigint::BigUint;
+
+const WORD_BITS: u64 = 256;
+const MAX_INTERMEDIATE_BITS: u64 = 512;
+
+/// Which optimizer passes run while a program is lowered.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub enum OptLevel {
+    /// Every expression is lowered as written.
+    O0,
+    /// Constant folding, dead `if` branch removal and identity rewrites
+    /// such as `x + 0`.
+    O1,
+    /// Everything in `O1`, plus caching repeated subexpressions, storage
+    /// reads included, in memory.
+    #[default]
+    O2,
+}
+
+impl OptLevel {
+    pub fn name(self) -> &'static str {
+        match self {
+            OptLevel::O0 => "O0",
+            OptLevel::O1 => "O1",
+            OptLevel::O2 => "O2",
+        }
+    }
+
+    pub fn folds(self) -> bool {
+        self >= OptLevel::O1
+    }
+
+    pub fn caches(self) -> bool {
+        self >= OptLevel::O2
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum ConstValue {
+    Int(BigUint),
+    Bool(bool),
+    Str(String),
+}
+
+pub fn fold_constants(expr: &Expression) -> Expression {
+    match eval_const(expr) {
+        Some(ConstValue::Int(n)) if n.bits() <= WORD_BITS => return Expression::Number(n),
+        Some(ConstValue::Bool(b)) => return Expression::Bool(b),
+        Some(ConstValue::Str(s)) => return Expression::String(s),
+        _ => {}
+    }
+
+    match expr {
+        Expression::Binary(op, left, right) => {
+            let folded = Expression::Binary(
+                op.clone(),
+                Box::new(fold_constants(left)),
+                Box::new(fold_constants(right)),
+            );
+            simplify_identity(&folded).unwrap_or(folded)
+        }
+        Expression::Unary(op, operand) => {
+            Expression::Unary(op.clone(), Box::new(fold_constants(operand)))
+        }
+        Expression::Call(callee, args) => Expression::Call(
+            Box::new(fold_constants(callee)),
+            args.iter().map(fold_constants).collect(),
+        ),
+        Expression::Index(base, key) => Expression::Index(
+            Box::new(fold_constants(base)),
+            Box::new(fold_constants(key)),
+        ),
+        Expression::Slice(base, start, end) => Expression::Slice(
+            Box::new(fold_constants(base)),
+            Box::new(fold_constants(start)),
+            Box::new(fold_constants(end)),
+        ),
+        Expression::Member(base, field) => {
+            Expression::Member(Box::new(fold_constants(base)), field.clone())
+        }
+        Expression::StructInit(name, fields) => Expression::StructInit(
+            name.clone(),
+            fields
+                .iter()
+                .map(|(f, v)| (f.clone(), fold_constants(v)))
+                .collect(),
+        ),
+        Expression::Tuple(items) => Expression::Tuple(items.iter().map(fold_constants).collect()),
+        other => other.clone(),
+    }
+}
+
+/// Rewrites `x + 0`, `x - 0`, `x * 1` and `x * 0` (either operand order
+/// where it commutes). `x * 0` only becomes `0` when `x` has no side effects.
+pub fn simplify_identity(expr: &Expression) -> Option<Expression> {
+    let Expression::Binary(op, left, right) = expr else { return None };
+    let is = |e: &Expression, v: u8| matches!(eval_const(e), Some(ConstValue::Int(n)) if n == BigUint::from(v));
+    let zero = || Expression::Number(BigUint::from(0u8));
+    match op {
+        BinaryOp::Add if is(right, 0) => Some((**left).clone()),
+        BinaryOp::Add if is(left, 0) => Some((**right).clone()),
+        BinaryOp::Sub if is(right, 0) => Some((**left).clone()),
+        BinaryOp::Mul if is(right, 1) => Some((**left).clone()),
+        BinaryOp::Mul if is(left, 1) => Some((**right).clone()),
+        BinaryOp::Mul if is(right, 0) && is_side_effect_free(left) => Some(zero()),
+        BinaryOp::Mul if is(left, 0) && is_side_effect_free(right) => Some(zero()),
+        _ => None,
+    }
+}
+
+/// Literals and plain reads. Arithmetic is excluded because checked math can revert.
+fn is_side_effect_free(expr: &Expression) -> bool {
+    match expr {
+        Expression::Number(_)
+        | Expression::HexNumber(_)
+        | Expression::Address(_)
+        | Expression::Bool(_)
+        | Expression::String(_)
+        | Expression::Bytes(_)
+        | Expression::Identifier(_) => true,
+        Expression::Member(base, _) => is_side_effect_free(base),
+        Expression::Index(base, key) => is_side_effect_free(base) && is_side_effect_free(key),
+        _ => false,
+    }
+}
+
+/// True when evaluating `expr` cannot write state or call out. Unlike
+/// `is_side_effect_free` this admits arithmetic: it may revert, but it
+/// reverts the same way each time, so one evaluation can stand in for two.
+pub fn is_pure(expr: &Expression) -> bool {
+    match expr {
+        Expression::Binary(_, left, right) => is_pure(left) && is_pure(right),
+        Expression::Unary(_, operand) => is_pure(operand),
+        Expression::Member(base, _) => is_pure(base),
+        Expression::Index(base, key) => is_pure(base) && is_pure(key),
+        Expression::Slice(base, start, end) => is_pure(base) && is_pure(start) && is_pure(end),
+        other => is_side_effect_free(other),
+    }
+}
+
+/// Operator subexpressions of a pure `expr` that occur more than once.
+/// Repeats nested inside an already repeated subexpression are not listed,
+/// since only the first copy of the outer one is ever evaluated.
+pub fn repeated_subexpressions(expr: &Expression) -> Vec<Expression> {
+    fn walk(expr: &Expression, seen: &mut Vec<(Expression, usize)>) {
+        if matches!(expr, Expression::Binary(..) | Expression::Unary(..)) {
+            if let Some((_, count)) = seen.iter_mut().find(|(e, _)| e == expr) {
+                *count += 1;
+                return;
+            }
+            seen.push((expr.clone(), 1));
+        }
+        match expr {
+            Expression::Binary(_, left, right) | Expression::Index(left, right) => {
+                walk(left, seen);
+                walk(right, seen);
+            }
+            Expression::Unary(_, operand) | Expression::Member(operand, _) => walk(operand, seen),
+            _ => {}
+        }
+    }
+
+    if !is_pure(expr) {
+        return Vec::new();
+    }
+    let mut seen = Vec::new();
+    walk(expr, &mut seen);
+    seen.into_iter().filter(|(_, count)| *count > 1).map(|(e, _)| e).collect()
+}
+
+pub fn constant_condition(expr: &Expression) -> Option<bool> {
+    match eval_const(expr)? {
+        ConstValue::Bool(b) => Some(b),
+        ConstValue::Int(_) | ConstValue::Str(_) => None,
+    }
+}
+
+pub fn signed_constant(expr: &Expression) -> Option<(bool, BigUint)> {
+    let (negative, inner) = match expr {
+        Expression::Unary(UnaryOp::Minus, operand) => (true, operand.as_ref()),
+        other => (false, other),
+    };
+    match eval_const(inner)? {
+        ConstValue::Int(n) if n.bits() <= WORD_BITS => Some((negative, n)),
+        _ => None,
+    }
+}
+
+pub fn slice_bounds(start: &Expression, end: &Expression) -> Option<(usize, usize)> {
+    let bound = |e: &Expression| match signed_constant(e)? {
+        (false, n) => usize::try_from(&n).ok(),
+        (true, _) => None,
+    };
+    let (start, end) = (bound(start)?, bound(end)?);
+    (start <= end && end <= 32).then_some((start, end))
+}
+
+/// Folds `PUSH b, PUSH a, op` runs in lowered IR into one push, so hardening
+/// never wraps constant arithmetic in overflow checks. Comparisons, bitwise
+/// ops and shifts follow the EVM's 256-bit wrapping semantics. Checked
+/// arithmetic is folded only when it cannot overflow, underflow or divide by
+/// zero; otherwise it is left for the runtime check to revert.
+pub fn fold_ir(ops: &[IrOp]) -> Vec<IrOp> {
+    let mut out: Vec<IrOp> = Vec::with_capacity(ops.len());
+    for op in ops {
+        let folded = match (op, out.as_slice()) {
+            (IrOp::IsZero | IrOp::Not, [.., IrOp::Push(a)]) => {
+                eval_ir_unary(op, BigUint::from_bytes_be(a)).map(|v| (1, v))
+            }
+            (_, [.., IrOp::Push(b), IrOp::Push(a)]) => {
+                eval_ir_binary(op, BigUint::from_bytes_be(a), BigUint::from_bytes_be(b)).map(|v| (2, v))
+            }
+            _ => None,
+        };
+        match folded {
+            Some((operands, value)) => {
+                out.truncate(out.len() - operands);
+                let bytes = value.to_bytes_be();
+                out.push(IrOp::Push(if value.bits() == 0 { vec![0] } else { bytes }));
+            }
+            None => out.push(op.clone()),
+        }
+    }
+    out
+}
+
+fn word_max() -> BigUint {
+    (BigUint::from(1u8) << WORD_BITS) - 1u8
+}
+
+fn eval_ir_unary(op: &IrOp, a: BigUint) -> Option<BigUint> {
+    match op {
+        IrOp::IsZero => Some(BigUint::from(u8::from(a.bits() == 0))),
+        IrOp::Not => Some(word_max() - a),
+        _ => None,
+    }
+}
+
+/// `a` is the top of the stack, as the EVM takes it.
+fn eval_ir_binary(op: &IrOp, a: BigUint, b: BigUint) -> Option<BigUint> {
+    let flag = |c: bool| BigUint::from(u8::from(c));
+    let value = match op {
+        IrOp::Add => a + b,
+        IrOp::Sub if a >= b => a - b,
+        IrOp::Mul => a * b,
+        IrOp::Div if b.bits() > 0 => a / b,
+        IrOp::Mod if b.bits() > 0 => a % b,
+        IrOp::Exp => {
+            let exp = u32::try_from(&b).ok()?;
+            if a.bits().saturating_mul(u64::from(exp)) > MAX_INTERMEDIATE_BITS {
+                return None;
+            }
+            a.pow(exp)
+        }
+        IrOp::Lt => flag(a < b),
+        IrOp::Gt => flag(a > b),
+        IrOp::Eq => flag(a == b),
+        IrOp::And => a & b,
+        IrOp::Or => a | b,
+        IrOp::Shl => match usize::try_from(&a) {
+            Ok(shift) if shift < WORD_BITS as usize => (b << shift) & word_max(),
+            _ => BigUint::from(0u8),
+        },
+        IrOp::Shr => match usize::try_from(&a) {
+            Ok(shift) => b >> shift,
+            Err(_) => BigUint::from(0u8),
+        },
+        _ => return None,
+    };
+    (value.bits() <= WORD_BITS).then_some(value)
+}
+
+fn eval_const(expr: &Expression) -> Option<ConstValue> {
+    match expr {
+        Expression::Number(n) | Expression::HexNumber(n) => Some(ConstValue::Int(n.clone())),
+        Expression::Bool(b) => Some(ConstValue::Bool(*b)),
+        Expression::String(s) => Some(ConstValue::Str(s.clone())),
+        Expression::Unary(UnaryOp::Not, operand) => match eval_const(operand)? {
+            ConstValue::Bool(b) => Some(ConstValue::Bool(!b)),
+            ConstValue::Int(_) | ConstValue::Str(_) => None,
+        },
+        Expression::Binary(op, left, right) => {
+            eval_binary(op, eval_const(left)?, eval_const(right)?)
+        }
+        _ => None,
+    }
+}
+
+fn eval_binary(op: &BinaryOp, left: ConstValue, right: ConstValue) -> Option<ConstValue> {
+    let (a, b) = match (left, right) {
+        (ConstValue::Int(a), ConstValue::Int(b)) => (a, b),
+        (ConstValue::Bool(a), ConstValue::Bool(b)) => {
+            return match op {
+                BinaryOp::And => Some(ConstValue::Bool(a && b)),
+                BinaryOp::Or => Some(ConstValue::Bool(a || b)),
+                BinaryOp::Equal => Some(ConstValue::Bool(a == b)),
+                BinaryOp::NotEqual => Some(ConstValue::Bool(a != b)),
+                _ => None,
+            };
+        }
+        (ConstValue::Str(a), ConstValue::Str(b)) => {
+            return match op {
+                BinaryOp::Add => Some(ConstValue::Str(a + &b)),
+                _ => None,
+            };
+        }
+        _ => return None,
+    };
+
+    let zero = BigUint::from(0u8);
+    let value = match op {
+        BinaryOp::Add => a + b,
+        BinaryOp::Sub if a >= b => a - b,
+        BinaryOp::Mul => a * b,
+        BinaryOp::Div if b != zero => a / b,
+        BinaryOp::Mod if b != zero => a % b,
+        BinaryOp::Pow => {
+            let exp = u32::try_from(&b).ok()?;
+            if a.bits().saturating_mul(u64::from(exp)) > MAX_INTERMEDIATE_BITS {
+                return None;
+            }
+            a.pow(exp)
+        }
+        BinaryOp::Equal => return Some(ConstValue::Bool(a == b)),
+        BinaryOp::NotEqual => return Some(ConstValue::Bool(a != b)),
+        BinaryOp::Less => return Some(ConstValue::Bool(a < b)),
+        BinaryOp::Greater => return Some(ConstValue::Bool(a > b)),
+        BinaryOp::LessEqual => return Some(ConstValue::Bool(a <= b)),
+        BinaryOp::GreaterEqual => return Some(ConstValue::Bool(a >= b)),
+        _ => return None,
+    };
+
+    if value.bits() > MAX_INTERMEDIATE_BITS {
+        return None;
+    }
+    Some(ConstValue::Int(value))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::parse_from_source;
+    use crate::{Item, Statement};
+
+    fn folded_return(src: &str) -> Expression {
+        let program = parse_from_source(src).unwrap();
+        let Item::Function(f) = &program.items[0] else { panic!() };
+        let Statement::Return(Some(e), _) = &f.body.statements[0] else { panic!() };
+        fold_constants(e)
+    }
+
+    #[test]
+    fn folds_arithmetic() {
+        let e = folded_return("def t() -> uint256: return 1 + 2 * 3");
+        assert_eq!(e, Expression::Number(BigUint::from(7u8)));
+    }
+
+    #[test]
+    fn folds_max_uint() {
+        let e = folded_return("def t() -> uint256: return 2 ** 256 - 1");
+        let Expression::Number(n) = e else { panic!() };
+        assert_eq!(n.bits(), 256);
+        assert_eq!(n.count_ones(), 256);
+    }
+
+    #[test]
+    fn folds_comparison_to_bool() {
+        let e = folded_return("def t() -> bool: return 1 < 2");
+        assert_eq!(e, Expression::Bool(true));
+    }
+
+    #[test]
+    fn constant_condition_requires_bool() {
+        let e = folded_return("def t() -> bool: return 1 < 2 and true");
+        assert_eq!(constant_condition(&e), Some(true));
+        let e = folded_return("def t(a: bool) -> bool: return a and true");
+        assert_eq!(constant_condition(&e), None);
+        assert_eq!(constant_condition(&Expression::Number(BigUint::from(1u8))), None);
+    }
+
+    #[test]
+    fn keeps_underflow_unfolded() {
+        let e = folded_return("def t() -> uint256: return 1 - 2");
+        assert!(matches!(e, Expression::Binary(BinaryOp::Sub, _, _)));
+    }
+
+    #[test]
+    fn keeps_oversized_result_unfolded() {
+        let e = folded_return("def t() -> uint256: return 2 ** 256");
+        assert!(matches!(e, Expression::Binary(BinaryOp::Pow, _, _)));
+    }
+
+    #[test]
+    fn folds_inside_non_constant_expression() {
+        let e = folded_return("def t(a: uint256) -> uint256: return a + 2 * 3");
+        let Expression::Binary(BinaryOp::Add, _, rhs) = e else { panic!() };
+        assert_eq!(*rhs, Expression::Number(BigUint::from(6u8)));
+    }
+
+    #[test]
+    fn simplifies_identities_inside_folding() {
+        let e = folded_return("def t(a: uint256) -> uint256: return a * (2 - 1) + 0");
+        assert_eq!(e, Expression::Identifier("a".into()));
+        let e = folded_return("def t(a: uint256) -> uint256: return 0 * a");
+        assert_eq!(e, Expression::Number(BigUint::from(0u8)));
+    }
+
+    #[test]
+    fn finds_repeated_pure_subexpressions() {
+        let e = folded_return("def t(a: uint256, b: uint256) -> uint256: return a * b + (a * b) * (a * b)");
+        let Expression::Binary(BinaryOp::Add, product, _) = &e else { panic!() };
+        assert_eq!(repeated_subexpressions(&e), [(**product).clone()]);
+        let e = folded_return("def t(a: uint256) -> uint256: return f(a * 2) + a * 2");
+        assert!(repeated_subexpressions(&e).is_empty());
+    }
+
+    #[test]
+    fn folds_string_concatenation() {
+        let e = folded_return("def t() -> string: return \"hello\" + \" world\"");
+        assert_eq!(e, Expression::String("hello world".to_string()));
+    }
+
+    #[test]
+    fn fold_ir_collapses_constant_ops() {
+        let push = |n: u8| IrOp::Push(vec![n]);
+        // 1 + 2 * 3, with the first operand on top of the stack.
+        let ops = [push(1), push(3), push(2), IrOp::Mul, IrOp::Add];
+        assert_eq!(fold_ir(&ops), [push(7)]);
+        let ops = [push(8), push(1), IrOp::Shl, IrOp::IsZero];
+        assert_eq!(fold_ir(&ops), [push(0)]);
+        let ops = [IrOp::CallValue, push(1), IrOp::Add];
+        assert_eq!(fold_ir(&ops), ops);
+    }
+
+    #[test]
+    fn fold_ir_leaves_reverting_arithmetic() {
+        let push = |n: u8| IrOp::Push(vec![n]);
+        let underflow = [push(2), push(1), IrOp::Sub];
+        assert_eq!(fold_ir(&underflow), underflow);
+        let by_zero = [push(0), push(1), IrOp::Div];
+        assert_eq!(fold_ir(&by_zero), by_zero);
+        let max = IrOp::Push(vec![0xff; 32]);
+        let overflow = [push(1), max.clone(), IrOp::Add];
+        assert_eq!(fold_ir(&overflow), overflow);
+        assert_eq!(fold_ir(&[max, push(1), IrOp::Shl]), [IrOp::Push([vec![0xff; 31], vec![0xfe]].concat())]);
+    }
+}
@@ -1,13 +1,64 @@
 use crate::ast::*;
 use crate::lexer::Token;
 use chumsky::prelude::*;
+use std::collections::{HashMap, HashSet};
+use std::ops::{Deref, Range};
 
-pub type ParseError = Simple<Token>;
+/// A syntax error. Boxed, since every parser returns one in its `Err` and
+/// `Simple` is large.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParseError(Box<Simple<Token>>);
+
+impl ParseError {
+    pub fn custom(span: Range<usize>, message: impl ToString) -> Self {
+        Self(Box::new(Simple::custom(span, message)))
+    }
+}
+
+impl Deref for ParseError {
+    type Target = Simple<Token>;
+
+    fn deref(&self) -> &Simple<Token> {
+        &self.0
+    }
+}
+
+impl chumsky::Error<Token> for ParseError {
+    type Span = Range<usize>;
+    type Label = &'static str;
+
+    fn expected_input_found<Iter: IntoIterator<Item = Option<Token>>>(
+        span: Range<usize>,
+        expected: Iter,
+        found: Option<Token>,
+    ) -> Self {
+        Self(Box::new(Simple::expected_input_found(span, expected, found)))
+    }
+
+    fn unclosed_delimiter(
+        unclosed_span: Range<usize>,
+        unclosed: Token,
+        span: Range<usize>,
+        expected: Token,
+        found: Option<Token>,
+    ) -> Self {
+        Self(Box::new(Simple::unclosed_delimiter(unclosed_span, unclosed, span, expected, found)))
+    }
+
+    fn with_label(self, label: &'static str) -> Self {
+        Self(Box::new(self.0.with_label(label)))
+    }
+
+    fn merge(self, other: Self) -> Self {
+        Self(Box::new(self.0.merge(*other.0)))
+    }
+}
 
 #[derive(Clone)]
 enum PostfixOp {
     Member(String),
     Index(Expression),
+    Slice(Expression, Expression),
     Call(Vec<Expression>),
 }
 
@@ -21,6 +72,9 @@ fn fold_postfix(lhs: Expression, op: PostfixOp) -> Expression {
     match op {
         PostfixOp::Member(name) => Expression::Member(Box::new(lhs), name),
         PostfixOp::Index(idx) => Expression::Index(Box::new(lhs), Box::new(idx)),
+        PostfixOp::Slice(start, end) => {
+            Expression::Slice(Box::new(lhs), Box::new(start), Box::new(end))
+        }
         PostfixOp::Call(args) => Expression::Call(Box::new(lhs), args),
     }
 }
@@ -33,6 +87,16 @@ fn fold_binary(left: Expression, (op, right): (BinaryOp, Expression)) -> Express
     Expression::Binary(op, Box::new(left), Box::new(right))
 }
 
+fn fold_power((base, exponents): (Expression, Vec<Expression>)) -> Expression {
+    let mut operands = exponents;
+    operands.insert(0, base);
+    let mut acc = operands.pop().unwrap();
+    while let Some(lhs) = operands.pop() {
+        acc = Expression::Binary(BinaryOp::Pow, Box::new(lhs), Box::new(acc));
+    }
+    acc
+}
+
 fn fold_target(lhs: Expression, op: TargetOp) -> Expression {
     match op {
         TargetOp::Member(name) => Expression::Member(Box::new(lhs), name),
@@ -40,6 +104,37 @@ fn fold_target(lhs: Expression, op: TargetOp) -> Expression {
     }
 }
 
+fn fold_parenthesized(mut items: Vec<Expression>) -> Expression {
+    if items.len() == 1 {
+        items.pop().unwrap()
+    } else {
+        Expression::Tuple(items)
+    }
+}
+
+/// Multiplier for a unit written after a number literal, as in `1 ether`
+/// or `3 days`. Time units count seconds.
+fn unit_scale(unit: &str) -> Option<num_bigint::BigUint> {
+    let scale = match unit {
+        "wei" | "seconds" => 1u64,
+        "gwei" => 1_000_000_000,
+        "ether" => 1_000_000_000_000_000_000,
+        "minutes" => 60,
+        "hours" => 60 * 60,
+        "days" => 24 * 60 * 60,
+        "weeks" => 7 * 24 * 60 * 60,
+        _ => return None,
+    };
+    Some(scale.into())
+}
+
+fn fold_unit((n, unit): (num_bigint::BigUint, Option<String>)) -> Expression {
+    match unit.as_deref().and_then(unit_scale) {
+        Some(scale) => Expression::Number(n * scale),
+        None => Expression::Number(n),
+    }
+}
+
 fn fold_field_init((name, value): (String, Expression)) -> (String, Expression) {
     (name, value)
 }
@@ -48,55 +143,352 @@ fn fold_struct_init((name, fields): (String, Vec<(String, Expression)>)) -> Expr
     Expression::StructInit(name, fields)
 }
 
+/// Parses already lexed tokens the way `parse_with_recovery` parses a
+/// source. Spans are token indices, since the tokens carry no offsets.
 pub fn parse_program(tokens: Vec<Token>) -> Result<Program, Vec<ParseError>> {
-    program_parser().parse(tokens)
+    let tokens: Vec<SpannedToken> = tokens
+        .into_iter()
+        .filter(|t| !matches!(t, Token::Comment))
+        .enumerate()
+        .map(|(i, t)| (t, i..i + 1))
+        .collect();
+    let end = tokens.len();
+    let (program, errors) = parse_tokens(&tokens, Vec::new(), end);
+    if errors.is_empty() {
+        Ok(program)
+    } else {
+        Err(errors)
+    }
 }
 
 pub fn parse_from_source(source: &str) -> Result<Program, Vec<ParseError>> {
+    let (program, errors) = parse_with_recovery(source);
+    if errors.is_empty() {
+        Ok(program)
+    } else {
+        Err(errors)
+    }
+}
+
+type SpannedToken = (Token, std::ops::Range<usize>);
+
+/// Parses every item that can be parsed and collects the errors of the rest,
+/// so one run reports each broken item and each broken statement inside it.
+/// The program holds only the items that parsed.
+pub fn parse_with_recovery(source: &str) -> (Program, Vec<ParseError>) {
     use crate::lexer::PyraLexer;
 
-    let lexer = PyraLexer::new(source);
-    let tokens: Vec<Token> = lexer.collect();
+    let mut lexer = PyraLexer::new(source);
+    let mut tokens = Vec::new();
+    while let Some(token) = lexer.next_token() {
+        if !token.is_error() && !matches!(token, Token::Comment) {
+            tokens.push((token, lexer.span()));
+        }
+    }
+    let lex_errors: Vec<ParseError> = lexer
+        .diagnostics()
+        .iter()
+        .map(|d| ParseError::custom(d.span.clone(), &d.message).with_label(d.code))
+        .collect();
+    parse_tokens(&tokens, lex_errors, source.len())
+}
 
-    let tokens: Vec<Token> = tokens.into_iter().filter(|t| !matches!(t, Token::Comment)).collect();
+/// Parses each logical line of `tokens` on its own, keeping the items that
+/// parse. `lex_errors` replace the parse errors of the lines they fall in.
+fn parse_tokens(tokens: &[SpannedToken], lex_errors: Vec<ParseError>, end: usize) -> (Program, Vec<ParseError>) {
+    let mut items = Vec::new();
+    let mut errors = Vec::new();
+    for line in split_lines(tokens) {
+        let range = line[0].1.start..line[line.len() - 1].1.end;
+        let lexed = lex_errors.iter().filter(|e| range.contains(&e.span().start));
+        if lexed.clone().next().is_some() {
+            // The parse error would only restate the bad token.
+            errors.extend(lexed.cloned());
+            continue;
+        }
+        match parse_line(item_parser(), line) {
+            Ok(item) => items.push(item),
+            Err(mut line_errors) => {
+                let after = line_errors[0].span().end;
+                if !line.iter().any(|(t, _)| *t == Token::Interface) {
+                    line_errors.extend(block_errors(line, after));
+                }
+                errors.extend(line_errors);
+            }
+        }
+    }
+    let unplaced: Vec<_> = lex_errors.into_iter().filter(|e| !errors.contains(e)).collect();
+    errors.extend(unplaced);
+    errors.sort_by_key(|e| e.span().start);
+
+    expand_modifiers(&mut items);
+    resolve_enum_types(&mut items);
+    let program = Program {
+        items,
+        span: Span { start: 0, end },
+        files: Vec::new(),
+        item_files: Vec::new(),
+    };
+    (program, errors)
+}
 
-    parse_program(tokens)
+/// Splits tokens into logical lines, each keeping the indented block and
+/// the decorators that belong to it.
+fn split_lines(tokens: &[SpannedToken]) -> Vec<&[SpannedToken]> {
+    let mut lines = Vec::new();
+    let mut depth = 0usize;
+    let mut braces = 0usize;
+    let mut start = 0;
+    let mut decorated = false;
+    let mut line_begins = true;
+    for (i, (token, _)) in tokens.iter().enumerate() {
+        match token {
+            Token::Indent => depth += 1,
+            Token::Dedent => depth = depth.saturating_sub(1),
+            Token::LBrace => braces += 1,
+            Token::RBrace => braces = braces.saturating_sub(1),
+            Token::Newline if depth == 0 && braces == 0 => {
+                line_begins = true;
+                // A comment line before a block's first statement leaves an
+                // extra newline ahead of the indent.
+                let continues = matches!(
+                    tokens[i + 1..].iter().find(|(t, _)| *t != Token::Newline),
+                    Some((Token::Indent, _))
+                );
+                if !continues && !decorated {
+                    lines.push(&tokens[start..=i]);
+                    start = i + 1;
+                }
+            }
+            _ if line_begins && depth == 0 => {
+                line_begins = false;
+                decorated = *token == Token::At;
+            }
+            _ => {}
+        }
+    }
+    lines.push(&tokens[start..]);
+    lines.retain(|line| line.iter().any(|(t, _)| *t != Token::Newline));
+    lines
 }
 
-fn program_parser() -> impl Parser<Token, Program, Error = ParseError> {
+fn parse_line<T>(
+    parser: impl Parser<Token, T, Error = ParseError>,
+    line: &[SpannedToken],
+) -> Result<T, Vec<ParseError>> {
+    let eoi = line[line.len() - 1].1.end;
     nl()
-        .ignore_then(
-            choice((
-                function_parser().map(Item::Function),
-                struct_parser().map(Item::Struct),
-                event_parser().map(Item::Event),
-                const_item_parser().map(Item::Const),
-            ))
-            .then_ignore(nl()),
+        .ignore_then(parser)
+        .then_ignore(nl())
+        .then_ignore(end())
+        .parse(chumsky::Stream::from_iter(eoi..eoi, line.iter().cloned()))
+}
+
+/// Errors in the statements of `line`'s indented blocks that start past
+/// `after`, the end of the error already reported for the line itself.
+fn block_errors(line: &[SpannedToken], after: usize) -> Vec<ParseError> {
+    if matches!(line.iter().find(|(t, _)| *t != Token::Newline), Some((Token::Asm, _))) {
+        return Vec::new();
+    }
+    let mut errors = Vec::new();
+    let mut depth = 0usize;
+    let mut body_start = 0;
+    for (i, (token, _)) in line.iter().enumerate() {
+        match token {
+            Token::Indent => {
+                if depth == 0 {
+                    body_start = i + 1;
+                }
+                depth += 1;
+            }
+            Token::Dedent if depth > 0 => {
+                depth -= 1;
+                if depth > 0 {
+                    continue;
+                }
+                for stmt in split_lines(&line[body_start..i]) {
+                    if stmt[0].1.start < after {
+                        // Already reported; its nested blocks may hold more.
+                        errors.extend(block_errors(stmt, after));
+                        continue;
+                    }
+                    if let Err(mut stmt_errors) = parse_line(statement_parser(), stmt) {
+                        let stmt_after = stmt_errors[0].span().end;
+                        stmt_errors.truncate(1);
+                        stmt_errors.extend(block_errors(stmt, stmt_after));
+                        errors.extend(stmt_errors);
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+    errors
+}
+
+/// Moves the modifiers each function's decorators name into its
+/// `modifiers`, first decorator outermost, and drops those decorators. A
+/// decorator naming a modifier from another file stays until imports are
+/// merged and this runs again.
+pub fn expand_modifiers(items: &mut [Item]) {
+    let modifiers: HashMap<String, ModifierDef> = items
+        .iter()
+        .filter_map(|item| match item {
+            Item::Modifier(m) => Some((m.name.clone(), m.clone())),
+            _ => None,
+        })
+        .collect();
+    if modifiers.is_empty() {
+        return;
+    }
+    for item in items {
+        let Item::Function(f) = item else { continue };
+        let applied: Vec<ModifierDef> =
+            f.decorators.iter().filter_map(|d| modifiers.get(&d.name).cloned()).collect();
+        f.modifiers.splice(0..0, applied);
+        f.decorators.retain(|d| !modifiers.contains_key(&d.name));
+    }
+}
+
+/// Rewrites every written type naming a declared enum from `Type::Custom` to
+/// `Type::Enum`. Runs again after imports are merged, since an enum may be
+/// declared in a different file from its uses.
+pub fn resolve_enum_types(items: &mut [Item]) {
+    let enums: HashSet<String> = items
+        .iter()
+        .filter_map(|item| match item {
+            Item::Enum(e) => Some(e.name.clone()),
+            _ => None,
+        })
+        .collect();
+    if enums.is_empty() {
+        return;
+    }
+    for item in items {
+        match item {
+            Item::Function(f) => resolve_function_types(f, &enums),
+            Item::Struct(s) => s.fields.iter_mut().for_each(|f| resolve_type(&mut f.type_, &enums)),
+            Item::Const(c) => resolve_type(&mut c.type_, &enums),
+            Item::State(s) => resolve_type(&mut s.type_, &enums),
+            Item::Event(e) => e.fields.iter_mut().for_each(|f| resolve_type(&mut f.type_, &enums)),
+            Item::Error(e) => e.params.iter_mut().for_each(|p| resolve_type(&mut p.type_, &enums)),
+            Item::Interface(i) => i.functions.iter_mut().for_each(|f| resolve_function_types(f, &enums)),
+            Item::Enum(_) | Item::Modifier(_) | Item::Import(_) => {}
+        }
+    }
+}
+
+fn resolve_function_types(f: &mut Function, enums: &HashSet<String>) {
+    for p in &mut f.params {
+        resolve_type(&mut p.type_, enums);
+    }
+    if let Some(ty) = &mut f.return_type {
+        resolve_type(ty, enums);
+    }
+    resolve_block_types(&mut f.body, enums);
+    for m in &mut f.modifiers {
+        resolve_block_types(&mut m.body, enums);
+    }
+}
+
+fn resolve_block_types(block: &mut Block, enums: &HashSet<String>) {
+    for stmt in &mut block.statements {
+        match stmt {
+            Statement::Let(l) => {
+                if let Some(ty) = &mut l.type_ {
+                    resolve_type(ty, enums);
+                }
+            }
+            Statement::If(if_stmt) => {
+                resolve_block_types(&mut if_stmt.then_branch, enums);
+                if let Some(eb) = &mut if_stmt.else_branch {
+                    resolve_block_types(eb, enums);
+                }
+            }
+            Statement::For(for_stmt) => resolve_block_types(&mut for_stmt.body, enums),
+            Statement::While(while_stmt) => resolve_block_types(&mut while_stmt.body, enums),
+            _ => {}
+        }
+    }
+}
+
+fn resolve_type(ty: &mut Type, enums: &HashSet<String>) {
+    match ty {
+        Type::Custom(name) if enums.contains(name.as_str()) => *ty = Type::Enum(std::mem::take(name)),
+        Type::Vec(inner) | Type::Array(inner, _) => resolve_type(inner, enums),
+        Type::Map(k, v) => {
+            resolve_type(k, enums);
+            resolve_type(v, enums);
+        }
+        Type::Generic(_, args) | Type::Tuple(args) => {
+            args.iter_mut().for_each(|t| resolve_type(t, enums));
+        }
+        _ => {}
+    }
+}
+
+fn item_parser() -> impl Parser<Token, Item, Error = ParseError> {
+    choice((
+        function_parser().map(Item::Function),
+        struct_parser().map(Item::Struct),
+        enum_parser().map(Item::Enum),
+        event_parser().map(Item::Event),
+        error_parser().map(Item::Error),
+        interface_parser().map(Item::Interface),
+        modifier_parser().map(Item::Modifier),
+        const_item_parser().map(Item::Const),
+        import_parser().map(Item::Import),
+        from_import_parser().map(Item::Import),
+        state_var_parser().map(Item::State),
+    ))
+}
+
+fn decorator_parser() -> impl Parser<Token, Decorator, Error = ParseError> {
+    just(Token::At)
+        .ignore_then(identifier())
+        .then(
+            identifier()
+                .separated_by(just(Token::Comma))
+                .allow_trailing()
+                .delimited_by(just(Token::LParen), just(Token::RParen))
+                .or_not(),
         )
-        .repeated()
-        .map(|items| Program {
-            items,
-            span: Span { start: 0, end: 0 },
+        .then_ignore(nl1())
+        .map_with_span(|(name, args), span: std::ops::Range<usize>| Decorator {
+            name,
+            args: args.unwrap_or_default(),
+            span: Span { start: span.start, end: span.end },
         })
-        .then_ignore(end())
 }
 
 fn function_parser() -> impl Parser<Token, Function, Error = ParseError> {
-    just(Token::Def)
-        .ignore_then(identifier())
+    decorator_parser()
+        .repeated()
+        .then_ignore(just(Token::Def))
+        .then(spanned_identifier())
         .then_ignore(just(Token::LParen))
         .then(parameter_list())
         .then_ignore(just(Token::RParen))
         .then(return_type().or_not())
         .then_ignore(just(Token::Colon))
         .then(suite_parser(statement_parser()))
-        .map(|(((name, params), return_type), body)| Function {
-            name,
-            params,
-            return_type,
-            body,
-            span: Span { start: 0, end: 0 },
+        .map_with_span(|((((decorators, (name, name_span)), params), ret), body), span: std::ops::Range<usize>| {
+            let (return_type, return_names) = match ret {
+                Some((ty, names)) => (Some(ty), names),
+                None => (None, Vec::new()),
+            };
+            Function {
+                decorators,
+                name,
+                name_span,
+                params,
+                return_type,
+                return_names,
+                body,
+                modifiers: Vec::new(),
+                internal: false,
+                span: Span { start: span.start, end: span.end },
+            }
         })
 }
 
@@ -118,28 +510,80 @@ fn parameter_parser() -> impl Parser<Token, Parameter, Error = ParseError> {
     identifier()
         .then_ignore(just(Token::Colon))
         .then(type_parser())
-        .map(|(name, type_)| Parameter {
+        .map_with_span(|(name, type_), span: std::ops::Range<usize>| Parameter {
             name,
             type_,
-            span: Span { start: 0, end: 0 },
+            span: Span { start: span.start, end: span.end },
         })
 }
 
-fn return_type() -> impl Parser<Token, Type, Error = ParseError> {
-    just(Token::Arrow).ignore_then(type_parser())
+fn return_type() -> impl Parser<Token, (Type, Vec<String>), Error = ParseError> {
+    let named = identifier()
+        .then_ignore(just(Token::Colon))
+        .then(type_parser())
+        .separated_by(just(Token::Comma))
+        .at_least(1)
+        .allow_trailing()
+        .delimited_by(just(Token::LParen), just(Token::RParen))
+        .map(|mut fields| {
+            if fields.len() == 1 {
+                let (name, ty) = fields.pop().unwrap();
+                (ty, vec![name])
+            } else {
+                let (names, types) = fields.into_iter().unzip();
+                (Type::Tuple(types), names)
+            }
+        });
+
+    just(Token::Arrow).ignore_then(choice((
+        named,
+        type_parser().map(|ty| (ty, Vec::new())),
+    )))
 }
 
 fn type_parser() -> impl Parser<Token, Type, Error = ParseError> {
-    choice((
-        just(Token::Uint8).to(Type::Uint8),
-        just(Token::Uint256).to(Type::Uint256),
-        just(Token::Int256).to(Type::Int256),
-        just(Token::Bool).to(Type::Bool),
-        just(Token::Address).to(Type::Address),
-        just(Token::Bytes).to(Type::Bytes),
-        just(Token::String).to(Type::String),
-        identifier().map(Type::Custom),
-    ))
+    recursive(|ty| {
+        let mapping = select! { Token::Identifier(name) if name == "mapping" => () }
+            .ignore_then(just(Token::LBracket))
+            .ignore_then(ty.clone())
+            .then_ignore(just(Token::FatArrow))
+            .then(ty.clone())
+            .then_ignore(just(Token::RBracket))
+            .map(|(k, v)| Type::Map(Box::new(k), Box::new(v)));
+
+        let array_len = select! { Token::Number(n) => n }.try_map(|n, span| {
+            usize::try_from(&n)
+                .ok()
+                .filter(|&len| len > 0)
+                .ok_or_else(|| ParseError::custom(span, "array length must be a positive integer"))
+        });
+        let array_suffix = array_len.or_not().delimited_by(just(Token::LBracket), just(Token::RBracket));
+
+        let base = choice((
+            select! {
+                Token::Uint(n) => Type::Uint(n),
+                Token::Int(n) => Type::Int(n),
+                Token::FixedBytes(n) => Type::FixedBytes(n),
+            },
+            just(Token::Bool).to(Type::Bool),
+            just(Token::Address).to(Type::Address),
+            just(Token::Bytes).to(Type::Bytes),
+            just(Token::String).to(Type::String),
+            mapping,
+            ty.clone()
+                .separated_by(just(Token::Comma))
+                .at_least(2)
+                .allow_trailing()
+                .delimited_by(just(Token::LParen), just(Token::RParen))
+                .map(Type::Tuple),
+            identifier().map(Type::Custom),
+        ));
+
+        base.then(array_suffix.repeated()).foldl(|ty, len| match len {
+            Some(len) => Type::Array(Box::new(ty), len),
+            None => Type::Vec(Box::new(ty)),
+        })
+    })
 }
 
 fn generic_params_parser() -> impl Parser<Token, (), Error = ParseError> {
@@ -156,7 +600,7 @@ fn generic_params_parser() -> impl Parser<Token, (), Error = ParseError> {
 fn struct_parser() -> impl Parser<Token, StructDef, Error = ParseError> {
     let sep = choice((just(Token::Comma).ignore_then(nl()).ignored(), nl1()));
     just(Token::Struct)
-        .ignore_then(identifier())
+        .ignore_then(spanned_identifier())
         .then_ignore(generic_params_parser().or_not())
         .then_ignore(nl())
         .then_ignore(just(Token::LBrace))
@@ -168,10 +612,11 @@ fn struct_parser() -> impl Parser<Token, StructDef, Error = ParseError> {
         .then_ignore(just(Token::Dedent).or_not())
         .then_ignore(nl())
         .then_ignore(just(Token::RBrace))
-        .map(|(name, fields)| StructDef {
+        .map_with_span(|((name, name_span), fields), span: std::ops::Range<usize>| StructDef {
             name,
+            name_span,
             fields,
-            span: Span { start: 0, end: 0 },
+            span: Span { start: span.start, end: span.end },
         })
 }
 
@@ -179,37 +624,187 @@ fn struct_field() -> impl Parser<Token, StructField, Error = ParseError> {
     identifier()
         .then_ignore(just(Token::Colon))
         .then(type_parser())
-        .map(|(name, type_)| StructField {
+        .map_with_span(|(name, type_), span: std::ops::Range<usize>| StructField {
             name,
             type_,
-            span: Span { start: 0, end: 0 },
+            span: Span { start: span.start, end: span.end },
         })
 }
 
 fn const_item_parser() -> impl Parser<Token, ConstDecl, Error = ParseError> {
     choice((just(Token::Const), just(Token::Let)))
-        .ignore_then(identifier())
+        .ignore_then(spanned_identifier())
         .then(just(Token::Colon).ignore_then(type_parser()).or_not())
         .then_ignore(just(Token::Assign))
         .then(expression_parser())
-        .map(|((name, type_), value)| ConstDecl {
+        .map_with_span(|(((name, name_span), type_), value), span: std::ops::Range<usize>| ConstDecl {
             name,
-            type_: type_.unwrap_or(Type::Uint256),
+            name_span,
+            type_: type_.unwrap_or(Type::Uint(256)),
             value,
-            span: Span { start: 0, end: 0 },
+            span: Span { start: span.start, end: span.end },
+        })
+}
+
+fn state_var_parser() -> impl Parser<Token, StateVar, Error = ParseError> {
+    spanned_identifier()
+        .then_ignore(just(Token::Colon))
+        .then(type_parser())
+        .map_with_span(|((name, name_span), type_), span: std::ops::Range<usize>| StateVar {
+            name,
+            name_span,
+            type_,
+            span: Span { start: span.start, end: span.end },
         })
 }
 
 fn event_parser() -> impl Parser<Token, EventDef, Error = ParseError> {
     just(Token::Event)
-        .ignore_then(identifier())
+        .ignore_then(spanned_identifier())
         .then_ignore(just(Token::LParen))
-        .then(parameter_list())
+        .then(
+            event_field()
+                .separated_by(just(Token::Comma))
+                .allow_trailing(),
+        )
         .then_ignore(just(Token::RParen))
-        .map(|(name, fields)| EventDef {
+        .map_with_span(|((name, name_span), fields), span: std::ops::Range<usize>| EventDef {
             name,
+            name_span,
             fields,
-            span: Span { start: 0, end: 0 },
+            span: Span { start: span.start, end: span.end },
+        })
+}
+
+fn enum_parser() -> impl Parser<Token, EnumDef, Error = ParseError> {
+    just(Token::Enum)
+        .ignore_then(spanned_identifier())
+        .then_ignore(just(Token::Colon))
+        .then(identifier().separated_by(just(Token::Comma)).at_least(1))
+        .map_with_span(|((name, name_span), variants), span: std::ops::Range<usize>| EnumDef {
+            name,
+            name_span,
+            variants,
+            span: Span { start: span.start, end: span.end },
+        })
+}
+
+fn modifier_parser() -> impl Parser<Token, ModifierDef, Error = ParseError> {
+    just(Token::Modifier)
+        .ignore_then(spanned_identifier())
+        .then_ignore(just(Token::Colon))
+        .then(suite_parser(statement_parser()))
+        .map_with_span(|((name, name_span), body), span: std::ops::Range<usize>| ModifierDef {
+            name,
+            name_span,
+            body,
+            span: Span { start: span.start, end: span.end },
+        })
+}
+
+fn error_parser() -> impl Parser<Token, ErrorDef, Error = ParseError> {
+    just(Token::CustomError)
+        .ignore_then(spanned_identifier())
+        .then_ignore(just(Token::LParen))
+        .then(parameter_list())
+        .then_ignore(just(Token::RParen))
+        .map_with_span(|((name, name_span), params), span: std::ops::Range<usize>| ErrorDef {
+            name,
+            name_span,
+            params,
+            span: Span { start: span.start, end: span.end },
+        })
+}
+
+fn interface_parser() -> impl Parser<Token, InterfaceDef, Error = ParseError> {
+    let signature = decorator_parser()
+        .repeated()
+        .then_ignore(just(Token::Def))
+        .then(spanned_identifier())
+        .then_ignore(just(Token::LParen))
+        .then(parameter_list())
+        .then_ignore(just(Token::RParen))
+        .then(return_type().or_not())
+        .map_with_span(|(((decorators, (name, name_span)), params), ret), span: std::ops::Range<usize>| {
+            let (return_type, return_names) = match ret {
+                Some((ty, names)) => (Some(ty), names),
+                None => (None, Vec::new()),
+            };
+            Function {
+                decorators,
+                name,
+                name_span,
+                params,
+                return_type,
+                return_names,
+                body: Block {
+                    statements: Vec::new(),
+                    span: Span { start: span.end, end: span.end },
+                },
+                modifiers: Vec::new(),
+                internal: false,
+                span: Span { start: span.start, end: span.end },
+            }
+        });
+
+    just(Token::Interface)
+        .ignore_then(spanned_identifier())
+        .then_ignore(just(Token::Colon))
+        .then_ignore(nl1())
+        .then_ignore(just(Token::Indent))
+        .then_ignore(nl())
+        .then(signature.separated_by(nl1()).allow_trailing())
+        .then_ignore(nl())
+        .then_ignore(just(Token::Dedent))
+        .map_with_span(|((name, name_span), functions), span: std::ops::Range<usize>| InterfaceDef {
+            name,
+            name_span,
+            functions,
+            span: Span { start: span.start, end: span.end },
+        })
+}
+
+fn event_field() -> impl Parser<Token, EventField, Error = ParseError> {
+    let indexed = just(Token::Indexed)
+        .ignore_then(type_parser().delimited_by(just(Token::LParen), just(Token::RParen)))
+        .map(|type_| (type_, true));
+    identifier()
+        .then_ignore(just(Token::Colon))
+        .then(indexed.or(type_parser().map(|type_| (type_, false))))
+        .map_with_span(|(name, (type_, indexed)), span: std::ops::Range<usize>| EventField {
+            name,
+            type_,
+            indexed,
+            span: Span { start: span.start, end: span.end },
+        })
+}
+
+fn import_parser() -> impl Parser<Token, ImportDecl, Error = ParseError> {
+    just(Token::Import)
+        .ignore_then(select! { Token::StringLiteral(s) => s })
+        .map_with_span(|path, span: std::ops::Range<usize>| ImportDecl {
+            path,
+            names: Vec::new(),
+            span: Span { start: span.start, end: span.end },
+        })
+}
+
+/// `from token.erc20 import Transfer, balance_of`. A dotted module names
+/// `token/erc20.pyra`; a quoted path is taken as written. `from` is only a
+/// keyword here, so it stays usable as a parameter name.
+fn from_import_parser() -> impl Parser<Token, ImportDecl, Error = ParseError> {
+    let module = select! { Token::StringLiteral(s) => s }.or(identifier()
+        .separated_by(just(Token::Dot))
+        .at_least(1)
+        .map(|parts| format!("{}.pyra", parts.join("/"))));
+    select! { Token::Identifier(name) if name == "from" => () }
+        .ignore_then(module)
+        .then_ignore(just(Token::Import))
+        .then(identifier().separated_by(just(Token::Comma)).at_least(1))
+        .map_with_span(|(path, names), span: std::ops::Range<usize>| ImportDecl {
+            path,
+            names,
+            span: Span { start: span.start, end: span.end },
         })
 }
 
@@ -237,15 +832,23 @@ fn expression_parser() -> impl Parser<Token, Expression, Error = ParseError> {
             .map(fold_struct_init as fn((String, Vec<(String, Expression)>)) -> Expression);
 
         let atom = choice((
-            select! { Token::Number(n) => Expression::Number(n) },
+            select! { Token::Number(n) => n }
+                .then(select! { Token::Identifier(unit) if unit_scale(&unit).is_some() => unit }.or_not())
+                .map(fold_unit as fn((num_bigint::BigUint, Option<String>)) -> Expression),
             select! { Token::HexNumber(n) => Expression::HexNumber(n) },
+            select! { Token::AddressLiteral(a) => Expression::Address(a) },
             select! { Token::StringLiteral(s) => Expression::String(s) },
             select! { Token::BytesLiteral(b) => Expression::Bytes(b) },
             just(Token::True).to(Expression::Bool(true)),
             just(Token::False).to(Expression::Bool(false)),
             struct_init,
             identifier().map(Expression::Identifier),
-            expr.clone().delimited_by(just(Token::LParen), just(Token::RParen)),
+            expr.clone()
+                .separated_by(just(Token::Comma))
+                .at_least(1)
+                .allow_trailing()
+                .delimited_by(just(Token::LParen), just(Token::RParen))
+                .map(fold_parenthesized as fn(Vec<Expression>) -> Expression),
         ));
 
         let postfix_ops = choice((
@@ -254,8 +857,12 @@ fn expression_parser() -> impl Parser<Token, Expression, Error = ParseError> {
                 .map(PostfixOp::Member),
             just(Token::LBracket)
                 .ignore_then(expr.clone())
+                .then(just(Token::Colon).ignore_then(expr.clone()).or_not())
                 .then_ignore(just(Token::RBracket))
-                .map(PostfixOp::Index),
+                .map(|(start, end)| match end {
+                    Some(end) => PostfixOp::Slice(start, end),
+                    None => PostfixOp::Index(start),
+                }),
             just(Token::LParen)
                 .ignore_then(expr.clone().separated_by(just(Token::Comma)).allow_trailing())
                 .then_ignore(just(Token::RParen))
@@ -268,12 +875,18 @@ fn expression_parser() -> impl Parser<Token, Expression, Error = ParseError> {
             .foldl(fold_postfix as fn(Expression, PostfixOp) -> Expression)
             .boxed();
 
+        let power = postfix
+            .clone()
+            .then(just(Token::Power).ignore_then(postfix).repeated())
+            .map(fold_power as fn((Expression, Vec<Expression>)) -> Expression)
+            .boxed();
+
         let unary = choice((
             just(Token::Not).to(UnaryOp::Not),
             just(Token::Minus).to(UnaryOp::Minus),
         ))
         .repeated()
-        .then(postfix)
+        .then(power)
         .foldr(fold_unary as fn(UnaryOp, Expression) -> Expression)
         .boxed();
 
@@ -311,6 +924,7 @@ fn expression_parser() -> impl Parser<Token, Expression, Error = ParseError> {
                     just(Token::GreaterEqual).to(BinaryOp::GreaterEqual),
                     just(Token::Less).to(BinaryOp::Less),
                     just(Token::Greater).to(BinaryOp::Greater),
+                    just(Token::In).to(BinaryOp::In),
                 ))
                 .then(sum)
                 .repeated(),
@@ -333,33 +947,54 @@ fn expression_parser() -> impl Parser<Token, Expression, Error = ParseError> {
 
 fn return_statement() -> impl Parser<Token, Statement, Error = ParseError> {
     just(Token::Return)
-        .ignore_then(expression_parser().or_not())
-        .map(Statement::Return)
+        .ignore_then(
+            expression_parser()
+                .separated_by(just(Token::Comma))
+                .at_least(1)
+                .map(fold_parenthesized as fn(Vec<Expression>) -> Expression)
+                .or_not(),
+        )
+        .map_with_span(|value, span: std::ops::Range<usize>| {
+            Statement::Return(value, Span { start: span.start, end: span.end })
+        })
 }
 
 fn require_statement() -> impl Parser<Token, Statement, Error = ParseError> {
     just(Token::Require)
         .ignore_then(expression_parser())
-        .map(Statement::Require)
+        .then(just(Token::Comma).ignore_then(select! { Token::StringLiteral(s) => s }).or_not())
+        .map_with_span(|(condition, message), span: std::ops::Range<usize>| {
+            Statement::Require(RequireStatement {
+                condition,
+                message,
+                span: Span { start: span.start, end: span.end },
+            })
+        })
 }
 
 fn identifier() -> impl Parser<Token, String, Error = ParseError> {
     select! { Token::Identifier(name) => name }
 }
 
+fn spanned_identifier() -> impl Parser<Token, (String, Span), Error = ParseError> {
+    identifier().map_with_span(|name, span: std::ops::Range<usize>| {
+        (name, Span { start: span.start, end: span.end })
+    })
+}
+
 fn let_statement() -> impl Parser<Token, Statement, Error = ParseError> {
     just(Token::Let)
         .ignore_then(just(Token::Mut).or_not())
         .then(identifier())
         .then(just(Token::Colon).ignore_then(type_parser()).or_not())
         .then(just(Token::Assign).ignore_then(expression_parser()).or_not())
-        .map(|(((mutable, name), type_), value)| {
+        .map_with_span(|(((mutable, name), type_), value), span: std::ops::Range<usize>| {
             Statement::Let(LetStatement {
                 name,
                 type_,
                 value,
                 mutable: mutable.is_some(),
-                span: Span { start: 0, end: 0 },
+                span: Span { start: span.start, end: span.end },
             })
         })
 }
@@ -378,7 +1013,7 @@ fn assign_statement() -> impl Parser<Token, Statement, Error = ParseError> {
     target
         .then(op)
         .then(expression_parser())
-        .map(|((target, op), rhs)| {
+        .map_with_span(|((target, op), rhs), span: std::ops::Range<usize>| {
             let value = match op {
                 None => rhs,
                 Some(bin_op) => Expression::Binary(bin_op, Box::new(target.clone()), Box::new(rhs)),
@@ -387,12 +1022,28 @@ fn assign_statement() -> impl Parser<Token, Statement, Error = ParseError> {
             Statement::Assign(AssignStatement {
                 target,
                 value,
-                span: Span { start: 0, end: 0 },
+                span: Span { start: span.start, end: span.end },
             })
         })
 }
 
+fn expression_statement() -> impl Parser<Token, Statement, Error = ParseError> {
+    expression_parser().map_with_span(|expr, span: std::ops::Range<usize>| {
+        Statement::Expression(expr, Span { start: span.start, end: span.end })
+    })
+}
+
 fn assignment_target_parser() -> impl Parser<Token, Expression, Error = ParseError> {
+    simple_target_parser()
+        .separated_by(just(Token::Comma))
+        .at_least(2)
+        .allow_trailing()
+        .delimited_by(just(Token::LParen), just(Token::RParen))
+        .map(Expression::Tuple)
+        .or(simple_target_parser())
+}
+
+fn simple_target_parser() -> impl Parser<Token, Expression, Error = ParseError> {
     let base = identifier().map(Expression::Identifier).boxed();
     let ops = choice((
         just(Token::Dot)
@@ -414,11 +1065,57 @@ fn emit_statement() -> impl Parser<Token, Statement, Error = ParseError> {
         .then_ignore(just(Token::LParen))
         .then(expression_parser().separated_by(just(Token::Comma)).allow_trailing())
         .then_ignore(just(Token::RParen))
-        .map(|(name, args)| {
+        .map_with_span(|(name, args), span: std::ops::Range<usize>| {
             Statement::Emit(EmitStatement {
                 name,
                 args,
-                span: Span { start: 0, end: 0 },
+                span: Span { start: span.start, end: span.end },
+            })
+        })
+}
+
+fn revert_statement() -> impl Parser<Token, Statement, Error = ParseError> {
+    just(Token::Revert)
+        .ignore_then(identifier())
+        .then_ignore(just(Token::LParen))
+        .then(expression_parser().separated_by(just(Token::Comma)).allow_trailing())
+        .then_ignore(just(Token::RParen))
+        .map_with_span(|(name, args), span: std::ops::Range<usize>| {
+            Statement::Revert(RevertStatement {
+                name,
+                args,
+                span: Span { start: span.start, end: span.end },
+            })
+        })
+}
+
+fn asm_statement() -> impl Parser<Token, Statement, Error = ParseError> {
+    let instruction = identifier()
+        .then(
+            select! {
+                Token::Number(n) => n,
+                Token::HexNumber(n) => n,
+                Token::AddressLiteral(a) => num_bigint::BigUint::parse_bytes(a.as_bytes(), 16).unwrap_or_default(),
+            }
+            .or_not(),
+        )
+        .map(|(mnemonic, immediate)| AsmInstruction { mnemonic, immediate });
+    let line = instruction.repeated().at_least(1).boxed();
+    let indented = nl1()
+        .ignore_then(just(Token::Indent))
+        .ignore_then(nl())
+        .ignore_then(line.clone().separated_by(nl1()).allow_trailing())
+        .then_ignore(nl())
+        .then_ignore(just(Token::Dedent))
+        .map(|lines| lines.into_iter().flatten().collect());
+
+    just(Token::Asm)
+        .ignore_then(just(Token::Colon))
+        .ignore_then(choice((indented, line)))
+        .map_with_span(|instructions, span: std::ops::Range<usize>| {
+            Statement::Asm(AsmBlock {
+                instructions,
+                span: Span { start: span.start, end: span.end },
             })
         })
 }
@@ -437,7 +1134,10 @@ fn statement_parser() -> BoxedParser<'static, Token, Statement, ParseError> {
                         just(Token::Elif)
                             .ignore_then(expression_parser())
                             .then_ignore(just(Token::Colon))
-                            .then(suite.clone()),
+                            .then(suite.clone())
+                            .map_with_span(|clause, span: std::ops::Range<usize>| {
+                                (clause, Span { start: span.start, end: span.end })
+                            }),
                     )
                     .repeated(),
             )
@@ -446,19 +1146,20 @@ fn statement_parser() -> BoxedParser<'static, Token, Statement, ParseError> {
                     .ignore_then(just(Token::Else).ignore_then(just(Token::Colon)).ignore_then(suite.clone()))
                     .or_not(),
             )
-            .map(|(((cond, then_branch), elifs), else_branch)| {
+            .map_with_span(|(((cond, then_branch), elifs), else_branch), span: std::ops::Range<usize>| {
                 let mut else_acc = else_branch;
-                for (elif_cond, elif_body) in elifs.into_iter().rev() {
+                for ((elif_cond, elif_body), elif_span) in elifs.into_iter().rev() {
+                    let end = else_acc.as_ref().map_or(elif_span.end, |b| b.span.end);
                     let nested = IfStatement {
                         condition: elif_cond,
                         then_branch: elif_body,
                         else_branch: else_acc,
-                        span: Span { start: 0, end: 0 },
+                        span: Span { start: elif_span.start, end },
                     };
 
                     else_acc = Some(Block {
                         statements: vec![Statement::If(nested)],
-                        span: Span { start: 0, end: 0 },
+                        span: Span { start: elif_span.start, end },
                     });
                 }
 
@@ -466,7 +1167,7 @@ fn statement_parser() -> BoxedParser<'static, Token, Statement, ParseError> {
                     condition: cond,
                     then_branch,
                     else_branch: else_acc,
-                    span: Span { start: 0, end: 0 },
+                    span: Span { start: span.start, end: span.end },
                 })
             });
 
@@ -476,12 +1177,12 @@ fn statement_parser() -> BoxedParser<'static, Token, Statement, ParseError> {
             .then(expression_parser())
             .then_ignore(just(Token::Colon))
             .then(suite.clone())
-            .map(|((var, iterable), body)| {
+            .map_with_span(|((var, iterable), body), span: std::ops::Range<usize>| {
                 Statement::For(ForStatement {
                     var,
                     iterable,
                     body,
-                    span: Span { start: 0, end: 0 },
+                    span: Span { start: span.start, end: span.end },
                 })
             });
 
@@ -489,11 +1190,11 @@ fn statement_parser() -> BoxedParser<'static, Token, Statement, ParseError> {
             .ignore_then(expression_parser())
             .then_ignore(just(Token::Colon))
             .then(suite)
-            .map(|(condition, body)| {
+            .map_with_span(|(condition, body), span: std::ops::Range<usize>| {
                 Statement::While(WhileStatement {
                     condition,
                     body,
-                    span: Span { start: 0, end: 0 },
+                    span: Span { start: span.start, end: span.end },
                 })
             });
 
@@ -502,10 +1203,19 @@ fn statement_parser() -> BoxedParser<'static, Token, Statement, ParseError> {
             for_stmt,
             while_stmt,
             emit_statement(),
+            revert_statement(),
+            asm_statement(),
             require_statement(),
             let_statement(),
             return_statement(),
+            just(Token::Break).map_with_span(|_, span: std::ops::Range<usize>| {
+                Statement::Break(Span { start: span.start, end: span.end })
+            }),
+            just(Token::Continue).map_with_span(|_, span: std::ops::Range<usize>| {
+                Statement::Continue(Span { start: span.start, end: span.end })
+            }),
             assign_statement(),
+            expression_statement(),
         ))
         .boxed()
     })
@@ -516,9 +1226,9 @@ fn suite_parser<S>(stmt: S) -> BoxedParser<'static, Token, Block, ParseError>
 where
     S: Parser<Token, Statement, Error = ParseError> + Clone + 'static,
 {
-    let single = stmt.clone().map(|st| Block {
+    let single = stmt.clone().map_with_span(|st, span: std::ops::Range<usize>| Block {
         statements: vec![st],
-        span: Span { start: 0, end: 0 },
+        span: Span { start: span.start, end: span.end },
     });
 
     let indented = nl1()
@@ -527,9 +1237,9 @@ where
         .ignore_then(stmt.separated_by(nl1()).allow_leading().allow_trailing())
         .then_ignore(nl())
         .then_ignore(just(Token::Dedent))
-        .map(|statements| Block {
+        .map_with_span(|statements, span: std::ops::Range<usize>| Block {
             statements,
-            span: Span { start: 0, end: 0 },
+            span: Span { start: span.start, end: span.end },
         });
 
     choice((indented, single)).boxed()
@@ -573,7 +1283,81 @@ mod tests {
         let Item::Function(f) = &program.items[0] else { panic!() };
         assert_eq!(f.body.statements.len(), 2);
         assert!(matches!(f.body.statements[0], Statement::Require(_)));
-        assert!(matches!(f.body.statements[1], Statement::Return(_)));
+        assert!(matches!(f.body.statements[1], Statement::Return(_, _)));
+    }
+
+    #[test]
+    fn parses_require_message_and_decorator() {
+        let source = "@allow(bare_revert)\ndef t(x: uint256):\n    require x > 0, \"x must be positive\"\n";
+        let program = parse_from_source(source).unwrap();
+        let Item::Function(f) = &program.items[0] else { panic!() };
+        assert_eq!(f.decorators[0].name, "allow");
+        assert_eq!(f.decorators[0].args, ["bare_revert"]);
+        assert!(f.allows("bare_revert"));
+        let Statement::Require(r) = &f.body.statements[0] else { panic!() };
+        assert_eq!(r.message.as_deref(), Some("x must be positive"));
+        assert_eq!(&source[r.span.start..r.span.end], "require x > 0, \"x must be positive\"");
+    }
+
+    #[test]
+    fn parses_mutability_decorators() {
+        for name in ["view", "pure", "payable"] {
+            let source = format!("@{name}\ndef t() -> uint256: return 1\n");
+            let program = parse_from_source(&source).unwrap();
+            let Item::Function(f) = &program.items[0] else { panic!() };
+            assert_eq!(f.mutability_decorators(), [name]);
+            assert_eq!(f.is_payable(), name == "payable");
+        }
+    }
+
+    #[test]
+    fn items_carry_name_spans() {
+        let source = "event Transfer(to: address)\n\nstruct Pool {\n    total: uint256\n}\n\nconst CAP: uint256 = 10\n\n@allow(bare_revert)\ndef transfer(to: address, amount: uint256):\n    require amount > 0\n";
+        let program = parse_from_source(source).unwrap();
+        let names: Vec<&str> = program
+            .items
+            .iter()
+            .map(|item| {
+                let span = match item {
+                    Item::Function(f) => &f.name_span,
+                    Item::Struct(s) => &s.name_span,
+                    Item::Enum(e) => &e.name_span,
+                    Item::Const(c) => &c.name_span,
+                    Item::Event(e) => &e.name_span,
+                    Item::Error(e) => &e.name_span,
+                    Item::Interface(i) => &i.name_span,
+                    Item::Modifier(m) => &m.name_span,
+                    Item::State(s) => &s.name_span,
+                    Item::Import(_) => panic!(),
+                };
+                &source[span.start..span.end]
+            })
+            .collect();
+        assert_eq!(names, ["Transfer", "Pool", "CAP", "transfer"]);
+    }
+
+    #[test]
+    fn statements_carry_source_spans() {
+        let source = "def f(x: uint256):\n    if x > 1:\n        emit Big(x)\n    elif x > 0:\n        revert Small()\n";
+        let program = parse_from_source(source).unwrap();
+        let Item::Function(f) = &program.items[0] else { panic!() };
+        assert_eq!(source[f.span.start..f.span.end].trim_end(), source.trim_end());
+        assert_eq!(&source[f.params[0].span.start..f.params[0].span.end], "x: uint256");
+        let Statement::If(outer) = &f.body.statements[0] else { panic!() };
+        let emit = outer.then_branch.statements[0].span();
+        assert_eq!(&source[emit.start..emit.end], "emit Big(x)");
+        let Statement::If(elif) = &outer.else_branch.as_ref().unwrap().statements[0] else { panic!() };
+        assert!(source[elif.span.start..elif.span.end].starts_with("elif x > 0:"));
+        assert!(source[elif.span.start..elif.span.end].trim_end().ends_with("revert Small()"));
+    }
+
+    #[test]
+    fn parses_bare_decorator() {
+        let source = "@reentrancy_guard\ndef withdraw() -> uint256: return 1\n";
+        let program = parse_from_source(source).unwrap();
+        let Item::Function(f) = &program.items[0] else { panic!() };
+        assert!(f.has_decorator("reentrancy_guard"));
+        assert!(f.decorators[0].args.is_empty());
     }
 
     #[test]
@@ -602,6 +1386,57 @@ mod tests {
         assert!(matches!(program.items[0], Item::Const(_)));
     }
 
+    #[test]
+    fn scales_denomination_literals() {
+        let source = "const PRICE: uint256 = 2 ether\n\ndef t(ether: uint256) -> uint256: return 5 gwei + 3 wei + ether\n";
+        let program = parse_from_source(source).unwrap();
+        let Item::Const(c) = &program.items[0] else { panic!() };
+        assert_eq!(c.value, Expression::Number(2_000_000_000_000_000_000u64.into()));
+        let Item::Function(f) = &program.items[1] else { panic!() };
+        let Statement::Return(Some(Expression::Binary(_, sum, ident)), _) = &f.body.statements[0] else { panic!() };
+        assert_eq!(**ident, Expression::Identifier("ether".into()));
+        let Expression::Binary(_, gwei, wei) = sum.as_ref() else { panic!() };
+        assert_eq!(**gwei, Expression::Number(5_000_000_000u64.into()));
+        assert_eq!(**wei, Expression::Number(3u8.into()));
+    }
+
+    #[test]
+    fn scales_time_literals_to_seconds() {
+        let source = "def t() -> uint256: return 1 hours + 3 days + 2 weeks + 5 minutes + 7 seconds\n";
+        let program = parse_from_source(source).unwrap();
+        let Item::Function(f) = &program.items[0] else { panic!() };
+        let Statement::Return(Some(sum), _) = &f.body.statements[0] else { panic!() };
+        let folded = crate::optimizer::fold_constants(sum);
+        assert_eq!(folded, Expression::Number((3600u32 + 3 * 86400 + 2 * 604800 + 300 + 7).into()));
+    }
+
+    #[test]
+    fn parses_array_types() {
+        let source = "def f(a: uint256[], b: address[3], c: uint8[2][]):\n    return\n";
+        let program = parse_from_source(source).unwrap();
+        let Item::Function(f) = &program.items[0] else { panic!() };
+        let types: Vec<_> = f.params.iter().map(|p| p.type_.clone()).collect();
+        assert_eq!(
+            types,
+            [
+                Type::Vec(Box::new(Type::Uint(256))),
+                Type::Array(Box::new(Type::Address), 3),
+                Type::Vec(Box::new(Type::Array(Box::new(Type::Uint(8)), 2))),
+            ]
+        );
+        assert!(parse_from_source("def f(a: uint256[0]):\n    return\n").is_err());
+    }
+
+    #[test]
+    fn parses_state_variable() {
+        let source = "owner: address\nbalances: mapping[address => uint256]\n\ndef t() -> address: return owner\n";
+        let program = parse_from_source(source).unwrap();
+        assert_eq!(program.items.len(), 3);
+        let Item::State(s) = &program.items[1] else { panic!() };
+        assert_eq!(s.name, "balances");
+        assert!(matches!(s.type_, Type::Map(_, _)));
+    }
+
     #[test]
     fn parses_for_loop() {
         let source = "def t():\n    for i in items:\n        let x = i\n";
@@ -611,6 +1446,27 @@ mod tests {
         assert!(matches!(f.body.statements[0], Statement::For(_)));
     }
 
+    #[test]
+    fn parses_statement_after_nested_block() {
+        let source = "def t(x: uint256):\n    if x == 0:\n        return\n    else:\n        y = 2\n    y = 1\n";
+        let program = parse_from_source(source).unwrap();
+        let Item::Function(f) = &program.items[0] else { panic!() };
+        assert_eq!(f.body.statements.len(), 2);
+        let Statement::If(if_stmt) = &f.body.statements[0] else { panic!() };
+        assert!(if_stmt.else_branch.is_some());
+    }
+
+    #[test]
+    fn parses_range_with_step() {
+        let source = "def t():\n    for i in range(10, 0, -2):\n        let x = i\n";
+        let program = parse_from_source(source).unwrap();
+        let Item::Function(f) = &program.items[0] else { panic!() };
+        let Statement::For(for_stmt) = &f.body.statements[0] else { panic!() };
+        let args = for_stmt.range_args().unwrap();
+        assert_eq!(args.len(), 3);
+        assert!(matches!(args[2], Expression::Unary(UnaryOp::Minus, _)));
+    }
+
     #[test]
     fn parses_while_loop() {
         let source = "def t():\n    while true:\n        let x = 1\n";
@@ -620,6 +1476,200 @@ mod tests {
         assert!(matches!(f.body.statements[0], Statement::While(_)));
     }
 
+    #[test]
+    fn reports_unexpected_character_with_span() {
+        let src = "def t() -> uint256:\n    return 1 $ 2\n";
+        let errors = parse_from_source(src).unwrap_err();
+        assert_eq!(errors.len(), 1);
+        assert_eq!(
+            errors[0].reason(),
+            &chumsky::error::SimpleReason::Custom("unexpected character `$`".into())
+        );
+        assert_eq!(errors[0].span(), 33..34);
+    }
+
+    #[test]
+    fn recovers_at_item_boundaries() {
+        let src = "def a():\n    x = = 1\n\ndef ok() -> uint256:\n    return 1\n\ndef b(:\n    return 2\n";
+        let (program, errors) = parse_with_recovery(src);
+        let starts: Vec<usize> = errors.iter().map(|e| e.span().start).collect();
+        assert_eq!(starts, [15, 63]);
+        assert!(matches!(&program.items[..], [Item::Function(f)] if f.name == "ok"));
+    }
+
+    #[test]
+    fn recovers_at_statement_boundaries() {
+        let src = "def f():\n    x = = 2\n    if x:\n        y = ] 1\n    let z = 3\n    z = )\n";
+        let errors = parse_from_source(src).unwrap_err();
+        let lines: Vec<usize> = errors
+            .iter()
+            .map(|e| crate::diagnostics::line_col(src, e.span().start).0)
+            .collect();
+        assert_eq!(lines, [2, 4, 6]);
+    }
+
+    #[test]
+    fn reports_lex_and_syntax_errors_together() {
+        let src = "def f():\n    let s = $\n\ndef g(:\n    return 1\n";
+        let errors = parse_from_source(src).unwrap_err();
+        assert_eq!(errors.len(), 2);
+        assert_eq!(
+            errors[0].reason(),
+            &chumsky::error::SimpleReason::Custom("unexpected character `$`".into())
+        );
+        assert_eq!(crate::diagnostics::line_col(src, errors[1].span().start).0, 4);
+    }
+
+    #[test]
+    fn parses_call_and_condition_across_lines() {
+        let source = "def add(a: uint256, b: uint256) -> uint256: return a + b\n\ndef t(\n    x: uint256,\n    y: uint256,\n) -> uint256:\n    if (x > 1 and\n            y > 2):\n        return add(\n            x,\n            y,\n        )\n    return 0\n";
+        let program = parse_from_source(source).unwrap();
+        let Item::Function(f) = &program.items[1] else { panic!() };
+        assert_eq!(f.params.len(), 2);
+        let Statement::If(if_stmt) = &f.body.statements[0] else { panic!() };
+        assert!(matches!(&if_stmt.then_branch.statements[0], Statement::Return(Some(Expression::Call(_, args)), _) if args.len() == 2));
+        assert_eq!(f.body.statements.len(), 2);
+    }
+
+    #[test]
+    fn struct_fields_may_be_separated_by_newlines() {
+        let source = "struct P {\n    a: uint256\n    b: uint256\n}\n\nlet p: P = P {\n    a: 1\n    b: 2\n}\n";
+        let program = parse_from_source(source).unwrap();
+        assert!(matches!(&program.items[0], Item::Struct(s) if s.fields.len() == 2));
+    }
+
+    #[test]
+    fn block_may_open_with_a_comment_line() {
+        let source = "def f():\n    # first\n    x = 1\n\ndef g():\n    y = 2\n";
+        let program = parse_from_source(source).unwrap();
+        assert_eq!(program.items.len(), 2);
+    }
+
+    #[test]
+    fn reports_fractional_scientific_literal() {
+        let src = "const RATE: uint256 = 1.25e1\n";
+        let errors = parse_from_source(src).unwrap_err();
+        assert_eq!(
+            errors[0].reason(),
+            &chumsky::error::SimpleReason::Custom("`1.25e1` is not a whole number".into())
+        );
+    }
+
+    #[test]
+    fn reports_indentation_error_line() {
+        let src = "def t() -> uint256:\n    let a = 1\n\treturn a\n";
+        let errors = parse_from_source(src).unwrap_err();
+        assert_eq!(
+            errors[0].reason(),
+            &chumsky::error::SimpleReason::Custom(
+                "mixed tabs and spaces in indentation on line 3".into()
+            )
+        );
+        assert_eq!(crate::diagnostics::line_col(src, errors[0].span().start).0, 3);
+    }
+
+    #[test]
+    fn parses_tuple_return_and_destructuring() {
+        let src = "def divmod(a: uint256, b: uint256) -> (uint256, uint256):\n    return a / b, a % b\n\ndef t(a: uint256):\n    (q, r) = divmod(a, 7)\n";
+        let program = parse_from_source(src).unwrap();
+        let Item::Function(f) = &program.items[0] else { panic!() };
+        assert_eq!(f.return_type, Some(Type::Tuple(vec![Type::Uint(256), Type::Uint(256)])));
+        let Statement::Return(Some(Expression::Tuple(values)), _) = &f.body.statements[0] else { panic!() };
+        assert_eq!(values.len(), 2);
+        let Item::Function(t) = &program.items[1] else { panic!() };
+        let Statement::Assign(a) = &t.body.statements[0] else { panic!() };
+        assert!(matches!(&a.target, Expression::Tuple(targets) if targets.len() == 2));
+        assert!(matches!(a.value, Expression::Call(_, _)));
+    }
+
+    #[test]
+    fn parses_named_return() {
+        let src = "def f() -> (result: uint256):\n    result = 5\n\ndef g() -> (q: uint256, r: bool):\n    q = 1\n";
+        let program = parse_from_source(src).unwrap();
+        let Item::Function(f) = &program.items[0] else { panic!() };
+        assert_eq!(f.return_type, Some(Type::Uint(256)));
+        assert_eq!(f.return_names, ["result"]);
+        let Item::Function(g) = &program.items[1] else { panic!() };
+        assert_eq!(g.return_type, Some(Type::Tuple(vec![Type::Uint(256), Type::Bool])));
+        assert_eq!(g.named_returns(), [("q", &Type::Uint(256)), ("r", &Type::Bool)]);
+    }
+
+    #[test]
+    fn parses_bytes_index_and_slice() {
+        let src = "def t(data: bytes) -> bytes:\n    let b = data[0]\n    return data[0:4]\n";
+        let program = parse_from_source(src).unwrap();
+        let Item::Function(f) = &program.items[0] else { panic!() };
+        let Statement::Let(l) = &f.body.statements[0] else { panic!() };
+        assert!(matches!(&l.value, Some(Expression::Index(base, idx))
+            if **base == Expression::Identifier("data".into()) && matches!(**idx, Expression::Number(_))));
+        let Statement::Return(Some(Expression::Slice(_, start, end)), _) = &f.body.statements[1] else { panic!() };
+        assert!(matches!(**start, Expression::Number(_)));
+        assert!(matches!(**end, Expression::Number(_)));
+    }
+
+    #[test]
+    fn parse_program_recovers_like_parse_from_source() {
+        let lex = |src| crate::lexer::PyraLexer::new(src).filter(|t| !t.is_error()).collect::<Vec<_>>();
+        let src = "modifier only:\n    _\n\n@only\ndef f() -> uint256: return 1\n";
+        let program = parse_program(lex(src)).unwrap();
+        let expected = parse_from_source(src).unwrap();
+        let (Item::Function(got), Item::Function(want)) = (&program.items[1], &expected.items[1]) else { panic!() };
+        assert_eq!(got.modifiers.len(), 1);
+        assert_eq!(got.modifiers[0].name, want.modifiers[0].name);
+        assert_eq!(got.body.statements.len(), want.body.statements.len());
+
+        let src = "def f() -> uint256: return +\n\ndef g() -> uint256: return 2\n\ndef h() -> : return 3\n";
+        let errors = parse_program(lex(src)).unwrap_err();
+        assert_eq!(errors.len(), 2, "{errors:?}");
+        assert_eq!(errors.len(), parse_from_source(src).unwrap_err().len());
+    }
+
+    #[test]
+    fn parses_asm_block() {
+        let src = "def t():\n    asm: PUSH1 0x01 PUSH1 0x02 ADD\n    asm:\n        POP\n        STOP\n";
+        let program = parse_from_source(src).unwrap();
+        let Item::Function(f) = &program.items[0] else { panic!() };
+        let Statement::Asm(block) = &f.body.statements[0] else { panic!() };
+        let mnemonics: Vec<&str> = block.instructions.iter().map(|i| i.mnemonic.as_str()).collect();
+        assert_eq!(mnemonics, ["PUSH1", "PUSH1", "ADD"]);
+        assert_eq!(block.instructions[1].immediate, Some(2u8.into()));
+        assert_eq!(block.instructions[2].immediate, None);
+        let Statement::Asm(block) = &f.body.statements[1] else { panic!() };
+        assert_eq!(block.instructions.len(), 2);
+
+        let src = "def t():\n    asm: PUSH20 0x5aAeb6053F3E94C9b9A09f33669435E7Ef1BeAed POP\n";
+        let program = parse_from_source(src).unwrap();
+        let Item::Function(f) = &program.items[0] else { panic!() };
+        let Statement::Asm(block) = &f.body.statements[0] else { panic!() };
+        let expected = num_bigint::BigUint::parse_bytes(b"5aaeb6053f3e94c9b9a09f33669435e7ef1beaed", 16);
+        assert_eq!(block.instructions[0].immediate, expected);
+        assert_eq!(block.instructions.len(), 2);
+    }
+
+    #[test]
+    fn parses_mapping_field_type() {
+        let src = "struct State {\n    allowances: mapping[address => mapping[address => uint256]]\n}\n";
+        let program = parse_from_source(src).unwrap();
+        let Item::Struct(s) = &program.items[0] else { panic!() };
+        let Type::Map(k, v) = &s.fields[0].type_ else { panic!() };
+        assert_eq!(**k, Type::Address);
+        assert!(matches!(**v, Type::Map(_, _)));
+    }
+
+    #[test]
+    fn parses_power_right_associative() {
+        let source = "def t() -> uint256: return 2 ** 3 ** 2";
+        let program = parse_from_source(source).unwrap();
+        let Item::Function(f) = &program.items[0] else { panic!() };
+        let Statement::Return(Some(Expression::Binary(BinaryOp::Pow, lhs, rhs)), _) =
+            &f.body.statements[0]
+        else {
+            panic!()
+        };
+        assert!(matches!(lhs.as_ref(), Expression::Number(_)));
+        assert!(matches!(rhs.as_ref(), Expression::Binary(BinaryOp::Pow, _, _)));
+    }
+
     #[test]
     fn parses_event_declaration() {
         let source = "event Transfer(from: address, to: address, amount: uint256)\n\ndef t() -> bool: return true\n";
@@ -628,6 +1678,105 @@ mod tests {
         assert!(matches!(program.items[0], Item::Event(_)));
     }
 
+    #[test]
+    fn parses_indexed_event_fields() {
+        let source = "event Transfer(from: indexed(address), to: indexed(address), amount: uint256)\n";
+        let program = parse_from_source(source).unwrap();
+        let Item::Event(event) = &program.items[0] else { panic!() };
+        let indexed: Vec<bool> = event.fields.iter().map(|f| f.indexed).collect();
+        assert_eq!(indexed, [true, true, false]);
+        assert_eq!(event.fields[0].type_, Type::Address);
+        assert_eq!(event.indexed_count(), 2);
+    }
+
+    #[test]
+    fn parses_error_declaration_and_revert() {
+        let source = "error Unauthorized(caller: address)\n\ndef t():\n    revert Unauthorized(msg.sender)\n";
+        let program = parse_from_source(source).unwrap();
+        let Item::Error(error) = &program.items[0] else { panic!() };
+        assert_eq!(error.name, "Unauthorized");
+        assert_eq!(error.params[0].type_, Type::Address);
+        let Item::Function(f) = &program.items[1] else { panic!() };
+        let Statement::Revert(rv) = &f.body.statements[0] else { panic!() };
+        assert_eq!(rv.name, "Unauthorized");
+        assert_eq!(rv.args.len(), 1);
+    }
+
+    #[test]
+    fn parses_interface_declaration() {
+        let source = "interface IERC20:\n    def transfer(to: address, amount: uint256) -> bool\n    @view\n    def balanceOf(owner: address) -> uint256\n\ndef t() -> bool: return true\n";
+        let program = parse_from_source(source).unwrap();
+        assert_eq!(program.items.len(), 2);
+        let Item::Interface(iface) = &program.items[0] else { panic!() };
+        assert_eq!(iface.name, "IERC20");
+        let names: Vec<&str> = iface.functions.iter().map(|f| f.name.as_str()).collect();
+        assert_eq!(names, ["transfer", "balanceOf"]);
+        assert!(!iface.functions[0].is_read_only());
+        assert!(iface.functions[1].is_read_only());
+        assert_eq!(iface.functions[1].return_type, Some(Type::Uint(256)));
+    }
+
+    #[test]
+    fn parses_enum_and_resolves_its_uses() {
+        let source = "enum Status: Pending, Active, Closed\n\nstatus: Status\n\ndef t(s: Status) -> bool: return true\n";
+        let program = parse_from_source(source).unwrap();
+        let Item::Enum(e) = &program.items[0] else { panic!() };
+        assert_eq!(e.variants, ["Pending", "Active", "Closed"]);
+        assert_eq!(e.variant("Closed"), Some(2));
+        let Item::State(s) = &program.items[1] else { panic!() };
+        assert_eq!(s.type_, Type::Enum("Status".into()));
+        let Item::Function(f) = &program.items[2] else { panic!() };
+        assert_eq!(f.params[0].type_, Type::Enum("Status".into()));
+    }
+
+    #[test]
+    fn expands_modifiers_around_function_body() {
+        let source = "modifier only_owner:\n    require msg.sender == owner\n    _\n\nmodifier counted:\n    _\n    calls += 1\n\n@only_owner\n@counted\n@view\ndef t() -> bool: return true\n";
+        let program = parse_from_source(source).unwrap();
+        let Item::Function(f) = &program.items[2] else { panic!() };
+        let names: Vec<&str> = f.decorators.iter().map(|d| d.name.as_str()).collect();
+        assert_eq!(names, ["view"]);
+        let modifiers: Vec<&str> = f.modifiers.iter().map(|m| m.name.as_str()).collect();
+        assert_eq!(modifiers, ["only_owner", "counted"]);
+        assert!(matches!(f.body.statements[..], [Statement::Return(_, _)]));
+    }
+
+    #[test]
+    fn parses_import_item() {
+        let source = "import \"common.pyra\"\n\ndef t() -> bool: return true\n";
+        let program = parse_from_source(source).unwrap();
+        assert_eq!(program.items.len(), 2);
+        let Item::Import(import) = &program.items[0] else { panic!() };
+        assert_eq!(import.path, "common.pyra");
+    }
+
+    #[test]
+    fn parses_from_import_item() {
+        let source = "from token.erc20 import Transfer, balance_of
+from \"lib/x.pyra\" import A
+
+def pull(from: address) -> address: return from
+";
+        let program = parse_from_source(source).unwrap();
+        let Item::Import(import) = &program.items[0] else { panic!() };
+        assert_eq!(import.path, "token/erc20.pyra");
+        assert_eq!(import.names, ["Transfer", "balance_of"]);
+        let Item::Import(import) = &program.items[1] else { panic!() };
+        assert_eq!(import.path, "lib/x.pyra");
+        assert_eq!(import.names, ["A"]);
+        let Item::Function(f) = &program.items[2] else { panic!() };
+        assert_eq!(f.params[0].name, "from");
+    }
+
+    #[test]
+    fn parses_break_and_continue() {
+        let source = "def t():\n    while true:\n        continue\n        break\n";
+        let program = parse_from_source(source).unwrap();
+        let Item::Function(f) = &program.items[0] else { panic!() };
+        let Statement::While(w) = &f.body.statements[0] else { panic!() };
+        assert!(matches!(w.body.statements[..], [Statement::Continue(_), Statement::Break(_)]));
+    }
+
     #[test]
     fn parses_emit_statement() {
         let source = "def t():\n    emit Transfer(a, b, c)\n";
@@ -0,0 +1,588 @@
+//! Project layout: the `pyra.toml` manifest at the root, contracts under
+//! `contracts/` and tests under `tests/`. The manifest also holds the
+//! compiler settings the project builds with and its dependencies.
+
+use crate::compiler::{CompileError, CompileOptions, OutputFormat};
+use crate::{EvmVersion, GuardMode, HardenConfig, OptLevel};
+use serde::Deserialize;
+use std::collections::{BTreeMap, HashSet, VecDeque};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// The manifest file that marks a project root.
+pub const MANIFEST: &str = "pyra.toml";
+
+/// Where git dependencies are checked out, relative to the project root.
+pub const DEPS_DIR: &str = ".pyra/deps";
+
+/// Where `pyra test` looks for test files, relative to the project root.
+pub const TESTS_DIR: &str = "tests";
+
+const STARTER_CONTRACT: &str = "\
+# A counter anyone can bump. Build the project with `pyra build`.
+
+event Incremented(by: address, count: uint256)
+
+count: uint256
+
+def increment():
+    self.count += 1
+    emit Incremented(msg.sender, self.count)
+
+@view
+def get() -> uint256:
+    return self.count
+";
+
+const STARTER_TEST: &str = "\
+# Run with `pyra test`. Each `test_` function is called on a fresh
+# deployment and passes unless it reverts.
+
+import \"../contracts/Counter.pyra\"
+
+def test_increment():
+    increment()
+    increment()
+    require get() == 2, \"count should be 2\"
+";
+
+const GITIGNORE: &str = "out/\nartifacts/\n.pyra/\n";
+
+/// The contents of `pyra.toml`. Unknown keys are rejected, so a misspelt
+/// setting is reported rather than ignored.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Manifest {
+    #[serde(default)]
+    pub project: Package,
+    #[serde(default)]
+    pub build: BuildSettings,
+    /// Import prefixes and where their files come from.
+    #[serde(default)]
+    pub dependencies: BTreeMap<String, Dependency>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Package {
+    #[serde(default)]
+    pub name: String,
+    #[serde(default)]
+    pub version: String,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct BuildSettings {
+    /// Directories searched, recursively, for `.pyra` contracts.
+    pub sources: Vec<PathBuf>,
+    /// Where artifacts are written; when unset, next to each contract for
+    /// the `pyra` format and the format's usual directory otherwise.
+    pub out: Option<PathBuf>,
+    /// Extra directories imports are looked up in.
+    pub include: Vec<PathBuf>,
+    pub evm_version: EvmVersion,
+    /// 0, 1 or 2, as with `-O`.
+    pub opt_level: u8,
+    pub format: OutputFormat,
+    pub artifact_format: ArtifactFormat,
+    pub strict: bool,
+    pub hardening: Hardening,
+}
+
+impl Default for BuildSettings {
+    fn default() -> Self {
+        Self {
+            sources: vec![PathBuf::from("contracts")],
+            out: None,
+            include: Vec::new(),
+            evm_version: EvmVersion::default(),
+            opt_level: 2,
+            format: OutputFormat::default(),
+            artifact_format: ArtifactFormat::default(),
+            strict: false,
+            hardening: Hardening::default(),
+        }
+    }
+}
+
+/// The checks `pyra build` inserts, as in [`HardenConfig`].
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Hardening {
+    pub add: bool,
+    pub sub: bool,
+    pub mul: bool,
+    pub exp: bool,
+    pub div: bool,
+    pub strict_calldata: bool,
+    /// Lock only the functions marked `@reentrancy_guard`.
+    pub explicit_guards: bool,
+}
+
+impl Default for Hardening {
+    fn default() -> Self {
+        let config = HardenConfig::default();
+        Self {
+            add: config.add,
+            sub: config.sub,
+            mul: config.mul,
+            exp: config.exp,
+            div: config.div,
+            strict_calldata: config.strict_calldata,
+            explicit_guards: config.guard_mode == GuardMode::Explicit,
+        }
+    }
+}
+
+/// The layout build output is written in.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ArtifactFormat {
+    #[default]
+    Pyra,
+    Foundry,
+    Hardhat,
+}
+
+/// A directory, relative to the manifest, or a git repository checked out
+/// at `rev` (a commit, tag or branch) or at its default branch. Exactly one
+/// of `path` and `git` is set.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Dependency {
+    pub path: Option<PathBuf>,
+    pub git: Option<String>,
+    pub rev: Option<String>,
+}
+
+impl Manifest {
+    /// Parses the text of the manifest at `path`.
+    pub fn parse(path: &Path, text: &str) -> Result<Manifest, CompileError> {
+        let error = |message: String, span| CompileError::Manifest {
+            path: path.to_path_buf(),
+            message,
+            span,
+        };
+        let manifest: Manifest =
+            toml::from_str(text).map_err(|e| error(e.message().to_string(), e.span()))?;
+
+        if manifest.build.opt_level > 2 {
+            return Err(error("`build.opt_level` must be 0, 1 or 2".to_string(), None));
+        }
+        for (name, dep) in &manifest.dependencies {
+            if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+                return Err(error(
+                    format!("dependency name `{name}` cannot be used in an import"),
+                    None,
+                ));
+            }
+            match (&dep.path, &dep.git) {
+                (Some(_), None) if dep.rev.is_some() => {
+                    return Err(error(format!("dependency `{name}` has `rev` but no `git`"), None))
+                }
+                (Some(_), None) | (None, Some(_)) => {}
+                _ => {
+                    return Err(error(
+                        format!("dependency `{name}` needs exactly one of `path` and `git`"),
+                        None,
+                    ))
+                }
+            }
+        }
+        Ok(manifest)
+    }
+}
+
+/// A manifest and the directory it sits in.
+#[derive(Debug, Clone)]
+pub struct Project {
+    pub root: PathBuf,
+    pub manifest: Manifest,
+}
+
+impl Project {
+    /// Reads the manifest in `root`.
+    pub fn load(root: &Path) -> Result<Project, CompileError> {
+        let path = root.join(MANIFEST);
+        let text = std::fs::read_to_string(&path)?;
+        Ok(Project {
+            root: root.to_path_buf(),
+            manifest: Manifest::parse(&path, &text)?,
+        })
+    }
+
+    /// Every `.pyra` file under the source directories, sorted.
+    pub fn sources(&self) -> Result<Vec<PathBuf>, CompileError> {
+        let mut files = Vec::new();
+        for dir in &self.manifest.build.sources {
+            let dir = self.root.join(dir);
+            if !dir.is_dir() {
+                return Err(CompileError::Manifest {
+                    path: self.root.join(MANIFEST),
+                    message: format!("source directory {} does not exist", dir.display()),
+                    span: None,
+                });
+            }
+            files.extend(pyra_files(&dir)?);
+        }
+        files.sort();
+        files.dedup();
+        Ok(files)
+    }
+
+    /// The configured output directory.
+    pub fn out_dir(&self) -> Option<PathBuf> {
+        self.manifest.build.out.as_ref().map(|out| self.root.join(out))
+    }
+
+    /// The directory holding the project's test files.
+    pub fn tests_dir(&self) -> PathBuf {
+        self.root.join(TESTS_DIR)
+    }
+
+    /// Options for compiling the project's sources. Git dependencies not yet
+    /// under [`DEPS_DIR`] are cloned first.
+    pub fn compile_options(&self) -> Result<CompileOptions, CompileError> {
+        let build = &self.manifest.build;
+        let hardening = &build.hardening;
+        Ok(CompileOptions {
+            include_paths: build.include.iter().map(|dir| self.root.join(dir)).collect(),
+            dependencies: self.dependency_dirs()?,
+            format: build.format,
+            strict: build.strict,
+            harden: HardenConfig {
+                add: hardening.add,
+                sub: hardening.sub,
+                mul: hardening.mul,
+                exp: hardening.exp,
+                div: hardening.div,
+                opt_level: match build.opt_level {
+                    0 => OptLevel::O0,
+                    1 => OptLevel::O1,
+                    _ => OptLevel::O2,
+                },
+                evm_version: build.evm_version,
+                strict_calldata: hardening.strict_calldata,
+                guard_mode: if hardening.explicit_guards { GuardMode::Explicit } else { GuardMode::All },
+            },
+            ..CompileOptions::default()
+        })
+    }
+
+    /// Each dependency name with the directories its imports are read from:
+    /// a dependency with a manifest of its own contributes its source
+    /// directories and its dependencies, any other its root. When two
+    /// dependencies share a name, the one nearer this project wins.
+    fn dependency_dirs(&self) -> Result<Vec<(String, PathBuf)>, CompileError> {
+        let mut dirs = Vec::new();
+        let mut seen = HashSet::new();
+        let mut queue = VecDeque::from([self.clone()]);
+        while let Some(project) = queue.pop_front() {
+            for (name, dep) in &project.manifest.dependencies {
+                if !seen.insert(name.clone()) {
+                    continue;
+                }
+                let root = match (&dep.path, &dep.git) {
+                    (Some(path), _) => project.root.join(path),
+                    (None, Some(url)) => {
+                        let checkout = self.root.join(DEPS_DIR).join(name);
+                        if !checkout.exists() {
+                            clone_dependency(name, url, dep.rev.as_deref(), &checkout)?;
+                        }
+                        checkout
+                    }
+                    (None, None) => unreachable!("checked when the manifest is parsed"),
+                };
+                if !root.is_dir() {
+                    return Err(CompileError::Manifest {
+                        path: project.root.join(MANIFEST),
+                        message: format!("dependency `{name}` not found at {}", root.display()),
+                        span: None,
+                    });
+                }
+                if root.join(MANIFEST).is_file() {
+                    let nested = Project::load(&root)?;
+                    for dir in &nested.manifest.build.sources {
+                        dirs.push((name.clone(), root.join(dir)));
+                    }
+                    queue.push_back(nested);
+                } else {
+                    dirs.push((name.clone(), root));
+                }
+            }
+        }
+        Ok(dirs)
+    }
+}
+
+/// The nearest directory at or above `start`, an absolute path, that holds
+/// a manifest. It is returned relative to `start`: empty for `start`
+/// itself, `..` for its parent and so on.
+pub fn find_root(start: &Path) -> Option<PathBuf> {
+    let mut relative = PathBuf::new();
+    for dir in start.ancestors() {
+        if dir.join(MANIFEST).is_file() {
+            return Some(relative);
+        }
+        relative.push("..");
+    }
+    None
+}
+
+/// Every `.pyra` file under `dir`, at any depth, sorted.
+pub fn pyra_files(dir: &Path) -> std::io::Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    let mut dirs = vec![dir.to_path_buf()];
+    while let Some(dir) = dirs.pop() {
+        for entry in std::fs::read_dir(dir)? {
+            let path = entry?.path();
+            if path.is_dir() {
+                dirs.push(path);
+            } else if path.extension().is_some_and(|ext| ext == "pyra") {
+                files.push(path);
+            }
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+/// Clones `url` into `dir` and checks out `rev`. Nothing is left behind on
+/// failure, so the next build tries again; to move an existing checkout to
+/// another `rev`, delete it. `--` keeps git from reading the url or the
+/// directory as an option; `checkout` cannot take it before a revision, so a
+/// `rev` that starts with `-` is refused instead.
+fn clone_dependency(name: &str, url: &str, rev: Option<&str>, dir: &Path) -> Result<(), CompileError> {
+    if let Some(rev) = rev.filter(|rev| rev.starts_with('-')) {
+        return Err(CompileError::Dependency {
+            name: name.to_string(),
+            message: format!("rev \"{rev}\" cannot start with `-`"),
+        });
+    }
+    if let Some(parent) = dir.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    let mut clone = Command::new("git");
+    clone.args(["clone", "--quiet", "--", url]).arg(dir);
+    let mut steps = vec![clone];
+    if let Some(rev) = rev {
+        let mut checkout = Command::new("git");
+        checkout.arg("-C").arg(dir).args(["checkout", "--quiet", rev]);
+        steps.push(checkout);
+    }
+    for mut step in steps {
+        let failure = match step.output() {
+            Ok(output) if output.status.success() => continue,
+            Ok(output) => String::from_utf8_lossy(&output.stderr).trim().to_string(),
+            Err(e) => format!("cannot run git: {e}"),
+        };
+        std::fs::remove_dir_all(dir).ok();
+        return Err(CompileError::Dependency {
+            name: name.to_string(),
+            message: failure,
+        });
+    }
+    Ok(())
+}
+
+/// Creates a project in `dir`, making the directory if needed: a manifest
+/// named after the directory, `contracts/Counter.pyra`, a test for it in
+/// `tests/` and a `.gitignore` for build output unless one exists. Nothing is
+/// overwritten; if the manifest or starter files are already there, no
+/// file is written. Returns the files created.
+pub fn init_project(dir: &Path) -> std::io::Result<Vec<PathBuf>> {
+    std::fs::create_dir_all(dir)?;
+    let name = project_name(&std::fs::canonicalize(dir)?);
+
+    let files = [
+        (PathBuf::from(MANIFEST), manifest(&name)),
+        (Path::new("contracts").join("Counter.pyra"), STARTER_CONTRACT.to_string()),
+        (Path::new(TESTS_DIR).join("test_counter.pyra"), STARTER_TEST.to_string()),
+    ];
+    if let Some((path, _)) = files.iter().find(|(path, _)| dir.join(path).exists()) {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::AlreadyExists,
+            format!("{} already exists", dir.join(path).display()),
+        ));
+    }
+
+    let gitignore = (PathBuf::from(".gitignore"), GITIGNORE.to_string());
+    let mut created = Vec::new();
+    for (path, content) in files.into_iter().chain(Some(gitignore)) {
+        let path = dir.join(path);
+        if path.exists() {
+            continue;
+        }
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        std::fs::write(&path, content)?;
+        created.push(path);
+    }
+    Ok(created)
+}
+
+fn manifest(name: &str) -> String {
+    format!(
+        "\
+[project]
+name = \"{name}\"
+version = \"0.1.0\"
+
+[build]
+# Directories searched for `.pyra` contracts, relative to this file.
+sources = [\"contracts\"]
+out = \"out\"
+evm_version = \"paris\"
+opt_level = 2
+
+[dependencies]
+# math = {{ path = \"../math\" }}
+# tokens = {{ git = \"https://github.com/example/tokens\", rev = \"v1.0.0\" }}
+"
+    )
+}
+
+/// The directory name with anything but ASCII letters, digits, `-` and `_`
+/// replaced, so it can be written into the manifest as is.
+fn project_name(dir: &Path) -> String {
+    let name: String = dir
+        .file_name()
+        .map(|n| n.to_string_lossy())
+        .unwrap_or_default()
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() || c == '-' || c == '_' { c } else { '_' })
+        .collect();
+    if name.is_empty() {
+        "project".to_string()
+    } else {
+        name
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compiler::{compile_sources, CompileOptions};
+    use crate::{check_warnings, format_source, run_tests};
+    use std::collections::BTreeMap;
+
+    #[test]
+    fn starter_contract_is_clean() {
+        let sources = BTreeMap::from([("Counter.pyra".to_string(), STARTER_CONTRACT.to_string())]);
+        let program = compile_sources("Counter.pyra", &sources, &CompileOptions::default()).unwrap();
+        assert!(check_warnings(&program).is_empty());
+        assert_eq!(format_source(STARTER_CONTRACT).unwrap(), STARTER_CONTRACT);
+    }
+
+    #[test]
+    fn starter_test_passes() {
+        let sources = BTreeMap::from([
+            ("contracts/Counter.pyra".to_string(), STARTER_CONTRACT.to_string()),
+            ("tests/test_counter.pyra".to_string(), STARTER_TEST.to_string()),
+        ]);
+        let options = CompileOptions::default();
+        let program = compile_sources("tests/test_counter.pyra", &sources, &options).unwrap();
+        let results = run_tests(&program, &options.harden).unwrap();
+        assert_eq!(results.len(), 1);
+        assert!(results[0].passed, "{results:?}");
+        assert_eq!(format_source(STARTER_TEST).unwrap(), STARTER_TEST);
+    }
+
+    #[test]
+    fn init_manifest_parses() {
+        let manifest = Manifest::parse(Path::new(MANIFEST), &manifest("demo")).unwrap();
+        assert_eq!(manifest.project.name, "demo");
+        assert_eq!(manifest.build.sources, [PathBuf::from("contracts")]);
+        assert_eq!(manifest.build.out, Some(PathBuf::from("out")));
+        assert_eq!(manifest.build.artifact_format, ArtifactFormat::Pyra);
+        assert!(manifest.dependencies.is_empty());
+    }
+
+    #[test]
+    fn manifest_errors_point_at_the_key() {
+        let text = "[build]\nopt_levl = 1\n";
+        let Err(CompileError::Manifest { message, span, .. }) = Manifest::parse(Path::new(MANIFEST), text)
+        else {
+            panic!("expected a manifest error");
+        };
+        assert!(message.contains("opt_levl"), "{message}");
+        assert_eq!(&text[span.unwrap()], "opt_levl");
+
+        let text = "[build]\nevm_version = \"london\"\n";
+        let err = Manifest::parse(Path::new(MANIFEST), text).unwrap_err();
+        assert!(err.to_string().contains("london"), "{err}");
+        let err = Manifest::parse(Path::new(MANIFEST), "[build]\nopt_level = 3\n").unwrap_err();
+        assert!(err.to_string().contains("must be 0, 1 or 2"), "{err}");
+    }
+
+    #[test]
+    fn dependencies_need_one_source() {
+        let parse = |deps: &str| Manifest::parse(Path::new(MANIFEST), &format!("[dependencies]\n{deps}\n"));
+        let manifest = parse("math = { path = \"../math\" }\ntokens = { git = \"https://example.com/t\", rev = \"v1\" }")
+            .unwrap();
+        assert_eq!(manifest.dependencies["math"].path, Some(PathBuf::from("../math")));
+        assert_eq!(manifest.dependencies["tokens"].rev.as_deref(), Some("v1"));
+
+        for bad in [
+            "math = {}",
+            "math = { path = \"m\", git = \"https://example.com/m\" }",
+            "math = { path = \"m\", rev = \"v1\" }",
+            "\"my-math\" = { path = \"m\" }",
+        ] {
+            assert!(matches!(parse(bad), Err(CompileError::Manifest { .. })), "{bad}");
+        }
+    }
+
+    #[test]
+    fn dependency_revs_cannot_be_options() {
+        let dir = std::env::temp_dir().join("pyra-rev-option").join("math");
+        let err = clone_dependency("math", "https://example.com/m", Some("--upload-pack=touch x"), &dir).unwrap_err();
+        assert!(matches!(err, CompileError::Dependency { ref message, .. } if message.contains("cannot start with `-`")));
+        assert!(!dir.exists());
+    }
+
+    #[test]
+    fn compile_options_follow_build_settings() {
+        let text = "\
+[build]
+include = [\"lib\"]
+evm_version = \"cancun\"
+opt_level = 0
+format = \"eof\"
+strict = true
+
+[build.hardening]
+add = false
+strict_calldata = true
+explicit_guards = true
+";
+        let project = Project {
+            root: PathBuf::from("app"),
+            manifest: Manifest::parse(Path::new(MANIFEST), text).unwrap(),
+        };
+        let options = project.compile_options().unwrap();
+        assert_eq!(options.include_paths, [Path::new("app").join("lib")]);
+        assert_eq!(options.format, OutputFormat::Eof);
+        assert!(options.strict);
+        assert_eq!(
+            options.harden,
+            HardenConfig {
+                add: false,
+                opt_level: OptLevel::O0,
+                evm_version: EvmVersion::Cancun,
+                strict_calldata: true,
+                guard_mode: GuardMode::Explicit,
+                ..HardenConfig::default()
+            }
+        );
+    }
+
+    #[test]
+    fn project_name_is_toml_safe() {
+        assert_eq!(project_name(Path::new("/work/my token\"")), "my_token_");
+        assert_eq!(project_name(Path::new("/work/vault-v2")), "vault-v2");
+        assert_eq!(project_name(Path::new("/")), "project");
+    }
+}
@@ -0,0 +1,482 @@
+//! Import resolution. `import "path.pyra"` merges every item of a file,
+//! while `from token.erc20 import Transfer` merges only the named items and
+//! the items they refer to, so the rest of the module cannot clash with the
+//! importer's names. Files are read through a [`SourceTree`], which lets the
+//! same rules run against the disk or an in-memory set of sources.
+
+use crate::compiler::CompileError;
+use crate::parser::parse_from_source;
+use crate::typer::TypeError;
+use crate::{Block, Expression, ImportDecl, Item, Program, Span, Statement, Type};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::path::{Component, Path, PathBuf};
+
+/// Where a program and the files it imports are read from.
+pub trait SourceTree {
+    /// A name shared by every path that reaches the same file.
+    fn identity(&self, path: &Path) -> std::io::Result<PathBuf>;
+    fn read(&self, path: &Path) -> std::io::Result<String>;
+    /// The file `target` names when imported from `importer`.
+    fn resolve(&self, importer: &Path, target: &str) -> Option<PathBuf>;
+}
+
+/// Files on disk. An import is looked up next to the importing file, then
+/// under the dependency its first component names, then in each include
+/// directory in turn.
+pub struct DiskSources<'a> {
+    pub include_paths: &'a [PathBuf],
+    pub dependencies: &'a [(String, PathBuf)],
+}
+
+impl SourceTree for DiskSources<'_> {
+    fn identity(&self, path: &Path) -> std::io::Result<PathBuf> {
+        std::fs::canonicalize(path)
+    }
+
+    fn read(&self, path: &Path) -> std::io::Result<String> {
+        std::fs::read_to_string(path)
+    }
+
+    fn resolve(&self, importer: &Path, target: &str) -> Option<PathBuf> {
+        let base = importer.parent().unwrap_or_else(|| Path::new("."));
+        let in_dependency = target.split_once('/').into_iter().flat_map(|(name, rest)| {
+            self.dependencies
+                .iter()
+                .filter(move |(dep, _)| dep == name)
+                .map(move |(_, dir)| dir.join(rest))
+        });
+        std::iter::once(base.join(target))
+            .chain(in_dependency)
+            .chain(self.include_paths.iter().map(|dir| dir.join(target)))
+            .find(|candidate| candidate.is_file())
+    }
+}
+
+/// Sources held in memory and keyed by path, such as standard JSON input.
+/// An import is looked up relative to the importing source, then as a key
+/// of its own.
+pub struct MemorySources<'a>(BTreeMap<PathBuf, &'a str>);
+
+impl<'a> MemorySources<'a> {
+    pub fn new(sources: &'a BTreeMap<String, String>) -> Self {
+        MemorySources(
+            sources
+                .iter()
+                .map(|(name, content)| (normalize(Path::new(name)), content.as_str()))
+                .collect(),
+        )
+    }
+}
+
+impl SourceTree for MemorySources<'_> {
+    fn identity(&self, path: &Path) -> std::io::Result<PathBuf> {
+        Ok(normalize(path))
+    }
+
+    fn read(&self, path: &Path) -> std::io::Result<String> {
+        self.0.get(&normalize(path)).map(|s| s.to_string()).ok_or_else(|| {
+            std::io::Error::new(
+                std::io::ErrorKind::NotFound,
+                format!("source \"{}\" not found", path.display()),
+            )
+        })
+    }
+
+    fn resolve(&self, importer: &Path, target: &str) -> Option<PathBuf> {
+        let base = importer.parent().unwrap_or_else(|| Path::new(""));
+        [normalize(&base.join(target)), normalize(Path::new(target))]
+            .into_iter()
+            .find(|candidate| self.0.contains_key(candidate))
+    }
+}
+
+/// Drops `.` and resolves `..` without touching the disk.
+pub(crate) fn normalize(path: &Path) -> PathBuf {
+    let mut out = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                out.pop();
+            }
+            other => out.push(other),
+        }
+    }
+    out
+}
+
+/// Loads `path` and everything it imports into one program. Two different
+/// items with the same name are reported together as duplicates; the same
+/// item reached through several imports is merged once.
+pub fn resolve_program(tree: &dyn SourceTree, path: &Path) -> Result<Program, CompileError> {
+    let mut resolver = Resolver {
+        tree,
+        stack: Vec::new(),
+        files: HashMap::new(),
+        span: Span { start: 0, end: 0 },
+    };
+    let mut files = vec![tree.identity(path)?];
+    let mut item_files = Vec::new();
+    let mut items = Vec::new();
+    for ((file, _), item) in resolver.file_items(path)? {
+        let index = files.iter().position(|f| *f == file).unwrap_or_else(|| {
+            files.push(file);
+            files.len() - 1
+        });
+        item_files.push(index);
+        items.push(item);
+    }
+
+    let mut names = HashSet::new();
+    let mut duplicates = Vec::new();
+    for (item, &file) in items.iter().zip(&item_files) {
+        let (Some(name), Some(span)) = (item_name(item), item_name_span(item)) else { continue };
+        if !names.insert(name) {
+            let error = TypeError::Duplicate { name: name.to_string(), file: files[file].clone() };
+            duplicates.push(TypeError::At { error: Box::new(error), span: span.clone(), file: None });
+        }
+    }
+    if !duplicates.is_empty() {
+        return Err(CompileError::Type(duplicates));
+    }
+    crate::parser::expand_modifiers(&mut items);
+    crate::parser::resolve_enum_types(&mut items);
+
+    Ok(Program {
+        items,
+        span: resolver.span,
+        files,
+        item_files,
+    })
+}
+
+/// Where an item was declared: its file and its position there.
+type Origin = (PathBuf, usize);
+
+struct Resolver<'a> {
+    tree: &'a dyn SourceTree,
+    stack: Vec<PathBuf>,
+    /// Each file's items with its own imports merged in.
+    files: HashMap<PathBuf, Vec<(Origin, Item)>>,
+    /// The span of the entry file's program.
+    span: Span,
+}
+
+impl Resolver<'_> {
+    fn file_items(&mut self, path: &Path) -> Result<Vec<(Origin, Item)>, CompileError> {
+        let identity = self.tree.identity(path)?;
+        if self.stack.contains(&identity) {
+            return Err(CompileError::ImportCycle(path.display().to_string()));
+        }
+        if let Some(items) = self.files.get(&identity) {
+            return Ok(items.clone());
+        }
+
+        let source = self.tree.read(path)?;
+        let program = parse_from_source(&source).map_err(|errors| CompileError::Parse {
+            path: path.to_path_buf(),
+            errors,
+        })?;
+
+        if self.stack.is_empty() {
+            self.span = program.span.clone();
+        }
+        self.stack.push(identity.clone());
+        let mut items = Vec::new();
+        for (index, item) in program.items.into_iter().enumerate() {
+            let Item::Import(import) = item else {
+                items.push(((identity.clone(), index), item));
+                continue;
+            };
+            let resolved = self
+                .tree
+                .resolve(path, &import.path)
+                .ok_or_else(|| CompileError::ImportNotFound {
+                    import: import.path.clone(),
+                    path: path.to_path_buf(),
+                    span: import.span.start..import.span.end,
+                })?;
+            let mut imported = self.file_items(&resolved)?;
+            if !import.names.is_empty() {
+                imported = select(imported, &import, path)?;
+            }
+            for (origin, item) in imported {
+                if !items.iter().any(|(seen, _)| *seen == origin) {
+                    items.push((origin, item));
+                }
+            }
+        }
+        self.stack.pop();
+
+        self.files.insert(identity, items.clone());
+        Ok(items)
+    }
+}
+
+/// The items named in a `from` import plus every item they refer to, in
+/// module order. The functions among them become internal to the importer.
+fn select(
+    items: Vec<(Origin, Item)>,
+    import: &ImportDecl,
+    importer: &Path,
+) -> Result<Vec<(Origin, Item)>, CompileError> {
+    let index: HashMap<&str, usize> = items
+        .iter()
+        .enumerate()
+        .filter_map(|(i, (_, item))| Some((item_name(item)?, i)))
+        .collect();
+
+    let mut keep = HashSet::new();
+    let mut pending = Vec::new();
+    for name in &import.names {
+        let i = *index.get(name.as_str()).ok_or_else(|| CompileError::ImportedNameNotFound {
+            name: name.clone(),
+            module: import.path.clone(),
+            path: importer.to_path_buf(),
+            span: import.span.start..import.span.end,
+        })?;
+        pending.push(i);
+    }
+    while let Some(i) = pending.pop() {
+        if !keep.insert(i) {
+            continue;
+        }
+        let mut refs = Vec::new();
+        item_references(&items[i].1, &mut refs);
+        pending.extend(refs.iter().filter_map(|r| index.get(r.as_str())));
+    }
+
+    Ok(items
+        .into_iter()
+        .enumerate()
+        .filter(|(i, _)| keep.contains(i))
+        .map(|(_, (origin, mut item))| {
+            if let Item::Function(f) = &mut item {
+                f.internal = true;
+            }
+            (origin, item)
+        })
+        .collect())
+}
+
+fn item_name(item: &Item) -> Option<&str> {
+    Some(match item {
+        Item::Function(f) => &f.name,
+        Item::Struct(s) => &s.name,
+        Item::Enum(e) => &e.name,
+        Item::Const(c) => &c.name,
+        Item::Event(e) => &e.name,
+        Item::Error(e) => &e.name,
+        Item::Interface(i) => &i.name,
+        Item::Modifier(m) => &m.name,
+        Item::State(s) => &s.name,
+        Item::Import(_) => return None,
+    })
+}
+
+fn item_name_span(item: &Item) -> Option<&Span> {
+    Some(match item {
+        Item::Function(f) => &f.name_span,
+        Item::Struct(s) => &s.name_span,
+        Item::Enum(e) => &e.name_span,
+        Item::Const(c) => &c.name_span,
+        Item::Event(e) => &e.name_span,
+        Item::Error(e) => &e.name_span,
+        Item::Interface(i) => &i.name_span,
+        Item::Modifier(m) => &m.name_span,
+        Item::State(s) => &s.name_span,
+        Item::Import(_) => return None,
+    })
+}
+
+/// Every name an item mentions: identifiers, types, events, errors and
+/// modifiers. Locals are collected too, which at worst brings along an
+/// item the import did not need.
+fn item_references(item: &Item, out: &mut Vec<String>) {
+    match item {
+        Item::Function(f) => {
+            out.extend(f.decorators.iter().map(|d| d.name.clone()));
+            f.params.iter().for_each(|p| type_references(&p.type_, out));
+            if let Some(ty) = &f.return_type {
+                type_references(ty, out);
+            }
+            block_references(&f.expanded_body(), out);
+        }
+        Item::Struct(s) => s.fields.iter().for_each(|f| type_references(&f.type_, out)),
+        Item::Const(c) => {
+            type_references(&c.type_, out);
+            expression_references(&c.value, out);
+        }
+        Item::Event(e) => e.fields.iter().for_each(|f| type_references(&f.type_, out)),
+        Item::Error(e) => e.params.iter().for_each(|p| type_references(&p.type_, out)),
+        Item::Interface(i) => i.functions.iter().for_each(|f| {
+            f.params.iter().for_each(|p| type_references(&p.type_, out));
+            if let Some(ty) = &f.return_type {
+                type_references(ty, out);
+            }
+        }),
+        Item::Modifier(m) => block_references(&m.body, out),
+        Item::State(s) => type_references(&s.type_, out),
+        Item::Enum(_) | Item::Import(_) => {}
+    }
+}
+
+fn type_references(ty: &Type, out: &mut Vec<String>) {
+    match ty {
+        Type::Custom(name) | Type::Enum(name) => out.push(name.clone()),
+        Type::Generic(name, args) => {
+            out.push(name.clone());
+            args.iter().for_each(|t| type_references(t, out));
+        }
+        Type::Vec(inner) | Type::Array(inner, _) => type_references(inner, out),
+        Type::Map(k, v) => {
+            type_references(k, out);
+            type_references(v, out);
+        }
+        Type::Tuple(items) => items.iter().for_each(|t| type_references(t, out)),
+        _ => {}
+    }
+}
+
+fn block_references(block: &Block, out: &mut Vec<String>) {
+    for stmt in &block.statements {
+        match stmt {
+            Statement::Let(l) => {
+                if let Some(ty) = &l.type_ {
+                    type_references(ty, out);
+                }
+                if let Some(v) = &l.value {
+                    expression_references(v, out);
+                }
+            }
+            Statement::Assign(a) => {
+                expression_references(&a.target, out);
+                expression_references(&a.value, out);
+            }
+            Statement::Return(Some(e), _) | Statement::Expression(e, _) => expression_references(e, out),
+            Statement::Require(r) => expression_references(&r.condition, out),
+            Statement::If(if_stmt) => {
+                expression_references(&if_stmt.condition, out);
+                block_references(&if_stmt.then_branch, out);
+                if let Some(eb) = &if_stmt.else_branch {
+                    block_references(eb, out);
+                }
+            }
+            Statement::For(for_stmt) => {
+                expression_references(&for_stmt.iterable, out);
+                block_references(&for_stmt.body, out);
+            }
+            Statement::While(while_stmt) => {
+                expression_references(&while_stmt.condition, out);
+                block_references(&while_stmt.body, out);
+            }
+            Statement::Emit(em) => {
+                out.push(em.name.clone());
+                em.args.iter().for_each(|a| expression_references(a, out));
+            }
+            Statement::Revert(rv) => {
+                out.push(rv.name.clone());
+                rv.args.iter().for_each(|a| expression_references(a, out));
+            }
+            Statement::Return(None, _) | Statement::Asm(_) | Statement::Break(_) | Statement::Continue(_) => {}
+        }
+    }
+}
+
+fn expression_references(expr: &Expression, out: &mut Vec<String>) {
+    match expr {
+        Expression::Identifier(name) => out.push(name.clone()),
+        Expression::StructInit(name, fields) => {
+            out.push(name.clone());
+            fields.iter().for_each(|(_, e)| expression_references(e, out));
+        }
+        Expression::Binary(_, l, r) | Expression::Index(l, r) => {
+            expression_references(l, out);
+            expression_references(r, out);
+        }
+        Expression::Unary(_, e) | Expression::Member(e, _) => expression_references(e, out),
+        Expression::Call(callee, args) => {
+            expression_references(callee, out);
+            args.iter().for_each(|a| expression_references(a, out));
+        }
+        Expression::Slice(base, start, end) => {
+            [base, start, end].into_iter().for_each(|e| expression_references(e, out))
+        }
+        Expression::Tuple(items) => items.iter().for_each(|e| expression_references(e, out)),
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn resolve(files: &[(&str, &str)]) -> Result<Program, CompileError> {
+        let sources: BTreeMap<String, String> = files
+            .iter()
+            .map(|(name, content)| (name.to_string(), content.to_string()))
+            .collect();
+        resolve_program(&MemorySources::new(&sources), Path::new(files[0].0))
+    }
+
+    fn names(program: &Program) -> Vec<&str> {
+        program.items.iter().filter_map(item_name).collect()
+    }
+
+    const MATH: &str = "\
+const WAD: uint256 = 1e18
+const RAY: uint256 = 1e27
+
+def _scale(a: uint256) -> uint256:
+    return a * WAD
+
+def mul_wad(a: uint256, b: uint256) -> uint256:
+    return _scale(a) * b
+
+def mul_ray(a: uint256, b: uint256) -> uint256:
+    return a * b / RAY
+";
+
+    #[test]
+    fn from_import_brings_named_items_and_their_dependencies() {
+        let program = resolve(&[
+            ("main.pyra", "from lib.math import mul_wad\n\ndef mul_ray() -> uint256:\n    return 1\n"),
+            ("lib/math.pyra", MATH),
+        ])
+        .unwrap();
+        assert_eq!(names(&program), ["WAD", "_scale", "mul_wad", "mul_ray"]);
+        let ids = crate::method_identifiers(&program);
+        assert_eq!(ids.keys().collect::<Vec<_>>(), ["mul_ray()"]);
+        assert!(!crate::program_to_abi_json(&program).unwrap().contains("mul_wad"));
+        let module = crate::lower_program(&program);
+        assert_eq!(module.functions.iter().map(|f| f.name.as_str()).collect::<Vec<_>>(), ["mul_ray"]);
+    }
+
+    #[test]
+    fn shared_imports_merge_once() {
+        let program = resolve(&[
+            ("main.pyra", "import \"a.pyra\"\nfrom math import WAD, mul_wad\n"),
+            ("a.pyra", "from math import WAD\n\nconst TWO_WAD: uint256 = 2 * WAD\n"),
+            ("math.pyra", MATH),
+        ])
+        .unwrap();
+        assert_eq!(names(&program), ["WAD", "TWO_WAD", "_scale", "mul_wad"]);
+    }
+
+    #[test]
+    fn reports_missing_names_cycles_and_duplicates() {
+        let err = resolve(&[("main.pyra", "from math import nope\n"), ("math.pyra", MATH)]).unwrap_err();
+        assert!(matches!(err, CompileError::ImportedNameNotFound { ref name, ref span, .. } if name == "nope" && *span == (0..21)));
+
+        let err = resolve(&[("a.pyra", "import \"b.pyra\"\n"), ("b.pyra", "from a import X\n")]).unwrap_err();
+        assert!(matches!(err, CompileError::ImportCycle(_)));
+
+        let err = resolve(&[("main.pyra", "const WAD: uint256 = 1\nimport \"math.pyra\"\n"), ("math.pyra", MATH)])
+            .unwrap_err();
+        let CompileError::Type(errors) = err else { panic!("{err:?}") };
+        let TypeError::Duplicate { name, file } = errors[0].kind() else { panic!("{errors:?}") };
+        assert_eq!((name.as_str(), file.as_path()), ("WAD", Path::new("math.pyra")));
+        let span = errors[0].span().unwrap();
+        assert_eq!(&MATH[span.start..span.end], "WAD");
+    }
+}
@@ -0,0 +1,135 @@
+//! Runs compiled contracts on the crate's built-in EVM interpreter, for
+//! tools and tests that want to check what bytecode does rather than what
+//! it contains. Each call is one transaction from a fixed caller, with
+//! gas metered as on Cancun.
+
+use crate::ir::compute_selector;
+use crate::vm::{calldata, Vm};
+use crate::{Item, Program};
+use num_bigint::BigUint;
+
+pub use crate::vm::{Log, Outcome};
+
+/// A deployed contract with its storage, kept between calls.
+pub struct ContractHandle {
+    vm: Vm,
+}
+
+/// What one transaction did.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CallResult {
+    pub outcome: Outcome,
+    /// Gas the transaction used, intrinsic cost included and refunds left
+    /// out.
+    pub gas_used: u64,
+    /// Events emitted; none if the call reverted.
+    pub logs: Vec<Log>,
+}
+
+impl CallResult {
+    pub fn is_success(&self) -> bool {
+        self.outcome.is_success()
+    }
+
+    /// The return data, or the revert data if the call reverted.
+    pub fn output(&self) -> &[u8] {
+        self.outcome.output()
+    }
+
+    /// The `index`th 32-byte word of the output, zero past its end.
+    pub fn word(&self, index: usize) -> BigUint {
+        self.outcome.word(index)
+    }
+}
+
+/// Runs `bytecode` as a contract creation, constructor arguments appended
+/// if it takes any. Fails with the creation's result if it does not
+/// return code.
+pub fn deploy(bytecode: &[u8]) -> Result<ContractHandle, CallResult> {
+    match Vm::try_deploy(bytecode) {
+        Ok(vm) => Ok(ContractHandle { vm }),
+        Err((outcome, gas_used)) => Err(CallResult {
+            outcome,
+            gas_used,
+            logs: Vec::new(),
+        }),
+    }
+}
+
+/// The selector of the function `name` in `program`.
+pub fn function_selector(program: &Program, name: &str) -> Option<[u8; 4]> {
+    program.items.iter().find_map(|item| match item {
+        Item::Function(f) if f.name == name => Some(compute_selector(f)),
+        _ => None,
+    })
+}
+
+impl ContractHandle {
+    /// Calls the function with `selector`, each argument encoded as one
+    /// word.
+    pub fn call(&mut self, selector: [u8; 4], args: &[BigUint]) -> CallResult {
+        self.call_raw(&calldata(selector, args))
+    }
+
+    /// Sends `data` as the calldata, as is.
+    pub fn call_raw(&mut self, data: &[u8]) -> CallResult {
+        let emitted = self.vm.logs.len();
+        let outcome = self.vm.call(data);
+        CallResult {
+            outcome,
+            gas_used: self.vm.gas_used,
+            logs: self.vm.logs[emitted..].to_vec(),
+        }
+    }
+
+    /// The deployed code.
+    pub fn code(&self) -> &[u8] {
+        &self.vm.code
+    }
+
+    /// The value in storage `slot`.
+    pub fn storage(&self, slot: &BigUint) -> BigUint {
+        self.vm.storage.get(slot).cloned().unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{parse_from_source, program_to_deploy_bytecode, program_to_runtime_bytecode};
+
+    #[test]
+    fn calls_keep_state_and_report_events() {
+        let src = "event Bumped(to: uint256)\n\ncount: uint256\n\n\
+                   def bump():\n    self.count += 1\n    emit Bumped(self.count)\n\n\
+                   @view\ndef get() -> uint256:\n    return self.count\n";
+        let program = parse_from_source(src).unwrap();
+        let mut contract = deploy(&program_to_deploy_bytecode(&program).unwrap()).unwrap();
+        assert_eq!(contract.code(), program_to_runtime_bytecode(&program).unwrap());
+
+        let bump = function_selector(&program, "bump").unwrap();
+        let first = contract.call(bump, &[]);
+        assert!(first.is_success());
+        assert_eq!(first.logs.len(), 1);
+        let second = contract.call(bump, &[]);
+        assert!(second.gas_used < first.gas_used, "the slot was already set");
+
+        let get = contract.call(function_selector(&program, "get").unwrap(), &[]);
+        assert_eq!(get.word(0), BigUint::from(2u8));
+        assert!(get.logs.is_empty());
+        assert_eq!(contract.storage(&BigUint::default()), BigUint::from(2u8));
+        assert!(!contract.call_raw(&[0xde, 0xad, 0xbe, 0xef]).is_success());
+    }
+
+    #[test]
+    fn failed_deployment_is_reported() {
+        let src = "def init(x: uint256):\n    require x > 0, \"x is zero\"\n";
+        let mut init = program_to_deploy_bytecode(&parse_from_source(src).unwrap()).unwrap();
+        init.extend([0u8; 32]);
+        let Err(result) = deploy(&init) else { panic!("deployed with x = 0") };
+        assert!(matches!(result.outcome, Outcome::Revert(_)));
+        assert!(result.gas_used > 53_000);
+        *init.last_mut().unwrap() = 1;
+        assert!(deploy(&init).is_ok());
+    }
+}
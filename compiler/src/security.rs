@@ -1,17 +1,74 @@
 use crate::ir::{IrModule, IrOp};
+use crate::codegen::EvmVersion;
+use crate::optimizer::OptLevel;
 
-pub fn harden(module: &mut IrModule) {
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HardenConfig {
+    pub add: bool,
+    pub sub: bool,
+    pub mul: bool,
+    /// Revert when `**` overflows; off unless asked for.
+    pub exp: bool,
+    /// Revert on a zero divisor in `/` and `%`; off unless asked for.
+    pub div: bool,
+    /// Optimizer passes run while lowering, before any check is inserted.
+    pub opt_level: OptLevel,
+    /// Fork the emitted bytecode targets.
+    pub evm_version: EvmVersion,
+    /// Revert calls whose calldata is shorter than the parameter head.
+    pub strict_calldata: bool,
+    /// Which functions the reentrancy lock wraps.
+    pub guard_mode: GuardMode,
+}
+
+impl Default for HardenConfig {
+    fn default() -> Self {
+        Self {
+            add: true,
+            sub: true,
+            mul: true,
+            exp: false,
+            div: false,
+            opt_level: OptLevel::default(),
+            evm_version: EvmVersion::default(),
+            strict_calldata: false,
+            guard_mode: GuardMode::default(),
+        }
+    }
+}
+
+pub fn harden(module: &mut IrModule, config: &HardenConfig) {
     for func in &mut module.functions {
-        func.ops = harden_ops(&func.ops, &mut module.label_count);
+        func.ops = harden_ops(&func.ops, config, &mut module.label_count);
     }
-    module.constructor_ops = harden_ops(&module.constructor_ops, &mut module.label_count);
+    module.constructor_ops = harden_ops(&module.constructor_ops, config, &mut module.label_count);
+}
+
+/// Which functions `add_reentrancy_guard_with_mode` wraps in the lock.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum GuardMode {
+    #[default]
+    All,
+    /// Only functions annotated with `@reentrancy_guard`.
+    Explicit,
 }
 
 pub fn add_reentrancy_guard(module: &mut IrModule, lock_slot: u64) {
+    add_reentrancy_guard_with_mode(module, lock_slot, GuardMode::All);
+}
+
+pub fn add_reentrancy_guard_with_mode(module: &mut IrModule, lock_slot: u64, mode: GuardMode) {
     let slot_bytes = slot_to_bytes(lock_slot);
     for func in &mut module.functions {
-        let body = std::mem::take(&mut func.ops);
-        let mut guarded = Vec::with_capacity(body.len() + 16);
+        if mode == GuardMode::Explicit && !func.guarded {
+            continue;
+        }
+        // The lock goes after the entry label the dispatcher jumps to.
+        let entry = IrOp::JumpDest(func.label);
+        let at = func.ops.iter().position(|op| *op == entry).map_or(0, |i| i + 1);
+        let body = func.ops.split_off(at);
+        let mut guarded = std::mem::take(&mut func.ops);
+        guarded.reserve(body.len() + 16);
         let ok_label = module.label_count;
         module.label_count += 1;
         guarded.push(IrOp::Push(slot_bytes.clone()));
@@ -22,6 +79,13 @@ pub fn add_reentrancy_guard(module: &mut IrModule, lock_slot: u64) {
         guarded.push(IrOp::Push(vec![0]));
         guarded.push(IrOp::Revert);
         guarded.push(IrOp::JumpDest(ok_label));
+        // A view function only checks the lock: taking it would be a
+        // storage write, which reverts when it is reached by STATICCALL.
+        if func.read_only {
+            guarded.extend(body);
+            func.ops = guarded;
+            continue;
+        }
         guarded.push(IrOp::Push(vec![1]));
         guarded.push(IrOp::Push(slot_bytes.clone()));
         guarded.push(IrOp::SStore);
@@ -40,6 +104,45 @@ pub fn add_reentrancy_guard(module: &mut IrModule, lock_slot: u64) {
     }
 }
 
+/// Reverts calls that send ether to functions not marked `@payable`. The
+/// check goes right after each function's entry label.
+pub fn add_callvalue_guard(module: &mut IrModule) {
+    for func in &mut module.functions {
+        if func.payable {
+            continue;
+        }
+        let ok_label = module.label_count;
+        module.label_count += 1;
+        let entry = IrOp::JumpDest(func.label);
+        let at = func.ops.iter().position(|op| *op == entry).map_or(0, |i| i + 1);
+        let mut guard = vec![IrOp::CallValue, IrOp::IsZero, IrOp::JumpI(ok_label)];
+        emit_revert(&mut guard);
+        guard.push(IrOp::JumpDest(ok_label));
+        func.ops.splice(at..at, guard);
+    }
+}
+
+/// Reverts calls with fewer calldata bytes than the function's selector and
+/// parameter head, rather than reading the missing words as zero.
+pub fn add_calldata_size_guard(module: &mut IrModule) {
+    for func in &mut module.functions {
+        let ok_label = module.label_count;
+        module.label_count += 1;
+        let entry = IrOp::JumpDest(func.label);
+        let at = func.ops.iter().position(|op| *op == entry).map_or(0, |i| i + 1);
+        let mut guard = vec![
+            IrOp::Push(slot_to_bytes(func.calldata_size as u64)),
+            IrOp::CallDataSize,
+            IrOp::Lt,
+            IrOp::IsZero,
+            IrOp::JumpI(ok_label),
+        ];
+        emit_revert(&mut guard);
+        guard.push(IrOp::JumpDest(ok_label));
+        func.ops.splice(at..at, guard);
+    }
+}
+
 fn slot_to_bytes(slot: u64) -> Vec<u8> {
     if slot == 0 {
         return vec![0];
@@ -49,19 +152,65 @@ fn slot_to_bytes(slot: u64) -> Vec<u8> {
     be[start..].to_vec()
 }
 
-fn harden_ops(ops: &[IrOp], label_count: &mut usize) -> Vec<IrOp> {
+fn harden_ops(ops: &[IrOp], config: &HardenConfig, label_count: &mut usize) -> Vec<IrOp> {
     let mut out = Vec::with_capacity(ops.len() * 2);
-    for op in ops {
+    for (i, op) in ops.iter().enumerate() {
         match op {
-            IrOp::Add => emit_checked_add(&mut out, label_count),
-            IrOp::Sub => emit_checked_sub(&mut out, label_count),
-            IrOp::Mul => emit_checked_mul(&mut out, label_count),
+            IrOp::Add if config.add => emit_checked_add(&mut out, label_count),
+            IrOp::Sub if config.sub => emit_checked_sub(&mut out, label_count),
+            IrOp::Mul if config.mul => emit_checked_mul(&mut out, label_count),
+            IrOp::Exp if config.exp => emit_checked_exp(&mut out, label_count),
+            IrOp::Div | IrOp::SDiv | IrOp::Mod if config.div => {
+                emit_nonzero_divisor(&mut out, op, label_count)
+            }
+            IrOp::Narrow { bits, signed } if i > 0 && is_checked(&ops[i - 1], config) => {
+                emit_checked_narrow(&mut out, *bits, *signed, label_count)
+            }
             other => out.push(other.clone()),
         }
     }
     out
 }
 
+/// Whether hardening checks `op` for overflow under `config`.
+fn is_checked(op: &IrOp, config: &HardenConfig) -> bool {
+    match op {
+        IrOp::Add => config.add,
+        IrOp::Sub => config.sub,
+        IrOp::Mul => config.mul,
+        IrOp::Exp => config.exp,
+        _ => false,
+    }
+}
+
+/// Reverts unless the value on top of the stack fits an integer of `bits`:
+/// below `2^bits` when unsigned, unchanged by sign extension when signed.
+fn emit_checked_narrow(out: &mut Vec<IrOp>, bits: u16, signed: bool, label_count: &mut usize) {
+    let ok_label = *label_count;
+    *label_count += 1;
+    out.push(IrOp::Dup(1));
+    if signed {
+        out.push(IrOp::Dup(1));
+        out.push(IrOp::Push(vec![(bits / 8 - 1) as u8]));
+        out.push(IrOp::SignExtend);
+        out.push(IrOp::Eq);
+    } else {
+        let mut bound = vec![1];
+        bound.resize(usize::from(bits / 8) + 1, 0);
+        out.push(IrOp::Push(bound));
+        out.push(IrOp::Gt);
+    }
+    out.push(IrOp::JumpI(ok_label));
+    emit_revert(out);
+    out.push(IrOp::JumpDest(ok_label));
+}
+
+fn emit_revert(out: &mut Vec<IrOp>) {
+    out.push(IrOp::Push(vec![0]));
+    out.push(IrOp::Push(vec![0]));
+    out.push(IrOp::Revert);
+}
+
 fn emit_checked_add(out: &mut Vec<IrOp>, label_count: &mut usize) {
     let ok_label = *label_count;
     *label_count += 1;
@@ -70,7 +219,7 @@ fn emit_checked_add(out: &mut Vec<IrOp>, label_count: &mut usize) {
     out.push(IrOp::Add);
     out.push(IrOp::Dup(1));
     out.push(IrOp::Dup(3));
-    out.push(IrOp::Lt);
+    out.push(IrOp::Gt);
     out.push(IrOp::IsZero);
     out.push(IrOp::JumpI(ok_label));
     out.push(IrOp::Push(vec![0]));
@@ -79,7 +228,6 @@ fn emit_checked_add(out: &mut Vec<IrOp>, label_count: &mut usize) {
     out.push(IrOp::JumpDest(ok_label));
     out.push(IrOp::Swap(2));
     out.push(IrOp::Pop);
-    out.push(IrOp::Swap(1));
     out.push(IrOp::Pop);
 }
 
@@ -108,24 +256,88 @@ fn emit_checked_mul(out: &mut Vec<IrOp>, label_count: &mut usize) {
     out.push(IrOp::Dup(2));
     out.push(IrOp::Dup(2));
     out.push(IrOp::Mul);
-    out.push(IrOp::Dup(1));
     out.push(IrOp::Dup(3));
+    out.push(IrOp::Dup(2));
     out.push(IrOp::Div);
-    out.push(IrOp::Dup(4));
+    out.push(IrOp::Dup(3));
     out.push(IrOp::Eq);
     out.push(IrOp::JumpI(ok_label));
     out.push(IrOp::Push(vec![0]));
     out.push(IrOp::Push(vec![0]));
     out.push(IrOp::Revert);
     out.push(IrOp::JumpDest(zero_label));
-    out.push(IrOp::Pop);
-    out.push(IrOp::Pop);
     out.push(IrOp::Push(vec![0]));
     out.push(IrOp::Jump(ok_label));
     out.push(IrOp::JumpDest(ok_label));
     out.push(IrOp::Swap(2));
     out.push(IrOp::Pop);
+    out.push(IrOp::Pop);
+}
+
+fn emit_nonzero_divisor(out: &mut Vec<IrOp>, op: &IrOp, label_count: &mut usize) {
+    let ok_label = *label_count;
+    *label_count += 1;
+    out.push(IrOp::Dup(2));
+    out.push(IrOp::JumpI(ok_label));
+    emit_revert(out);
+    out.push(IrOp::JumpDest(ok_label));
+    out.push(op.clone());
+}
+
+// Computes the wrapped power, then divides it back down by the base once per
+// unit of exponent; only an exact power survives that with a remainder-free 1.
+fn emit_checked_exp(out: &mut Vec<IrOp>, label_count: &mut usize) {
+    let loop_label = *label_count;
+    let fail_label = *label_count + 1;
+    let end_label = *label_count + 2;
+    let ok_label = *label_count + 3;
+    *label_count += 4;
+
+    out.push(IrOp::Dup(2));
+    out.push(IrOp::Dup(2));
+    out.push(IrOp::Exp);
+    out.push(IrOp::Swap(2));
+    out.push(IrOp::Swap(1));
+
+    out.push(IrOp::Push(vec![2]));
+    out.push(IrOp::Dup(2));
+    out.push(IrOp::Lt);
+    out.push(IrOp::JumpI(ok_label));
+    out.push(IrOp::Push(vec![0xff]));
+    out.push(IrOp::Dup(3));
+    out.push(IrOp::Gt);
+    out.push(IrOp::JumpI(fail_label));
+    out.push(IrOp::Dup(3));
+
+    out.push(IrOp::JumpDest(loop_label));
+    out.push(IrOp::Dup(3));
+    out.push(IrOp::IsZero);
+    out.push(IrOp::JumpI(end_label));
+    out.push(IrOp::Dup(2));
+    out.push(IrOp::Dup(2));
+    out.push(IrOp::Mod);
+    out.push(IrOp::JumpI(fail_label));
+    out.push(IrOp::Dup(2));
     out.push(IrOp::Swap(1));
+    out.push(IrOp::Div);
+    out.push(IrOp::Swap(2));
+    out.push(IrOp::Push(vec![1]));
+    out.push(IrOp::Swap(1));
+    out.push(IrOp::Sub);
+    out.push(IrOp::Swap(2));
+    out.push(IrOp::Jump(loop_label));
+
+    out.push(IrOp::JumpDest(fail_label));
+    emit_revert(out);
+
+    out.push(IrOp::JumpDest(end_label));
+    out.push(IrOp::Push(vec![1]));
+    out.push(IrOp::Eq);
+    out.push(IrOp::IsZero);
+    out.push(IrOp::JumpI(fail_label));
+
+    out.push(IrOp::JumpDest(ok_label));
+    out.push(IrOp::Pop);
     out.push(IrOp::Pop);
 }
 
@@ -141,6 +353,10 @@ mod tests {
                 selector: [0; 4],
                 ops,
                 label: 0,
+                guarded: false,
+                payable: false,
+                read_only: false,
+                calldata_size: 4,
             }],
             constructor_ops: vec![],
             label_count: 1,
@@ -155,13 +371,28 @@ mod tests {
             IrOp::Add,
             IrOp::Return,
         ]);
-        harden(&mut module);
+        harden(&mut module, &HardenConfig::default());
         let ops = &module.functions[0].ops;
         assert!(ops.len() > 4);
         assert!(ops.iter().any(|op| matches!(op, IrOp::Revert)));
         assert!(ops.iter().any(|op| matches!(op, IrOp::JumpDest(_))));
     }
 
+    #[test]
+    fn narrow_after_a_checked_op_becomes_a_range_check() {
+        let narrow = IrOp::Narrow { bits: 8, signed: false };
+        let ops = vec![IrOp::Push(vec![1]), IrOp::Push(vec![2]), IrOp::Add, narrow.clone(), IrOp::Return];
+        let mut module = make_module(ops.clone());
+        harden(&mut module, &HardenConfig::default());
+        let hardened = &module.functions[0].ops;
+        assert!(!hardened.contains(&narrow));
+        assert!(hardened.windows(2).any(|w| w == [IrOp::Push(vec![1, 0]), IrOp::Gt]));
+
+        let mut module = make_module(ops);
+        harden(&mut module, &HardenConfig { add: false, ..Default::default() });
+        assert!(module.functions[0].ops.contains(&narrow));
+    }
+
     #[test]
     fn harden_replaces_sub() {
         let mut module = make_module(vec![
@@ -170,7 +401,7 @@ mod tests {
             IrOp::Sub,
             IrOp::Return,
         ]);
-        harden(&mut module);
+        harden(&mut module, &HardenConfig::default());
         let ops = &module.functions[0].ops;
         assert!(ops.iter().any(|op| matches!(op, IrOp::Revert)));
         assert!(ops.iter().filter(|op| matches!(op, IrOp::Sub)).count() == 1);
@@ -184,12 +415,110 @@ mod tests {
             IrOp::Mul,
             IrOp::Return,
         ]);
-        harden(&mut module);
+        harden(&mut module, &HardenConfig::default());
         let ops = &module.functions[0].ops;
         assert!(ops.len() > 4);
         assert!(ops.iter().any(|op| matches!(op, IrOp::Revert)));
     }
 
+    #[test]
+    fn harden_config_disables_single_check() {
+        let mut module = make_module(vec![
+            IrOp::Push(vec![1]),
+            IrOp::Push(vec![2]),
+            IrOp::Add,
+            IrOp::Push(vec![3]),
+            IrOp::Mul,
+            IrOp::Return,
+        ]);
+        let config = HardenConfig {
+            mul: false,
+            ..HardenConfig::default()
+        };
+        harden(&mut module, &config);
+        let ops = &module.functions[0].ops;
+        let mul = ops.iter().position(|op| matches!(op, IrOp::Mul)).unwrap();
+        assert!(matches!(&ops[mul - 1], IrOp::Push(v) if v == &[3]));
+        assert!(matches!(ops[mul + 1], IrOp::Return));
+        assert_eq!(ops.iter().filter(|op| matches!(op, IrOp::Revert)).count(), 1);
+        assert!(ops.iter().any(|op| matches!(op, IrOp::Gt)));
+    }
+
+    #[test]
+    fn harden_checks_divisor() {
+        let mut module = make_module(vec![
+            IrOp::Push(vec![0]),
+            IrOp::Push(vec![6]),
+            IrOp::Div,
+            IrOp::Return,
+        ]);
+        harden(&mut module, &HardenConfig { div: true, ..HardenConfig::default() });
+        let ops = &module.functions[0].ops;
+        assert!(matches!(&ops[2..4], [IrOp::Dup(2), IrOp::JumpI(_)]));
+        assert!(matches!(ops[ops.len() - 2], IrOp::Div));
+    }
+
+    #[test]
+    fn checked_exp_reverts_on_overflow() {
+        let config = HardenConfig { exp: true, ..HardenConfig::default() };
+        let mut vm = crate::vm::Vm::from_source_with_config(
+            "def pow(a: uint256, b: uint256) -> uint256: return a ** b",
+            &config,
+        );
+        let mut call = |a: u32, b: u32| vm.call_function("pow", &[a.into(), b.into()]);
+        assert_eq!(call(2, 10).word(0), 1024u32.into());
+        assert_eq!(call(0, 0).word(0), 1u8.into());
+        assert_eq!(call(1, 1000).word(0), 1u8.into());
+        assert_eq!(call(2, 255).word(0), num_bigint::BigUint::from(1u8) << 255);
+        assert!(call(3, 161).is_success());
+        assert!(!call(3, 162).is_success());
+        assert!(!call(2, 256).is_success());
+    }
+
+    #[test]
+    fn checked_add_reverts_on_overflow() {
+        let mut vm = crate::vm::Vm::from_source(
+            "def add(a: uint256, b: uint256) -> uint256: return a + b",
+        );
+        let max: num_bigint::BigUint = (num_bigint::BigUint::from(1u8) << 256) - 1u8;
+        assert_eq!(vm.call_function("add", &[2u8.into(), 3u8.into()]).word(0), 5u8.into());
+        assert_eq!(vm.call_function("add", &[max.clone(), 0u8.into()]).word(0), max.clone());
+        assert!(!vm.call_function("add", &[max, 1u8.into()]).is_success());
+    }
+
+    #[test]
+    fn checked_mul_reverts_on_overflow() {
+        let mut vm = crate::vm::Vm::from_source(
+            "def mul(a: uint256, b: uint256) -> uint256: return a * b",
+        );
+        let half: num_bigint::BigUint = num_bigint::BigUint::from(1u8) << 128;
+        assert_eq!(vm.call_function("mul", &[6u8.into(), 7u8.into()]).word(0), 42u8.into());
+        assert_eq!(vm.call_function("mul", &[0u8.into(), 7u8.into()]).word(0), 0u8.into());
+        assert_eq!(vm.call_function("mul", &[7u8.into(), 0u8.into()]).word(0), 0u8.into());
+        assert!(!vm.call_function("mul", &[half.clone(), half]).is_success());
+    }
+
+    #[test]
+    fn checked_div_reverts_on_zero_divisor() {
+        let config = HardenConfig { div: true, ..HardenConfig::default() };
+        let mut vm = crate::vm::Vm::from_source_with_config(
+            "def div(a: uint256, b: uint256) -> uint256: return a / b",
+            &config,
+        );
+        assert_eq!(vm.call_function("div", &[7u8.into(), 2u8.into()]).word(0), 3u8.into());
+        assert!(!vm.call_function("div", &[7u8.into(), 0u8.into()]).is_success());
+    }
+
+    #[test]
+    fn division_and_exp_are_unchecked_by_default() {
+        let mut vm = crate::vm::Vm::from_source(
+            "def div(a: uint256, b: uint256) -> uint256: return a / b\n\
+             def pow(a: uint256, b: uint256) -> uint256: return a ** b",
+        );
+        assert_eq!(vm.call_function("div", &[7u8.into(), 0u8.into()]).word(0), 0u8.into());
+        assert_eq!(vm.call_function("pow", &[2u16.into(), 256u16.into()]).word(0), 0u8.into());
+    }
+
     #[test]
     fn harden_leaves_sload_untouched() {
         let mut module = make_module(vec![
@@ -197,7 +526,7 @@ mod tests {
             IrOp::SLoad,
             IrOp::Return,
         ]);
-        harden(&mut module);
+        harden(&mut module, &HardenConfig::default());
         let ops = &module.functions[0].ops;
         assert_eq!(ops.len(), 3);
     }
@@ -214,7 +543,7 @@ mod tests {
             ],
             label_count: 0,
         };
-        harden(&mut module);
+        harden(&mut module, &HardenConfig::default());
         assert!(module.constructor_ops.len() > 4);
         assert!(module.constructor_ops.iter().any(|op| matches!(op, IrOp::Revert)));
     }
@@ -231,7 +560,7 @@ mod tests {
             IrOp::Mul,
             IrOp::Return,
         ]);
-        harden(&mut module);
+        harden(&mut module, &HardenConfig::default());
         let ops = &module.functions[0].ops;
         assert!(ops.iter().filter(|op| matches!(op, IrOp::Revert)).count() >= 3);
     }
@@ -246,7 +575,7 @@ mod tests {
             IrOp::Add,
             IrOp::Return,
         ]);
-        harden(&mut module);
+        harden(&mut module, &HardenConfig::default());
         let labels: Vec<usize> = module.functions[0]
             .ops
             .iter()
@@ -290,6 +619,14 @@ mod tests {
         assert!(matches!(pre_return[2], IrOp::SStore));
     }
 
+    #[test]
+    fn reentrancy_guard_follows_entry_label() {
+        let mut module = make_module(vec![IrOp::JumpDest(0), IrOp::Stop]);
+        add_reentrancy_guard(&mut module, 3);
+        let ops = &module.functions[0].ops;
+        assert!(matches!(&ops[..3], [IrOp::JumpDest(0), IrOp::Push(v), IrOp::SLoad] if v == &[3]));
+    }
+
     #[test]
     fn reentrancy_guard_uses_correct_slot() {
         let mut module = make_module(vec![IrOp::Stop]);
@@ -298,6 +635,105 @@ mod tests {
         assert!(ops.iter().any(|op| matches!(op, IrOp::Push(ref v) if v == &[10])));
     }
 
+    #[test]
+    fn explicit_guard_wraps_only_annotated_function() {
+        let src = "@reentrancy_guard\ndef withdraw() -> uint256: return 1\n\ndef peek() -> uint256: return 2\n";
+        let program = crate::parse_from_source(src).unwrap();
+        let mut module = crate::lower_program(&program);
+        add_reentrancy_guard_with_mode(&mut module, 7, GuardMode::Explicit);
+        let touches_lock = |ops: &[IrOp]| {
+            ops.windows(2).any(|w| {
+                matches!(&w[0], IrOp::Push(v) if v == &[7]) && matches!(w[1], IrOp::SLoad | IrOp::SStore)
+            })
+        };
+        assert_eq!(module.functions[0].name, "withdraw");
+        assert!(touches_lock(&module.functions[0].ops));
+        assert!(!touches_lock(&module.functions[1].ops));
+    }
+
+    #[test]
+    fn explicit_guards_are_opt_in() {
+        let src = "x: uint256\n\n@reentrancy_guard\ndef withdraw() -> uint256: return 1\n\ndef peek() -> uint256: return 2\n";
+        let program = crate::parse_from_source(src).unwrap();
+        let locks = |config: &HardenConfig| {
+            crate::hardened_module(&program, config)
+                .functions
+                .iter()
+                .map(|f| f.ops.iter().any(|op| matches!(op, IrOp::SStore)))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(locks(&HardenConfig::default()), [true, true]);
+        let explicit = HardenConfig { guard_mode: GuardMode::Explicit, ..HardenConfig::default() };
+        assert_eq!(locks(&explicit), [true, false]);
+    }
+
+    #[test]
+    fn view_functions_check_the_lock_without_taking_it() {
+        let src = "x: uint256\n\n@view\ndef peek() -> uint256: return x\n";
+        let program = crate::parse_from_source(src).unwrap();
+        let module = crate::hardened_module(&program, &HardenConfig::default());
+        let ops = &module.functions[0].ops;
+        assert!(ops.windows(2).any(|w| matches!(&w[0], IrOp::Push(v) if v == &[1]) && w[1] == IrOp::SLoad));
+        assert!(!ops.iter().any(|op| matches!(op, IrOp::SStore)));
+    }
+
+    #[test]
+    fn callvalue_guard_skips_payable_functions() {
+        let src = "@payable\ndef deposit() -> uint256: return msg.value\n\ndef peek() -> uint256: return 2\n";
+        let program = crate::parse_from_source(src).unwrap();
+        let mut module = crate::lower_program(&program);
+        add_callvalue_guard(&mut module);
+        let deposit = &module.functions[0].ops;
+        assert_eq!(deposit.iter().filter(|op| matches!(op, IrOp::CallValue)).count(), 1);
+        let peek = &module.functions[1].ops;
+        assert!(matches!(peek[0], IrOp::JumpDest(_)));
+        assert_eq!(&peek[1..3], &[IrOp::CallValue, IrOp::IsZero]);
+        assert!(crate::verify_module(&module).is_empty());
+    }
+
+    #[test]
+    fn nonpayable_function_rejects_value() {
+        let src = "@payable\ndef deposit() -> uint256: return msg.value\n\ndef peek() -> uint256: return 2\n";
+        let mut vm = crate::vm::Vm::from_source(src);
+        vm.value = 5u8.into();
+        assert_eq!(vm.call_function("deposit", &[]).word(0), 5u8.into());
+        assert!(matches!(vm.call_function("peek", &[]), crate::vm::Outcome::Revert(_)));
+        vm.value = 0u8.into();
+        assert!(vm.call_function("peek", &[]).is_success());
+    }
+
+    #[test]
+    fn calldata_size_guard_counts_param_head() {
+        let src = "def pick(a: uint256, xs: uint256[3]) -> uint256: return a\n";
+        let program = crate::parse_from_source(src).unwrap();
+        let mut module = crate::lower_program(&program);
+        assert_eq!(module.functions[0].calldata_size, 4 + 32 * 4);
+        add_calldata_size_guard(&mut module);
+        let ops = &module.functions[0].ops;
+        assert_eq!(&ops[1..3], &[IrOp::Push(vec![132]), IrOp::CallDataSize]);
+        assert!(crate::verify_module(&module).is_empty());
+    }
+
+    #[test]
+    fn strict_calldata_rejects_short_input() {
+        let src = "def pick(a: uint256, b: uint256) -> uint256: return b\n";
+        let program = crate::parse_from_source(src).unwrap();
+        let crate::Item::Function(f) = &program.items[0] else { unreachable!() };
+        let full = crate::vm::calldata(crate::ir::compute_selector(f), &[1u8.into(), 2u8.into()]);
+        let short = &full[..full.len() - 1];
+
+        let deploy = |strict_calldata| {
+            let config = HardenConfig { strict_calldata, ..HardenConfig::default() };
+            let init = crate::program_to_deploy_bytecode_with_config(&program, &config).unwrap();
+            crate::vm::Vm::deploy(&init)
+        };
+        let mut lenient = deploy(false);
+        assert!(lenient.call(short).is_success());
+        let mut strict = deploy(true);
+        assert!(matches!(strict.call(short), crate::vm::Outcome::Revert(_)));
+        assert_eq!(strict.call(&full).word(0), 2u8.into());
+    }
+
     #[test]
     fn reentrancy_skips_constructor() {
         let mut module = IrModule {
@@ -0,0 +1,494 @@
+//! solc's standard JSON interface, so build tools that drive solc can drive
+//! Pyra with the same input and read the same output. Each source is one
+//! contract, named after its file stem.
+
+use crate::compiler::{compile_sources, CompileError, CompileOptions};
+use crate::diagnostics::{render, Diagnostic, Severity};
+use crate::resolver::normalize;
+use crate::{
+    check_warnings, disassemble, method_identifiers, parse_from_source, program_to_abi_json,
+    program_to_deploy_bytecode_with_source_map, program_to_runtime_bytecode_with_source_map,
+    CodegenError, EvmVersion, HardenConfig, MappedBytecode, OptLevel, Program,
+};
+use serde::Deserialize;
+use serde_json::{json, Map, Value};
+use std::collections::BTreeMap;
+use std::path::Path;
+
+#[derive(Deserialize)]
+struct Input {
+    language: String,
+    sources: BTreeMap<String, SourceInput>,
+    #[serde(default)]
+    settings: Settings,
+}
+
+#[derive(Deserialize)]
+struct SourceInput {
+    content: Option<String>,
+}
+
+#[derive(Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Settings {
+    #[serde(default)]
+    optimizer: Optimizer,
+    evm_version: Option<String>,
+    /// File, then contract, then the outputs wanted; `*` matches any name
+    /// and the empty contract name selects per-file outputs such as `ast`.
+    #[serde(default)]
+    output_selection: BTreeMap<String, BTreeMap<String, Vec<String>>>,
+}
+
+#[derive(Default, Deserialize)]
+struct Optimizer {
+    enabled: Option<bool>,
+}
+
+/// Compiles a standard JSON input document and returns the output document.
+/// Problems with the input itself come back as a `JSONError` entry in
+/// `errors`, as with solc, so the result is always valid JSON.
+pub fn compile_standard_json(input: &str) -> String {
+    let output = match serde_json::from_str::<Input>(input) {
+        Ok(input) => compile_input(input),
+        Err(e) => input_error(format!("invalid input: {e}")),
+    };
+    output.to_string()
+}
+
+fn input_error(message: String) -> Value {
+    json!({
+        "errors": [{
+            "component": "general",
+            "formattedMessage": format!("JSONError: {message}"),
+            "message": message,
+            "severity": "error",
+            "type": "JSONError",
+        }]
+    })
+}
+
+fn compile_input(input: Input) -> Value {
+    if input.language != "Pyra" {
+        return input_error(format!(
+            "only \"Pyra\" is supported as a language, got \"{}\"",
+            input.language
+        ));
+    }
+    let mut sources = BTreeMap::new();
+    for (name, source) in input.sources {
+        let Some(content) = source.content else {
+            return input_error(format!("source \"{name}\" has no \"content\"; \"urls\" are not supported"));
+        };
+        sources.insert(name, content);
+    }
+
+    let settings = input.settings;
+    let evm_version = match settings.evm_version.as_deref() {
+        None => EvmVersion::default(),
+        Some("paris") => EvmVersion::Paris,
+        Some("shanghai") => EvmVersion::Shanghai,
+        Some("cancun") => EvmVersion::Cancun,
+        Some(other) => return input_error(format!("unsupported EVM version \"{other}\"")),
+    };
+    let opt_level = match settings.optimizer.enabled {
+        Some(false) => OptLevel::O0,
+        _ => OptLevel::default(),
+    };
+    let options = CompileOptions {
+        harden: HardenConfig {
+            opt_level,
+            evm_version,
+            ..HardenConfig::default()
+        },
+        ..CompileOptions::default()
+    };
+
+    let mut errors = Vec::new();
+    let mut source_out = Map::new();
+    let mut contracts = Map::new();
+    for (id, (name, content)) in sources.iter().enumerate() {
+        let mut entry = json!({ "id": id });
+        if wants(&selected(&settings.output_selection, name, ""), "ast") {
+            // A source that does not parse is reported by the compile below.
+            if let Some(ast) = parse_from_source(content)
+                .ok()
+                .and_then(|ast| serde_json::to_value(ast).ok())
+            {
+                entry["ast"] = ast;
+            }
+        }
+        source_out.insert(name.clone(), entry);
+
+        let contract = Path::new(name)
+            .file_stem()
+            .map_or_else(|| name.clone(), |s| s.to_string_lossy().into_owned());
+        let wanted = selected(&settings.output_selection, name, &contract);
+        let output = compile_sources(name, &sources, &options).and_then(|program| {
+            for warning in check_warnings(&program) {
+                let file = source_name(warning.file(), name, &sources);
+                let source = sources.get(file).map(String::as_str);
+                push_diagnostic(&mut errors, &Diagnostic::from_warning(&warning), file, source);
+            }
+            // Source maps number the program's files; standard JSON numbers
+            // every source by its `id`.
+            let ids: Vec<usize> = program
+                .files
+                .iter()
+                .map(|file| sources.keys().position(|name| normalize(Path::new(name)) == *file).unwrap_or(id))
+                .collect();
+            contract_output(&program, &options, &wanted, &ids)
+        });
+        match output {
+            Ok(output) if output.is_empty() => {}
+            Ok(output) => {
+                contracts.insert(name.clone(), json!({ contract: output }));
+            }
+            Err(err) => report(&mut errors, &err, name, &sources),
+        }
+    }
+
+    json!({
+        "contracts": contracts,
+        "errors": errors,
+        "sources": source_out,
+    })
+}
+
+/// The outputs requested for `contract` in `file`, wildcards included.
+fn selected(
+    selection: &BTreeMap<String, BTreeMap<String, Vec<String>>>,
+    file: &str,
+    contract: &str,
+) -> Vec<String> {
+    [file, "*"]
+        .iter()
+        .filter_map(|f| selection.get(*f))
+        .flat_map(|contracts| {
+            let any = if contract.is_empty() { None } else { contracts.get("*") };
+            contracts.get(contract).into_iter().chain(any)
+        })
+        .flatten()
+        .cloned()
+        .collect()
+}
+
+/// Whether `output` is selected by name, through a parent such as `evm`
+/// for `evm.bytecode.object`, or by `*`.
+fn wants(selected: &[String], output: &str) -> bool {
+    selected.iter().any(|s| {
+        s == "*"
+            || output
+                .strip_prefix(s.as_str())
+                .is_some_and(|rest| rest.is_empty() || rest.starts_with('.'))
+    })
+}
+
+fn contract_output(
+    program: &Program,
+    options: &CompileOptions,
+    wanted: &[String],
+    ids: &[usize],
+) -> Result<Map<String, Value>, CompileError> {
+    let mut out = Map::new();
+    if wants(wanted, "abi") {
+        let abi = program_to_abi_json(program)?;
+        out.insert("abi".into(), serde_json::from_str(&abi).map_err(std::io::Error::from)?);
+    }
+
+    let mut evm = Map::new();
+    let deploy = || program_to_deploy_bytecode_with_source_map(program, &options.harden);
+    let bytecode = bytecode_output(deploy, "evm.bytecode", &[], wanted, ids)?;
+    if !bytecode.is_empty() {
+        evm.insert("bytecode".into(), Value::Object(bytecode));
+    }
+    let runtime = || program_to_runtime_bytecode_with_source_map(program, &options.harden);
+    let deployed = bytecode_output(runtime, "evm.deployedBytecode", &["immutableReferences"], wanted, ids)?;
+    if !deployed.is_empty() {
+        evm.insert("deployedBytecode".into(), Value::Object(deployed));
+    }
+    if wants(wanted, "evm.methodIdentifiers") {
+        evm.insert("methodIdentifiers".into(), json!(method_identifiers(program)));
+    }
+    if !evm.is_empty() {
+        out.insert("evm".into(), Value::Object(evm));
+    }
+    Ok(out)
+}
+
+/// The selected fields of `prefix`, generating the code only if one is.
+/// `extra` names fields that are always empty objects since Pyra has no
+/// libraries or immutables.
+fn bytecode_output(
+    code: impl FnOnce() -> Result<MappedBytecode, CodegenError>,
+    prefix: &str,
+    extra: &[&str],
+    wanted: &[String],
+    ids: &[usize],
+) -> Result<Map<String, Value>, CompileError> {
+    let fields: Vec<&str> = ["object", "opcodes", "sourceMap", "linkReferences"]
+        .iter()
+        .chain(extra)
+        .copied()
+        .filter(|field| wants(wanted, &format!("{prefix}.{field}")))
+        .collect();
+    let mut out = Map::new();
+    if fields.is_empty() {
+        return Ok(out);
+    }
+    let mapped = code()?;
+    for field in fields {
+        let value = match field {
+            "object" => Value::String(hex::encode(&mapped.code)),
+            "opcodes" => Value::String(
+                disassemble(&mapped.code)
+                    .iter()
+                    .map(ToString::to_string)
+                    .collect::<Vec<_>>()
+                    .join(" "),
+            ),
+            "sourceMap" => Value::String(with_file_ids(&mapped.source_map, ids)),
+            _ => json!({}),
+        };
+        out.insert(field.into(), value);
+    }
+    Ok(out)
+}
+
+/// Renumbers the file field of each source map entry from the program's
+/// own file index to the source `id` in `ids`.
+fn with_file_ids(map: &str, ids: &[usize]) -> String {
+    map.split(';')
+        .map(|entry| {
+            let mut fields: Vec<String> = entry.split(':').map(String::from).collect();
+            if let Some(id) = fields.get(2).and_then(|f| f.parse::<usize>().ok()).and_then(|i| ids.get(i)) {
+                fields[2] = id.to_string();
+            }
+            fields.join(":")
+        })
+        .collect::<Vec<_>>()
+        .join(";")
+}
+
+/// The source key of `file`, the imported file a diagnostic is in, or
+/// `name` when it is in the source being compiled.
+fn source_name<'a>(file: Option<&Path>, name: &'a str, sources: &'a BTreeMap<String, String>) -> &'a str {
+    file.and_then(|file| sources.keys().find(|s| normalize(Path::new(s)) == file))
+        .map_or(name, String::as_str)
+}
+
+fn report(errors: &mut Vec<Value>, err: &CompileError, name: &str, sources: &BTreeMap<String, String>) {
+    if let CompileError::Type(type_errors) = err {
+        for e in type_errors {
+            let file = source_name(e.file(), name, sources);
+            let source = sources.get(file).map(String::as_str);
+            push_diagnostic(errors, &Diagnostic::from_type_error(e), file, source);
+        }
+        return;
+    }
+    let file = match err {
+        CompileError::Parse { path, .. } => path.to_string_lossy().into_owned(),
+        _ => name.to_string(),
+    };
+    let source = sources.get(&file).map(String::as_str);
+    for diag in err.diagnostics(source) {
+        push_diagnostic(errors, &diag, &file, source);
+    }
+}
+
+/// Adds `diag` unless an identical entry is already there, which happens
+/// when several sources import the same broken file.
+fn push_diagnostic(errors: &mut Vec<Value>, diag: &Diagnostic, file: &str, source: Option<&str>) {
+    let kind = match (diag.severity, diag.code.and_then(|c| c.get(1..2))) {
+        (Severity::Warning, _) => "Warning",
+        (_, Some("0" | "1")) => "ParserError",
+        (_, Some("2")) => "TypeError",
+        (_, Some("3")) => "CodeGenerationError",
+        (_, Some("5")) => "IOError",
+        _ => "CompilerError",
+    };
+    let mut entry = json!({
+        "component": "general",
+        "formattedMessage": render(diag, Path::new(file), source, false),
+        "message": diag.message,
+        "severity": match diag.severity {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+        },
+        "type": kind,
+    });
+    if let Some(code) = diag.code {
+        entry["errorCode"] = json!(code);
+    }
+    if let Some(span) = &diag.span {
+        entry["sourceLocation"] = json!({ "file": file, "start": span.start, "end": span.end });
+    }
+    if !errors.contains(&entry) {
+        errors.push(entry);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn compile(input: Value) -> Value {
+        serde_json::from_str(&compile_standard_json(&input.to_string())).unwrap()
+    }
+
+    #[test]
+    fn compiles_selected_outputs() {
+        let out = compile(json!({
+            "language": "Pyra",
+            "sources": {
+                "contracts/Counter.pyra": {
+                    "content": "import \"lib.pyra\"\n\ndef get() -> uint256:\n    return LIMIT\n"
+                },
+                "contracts/lib.pyra": { "content": "const LIMIT: uint256 = 7\n" },
+            },
+            "settings": {
+                "outputSelection": {
+                    "*": { "*": ["abi", "evm.bytecode.object", "evm.methodIdentifiers"] },
+                    "contracts/Counter.pyra": { "": ["ast"], "Counter": ["evm.deployedBytecode"] },
+                }
+            }
+        }));
+        assert_eq!(out["errors"], json!([]));
+        assert_eq!(out["sources"]["contracts/Counter.pyra"]["id"], 0);
+        assert_eq!(out["sources"]["contracts/lib.pyra"]["id"], 1);
+        assert!(out["sources"]["contracts/Counter.pyra"]["ast"]["items"].is_array());
+        assert!(out["sources"]["contracts/lib.pyra"].get("ast").is_none());
+
+        let counter = &out["contracts"]["contracts/Counter.pyra"]["Counter"];
+        assert_eq!(counter["abi"][0]["name"], "get");
+        assert_eq!(counter["evm"]["methodIdentifiers"]["get()"], "6d4ce63c");
+        let object = counter["evm"]["bytecode"]["object"].as_str().unwrap();
+        assert!(object.starts_with("60"));
+        assert!(counter["evm"]["bytecode"].get("sourceMap").is_none());
+
+        let deployed = &counter["evm"]["deployedBytecode"];
+        assert!(object.ends_with(deployed["object"].as_str().unwrap()));
+        assert!(deployed["opcodes"].as_str().unwrap().starts_with("PUSH1 0x00 CALLDATALOAD"));
+        let map = deployed["sourceMap"].as_str().unwrap();
+        assert!(map.split(';').any(|e| e.split(':').nth(2) == Some("0")));
+        assert_eq!(with_file_ids("-1:-1:-1:-;;5:3:0;;6:1:1", &[2, 0]), "-1:-1:-1:-;;5:3:2;;6:1:0");
+        assert_eq!(deployed["immutableReferences"], json!({}));
+    }
+
+    #[test]
+    fn source_maps_number_each_statement_by_its_file() {
+        let out = compile(json!({
+            "language": "Pyra",
+            "sources": {
+                "a.pyra": { "content": "def twice(x: uint256) -> uint256:\n    return x * 2\n" },
+                "b.pyra": { "content": "import \"a.pyra\"\n\ndef get() -> uint256:\n    return twice(3)\n" },
+            },
+            "settings": { "outputSelection": { "b.pyra": { "b": ["evm.deployedBytecode.sourceMap"] } } }
+        }));
+        assert_eq!(out["errors"], json!([]));
+        let map = out["contracts"]["b.pyra"]["b"]["evm"]["deployedBytecode"]["sourceMap"].as_str().unwrap();
+        let mut file = "";
+        let mut statements = std::collections::BTreeSet::new();
+        for entry in map.split(';') {
+            let fields: Vec<&str> = entry.split(':').collect();
+            if let Some(f) = fields.get(2).filter(|f| !f.is_empty()) {
+                file = f;
+            }
+            if let Some(start) = fields.first().filter(|s| !s.is_empty() && **s != "-1") {
+                statements.insert((file, *start));
+            }
+        }
+        // `return x * 2` in a.pyra, whose id is 0, and `return twice(3)` in b.pyra.
+        assert!(statements.contains(&("0", "38")), "{statements:?}");
+        assert!(statements.contains(&("1", "43")), "{statements:?}");
+    }
+
+    #[test]
+    fn reports_imported_warnings_against_their_source() {
+        let out = compile(json!({
+            "language": "Pyra",
+            "sources": {
+                "lib.pyra": { "content": "def helper() -> uint256:\n    let unused = 1\n    return 2\n" },
+                "main.pyra": { "content": "import \"lib.pyra\"\n\ndef get() -> uint256:\n    return helper()\n" },
+            },
+            "settings": { "outputSelection": { "*": { "*": ["abi"] } } }
+        }));
+        let errors = out["errors"].as_array().unwrap();
+        let unused: Vec<_> = errors.iter().filter(|e| e["errorCode"] == "W2002").collect();
+        assert_eq!(unused.len(), 1, "{errors:?}");
+        assert_eq!(unused[0]["sourceLocation"]["file"], "lib.pyra");
+        assert_eq!(unused[0]["sourceLocation"]["start"], 29);
+        assert!(unused[0]["formattedMessage"].as_str().unwrap().contains("--> lib.pyra:2:5"));
+    }
+
+    #[test]
+    fn reports_errors_against_their_source() {
+        let out = compile(json!({
+            "language": "Pyra",
+            "sources": {
+                "a.pyra": { "content": "import \"b.pyra\"\n" },
+                "b.pyra": { "content": "def f(:\n" },
+                "c.pyra": { "content": "def g() -> uint256:\n    return missing\n" },
+                "d.pyra": { "content": "const X: uint256 = 1\nimport \"e.pyra\"\n" },
+                "e.pyra": { "content": "const X: uint256 = 2\n" },
+            },
+            "settings": { "outputSelection": { "*": { "*": ["*"] } } }
+        }));
+        let errors = out["errors"].as_array().unwrap();
+        let parse: Vec<_> = errors.iter().filter(|e| e["type"] == "ParserError").collect();
+        assert_eq!(parse.len(), 1, "{errors:?}");
+        assert_eq!(parse[0]["sourceLocation"]["file"], "b.pyra");
+        assert!(parse[0]["formattedMessage"].as_str().unwrap().contains("--> b.pyra:1:"));
+        assert!(errors
+            .iter()
+            .any(|e| e["type"] == "TypeError" && e["sourceLocation"]["file"] == "c.pyra"));
+        let duplicate = errors.iter().find(|e| e["errorCode"] == "E2007").unwrap();
+        assert_eq!(duplicate["sourceLocation"], json!({ "file": "e.pyra", "start": 6, "end": 7 }));
+        assert!(out["contracts"].get("c.pyra").is_none());
+        assert!(out["contracts"].get("a.pyra").is_none());
+    }
+
+    #[test]
+    fn rejects_bad_input() {
+        let out = compile(json!({ "language": "Solidity", "sources": {} }));
+        assert_eq!(out["errors"][0]["type"], "JSONError");
+
+        let out: Value = serde_json::from_str(&compile_standard_json("{")).unwrap();
+        assert_eq!(out["errors"][0]["severity"], "error");
+
+        let out = compile(json!({
+            "language": "Pyra",
+            "sources": { "a.pyra": { "urls": ["a.pyra"] } },
+        }));
+        assert!(out["errors"][0]["message"].as_str().unwrap().contains("urls"));
+    }
+
+    #[test]
+    fn follows_settings() {
+        let input = |settings: Value| {
+            json!({
+                "language": "Pyra",
+                "sources": {
+                    "t.pyra": { "content": "def t() -> uint256:\n    asm: PUSH1 0x01 PUSH1 0x00 TSTORE\n    return 2 + 3\n" },
+                },
+                "settings": settings,
+            })
+        };
+        let out = compile(input(json!({ "outputSelection": { "*": { "*": ["evm.bytecode"] } } })));
+        assert_eq!(out["errors"][0]["type"], "CodeGenerationError");
+
+        let select = json!({ "*": { "*": ["evm.deployedBytecode.opcodes"] } });
+        let out = compile(input(json!({ "evmVersion": "cancun", "outputSelection": select })));
+        let opcodes = out["contracts"]["t.pyra"]["t"]["evm"]["deployedBytecode"]["opcodes"].clone();
+        assert!(opcodes.as_str().unwrap().contains("TSTORE"));
+        assert!(opcodes.as_str().unwrap().contains("PUSH1 0x05"));
+
+        let settings = json!({ "evmVersion": "cancun", "optimizer": { "enabled": false }, "outputSelection": select });
+        let out = compile(input(settings));
+        let opcodes = out["contracts"]["t.pyra"]["t"]["evm"]["deployedBytecode"]["opcodes"].clone();
+        assert!(!opcodes.as_str().unwrap().contains("PUSH1 0x05"));
+
+        let out = compile(input(json!({ "evmVersion": "london" })));
+        assert_eq!(out["errors"][0]["type"], "JSONError");
+    }
+}
@@ -1,10 +1,26 @@
 use std::collections::HashMap;
-use crate::{Expression, Item, Program, Statement, Type};
+use crate::{
+    BinaryOp, Expression, Item, Program, RequireStatement, Statement, StructField, Type,
+};
 
 #[derive(Debug, Clone, PartialEq)]
 pub enum StorageKind {
     Value,
     Mapping,
+    /// Dynamic array: the length sits in the slot and element `i` at
+    /// `keccak(slot) + i`.
+    Array,
+    /// Fixed-size array occupying `len` consecutive slots.
+    FixedArray(u64),
+}
+
+impl StorageKind {
+    fn slots(&self) -> u64 {
+        match self {
+            StorageKind::FixedArray(len) => *len,
+            _ => 1,
+        }
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -16,6 +32,12 @@ pub struct StorageSlot {
 #[derive(Debug, Clone)]
 pub struct StorageLayout {
     slots: HashMap<String, StorageSlot>,
+    instances: HashMap<String, String>,
+    /// Mappings whose values are structs: the number of keys to reach an
+    /// entry and the struct's fields, which sit in consecutive slots from
+    /// the entry's slot.
+    entry_structs: HashMap<String, (usize, Vec<String>)>,
+    collisions: Vec<String>,
     next_slot: u64,
 }
 
@@ -23,34 +45,52 @@ impl StorageLayout {
     pub fn from_program(program: &Program) -> Self {
         let mut layout = Self {
             slots: HashMap::with_capacity(16),
+            instances: HashMap::new(),
+            entry_structs: HashMap::new(),
+            collisions: Vec::new(),
             next_slot: 0,
         };
 
-        for item in &program.items {
-            if let Item::Const(c) = item {
-                let kind = match &c.type_ {
-                    Type::Map(_, _) => StorageKind::Mapping,
-                    _ => StorageKind::Value,
-                };
-                layout.alloc(&c.name, kind);
-            }
-        }
+        let structs: HashMap<&str, &[StructField]> = program
+            .items
+            .iter()
+            .filter_map(|item| match item {
+                Item::Struct(s) => Some((s.name.as_str(), s.fields.as_slice())),
+                _ => None,
+            })
+            .collect();
 
         for item in &program.items {
-            if let Item::Struct(s) = item {
-                for field in &s.fields {
-                    let kind = match &field.type_ {
-                        Type::Map(_, _) => StorageKind::Mapping,
-                        _ => StorageKind::Value,
-                    };
-                    layout.alloc(&field.name, kind);
+            let (var, type_) = match item {
+                Item::Const(c) => (&c.name, &c.type_),
+                Item::State(s) => (&s.name, &s.type_),
+                _ => continue,
+            };
+            let struct_name = match type_ {
+                Type::Custom(name) | Type::Generic(name, _) => Some(name.as_str()),
+                _ => None,
+            };
+            if let Some((name, fields)) = struct_name.and_then(|n| structs.get_key_value(n)) {
+                if layout.slots.contains_key(var) || layout.instances.contains_key(var) {
+                    layout.collide(var);
+                    continue;
+                }
+                layout.instances.insert(var.clone(), name.to_string());
+                for field in fields.iter() {
+                    let qualified = format!("{}.{}", var, field.name);
+                    layout.alloc(&qualified, kind_of(&field.type_));
+                    layout.record_entry_struct(&qualified, &field.type_, &structs);
                 }
+                continue;
             }
+            layout.alloc(var, kind_of(type_));
+            layout.record_entry_struct(var, type_, &structs);
         }
 
         for item in &program.items {
             if let Item::Function(f) = item {
                 let mut locals: Vec<&str> = f.params.iter().map(|p| p.name.as_str()).collect();
+                locals.extend(f.return_names.iter().map(String::as_str));
                 discover_state(&f.body.statements, &mut locals, &mut layout);
             }
         }
@@ -59,12 +99,36 @@ impl StorageLayout {
     }
 
     fn alloc(&mut self, name: &str, kind: StorageKind) {
-        if !self.slots.contains_key(name) {
+        if self.instances.contains_key(name) {
+            self.collide(name);
+        } else if !self.slots.contains_key(name) {
+            let size = kind.slots();
             self.slots.insert(name.to_string(), StorageSlot {
                 slot: self.next_slot,
                 kind,
             });
-            self.next_slot += 1;
+            self.next_slot += size;
+        }
+    }
+
+    fn record_entry_struct(&mut self, name: &str, ty: &Type, structs: &HashMap<&str, &[StructField]>) {
+        let mut depth = 0;
+        let mut value = ty;
+        while let Type::Map(_, inner) = value {
+            depth += 1;
+            value = inner;
+        }
+        if let (1.., Type::Custom(s)) = (depth, value) {
+            if let Some(fields) = structs.get(s.as_str()) {
+                let names = fields.iter().map(|f| f.name.clone()).collect();
+                self.entry_structs.insert(name.to_string(), (depth, names));
+            }
+        }
+    }
+
+    fn collide(&mut self, name: &str) {
+        if !self.collisions.iter().any(|c| c == name) {
+            self.collisions.push(name.to_string());
         }
     }
 
@@ -72,6 +136,44 @@ impl StorageLayout {
         self.slots.get(name)
     }
 
+    /// Slot of `field` inside the struct instance `instance`.
+    pub fn field(&self, instance: &str, field: &str) -> Option<&StorageSlot> {
+        if !self.instances.contains_key(instance) {
+            return None;
+        }
+        self.slots.get(&format!("{instance}.{field}"))
+    }
+
+    /// Offset of `field` from the slot of an entry in the struct-valued
+    /// mapping `mapping`, given the number of keys used to reach it.
+    pub fn entry_field(&self, mapping: &str, depth: usize, field: &str) -> Option<u64> {
+        let fields = self.entry_fields(mapping, depth)?;
+        fields.iter().position(|f| f == field).map(|i| i as u64)
+    }
+
+    /// Fields of the struct held by `mapping`'s entries, in slot order.
+    pub fn entry_fields(&self, mapping: &str, depth: usize) -> Option<&[String]> {
+        let (entry_depth, fields) = self.entry_structs.get(mapping)?;
+        (*entry_depth == depth).then_some(fields.as_slice())
+    }
+
+    /// Fields of the struct instance `instance` with their slots.
+    pub fn instance_fields<'a>(&'a self, instance: &'a str) -> impl Iterator<Item = (&'a str, &'a StorageSlot)> {
+        self.slots.iter().filter_map(move |(name, slot)| {
+            let field = name.strip_prefix(instance)?.strip_prefix('.')?;
+            self.is_instance(instance).then_some((field, slot))
+        })
+    }
+
+    pub fn is_instance(&self, name: &str) -> bool {
+        self.instances.contains_key(name)
+    }
+
+    /// Struct instances whose names are also used as plain storage variables.
+    pub fn collisions(&self) -> &[String] {
+        &self.collisions
+    }
+
     pub fn iter(&self) -> impl Iterator<Item = (&String, &StorageSlot)> {
         self.slots.iter()
     }
@@ -81,6 +183,15 @@ impl StorageLayout {
     }
 }
 
+fn kind_of(ty: &Type) -> StorageKind {
+    match ty {
+        Type::Map(_, _) => StorageKind::Mapping,
+        Type::Vec(_) => StorageKind::Array,
+        Type::Array(_, len) => StorageKind::FixedArray(*len as u64),
+        _ => StorageKind::Value,
+    }
+}
+
 fn discover_state<'a>(
     stmts: &'a [Statement],
     locals: &mut Vec<&'a str>,
@@ -95,10 +206,19 @@ fn discover_state<'a>(
                 locals.push(&l.name);
             }
             Statement::Assign(a) => {
-                discover_target(&a.target, locals, layout);
+                // Assigning a struct literal to an instance writes its fields.
+                let whole_instance = matches!(
+                    (&a.target, &a.value),
+                    (Expression::Identifier(name), Expression::StructInit(..)) if layout.is_instance(name)
+                );
+                if !whole_instance {
+                    discover_target(&a.target, locals, layout);
+                }
                 discover_expr_mappings(&a.value, locals, layout);
             }
-            Statement::Return(Some(e)) | Statement::Require(e) | Statement::Expression(e) => {
+            Statement::Return(Some(e), _)
+            | Statement::Require(RequireStatement { condition: e, .. })
+            | Statement::Expression(e, _) => {
                 discover_expr_mappings(e, locals, layout);
             }
             Statement::Emit(em) => {
@@ -106,6 +226,11 @@ fn discover_state<'a>(
                     discover_expr_mappings(arg, locals, layout);
                 }
             }
+            Statement::Revert(rv) => {
+                for arg in &rv.args {
+                    discover_expr_mappings(arg, locals, layout);
+                }
+            }
             Statement::If(if_stmt) => {
                 discover_expr_mappings(&if_stmt.condition, locals, layout);
                 discover_state(&if_stmt.then_branch.statements, locals, layout);
@@ -123,28 +248,33 @@ fn discover_state<'a>(
                 discover_expr_mappings(&while_stmt.condition, locals, layout);
                 discover_state(&while_stmt.body.statements, locals, layout);
             }
-            Statement::Return(None) => {}
+            Statement::Return(None, _) | Statement::Asm(_) | Statement::Break(_) | Statement::Continue(_) => {}
         }
     }
 }
 
 fn discover_target(expr: &Expression, locals: &[&str], layout: &mut StorageLayout) {
     match expr {
-        Expression::Identifier(name) => {
-            if !locals.contains(&name.as_str()) && !is_builtin(name) {
-                layout.alloc(name, StorageKind::Value);
-            }
+        Expression::Identifier(name) if !locals.contains(&name.as_str()) && !is_builtin(name) => {
+            layout.alloc(name, StorageKind::Value);
         }
-        Expression::Index(base, _) => {
-            if let Expression::Identifier(name) = base.as_ref() {
-                if !locals.contains(&name.as_str()) && !is_builtin(name) {
-                    layout.alloc(name, StorageKind::Mapping);
-                }
+        Expression::Index(base, _) => match state_name(base, locals) {
+            Some(name) => layout.alloc(name, StorageKind::Mapping),
+            None => discover_target(base, locals, layout),
+        },
+        Expression::Tuple(targets) => {
+            for target in targets {
+                discover_target(target, locals, layout);
             }
         }
-        Expression::Member(base, _) => {
-            discover_target(base, locals, layout);
-        }
+        Expression::Member(base, field) => match base.as_ref() {
+            Expression::Identifier(name) if name == "self" => {
+                layout.alloc(field, StorageKind::Value);
+            }
+            Expression::Identifier(name) if layout.is_instance(name) => {}
+            inner if state_name(inner, locals).is_some_and(|n| layout.is_instance(n)) => {}
+            _ => discover_target(base, locals, layout),
+        },
         _ => {}
     }
 }
@@ -152,13 +282,19 @@ fn discover_target(expr: &Expression, locals: &[&str], layout: &mut StorageLayou
 fn discover_expr_mappings(expr: &Expression, locals: &[&str], layout: &mut StorageLayout) {
     match expr {
         Expression::Index(base, idx) => {
-            if let Expression::Identifier(name) = base.as_ref() {
-                if !locals.contains(&name.as_str()) && !is_builtin(name) {
-                    layout.alloc(name, StorageKind::Mapping);
-                }
+            match state_name(base, locals) {
+                Some(name) => layout.alloc(name, StorageKind::Mapping),
+                None => discover_expr_mappings(base, locals, layout),
             }
             discover_expr_mappings(idx, locals, layout);
         }
+        Expression::Binary(BinaryOp::In, key, container) => {
+            discover_expr_mappings(key, locals, layout);
+            match state_name(container, locals) {
+                Some(name) => layout.alloc(name, StorageKind::Mapping),
+                None => discover_expr_mappings(container, locals, layout),
+            }
+        }
         Expression::Binary(_, l, r) => {
             discover_expr_mappings(l, locals, layout);
             discover_expr_mappings(r, locals, layout);
@@ -175,10 +311,34 @@ fn discover_expr_mappings(expr: &Expression, locals: &[&str], layout: &mut Stora
         Expression::Member(base, _) => {
             discover_expr_mappings(base, locals, layout);
         }
+        Expression::Slice(base, start, end) => {
+            discover_expr_mappings(base, locals, layout);
+            discover_expr_mappings(start, locals, layout);
+            discover_expr_mappings(end, locals, layout);
+        }
+        Expression::Tuple(items) => {
+            for item in items {
+                discover_expr_mappings(item, locals, layout);
+            }
+        }
         _ => {}
     }
 }
 
+/// Storage variable named by `expr`, either bare or qualified as `self.name`.
+fn state_name<'a>(expr: &'a Expression, locals: &[&str]) -> Option<&'a str> {
+    match expr {
+        Expression::Identifier(name) if !locals.contains(&name.as_str()) && !is_builtin(name) => {
+            Some(name)
+        }
+        Expression::Member(base, field) => match base.as_ref() {
+            Expression::Identifier(b) if b == "self" => Some(field),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
 fn is_builtin(name: &str) -> bool {
     matches!(name, "msg" | "block" | "tx" | "self")
 }
@@ -198,6 +358,14 @@ mod tests {
         assert_eq!(slot.kind, StorageKind::Value);
     }
 
+    #[test]
+    fn layout_discovers_mapping_from_membership() {
+        let src = "def t(k: address) -> bool: return k in admins\n";
+        let program = parse_from_source(src).unwrap();
+        let layout = StorageLayout::from_program(&program);
+        assert_eq!(layout.get("admins").unwrap().kind, StorageKind::Mapping);
+    }
+
     #[test]
     fn layout_discovers_mapping_from_assign() {
         let src = "def t():\n    balances[msg.sender] = 100\n";
@@ -207,6 +375,52 @@ mod tests {
         assert_eq!(slot.kind, StorageKind::Mapping);
     }
 
+    #[test]
+    fn layout_discovers_nested_mapping() {
+        let src = "def t(a: address, b: address) -> uint256:\n    allowances[a][b] = 1\n    return approvals[a][b]\n";
+        let program = parse_from_source(src).unwrap();
+        let layout = StorageLayout::from_program(&program);
+        assert_eq!(layout.get("allowances").unwrap().kind, StorageKind::Mapping);
+        assert_eq!(layout.get("approvals").unwrap().kind, StorageKind::Mapping);
+        assert_eq!(layout.slot_count(), 2);
+    }
+
+    #[test]
+    fn layout_reserves_slots_for_fixed_arrays() {
+        let src = "owners: address[3]\nholders: address[]\ncount: uint256\n";
+        let program = parse_from_source(src).unwrap();
+        let layout = StorageLayout::from_program(&program);
+        let owners = layout.get("owners").unwrap();
+        assert_eq!((owners.slot, &owners.kind), (0, &StorageKind::FixedArray(3)));
+        let holders = layout.get("holders").unwrap();
+        assert_eq!((holders.slot, &holders.kind), (3, &StorageKind::Array));
+        assert_eq!(layout.get("count").unwrap().slot, 4);
+        assert_eq!(layout.slot_count(), 5);
+    }
+
+    #[test]
+    fn layout_places_struct_entry_fields_in_consecutive_slots() {
+        let src = "struct User {\n    balance: uint256,\n    age: uint256\n}\n\nusers: mapping[address => User]\ngrid: mapping[uint256 => mapping[uint256 => User]]\n";
+        let program = parse_from_source(src).unwrap();
+        let layout = StorageLayout::from_program(&program);
+        assert_eq!(layout.entry_field("users", 1, "balance"), Some(0));
+        assert_eq!(layout.entry_field("users", 1, "age"), Some(1));
+        assert_eq!(layout.entry_field("users", 2, "age"), None);
+        assert_eq!(layout.entry_field("grid", 2, "age"), Some(1));
+        assert_eq!(layout.slot_count(), 2);
+    }
+
+    #[test]
+    fn layout_allocates_declared_state_first() {
+        let src = "owner: address\nbalances: mapping[address => uint256]\n\ndef t():\n    total = 1\n";
+        let program = parse_from_source(src).unwrap();
+        let layout = StorageLayout::from_program(&program);
+        assert_eq!(layout.get("owner").unwrap().kind, StorageKind::Value);
+        assert_eq!(layout.get("balances").unwrap().kind, StorageKind::Mapping);
+        assert!(layout.get("owner").unwrap().slot < layout.get("balances").unwrap().slot);
+        assert!(layout.get("balances").unwrap().slot < layout.get("total").unwrap().slot);
+    }
+
     #[test]
     fn layout_discovers_mapping_from_read() {
         let src = "def t(owner: address) -> uint256: return balances[owner]\n";
@@ -243,4 +457,28 @@ mod tests {
         assert_eq!(layout.get("c").unwrap().slot, 2);
         assert_eq!(layout.slot_count(), 3);
     }
+
+    #[test]
+    fn layout_scopes_struct_fields_under_instance() {
+        let src = "struct Ledger {\n    balances: mapping[address => uint256],\n    owner: address\n}\n\nlet ledger: Ledger = Ledger { owner: 0 }\n\ndef t(a: address):\n    balances[a] = 1\n";
+        let program = parse_from_source(src).unwrap();
+        let layout = StorageLayout::from_program(&program);
+        let field = layout.field("ledger", "balances").unwrap();
+        let global = layout.get("balances").unwrap();
+        assert_eq!(field.kind, StorageKind::Mapping);
+        assert_eq!(global.kind, StorageKind::Mapping);
+        assert_ne!(field.slot, global.slot);
+        assert_eq!(layout.field("ledger", "owner").unwrap().slot, 1);
+        assert!(layout.get("owner").is_none());
+        assert!(layout.collisions().is_empty());
+    }
+
+    #[test]
+    fn layout_reports_instance_used_as_variable() {
+        let src = "struct State {\n    paused: bool\n}\n\nlet state: State = State { paused: false }\n\ndef t():\n    state = 1\n";
+        let program = parse_from_source(src).unwrap();
+        let layout = StorageLayout::from_program(&program);
+        assert_eq!(layout.collisions(), ["state".to_string()]);
+        assert!(layout.get("state").is_none());
+    }
 }
@@ -0,0 +1,173 @@
+//! `pyra test`. A test file is an ordinary contract, usually importing the
+//! contracts under test; every function whose name starts with `test_` is
+//! a test. Each test runs as one transaction against a fresh deployment on
+//! the built-in interpreter, and passes if the call succeeds. A test named
+//! `test_revert_*` passes only if the call reverts.
+
+use crate::compiler::{compile_file_with_options, CompileError, CompileOptions};
+use crate::ir::{compute_error_selector, compute_selector};
+use crate::runtime::{deploy, CallResult, Outcome};
+use crate::{program_to_deploy_bytecode_with_config, ErrorDef, HardenConfig, Item, Program};
+use num_bigint::BigUint;
+use std::path::Path;
+
+/// How one test went.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TestResult {
+    pub name: String,
+    pub passed: bool,
+    /// Gas the test transaction used, intrinsic cost included and refunds
+    /// left out.
+    pub gas: u64,
+    /// Why the test failed, or the revert reason a `test_revert_*` test
+    /// expected and got.
+    pub reason: Option<String>,
+}
+
+/// Compiles the test file at `path` and runs its tests in source order.
+pub fn run_test_file(path: &Path, options: &CompileOptions) -> Result<Vec<TestResult>, CompileError> {
+    let program = compile_file_with_options(path, options)?;
+    run_tests(&program, &options.harden)
+}
+
+/// Runs the tests of a checked program, built with `config`.
+pub fn run_tests(program: &Program, config: &HardenConfig) -> Result<Vec<TestResult>, CompileError> {
+    let init = program_to_deploy_bytecode_with_config(program, config)?;
+    let errors: Vec<&ErrorDef> = program
+        .items
+        .iter()
+        .filter_map(|item| match item {
+            Item::Error(def) => Some(def),
+            _ => None,
+        })
+        .collect();
+
+    let tests = program.items.iter().filter_map(|item| match item {
+        Item::Function(f) if f.name.starts_with("test_") => Some(f),
+        _ => None,
+    });
+    Ok(tests
+        .map(|test| {
+            let fail = |gas, reason: String| TestResult {
+                name: test.name.clone(),
+                passed: false,
+                gas,
+                reason: Some(reason),
+            };
+            if !test.params.is_empty() {
+                return fail(0, "tests take no parameters".into());
+            }
+            let mut contract = match deploy(&init) {
+                Ok(contract) => contract,
+                Err(result) => {
+                    return fail(0, format!("deployment failed: {}", describe(&result.outcome, &errors)));
+                }
+            };
+            let CallResult { outcome, gas_used, .. } = contract.call(compute_selector(test), &[]);
+            let expect_revert = test.name.starts_with("test_revert_");
+            let passed = if expect_revert {
+                matches!(outcome, Outcome::Revert(_))
+            } else {
+                outcome.is_success()
+            };
+            let reason = match (passed, expect_revert) {
+                (true, false) => None,
+                (false, true) if outcome.is_success() => Some("expected a revert".into()),
+                _ => Some(describe(&outcome, &errors)),
+            };
+            TestResult {
+                name: test.name.clone(),
+                passed,
+                gas: gas_used,
+                reason,
+            }
+        })
+        .collect())
+}
+
+/// Explains a failed call: the `Error(string)` message, the custom error
+/// with its arguments, or what stopped execution.
+fn describe(outcome: &Outcome, errors: &[&ErrorDef]) -> String {
+    let data = match outcome {
+        Outcome::Revert(data) => data,
+        Outcome::Fault(message) => return format!("execution failed: {message}"),
+        Outcome::Return(_) | Outcome::Stop => return "succeeded".into(),
+    };
+    if data.is_empty() {
+        return "reverted without a reason".into();
+    }
+    if data.len() >= 4 && data[..4] == [0x08, 0xc3, 0x79, 0xa0] {
+        if let Some(message) = error_string(&data[4..]) {
+            return message;
+        }
+    }
+    let custom = errors
+        .iter()
+        .find(|def| data.len() >= 4 && data[..4] == compute_error_selector(def));
+    match custom {
+        Some(def) => {
+            let args: Vec<String> = data[4..]
+                .chunks(32)
+                .take(def.params.len())
+                .map(|word| BigUint::from_bytes_be(word).to_string())
+                .collect();
+            format!("{}({})", def.name, args.join(", "))
+        }
+        None => format!("reverted with 0x{}", hex::encode(data)),
+    }
+}
+
+/// Decodes the ABI-encoded string argument of `Error(string)`.
+fn error_string(args: &[u8]) -> Option<String> {
+    let word = |at: usize| -> Option<usize> {
+        let bytes = args.get(at..at + 32)?;
+        usize::try_from(BigUint::from_bytes_be(bytes)).ok()
+    };
+    let offset = word(0)?;
+    let len = word(offset)?;
+    let start = offset.checked_add(32)?;
+    let text = args.get(start..start.checked_add(len)?)?;
+    Some(String::from_utf8_lossy(text).into_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn revert_with(selector: [u8; 4], words: &[&[u8]]) -> Outcome {
+        let mut data = selector.to_vec();
+        for word in words {
+            let mut padded = [0u8; 32];
+            padded[32 - word.len()..].copy_from_slice(word);
+            data.extend_from_slice(&padded);
+        }
+        Outcome::Revert(data)
+    }
+
+    #[test]
+    fn describes_error_strings() {
+        let mut outcome = revert_with([0x08, 0xc3, 0x79, 0xa0], &[&[0x20], &[5]]);
+        if let Outcome::Revert(data) = &mut outcome {
+            data.extend_from_slice(b"nope!");
+            data.resize(4 + 96, 0);
+        }
+        assert_eq!(describe(&outcome, &[]), "nope!");
+        assert_eq!(describe(&Outcome::Revert(Vec::new()), &[]), "reverted without a reason");
+        assert_eq!(
+            describe(&Outcome::Fault("stack underflow".into()), &[]),
+            "execution failed: stack underflow"
+        );
+    }
+
+    #[test]
+    fn describes_custom_errors() {
+        let program = crate::parse_from_source("error TooLow(have: uint256, want: uint256)\n").unwrap();
+        let Item::Error(def) = &program.items[0] else { panic!() };
+        let outcome = revert_with(compute_error_selector(def), &[&[3], &[7]]);
+        assert_eq!(describe(&outcome, &[def]), "TooLow(3, 7)");
+        assert_eq!(
+            describe(&revert_with([1, 2, 3, 4], &[]), &[def]),
+            "reverted with 0x01020304"
+        );
+    }
+}
@@ -1,6 +1,12 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use crate::ir::{align_fixed_bytes, asm_op, IrOp};
+use crate::optimizer::{signed_constant, slice_bounds};
+use num_bigint::BigUint;
+use tiny_keccak::{Hasher, Keccak};
 use crate::{
-    BinaryOp, Block, Expression, Function, Item, Program, Statement, Type, UnaryOp,
+    BinaryOp, Block, EnumDef, EventField, Expression, ForStatement, Function, InterfaceDef, Item, Program,
+    RequireStatement, Span, Statement, Type, UnaryOp,
 };
 use crate::storage::{StorageKind, StorageLayout};
 
@@ -24,28 +30,359 @@ pub enum TypeError {
     #[error("cannot index into non-mapping type {0}")]
     IndexNonMapping(String),
 
-    #[error("duplicate definition `{0}`")]
-    Duplicate(String),
+    /// Two top-level items share a name; `file` holds the later one.
+    #[error("duplicate definition `{name}`")]
+    Duplicate { name: String, file: std::path::PathBuf },
+
+    #[error("{context} must be bool, got {got}; compare with `!= 0` instead")]
+    NonBoolCondition { context: String, got: String },
+
+    #[error("for loops can only iterate over range()")]
+    UnsupportedIterable,
+
+    #[error("range() takes 1 to 3 arguments, got {0}")]
+    RangeArity(usize),
+
+    #[error("range() step must be a compile-time constant")]
+    NonConstantRangeStep,
+
+    #[error("range() step must not be zero")]
+    ZeroRangeStep,
+
+    #[error("mapping key must be a value type, got dynamic {0}")]
+    DynamicMappingKey(String),
+
+    #[error("cannot destructure {got} value(s) into {expected} target(s)")]
+    TupleArity { expected: usize, got: usize },
+
+    #[error("`{name}` takes {expected} argument(s), got {got}")]
+    CallArity { name: String, expected: usize, got: usize },
+
+    #[error("undefined event `{0}`")]
+    UndefinedEvent(String),
+
+    #[error("undefined error `{0}`")]
+    UndefinedError(String),
+
+    #[error("event `{0}` has more than 3 indexed fields")]
+    TooManyIndexed(String),
+
+    #[error("`{0}` outside of a loop")]
+    OutsideLoop(&'static str),
+
+    #[error("@{mutability} function `{name}` cannot modify state")]
+    StateChangeInReadOnly { name: String, mutability: String },
+
+    #[error("interface `{interface}` has no function `{name}`")]
+    UndefinedInterfaceFunction { interface: String, name: String },
+
+    #[error("recursive call to `{0}` is not supported")]
+    RecursiveCall(String),
+
+    #[error("`in` requires a mapping on the right, got {0}")]
+    NotAContainer(String),
+
+    #[error("unsupported asm instruction `{0}`")]
+    UnsupportedAsm(String),
+
+    #[error("const `{name}` reads `{dependency}` before it is initialized")]
+    ConstUsedBeforeInit { name: String, dependency: String },
+
+    #[error("bytes slice bounds must be constants with start <= end <= 32")]
+    InvalidSlice,
+
+    #[error("`{0}` is a struct instance and cannot also be used as a storage variable")]
+    StorageCollision(String),
+
+    #[error("cannot combine string and {0} with `{1}`; strings only concatenate with other strings")]
+    MixedStringOperand(String, String),
+
+    #[error("struct `{ty}` has no field `{field}`")]
+    UndefinedField { ty: String, field: String },
+
+    #[error("enum `{ty}` has no variant `{variant}`")]
+    UndefinedVariant { ty: String, variant: String },
+
+    #[error("modifier `{0}` has no `_` placeholder for the function body")]
+    MissingPlaceholder(String),
+
+    #[error("unsupported array `{ty}`: {reason}")]
+    UnsupportedArray { ty: String, reason: &'static str },
+
+    #[error("index {index} is out of bounds for an array of length {len}")]
+    IndexOutOfBounds { index: String, len: usize },
+
+    #[error("literal `{value}` does not fit in {ty}")]
+    LiteralOutOfRange { value: String, ty: String },
+
+    #[error("local `{0}` shadows the state variable of the same name")]
+    ShadowsState(String),
+
+    #[error("`{name}` cannot be both @{first} and @{second}")]
+    ConflictingMutability { name: String, first: String, second: String },
+
+    #[error("a returned {0} must be a literal, a string constant, a parameter or a slice; other {0} values cannot be encoded yet")]
+    UnencodableReturn(String),
+
+    #[error("string constant `{0}` can only be returned directly; it has no value anywhere else yet")]
+    StringConstUse(String),
+
+    #[error("{ty} has no method `{name}`")]
+    UndefinedMethod { ty: String, name: String },
+
+    #[error("an indexed {0} field must be a literal or a parameter; other {0} values cannot be hashed yet")]
+    UnhashableTopic(String),
+
+    #[error("a {0} argument to an external call must be a literal or a parameter; other {0} values cannot be encoded yet")]
+    UnencodableArgument(String),
+
+    #[error("`{0}` is an array parameter read from calldata; its elements cannot be assigned")]
+    CalldataWrite(String),
+
+    #[error("`{name}` returns {ty}, which an internal call cannot pass back yet")]
+    DynamicInternalReturn { name: String, ty: String },
+
+    #[error("a returned tuple cannot hold {0} yet")]
+    DynamicTupleReturn(String),
+
+    #[error("modifier `{modifier}` runs code after `_`, which a function returning {ty} cannot do yet")]
+    CodeAfterDynamicReturn { modifier: String, ty: String },
+
+    #[error("{0}")]
+    Warning(TypeWarning),
+
+    /// Another error tagged with the source range it was found in, and the
+    /// imported file holding that range.
+    #[error("{error}")]
+    At { error: Box<TypeError>, span: Span, file: Option<PathBuf> },
+}
+
+impl TypeError {
+    /// The error without its location.
+    pub fn kind(&self) -> &TypeError {
+        match self {
+            TypeError::At { error, .. } => error.kind(),
+            e => e,
+        }
+    }
+
+    /// Stable identifier, like `E2002` for a type mismatch.
+    pub fn code(&self) -> &'static str {
+        match self {
+            TypeError::Undefined(..) => "E2001",
+            TypeError::Mismatch { .. } => "E2002",
+            TypeError::BinaryOp { .. } => "E2003",
+            TypeError::RequireBool(..) => "E2004",
+            TypeError::ReturnMismatch { .. } => "E2005",
+            TypeError::IndexNonMapping(..) => "E2006",
+            TypeError::Duplicate { .. } => "E2007",
+            TypeError::NonBoolCondition { .. } => "E2008",
+            TypeError::UnsupportedIterable => "E2009",
+            TypeError::RangeArity(..) => "E2010",
+            TypeError::NonConstantRangeStep => "E2011",
+            TypeError::ZeroRangeStep => "E2012",
+            TypeError::DynamicMappingKey(..) => "E2013",
+            TypeError::TupleArity { .. } => "E2014",
+            TypeError::CallArity { .. } => "E2015",
+            TypeError::UndefinedEvent(..) => "E2016",
+            TypeError::UndefinedError(..) => "E2017",
+            TypeError::TooManyIndexed(..) => "E2018",
+            TypeError::OutsideLoop(..) => "E2019",
+            TypeError::StateChangeInReadOnly { .. } => "E2020",
+            TypeError::UndefinedInterfaceFunction { .. } => "E2021",
+            TypeError::RecursiveCall(..) => "E2022",
+            TypeError::NotAContainer(..) => "E2023",
+            TypeError::UnsupportedAsm(..) => "E2024",
+            TypeError::ConstUsedBeforeInit { .. } => "E2025",
+            TypeError::InvalidSlice => "E2026",
+            TypeError::StorageCollision(..) => "E2027",
+            TypeError::MixedStringOperand(..) => "E2028",
+            TypeError::UndefinedField { .. } => "E2029",
+            TypeError::UndefinedVariant { .. } => "E2030",
+            TypeError::MissingPlaceholder(..) => "E2031",
+            TypeError::UnsupportedArray { .. } => "E2032",
+            TypeError::IndexOutOfBounds { .. } => "E2033",
+            TypeError::LiteralOutOfRange { .. } => "E2034",
+            TypeError::ShadowsState(..) => "E2035",
+            TypeError::ConflictingMutability { .. } => "E2036",
+            TypeError::UnencodableReturn(..) => "E2037",
+            TypeError::StringConstUse(..) => "E2038",
+            TypeError::UndefinedMethod { .. } => "E2039",
+            TypeError::UnhashableTopic(..) => "E2040",
+            TypeError::UnencodableArgument(..) => "E2041",
+            TypeError::CalldataWrite(..) => "E2042",
+            TypeError::DynamicInternalReturn { .. } => "E2043",
+            TypeError::DynamicTupleReturn(..) => "E2044",
+            TypeError::CodeAfterDynamicReturn { .. } => "E2045",
+            TypeError::Warning(w) => w.code(),
+            TypeError::At { error, .. } => error.code(),
+        }
+    }
+
+    pub fn span(&self) -> Option<&Span> {
+        match self {
+            TypeError::At { span, .. } => Some(span),
+            TypeError::Warning(w) => Some(w.span()),
+            _ => None,
+        }
+    }
+
+    /// The file the error is in, when it is not the file being compiled.
+    pub fn file(&self) -> Option<&Path> {
+        match self {
+            TypeError::At { file: Some(file), .. } | TypeError::Duplicate { file, .. } => Some(file),
+            TypeError::At { error, .. } => error.file(),
+            TypeError::Warning(w) => w.file(),
+            _ => None,
+        }
+    }
+}
+
+#[derive(thiserror::Error, Debug, Clone, PartialEq)]
+pub enum TypeWarning {
+    #[error("`require` without a message reverts with no reason; add one with `require cond, \"reason\"`")]
+    BareRevert { span: Span },
+
+    #[error("unused variable `{name}`; prefix it with `_` if this is intentional")]
+    UnusedVariable { name: String, span: Span },
+
+    #[error("result of external call is ignored; check it with `require`")]
+    UncheckedCall { span: Span },
+
+    #[error("`msg.value` is always zero in `{name}`; mark it `@payable` to accept ether")]
+    NonPayableValue { name: String, span: Span },
+
+    #[error("write to storage variable `{name}` without `self.`; write `self.{name}` to make it explicit")]
+    ImplicitStorageWrite { name: String, span: Span },
+
+    #[error("address literal `0x{literal}` fails its EIP-55 checksum; write `0x{expected}`")]
+    AddressChecksum { literal: String, expected: String, span: Span },
+
+    /// Another warning found in an imported file.
+    #[error("{warning}")]
+    In { warning: Box<TypeWarning>, file: PathBuf },
+}
+
+impl TypeWarning {
+    /// Name that `@allow(...)` takes to silence the warning.
+    pub fn lint(&self) -> &'static str {
+        match self {
+            TypeWarning::BareRevert { .. } => "bare_revert",
+            TypeWarning::UnusedVariable { .. } => "unused_variable",
+            TypeWarning::UncheckedCall { .. } => "unchecked_call",
+            TypeWarning::NonPayableValue { .. } => "nonpayable_value",
+            TypeWarning::ImplicitStorageWrite { .. } => "implicit_storage",
+            TypeWarning::AddressChecksum { .. } => "address_checksum",
+            TypeWarning::In { warning, .. } => warning.lint(),
+        }
+    }
+
+    pub fn code(&self) -> &'static str {
+        match self {
+            TypeWarning::BareRevert { .. } => "W2001",
+            TypeWarning::UnusedVariable { .. } => "W2002",
+            TypeWarning::UncheckedCall { .. } => "W2003",
+            TypeWarning::NonPayableValue { .. } => "W2004",
+            TypeWarning::ImplicitStorageWrite { .. } => "W2005",
+            TypeWarning::AddressChecksum { .. } => "W2006",
+            TypeWarning::In { warning, .. } => warning.code(),
+        }
+    }
+
+    pub fn span(&self) -> &Span {
+        match self {
+            TypeWarning::BareRevert { span }
+            | TypeWarning::UnusedVariable { span, .. }
+            | TypeWarning::UncheckedCall { span }
+            | TypeWarning::NonPayableValue { span, .. }
+            | TypeWarning::ImplicitStorageWrite { span, .. }
+            | TypeWarning::AddressChecksum { span, .. } => span,
+            TypeWarning::In { warning, .. } => warning.span(),
+        }
+    }
+
+    /// The warning without its file.
+    pub fn kind(&self) -> &TypeWarning {
+        match self {
+            TypeWarning::In { warning, .. } => warning.kind(),
+            w => w,
+        }
+    }
+
+    /// The file the warning is in, when it is not the file being compiled.
+    pub fn file(&self) -> Option<&Path> {
+        match self {
+            TypeWarning::In { file, .. } => Some(file),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct CheckOptions {
+    /// Report every warning as an error.
+    pub strict: bool,
 }
 
 struct Scope {
     vars: HashMap<String, Type>,
 }
 
+/// Parameter types and return type of a callable function.
+type Signature = (Vec<Type>, Option<Type>);
+
 struct CheckCtx {
     globals: HashMap<String, Type>,
+    /// Names declared with top-level `name: type` state syntax.
+    state: HashSet<String>,
+    /// Constants of type `string`, whose text is known at compile time.
+    string_consts: HashSet<String>,
+    /// The `string` and `bytes` parameters of the function being checked.
+    dynamic_params: HashSet<String>,
+    /// The array parameters of the function being checked, which are read
+    /// in place from calldata.
+    calldata_arrays: HashSet<String>,
+    structs: HashMap<String, Vec<(String, Type)>>,
+    enums: HashMap<String, EnumDef>,
+    functions: HashMap<String, Signature>,
+    events: HashMap<String, Vec<EventField>>,
+    /// Parameter types of each declared custom error.
+    custom_errors: HashMap<String, Vec<Type>>,
+    interfaces: HashMap<String, InterfaceDef>,
     scopes: Vec<Scope>,
     errors: Vec<TypeError>,
+    /// Innermost statement or declaration being checked, attached to
+    /// every error reported inside it, and its file if it was imported.
+    span: Option<Span>,
+    file: Option<PathBuf>,
     current_return: Option<Type>,
+    /// Name and declared mutability of the `@view` or `@pure` function
+    /// being checked.
+    read_only: Option<(String, String)>,
+    loop_depth: usize,
 }
 
 impl CheckCtx {
     fn new() -> Self {
         Self {
             globals: HashMap::with_capacity(16),
+            state: HashSet::new(),
+            string_consts: HashSet::new(),
+            dynamic_params: HashSet::new(),
+            calldata_arrays: HashSet::new(),
+            structs: HashMap::new(),
+            enums: HashMap::new(),
+            functions: HashMap::with_capacity(16),
+            events: HashMap::new(),
+            custom_errors: HashMap::new(),
+            interfaces: HashMap::new(),
             scopes: Vec::new(),
             errors: Vec::new(),
+            span: None,
+            file: None,
             current_return: None,
+            read_only: None,
+            loop_depth: 0,
         }
     }
 
@@ -60,6 +397,9 @@ impl CheckCtx {
     }
 
     fn define(&mut self, name: &str, ty: Type) {
+        if self.state.contains(name) {
+            self.err(TypeError::ShadowsState(name.to_string()));
+        }
         if let Some(scope) = self.scopes.last_mut() {
             scope.vars.insert(name.to_string(), ty);
         }
@@ -74,9 +414,39 @@ impl CheckCtx {
         self.globals.get(name)
     }
 
+    fn is_local(&self, name: &str) -> bool {
+        self.scopes.iter().any(|s| s.vars.contains_key(name))
+    }
+
+    fn state_change(&mut self) {
+        if let Some((name, mutability)) = self.read_only.clone() {
+            self.err(TypeError::StateChangeInReadOnly { name, mutability });
+        }
+    }
+
     fn err(&mut self, e: TypeError) {
+        let e = match &self.span {
+            Some(span) => TypeError::At { error: Box::new(e), span: span.clone(), file: self.file.clone() },
+            None => e,
+        };
         self.errors.push(e);
     }
+
+    /// Runs `f` with errors pointing at `span`.
+    fn at<T>(&mut self, span: &Span, f: impl FnOnce(&mut Self) -> T) -> T {
+        let outer = self.span.replace(span.clone());
+        let result = f(self);
+        self.span = outer;
+        result
+    }
+
+    /// Runs `f` with errors placed in the file of `program`'s item `index`.
+    fn in_item<T>(&mut self, program: &Program, index: usize, f: impl FnOnce(&mut Self) -> T) -> T {
+        let outer = std::mem::replace(&mut self.file, program.imported_file(index).cloned());
+        let result = f(self);
+        self.file = outer;
+        result
+    }
 }
 
 fn is_builtin(name: &str) -> bool {
@@ -84,209 +454,1262 @@ fn is_builtin(name: &str) -> bool {
 }
 
 pub fn check_program(program: &Program) -> Vec<TypeError> {
+    check_program_with_options(program, &CheckOptions::default())
+}
+
+pub fn check_program_with_options(program: &Program, options: &CheckOptions) -> Vec<TypeError> {
+    let mut errors = check_types(program);
+    if options.strict {
+        errors.extend(check_warnings(program).into_iter().map(TypeError::Warning));
+    }
+    errors
+}
+
+fn check_types(program: &Program) -> Vec<TypeError> {
     let mut ctx = CheckCtx::new();
     let layout = StorageLayout::from_program(program);
 
-    for item in &program.items {
-        if let Item::Const(c) = item {
-            ctx.globals.insert(c.name.clone(), c.type_.clone());
+    for (i, item) in program.items.iter().enumerate() {
+        match item {
+            Item::Const(c) => {
+                if c.type_ == Type::String {
+                    ctx.string_consts.insert(c.name.clone());
+                }
+                ctx.globals.insert(c.name.clone(), c.type_.clone());
+            }
+            Item::State(s) => {
+                ctx.in_item(program, i, |ctx| ctx.at(&s.name_span, |ctx| check_storage_type(ctx, &s.type_)));
+                ctx.globals.insert(s.name.clone(), s.type_.clone());
+                ctx.state.insert(s.name.clone());
+            }
+            _ => {}
+        }
+    }
+
+    check_const_order(&mut ctx, program);
+
+    for (i, item) in program.items.iter().enumerate() {
+        if let Item::Struct(s) = item {
+            for field in &s.fields {
+                ctx.in_item(program, i, |ctx| ctx.at(&field.span, |ctx| check_storage_type(ctx, &field.type_)));
+            }
+            let fields = s.fields.iter().map(|f| (f.name.clone(), f.type_.clone())).collect();
+            ctx.structs.insert(s.name.clone(), fields);
         }
     }
 
+    for name in layout.collisions() {
+        ctx.err(TypeError::StorageCollision(name.clone()));
+    }
+
     for (name, slot) in layout.iter() {
-        if !ctx.globals.contains_key(name) {
+        if !name.contains('.') && !ctx.globals.contains_key(name) {
             let ty = match slot.kind {
-                StorageKind::Mapping => Type::Map(Box::new(Type::Uint256), Box::new(Type::Uint256)),
-                StorageKind::Value => Type::Uint256,
+                StorageKind::Mapping => Type::Map(Box::new(Type::Uint(256)), Box::new(Type::Uint(256))),
+                // Arrays only come from declared types, which are already globals.
+                StorageKind::Value | StorageKind::Array | StorageKind::FixedArray(_) => Type::Uint(256),
             };
             ctx.globals.insert(name.clone(), ty);
         }
     }
 
-    for item in &program.items {
-        if let Item::Function(f) = item {
-            check_function(&mut ctx, f);
+    for (i, item) in program.items.iter().enumerate() {
+        match item {
+            Item::Function(f) => {
+                let params = f.params.iter().map(|p| p.type_.clone()).collect();
+                ctx.functions.insert(f.name.clone(), (params, f.return_type.clone()));
+            }
+            Item::Event(ev) => {
+                if ev.indexed_count() > 3 {
+                    ctx.in_item(program, i, |ctx| {
+                        ctx.at(&ev.name_span, |ctx| ctx.err(TypeError::TooManyIndexed(ev.name.clone())))
+                    });
+                }
+                ctx.events.insert(ev.name.clone(), ev.fields.clone());
+            }
+            Item::Error(e) => {
+                let params = e.params.iter().map(|p| p.type_.clone()).collect();
+                ctx.custom_errors.insert(e.name.clone(), params);
+            }
+            Item::Enum(e) => {
+                ctx.enums.insert(e.name.clone(), e.clone());
+            }
+            Item::Modifier(m) if !m.has_placeholder() => {
+                ctx.in_item(program, i, |ctx| {
+                    ctx.at(&m.name_span, |ctx| ctx.err(TypeError::MissingPlaceholder(m.name.clone())))
+                });
+            }
+            Item::Interface(iface) => {
+                ctx.interfaces.insert(iface.name.clone(), iface.clone());
+            }
+            _ => {}
         }
     }
 
-    ctx.errors
-}
-
-fn check_function(ctx: &mut CheckCtx, func: &Function) {
-    ctx.push_scope();
-    ctx.current_return = func.return_type.clone();
-
-    for p in &func.params {
-        ctx.define(&p.name, p.type_.clone());
+    for (i, item) in program.items.iter().enumerate() {
+        if let Item::Const(c) = item {
+            ctx.in_item(program, i, |ctx| ctx.at(&c.span, |ctx| infer_expression(ctx, &c.value)));
+        }
     }
 
-    check_block(ctx, &func.body);
+    for (i, item) in program.items.iter().enumerate() {
+        if let Item::Function(f) = item {
+            ctx.in_item(program, i, |ctx| ctx.at(&f.name_span, |ctx| check_function(ctx, f)));
+        }
+    }
 
-    ctx.current_return = None;
-    ctx.pop_scope();
-}
+    check_recursion(&mut ctx, program);
 
-fn check_block(ctx: &mut CheckCtx, block: &Block) {
-    for stmt in &block.statements {
-        check_statement(ctx, stmt);
-    }
+    ctx.errors
 }
 
-fn check_statement(ctx: &mut CheckCtx, stmt: &Statement) {
-    match stmt {
-        Statement::Let(l) => {
-            if let Some(val) = &l.value {
-                let val_ty = infer_expression(ctx, val);
-                if let (Some(declared), Some(inferred)) = (&l.type_, &val_ty) {
-                    if !types_compatible(declared, inferred) {
-                        ctx.err(TypeError::Mismatch {
-                            expected: fmt_type(declared),
-                            got: fmt_type(inferred),
-                        });
-                    }
-                }
-                let ty = l.type_.clone().or(val_ty).unwrap_or(Type::Uint256);
-                ctx.define(&l.name, ty);
-            } else {
-                let ty = l.type_.clone().unwrap_or(Type::Uint256);
-                ctx.define(&l.name, ty);
-            }
-        }
-        Statement::Assign(a) => {
-            let _target_ty = infer_expression(ctx, &a.target);
-            let _val_ty = infer_expression(ctx, &a.value);
+/// Advisory checks that never fail the build. A function opts out of a lint
+/// with `@allow(name)`.
+pub fn check_warnings(program: &Program) -> Vec<TypeWarning> {
+    let mut warnings = Vec::new();
+    for (i, item) in program.items.iter().enumerate() {
+        let start = warnings.len();
+        if let Item::Const(c) = item {
+            collect_bad_checksums([&c.value], &c.name_span, &mut warnings);
         }
-        Statement::Return(Some(e)) => {
-            let val_ty = infer_expression(ctx, e);
-            if let (Some(expected), Some(got)) = (&ctx.current_return, &val_ty) {
-                if !types_compatible(expected, got) {
-                    ctx.err(TypeError::ReturnMismatch {
-                        expected: fmt_type(expected),
-                        got: fmt_type(got),
+        if let Item::Function(f) = item {
+            let body = f.expanded_body();
+            if !f.allows("address_checksum") {
+                let mut exprs = Vec::new();
+                block_expressions(&body.statements, &mut exprs);
+                collect_bad_checksums(exprs, &f.name_span, &mut warnings);
+            }
+            if !f.allows("bare_revert") {
+                collect_bare_reverts(&body.statements, &mut warnings);
+            }
+            if !f.allows("unused_variable") {
+                collect_unused_variables(f, &mut warnings);
+            }
+            if !f.allows("unchecked_call") {
+                collect_unchecked_calls(program, &body.statements, &mut warnings);
+            }
+            if !f.allows("implicit_storage") {
+                let mut locals: HashSet<String> = f.params.iter().map(|p| p.name.clone()).collect();
+                locals.extend(f.return_names.iter().cloned());
+                collect_implicit_storage_writes(&body.statements, &mut locals, &mut warnings);
+            }
+            if !f.allows("nonpayable_value") && !f.is_payable() && f.name != "init" {
+                let mut exprs = Vec::new();
+                block_expressions(&body.statements, &mut exprs);
+                if exprs.into_iter().any(reads_msg_value) {
+                    warnings.push(TypeWarning::NonPayableValue {
+                        name: f.name.clone(),
+                        span: f.name_span.clone(),
                     });
                 }
             }
         }
-        Statement::Return(None) => {}
-        Statement::Require(e) => {
-            let ty = infer_expression(ctx, e);
-            if let Some(t) = &ty {
-                if !matches!(t, Type::Bool) {
-                    ctx.err(TypeError::RequireBool(fmt_type(t)));
-                }
+        if let Some(file) = program.imported_file(i) {
+            for w in &mut warnings[start..] {
+                let warning = Box::new(w.clone());
+                *w = TypeWarning::In { warning, file: file.clone() };
             }
         }
-        Statement::If(if_stmt) => {
-            let cond_ty = infer_expression(ctx, &if_stmt.condition);
-            if let Some(t) = &cond_ty {
-                if !matches!(t, Type::Bool) {
-                    ctx.err(TypeError::Mismatch {
-                        expected: "bool".into(),
-                        got: fmt_type(t),
-                    });
+    }
+    warnings
+}
+
+fn collect_bare_reverts(stmts: &[Statement], warnings: &mut Vec<TypeWarning>) {
+    for stmt in stmts {
+        match stmt {
+            Statement::Require(r) if r.message.is_none() => {
+                warnings.push(TypeWarning::BareRevert { span: r.span.clone() });
+            }
+            Statement::If(if_stmt) => {
+                collect_bare_reverts(&if_stmt.then_branch.statements, warnings);
+                if let Some(eb) = &if_stmt.else_branch {
+                    collect_bare_reverts(&eb.statements, warnings);
                 }
             }
-            check_block(ctx, &if_stmt.then_branch);
-            if let Some(eb) = &if_stmt.else_branch {
-                check_block(ctx, eb);
+            Statement::For(for_stmt) => collect_bare_reverts(&for_stmt.body.statements, warnings),
+            Statement::While(while_stmt) => {
+                collect_bare_reverts(&while_stmt.body.statements, warnings)
             }
+            _ => {}
         }
-        Statement::For(for_stmt) => {
-            ctx.push_scope();
-            ctx.define(&for_stmt.var, Type::Uint256);
-            check_block(ctx, &for_stmt.body);
-            ctx.pop_scope();
-        }
-        Statement::While(while_stmt) => {
-            let cond_ty = infer_expression(ctx, &while_stmt.condition);
-            if let Some(t) = &cond_ty {
-                if !matches!(t, Type::Bool) {
-                    ctx.err(TypeError::Mismatch {
-                        expected: "bool".into(),
-                        got: fmt_type(t),
-                    });
+    }
+}
+
+/// Flags `Iface(addr).method(...)` statements whose method returns a value,
+/// since that value is usually the call's success flag.
+fn collect_unchecked_calls(program: &Program, stmts: &[Statement], warnings: &mut Vec<TypeWarning>) {
+    for stmt in stmts {
+        match stmt {
+            Statement::Expression(Expression::Call(callee, _), span) if returns_external_value(program, callee) => {
+                warnings.push(TypeWarning::UncheckedCall { span: span.clone() });
+            }
+            Statement::If(if_stmt) => {
+                collect_unchecked_calls(program, &if_stmt.then_branch.statements, warnings);
+                if let Some(eb) = &if_stmt.else_branch {
+                    collect_unchecked_calls(program, &eb.statements, warnings);
                 }
             }
-            check_block(ctx, &while_stmt.body);
-        }
-        Statement::Emit(em) => {
-            for arg in &em.args {
-                infer_expression(ctx, arg);
+            Statement::For(for_stmt) => collect_unchecked_calls(program, &for_stmt.body.statements, warnings),
+            Statement::While(while_stmt) => {
+                collect_unchecked_calls(program, &while_stmt.body.statements, warnings)
             }
-        }
-        Statement::Expression(e) => {
-            infer_expression(ctx, e);
+            _ => {}
         }
     }
 }
 
-fn infer_expression(ctx: &mut CheckCtx, expr: &Expression) -> Option<Type> {
-    match expr {
-        Expression::Number(_) | Expression::HexNumber(_) => Some(Type::Uint256),
-        Expression::Bool(_) => Some(Type::Bool),
-        Expression::String(_) => Some(Type::String),
-        Expression::Bytes(_) => Some(Type::Bytes),
-        Expression::Identifier(name) => {
-            if is_builtin(name) {
-                None
-            } else if let Some(ty) = ctx.lookup(name) {
-                Some(ty.clone())
-            } else {
-                ctx.err(TypeError::Undefined(name.clone()));
-                None
-            }
+fn returns_external_value(program: &Program, callee: &Expression) -> bool {
+    let Expression::Member(target, method) = callee else { return false };
+    let Expression::Call(iface, _) = target.as_ref() else { return false };
+    let Expression::Identifier(iface) = iface.as_ref() else { return false };
+    program.items.iter().any(|item| match item {
+        Item::Interface(def) if def.name == *iface => {
+            def.function(method).is_some_and(|f| f.return_type.is_some())
         }
-        Expression::Member(base, field) => {
-            if let Expression::Identifier(name) = base.as_ref() {
-                match (name.as_str(), field.as_str()) {
-                    ("msg", "sender") => return Some(Type::Address),
-                    ("msg", "value") => return Some(Type::Uint256),
-                    ("block", "timestamp") => return Some(Type::Uint256),
-                    ("block", "number") => return Some(Type::Uint256),
-                    _ => {}
+        _ => false,
+    })
+}
+
+fn block_expressions<'a>(stmts: &'a [Statement], out: &mut Vec<&'a Expression>) {
+    for stmt in stmts {
+        match stmt {
+            // 40 hex digits given a number type are a number, not an address.
+            Statement::Let(l)
+                if l.type_.as_ref().is_some_and(is_numeric) && matches!(l.value, Some(Expression::Address(_))) => {}
+            Statement::Let(l) => out.extend(&l.value),
+            Statement::Assign(a) => out.extend([&a.target, &a.value]),
+            Statement::Return(Some(e), _)
+            | Statement::Require(RequireStatement { condition: e, .. })
+            | Statement::Expression(e, _) => out.push(e),
+            Statement::If(if_stmt) => {
+                out.push(&if_stmt.condition);
+                block_expressions(&if_stmt.then_branch.statements, out);
+                if let Some(eb) = &if_stmt.else_branch {
+                    block_expressions(&eb.statements, out);
                 }
             }
-            infer_expression(ctx, base);
-            None
-        }
-        Expression::Index(base, key) => {
-            let base_ty = infer_expression(ctx, base);
-            infer_expression(ctx, key);
-            if let Some(Type::Map(_, v)) = base_ty {
-                Some(*v)
-            } else {
-                None
+            Statement::For(for_stmt) => {
+                out.push(&for_stmt.iterable);
+                block_expressions(&for_stmt.body.statements, out);
+            }
+            Statement::While(while_stmt) => {
+                out.push(&while_stmt.condition);
+                block_expressions(&while_stmt.body.statements, out);
             }
+            Statement::Emit(em) => out.extend(&em.args),
+            Statement::Revert(rv) => out.extend(&rv.args),
+            Statement::Return(None, _) | Statement::Asm(_) | Statement::Break(_) | Statement::Continue(_) => {}
         }
-        Expression::Binary(op, left, right) => {
-            let lt = infer_expression(ctx, left);
-            let rt = infer_expression(ctx, right);
-            infer_binary_op(ctx, op, &lt, &rt)
+    }
+}
+
+/// Address literals are located by the declaration they appear in, since
+/// expressions carry no spans of their own.
+fn collect_bad_checksums<'a>(
+    exprs: impl IntoIterator<Item = &'a Expression>,
+    span: &Span,
+    warnings: &mut Vec<TypeWarning>,
+) {
+    let mut literals = Vec::new();
+    for expr in exprs {
+        address_literals(expr, &mut literals);
+    }
+    for literal in literals {
+        let expected = checksum_address(literal);
+        if literal != expected {
+            warnings.push(TypeWarning::AddressChecksum {
+                literal: literal.to_string(),
+                expected,
+                span: span.clone(),
+            });
         }
-        Expression::Unary(op, operand) => {
-            let t = infer_expression(ctx, operand);
-            match op {
-                UnaryOp::Not => Some(Type::Bool),
-                UnaryOp::Minus => t,
-            }
+    }
+}
+
+fn address_literals<'a>(expr: &'a Expression, out: &mut Vec<&'a str>) {
+    match expr {
+        Expression::Address(digits) => out.push(digits),
+        Expression::Member(base, _) | Expression::Unary(_, base) => address_literals(base, out),
+        Expression::Binary(_, l, r) | Expression::Index(l, r) => {
+            address_literals(l, out);
+            address_literals(r, out);
         }
         Expression::Call(callee, args) => {
-            infer_expression(ctx, callee);
-            for arg in args {
-                infer_expression(ctx, arg);
-            }
-            None
+            address_literals(callee, out);
+            args.iter().for_each(|a| address_literals(a, out));
         }
-        Expression::StructInit(name, fields) => {
-            for (_, val) in fields {
-                infer_expression(ctx, val);
-            }
-            Some(Type::Custom(name.clone()))
+        Expression::Slice(base, start, end) => {
+            [base, start, end].into_iter().for_each(|e| address_literals(e, out));
         }
+        Expression::Tuple(items) => items.iter().for_each(|e| address_literals(e, out)),
+        Expression::StructInit(_, fields) => fields.iter().for_each(|(_, e)| address_literals(e, out)),
+        _ => {}
     }
 }
 
-fn infer_binary_op(
+/// The EIP-55 spelling of 40 hex digits: a letter is upper case when the
+/// matching nibble of the keccak hash of the lower-case digits is 8 or more.
+fn checksum_address(digits: &str) -> String {
+    let lower = digits.to_ascii_lowercase();
+    let mut hash = [0u8; 32];
+    let mut hasher = Keccak::v256();
+    hasher.update(lower.as_bytes());
+    hasher.finalize(&mut hash);
+    lower
+        .chars()
+        .enumerate()
+        .map(|(i, c)| {
+            let nibble = if i % 2 == 0 { hash[i / 2] >> 4 } else { hash[i / 2] & 0x0f };
+            if nibble >= 8 { c.to_ascii_uppercase() } else { c }
+        })
+        .collect()
+}
+
+fn reads_msg_value(expr: &Expression) -> bool {
+    match expr {
+        Expression::Member(base, field) => {
+            (field == "value" && matches!(base.as_ref(), Expression::Identifier(b) if b == "msg"))
+                || reads_msg_value(base)
+        }
+        Expression::Binary(_, l, r) | Expression::Index(l, r) => reads_msg_value(l) || reads_msg_value(r),
+        Expression::Unary(_, e) => reads_msg_value(e),
+        Expression::Call(callee, args) => reads_msg_value(callee) || args.iter().any(reads_msg_value),
+        Expression::Slice(base, start, end) => [base, start, end].into_iter().any(|e| reads_msg_value(e)),
+        Expression::Tuple(items) => items.iter().any(reads_msg_value),
+        Expression::StructInit(_, fields) => fields.iter().any(|(_, e)| reads_msg_value(e)),
+        _ => false,
+    }
+}
+
+/// Assignments to a storage variable named bare rather than as `self.name`.
+/// Locals are tracked in declaration order, so a write before a `let` of the
+/// same name still counts as storage.
+fn collect_implicit_storage_writes(
+    stmts: &[Statement],
+    locals: &mut HashSet<String>,
+    warnings: &mut Vec<TypeWarning>,
+) {
+    for stmt in stmts {
+        match stmt {
+            Statement::Let(l) => {
+                locals.insert(l.name.clone());
+            }
+            Statement::Assign(a) => {
+                let targets = match &a.target {
+                    Expression::Tuple(targets) => targets.iter().collect(),
+                    target => vec![target],
+                };
+                for target in targets {
+                    let Some(name) = root_name(target) else { continue };
+                    if !locals.contains(name) && !is_builtin(name) {
+                        warnings.push(TypeWarning::ImplicitStorageWrite {
+                            name: name.to_string(),
+                            span: a.span.clone(),
+                        });
+                    }
+                }
+            }
+            Statement::If(if_stmt) => {
+                collect_implicit_storage_writes(&if_stmt.then_branch.statements, locals, warnings);
+                if let Some(eb) = &if_stmt.else_branch {
+                    collect_implicit_storage_writes(&eb.statements, locals, warnings);
+                }
+            }
+            Statement::For(for_stmt) => {
+                locals.insert(for_stmt.var.clone());
+                collect_implicit_storage_writes(&for_stmt.body.statements, locals, warnings);
+            }
+            Statement::While(while_stmt) => {
+                collect_implicit_storage_writes(&while_stmt.body.statements, locals, warnings)
+            }
+            _ => {}
+        }
+    }
+}
+
+/// The variable at the bottom of an index and member chain.
+fn root_name(expr: &Expression) -> Option<&str> {
+    match expr {
+        Expression::Identifier(name) => Some(name),
+        Expression::Index(base, _) | Expression::Member(base, _) => root_name(base),
+        _ => None,
+    }
+}
+
+fn collect_unused_variables(f: &Function, warnings: &mut Vec<TypeWarning>) {
+    let mut declared = Vec::new();
+    let mut read = Vec::new();
+    collect_locals(&f.expanded_body().statements, &mut declared, &mut read);
+    for (name, span) in declared {
+        if !name.starts_with('_') && !read.contains(&name) && !f.return_names.contains(&name) {
+            warnings.push(TypeWarning::UnusedVariable { name, span });
+        }
+    }
+}
+
+fn collect_locals(stmts: &[Statement], declared: &mut Vec<(String, Span)>, read: &mut Vec<String>) {
+    for stmt in stmts {
+        match stmt {
+            Statement::Let(l) => {
+                if let Some(v) = &l.value {
+                    collect_identifiers(v, read);
+                }
+                declared.push((l.name.clone(), l.span.clone()));
+            }
+            Statement::Assign(a) => {
+                if !matches!(a.target, Expression::Identifier(_)) {
+                    collect_identifiers(&a.target, read);
+                }
+                collect_identifiers(&a.value, read);
+            }
+            Statement::Return(Some(e), _)
+            | Statement::Require(RequireStatement { condition: e, .. })
+            | Statement::Expression(e, _) => collect_identifiers(e, read),
+            Statement::If(if_stmt) => {
+                collect_identifiers(&if_stmt.condition, read);
+                collect_locals(&if_stmt.then_branch.statements, declared, read);
+                if let Some(eb) = &if_stmt.else_branch {
+                    collect_locals(&eb.statements, declared, read);
+                }
+            }
+            Statement::For(for_stmt) => {
+                collect_identifiers(&for_stmt.iterable, read);
+                collect_locals(&for_stmt.body.statements, declared, read);
+            }
+            Statement::While(while_stmt) => {
+                collect_identifiers(&while_stmt.condition, read);
+                collect_locals(&while_stmt.body.statements, declared, read);
+            }
+            Statement::Emit(em) => em.args.iter().for_each(|a| collect_identifiers(a, read)),
+            Statement::Revert(rv) => rv.args.iter().for_each(|a| collect_identifiers(a, read)),
+            Statement::Return(None, _) | Statement::Asm(_) | Statement::Break(_) | Statement::Continue(_) => {}
+        }
+    }
+}
+
+fn check_const_order(ctx: &mut CheckCtx, program: &Program) {
+    let consts: Vec<_> = program
+        .items
+        .iter()
+        .enumerate()
+        .filter_map(|(index, item)| match item {
+            Item::Const(c) => Some((index, c)),
+            _ => None,
+        })
+        .collect();
+    for (i, &(index, c)) in consts.iter().enumerate() {
+        let mut names = Vec::new();
+        collect_identifiers(&c.value, &mut names);
+        for (_, later) in &consts[i..] {
+            if names.contains(&later.name) {
+                ctx.in_item(program, index, |ctx| {
+                    ctx.at(&c.name_span, |ctx| {
+                        ctx.err(TypeError::ConstUsedBeforeInit {
+                            name: c.name.clone(),
+                            dependency: later.name.clone(),
+                        })
+                    })
+                });
+            }
+        }
+    }
+}
+
+fn collect_identifiers(expr: &Expression, names: &mut Vec<String>) {
+    match expr {
+        Expression::Identifier(name) => names.push(name.clone()),
+        Expression::Binary(_, l, r) | Expression::Index(l, r) => {
+            collect_identifiers(l, names);
+            collect_identifiers(r, names);
+        }
+        Expression::Unary(_, e) | Expression::Member(e, _) => collect_identifiers(e, names),
+        Expression::Call(callee, args) => {
+            collect_identifiers(callee, names);
+            args.iter().for_each(|a| collect_identifiers(a, names));
+        }
+        Expression::Slice(base, start, end) => {
+            [base, start, end].into_iter().for_each(|e| collect_identifiers(e, names))
+        }
+        Expression::Tuple(items) => items.iter().for_each(|e| collect_identifiers(e, names)),
+        Expression::StructInit(_, fields) => {
+            fields.iter().for_each(|(_, e)| collect_identifiers(e, names))
+        }
+        _ => {}
+    }
+}
+
+fn check_recursion(ctx: &mut CheckCtx, program: &Program) {
+    let mut graph: HashMap<&str, Vec<String>> = HashMap::new();
+    for item in &program.items {
+        if let Item::Function(f) = item {
+            let mut calls = Vec::new();
+            collect_block_calls(&f.expanded_body(), &mut calls);
+            calls.retain(|c| ctx.functions.contains_key(c));
+            graph.insert(&f.name, calls);
+        }
+    }
+
+    for (i, item) in program.items.iter().enumerate() {
+        if let Item::Function(f) = item {
+            let mut stack = vec![f.name.as_str()];
+            let mut seen: Vec<&str> = Vec::new();
+            while let Some(name) = stack.pop() {
+                let callees = graph.get(name).into_iter().flatten();
+                if callees.clone().any(|c| c == &f.name) {
+                    ctx.in_item(program, i, |ctx| {
+                        ctx.at(&f.name_span, |ctx| ctx.err(TypeError::RecursiveCall(f.name.clone())))
+                    });
+                    break;
+                }
+                for callee in callees {
+                    if !seen.contains(&callee.as_str()) {
+                        seen.push(callee);
+                        stack.push(callee);
+                    }
+                }
+            }
+        }
+    }
+}
+
+fn collect_block_calls(block: &Block, calls: &mut Vec<String>) {
+    for stmt in &block.statements {
+        match stmt {
+            Statement::Let(l) => {
+                if let Some(v) = &l.value {
+                    collect_calls(v, calls);
+                }
+            }
+            Statement::Assign(a) => {
+                collect_calls(&a.target, calls);
+                collect_calls(&a.value, calls);
+            }
+            Statement::Return(Some(e), _)
+            | Statement::Require(RequireStatement { condition: e, .. })
+            | Statement::Expression(e, _) => {
+                collect_calls(e, calls);
+            }
+            Statement::Return(None, _) | Statement::Asm(_) | Statement::Break(_) | Statement::Continue(_) => {}
+            Statement::If(if_stmt) => {
+                collect_calls(&if_stmt.condition, calls);
+                collect_block_calls(&if_stmt.then_branch, calls);
+                if let Some(eb) = &if_stmt.else_branch {
+                    collect_block_calls(eb, calls);
+                }
+            }
+            Statement::For(for_stmt) => {
+                collect_calls(&for_stmt.iterable, calls);
+                collect_block_calls(&for_stmt.body, calls);
+            }
+            Statement::While(while_stmt) => {
+                collect_calls(&while_stmt.condition, calls);
+                collect_block_calls(&while_stmt.body, calls);
+            }
+            Statement::Emit(em) => {
+                for arg in &em.args {
+                    collect_calls(arg, calls);
+                }
+            }
+            Statement::Revert(rv) => {
+                for arg in &rv.args {
+                    collect_calls(arg, calls);
+                }
+            }
+        }
+    }
+}
+
+fn collect_calls(expr: &Expression, calls: &mut Vec<String>) {
+    match expr {
+        Expression::Call(callee, args) => {
+            if let Expression::Identifier(name) = callee.as_ref() {
+                calls.push(name.clone());
+            }
+            args.iter().for_each(|a| collect_calls(a, calls));
+        }
+        Expression::Binary(_, l, r) | Expression::Index(l, r) => {
+            collect_calls(l, calls);
+            collect_calls(r, calls);
+        }
+        Expression::Unary(_, e) | Expression::Member(e, _) => collect_calls(e, calls),
+        Expression::Slice(base, start, end) => {
+            [base, start, end].into_iter().for_each(|e| collect_calls(e, calls))
+        }
+        Expression::Tuple(items) => items.iter().for_each(|e| collect_calls(e, calls)),
+        Expression::StructInit(_, fields) => fields.iter().for_each(|(_, e)| collect_calls(e, calls)),
+        _ => {}
+    }
+}
+
+fn check_function(ctx: &mut CheckCtx, func: &Function) {
+    if let [first, rest @ ..] = func.mutability_decorators().as_slice() {
+        if let Some(second) = rest.iter().find(|m| *m != first) {
+            ctx.err(TypeError::ConflictingMutability {
+                name: func.name.clone(),
+                first: first.to_string(),
+                second: second.to_string(),
+            });
+        }
+    }
+
+    ctx.push_scope();
+    ctx.current_return = func.return_type.clone();
+    ctx.read_only = func
+        .mutability_decorators()
+        .into_iter()
+        .find(|m| *m != "payable")
+        .map(|m| (func.name.clone(), m.to_string()));
+
+    for p in &func.params {
+        if is_array(&p.type_) {
+            check_storage_type(ctx, &p.type_);
+            ctx.calldata_arrays.insert(p.name.clone());
+        }
+        if matches!(p.type_, Type::String | Type::Bytes) {
+            ctx.dynamic_params.insert(p.name.clone());
+        }
+        ctx.define(&p.name, p.type_.clone());
+    }
+    if let Some(ty) = func.return_type.as_ref().filter(|t| is_array(t)) {
+        ctx.err(TypeError::UnsupportedArray {
+            ty: fmt_type(ty),
+            reason: "functions cannot return arrays yet",
+        });
+    }
+    if let Some(Type::Tuple(items)) = &func.return_type {
+        if let Some(ty) = items.iter().find(|t| matches!(t, Type::String | Type::Bytes)) {
+            ctx.err(TypeError::DynamicTupleReturn(fmt_type(ty)));
+        }
+    }
+    if let Some(ty @ (Type::String | Type::Bytes)) = &func.return_type {
+        for m in func.modifiers.iter().filter(|m| m.has_code_after_placeholder()) {
+            ctx.err(TypeError::CodeAfterDynamicReturn { modifier: m.name.clone(), ty: fmt_type(ty) });
+        }
+    }
+    for (name, ty) in func.named_returns() {
+        if matches!(ty, Type::String | Type::Bytes) {
+            ctx.err(TypeError::UnencodableReturn(fmt_type(ty)));
+        }
+        ctx.define(name, ty.clone());
+    }
+
+    check_block(ctx, &func.expanded_body());
+
+    ctx.dynamic_params.clear();
+    ctx.calldata_arrays.clear();
+    ctx.current_return = None;
+    ctx.read_only = None;
+    ctx.pop_scope();
+}
+
+fn check_block(ctx: &mut CheckCtx, block: &Block) {
+    for stmt in &block.statements {
+        check_statement(ctx, stmt);
+    }
+}
+
+fn check_statement(ctx: &mut CheckCtx, stmt: &Statement) {
+    ctx.at(stmt.span(), |ctx| check_statement_at(ctx, stmt));
+}
+
+fn check_statement_at(ctx: &mut CheckCtx, stmt: &Statement) {
+    match stmt {
+        Statement::Let(l) => {
+            if let Some(val) = &l.value {
+                let val_ty = infer_expression(ctx, val);
+                if let Some(declared) = &l.type_ {
+                    check_literal_range(ctx, declared, val);
+                }
+                if let (Some(declared), Some(inferred)) = (&l.type_, &val_ty) {
+                    if !accepts(declared, val, inferred) {
+                        ctx.err(TypeError::Mismatch {
+                            expected: fmt_type(declared),
+                            got: fmt_type(inferred),
+                        });
+                    }
+                }
+                let ty = l.type_.clone().or(val_ty).unwrap_or(Type::Uint(256));
+                if is_array(&ty) {
+                    ctx.err(TypeError::UnsupportedArray {
+                        ty: fmt_type(&ty),
+                        reason: "arrays live in storage or calldata and are read by index",
+                    });
+                }
+                ctx.define(&l.name, ty);
+            } else {
+                let ty = l.type_.clone().unwrap_or(Type::Uint(256));
+                ctx.define(&l.name, ty);
+            }
+        }
+        Statement::Assign(a) => {
+            if writes_storage(ctx, &a.target) {
+                ctx.state_change();
+            }
+            let targets = match &a.target {
+                Expression::Tuple(targets) => targets.iter().collect(),
+                target => vec![target],
+            };
+            for target in targets {
+                if let Expression::Index(base, _) = target {
+                    if let Expression::Identifier(name) = base.as_ref() {
+                        if ctx.calldata_arrays.contains(name) {
+                            ctx.err(TypeError::CalldataWrite(name.clone()));
+                        }
+                    }
+                }
+            }
+            let target_ty = infer_expression(ctx, &a.target);
+            if let Some(ty) = &target_ty {
+                check_literal_range(ctx, ty, &a.value);
+            }
+            let val_ty = infer_expression(ctx, &a.value);
+            let expected = match &a.target {
+                Expression::Tuple(targets) => targets.len(),
+                _ => 1,
+            };
+            let got = match &val_ty {
+                Some(Type::Tuple(items)) => items.len(),
+                _ => 1,
+            };
+            if val_ty.is_some() && expected != got {
+                ctx.err(TypeError::TupleArity { expected, got });
+            }
+            // Only enum assignments are checked, so a variant is never mixed
+            // with a plain integer or another enum.
+            if let (Some(t), Some(v)) = (&target_ty, &val_ty) {
+                if t != v && (matches!(t, Type::Enum(_)) || matches!(v, Type::Enum(_))) {
+                    ctx.err(TypeError::Mismatch { expected: fmt_type(t), got: fmt_type(v) });
+                }
+            }
+        }
+        Statement::Return(Some(e), _) => {
+            if let Some(expected) = ctx.current_return.clone() {
+                check_literal_range(ctx, &expected, e);
+            }
+            let val_ty = if is_string_const(ctx, e) {
+                Some(Type::String)
+            } else {
+                infer_expression(ctx, e)
+            };
+            if let (Some(expected), Some(got)) = (&ctx.current_return, &val_ty) {
+                if !accepts(expected, e, got) {
+                    ctx.err(TypeError::ReturnMismatch {
+                        expected: fmt_type(expected),
+                        got: fmt_type(got),
+                    });
+                }
+            }
+            if let Some(ty @ (Type::String | Type::Bytes)) = ctx.current_return.clone() {
+                if !is_encodable_return(ctx, e) {
+                    ctx.err(TypeError::UnencodableReturn(fmt_type(&ty)));
+                }
+            }
+        }
+        Statement::Return(None, _) => {}
+        Statement::Require(r) => {
+            let ty = infer_expression(ctx, &r.condition);
+            if let Some(t) = &ty {
+                if is_numeric(t) {
+                    ctx.err(non_bool_condition("require condition", t));
+                } else if !matches!(t, Type::Bool) {
+                    ctx.err(TypeError::RequireBool(fmt_type(t)));
+                }
+            }
+        }
+        Statement::If(if_stmt) => {
+            let cond_ty = infer_expression(ctx, &if_stmt.condition);
+            check_condition(ctx, "if condition", &cond_ty);
+            check_block(ctx, &if_stmt.then_branch);
+            if let Some(eb) = &if_stmt.else_branch {
+                check_block(ctx, eb);
+            }
+        }
+        Statement::For(for_stmt) => {
+            check_range(ctx, for_stmt);
+            ctx.push_scope();
+            ctx.define(&for_stmt.var, Type::Uint(256));
+            ctx.loop_depth += 1;
+            check_block(ctx, &for_stmt.body);
+            ctx.loop_depth -= 1;
+            ctx.pop_scope();
+        }
+        Statement::While(while_stmt) => {
+            let cond_ty = infer_expression(ctx, &while_stmt.condition);
+            check_condition(ctx, "while condition", &cond_ty);
+            ctx.loop_depth += 1;
+            check_block(ctx, &while_stmt.body);
+            ctx.loop_depth -= 1;
+        }
+        Statement::Emit(em) => {
+            ctx.state_change();
+            match ctx.events.get(&em.name).cloned() {
+                Some(fields) => {
+                    let types: Vec<Type> = fields.iter().map(|f| f.type_.clone()).collect();
+                    check_args(ctx, &em.name, &types, &em.args);
+                    for (field, arg) in fields.iter().zip(&em.args) {
+                        // A string constant is already reported as `StringConstUse`.
+                        let dynamic = matches!(field.type_, Type::String | Type::Bytes);
+                        let hashable = has_known_contents(ctx, arg) || is_string_const(ctx, arg);
+                        if field.indexed && dynamic && !hashable {
+                            ctx.err(TypeError::UnhashableTopic(fmt_type(&field.type_)));
+                        }
+                    }
+                }
+                None => {
+                    ctx.err(TypeError::UndefinedEvent(em.name.clone()));
+                    for arg in &em.args {
+                        infer_expression(ctx, arg);
+                    }
+                }
+            }
+        }
+        Statement::Revert(rv) => match ctx.custom_errors.get(&rv.name).cloned() {
+            Some(params) => check_args(ctx, &rv.name, &params, &rv.args),
+            None => {
+                ctx.err(TypeError::UndefinedError(rv.name.clone()));
+                for arg in &rv.args {
+                    infer_expression(ctx, arg);
+                }
+            }
+        },
+        Statement::Expression(e, _) => {
+            infer_expression(ctx, e);
+        }
+        Statement::Asm(block) => {
+            for ins in &block.instructions {
+                match asm_op(&ins.mnemonic, ins.immediate.as_ref()) {
+                    Some(IrOp::SStore | IrOp::Log(_) | IrOp::Call) => ctx.state_change(),
+                    Some(_) => {}
+                    None => {
+                        let text = match &ins.immediate {
+                            Some(n) => format!("{} 0x{n:x}", ins.mnemonic),
+                            None => ins.mnemonic.clone(),
+                        };
+                        ctx.err(TypeError::UnsupportedAsm(text));
+                    }
+                }
+            }
+        }
+        Statement::Break(_) | Statement::Continue(_) => {
+            if ctx.loop_depth == 0 {
+                let keyword = if matches!(stmt, Statement::Break(_)) { "break" } else { "continue" };
+                ctx.err(TypeError::OutsideLoop(keyword));
+            }
+        }
+    }
+}
+
+/// Whether lowering can ABI-encode `value` as a returned `string` or
+/// `bytes`. The encoding needs the length, which only these forms carry.
+fn is_encodable_return(ctx: &CheckCtx, value: &Expression) -> bool {
+    match value {
+        Expression::String(_) | Expression::Bytes(_) | Expression::Slice(..) => true,
+        Expression::Identifier(name) => ctx.dynamic_params.contains(name) || is_string_const(ctx, value),
+        _ => false,
+    }
+}
+
+/// Whether lowering can read the contents of a `string` or `bytes` value,
+/// to hash it into a topic or encode it as an external call argument.
+fn has_known_contents(ctx: &CheckCtx, value: &Expression) -> bool {
+    match value {
+        Expression::String(_) | Expression::Bytes(_) => true,
+        Expression::Identifier(name) => ctx.dynamic_params.contains(name),
+        _ => false,
+    }
+}
+
+/// Whether `value` names a `string` constant that no local shadows. Its
+/// text is only inlined where it is returned directly.
+fn is_string_const(ctx: &CheckCtx, value: &Expression) -> bool {
+    matches!(value, Expression::Identifier(name) if ctx.string_consts.contains(name) && !ctx.is_local(name))
+}
+
+/// True when assigning to `target` writes storage: its root name is not a
+/// parameter or local, so the storage layout owns it.
+fn writes_storage(ctx: &CheckCtx, target: &Expression) -> bool {
+    match target {
+        Expression::Identifier(name) => !ctx.is_local(name),
+        Expression::Index(base, _) | Expression::Member(base, _) => writes_storage(ctx, base),
+        Expression::Tuple(targets) => targets.iter().any(|t| writes_storage(ctx, t)),
+        _ => false,
+    }
+}
+
+/// Mapping keys must be value types, and arrays hold value types and sit
+/// directly in a slot rather than inside a mapping.
+fn check_storage_type(ctx: &mut CheckCtx, ty: &Type) {
+    match ty {
+        Type::Map(key, value) => {
+            if matches!(**key, Type::Bytes | Type::String | Type::Vec(_) | Type::Array(..) | Type::Map(_, _)) {
+                ctx.err(TypeError::DynamicMappingKey(fmt_type(key)));
+            }
+            if matches!(**value, Type::Vec(_) | Type::Array(..)) {
+                ctx.err(TypeError::UnsupportedArray {
+                    ty: fmt_type(value),
+                    reason: "arrays cannot be mapping values",
+                });
+            }
+            check_storage_type(ctx, value);
+        }
+        Type::Vec(elem) | Type::Array(elem, _) if !is_value_type(elem) => {
+            ctx.err(TypeError::UnsupportedArray {
+                ty: fmt_type(ty),
+                reason: "elements must be value types",
+            });
+        }
+        _ => {}
+    }
+}
+
+fn is_value_type(ty: &Type) -> bool {
+    matches!(
+        ty,
+        Type::Uint(_) | Type::Int(_) | Type::Bool | Type::Address | Type::FixedBytes(_) | Type::Enum(_)
+    )
+}
+
+fn is_array(ty: &Type) -> bool {
+    matches!(ty, Type::Vec(_) | Type::Array(..))
+}
+
+fn check_range(ctx: &mut CheckCtx, for_stmt: &ForStatement) {
+    let Some(args) = for_stmt.range_args() else {
+        ctx.err(TypeError::UnsupportedIterable);
+        return;
+    };
+    if args.is_empty() || args.len() > 3 {
+        ctx.err(TypeError::RangeArity(args.len()));
+    }
+    for arg in args.iter().take(2) {
+        if let Some(t) = infer_expression(ctx, arg) {
+            if !is_numeric(&t) {
+                ctx.err(TypeError::Mismatch {
+                    expected: "uint256".into(),
+                    got: fmt_type(&t),
+                });
+            }
+        }
+    }
+    if let Some(step) = args.get(2) {
+        match signed_constant(step) {
+            None => ctx.err(TypeError::NonConstantRangeStep),
+            Some((_, n)) if n.bits() == 0 => ctx.err(TypeError::ZeroRangeStep),
+            Some(_) => {}
+        }
+    }
+}
+
+fn check_condition(ctx: &mut CheckCtx, context: &str, ty: &Option<Type>) {
+    if let Some(t) = ty {
+        if is_numeric(t) {
+            ctx.err(non_bool_condition(context, t));
+        } else if !matches!(t, Type::Bool) {
+            ctx.err(TypeError::Mismatch {
+                expected: "bool".into(),
+                got: fmt_type(t),
+            });
+        }
+    }
+}
+
+fn non_bool_condition(context: &str, ty: &Type) -> TypeError {
+    TypeError::NonBoolCondition {
+        context: context.to_string(),
+        got: fmt_type(ty),
+    }
+}
+
+fn infer_expression(ctx: &mut CheckCtx, expr: &Expression) -> Option<Type> {
+    match expr {
+        Expression::Number(n) | Expression::HexNumber(n) => {
+            if n.bits() > 256 {
+                ctx.err(TypeError::LiteralOutOfRange { value: n.to_string(), ty: "uint256".into() });
+            }
+            Some(Type::Uint(256))
+        }
+        Expression::Bool(_) => Some(Type::Bool),
+        Expression::Address(_) => Some(Type::Address),
+        Expression::String(_) => Some(Type::String),
+        Expression::Bytes(_) => Some(Type::Bytes),
+        Expression::Identifier(name) => {
+            if is_builtin(name) {
+                None
+            } else if is_string_const(ctx, expr) {
+                ctx.err(TypeError::StringConstUse(name.clone()));
+                Some(Type::String)
+            } else if let Some(ty) = ctx.lookup(name) {
+                Some(ty.clone())
+            } else {
+                ctx.err(TypeError::Undefined(name.clone()));
+                None
+            }
+        }
+        Expression::Member(base, field) => {
+            if let Expression::Identifier(name) = base.as_ref() {
+                match (name.as_str(), field.as_str()) {
+                    ("msg", "sender") => return Some(Type::Address),
+                    ("msg", "value") => return Some(Type::Uint(256)),
+                    ("block", "timestamp") => return Some(Type::Uint(256)),
+                    ("block", "number") => return Some(Type::Uint(256)),
+                    ("self", _) => {
+                        let ty = ctx.globals.get(field).cloned();
+                        if ty.is_none() {
+                            ctx.err(TypeError::Undefined(field.clone()));
+                        }
+                        return ty;
+                    }
+                    _ => {}
+                }
+                if let Some(def) = ctx.enums.get(name).filter(|_| ctx.lookup(name).is_none()) {
+                    if def.variant(field).is_none() {
+                        ctx.err(TypeError::UndefinedVariant { ty: name.clone(), variant: field.clone() });
+                        return None;
+                    }
+                    return Some(Type::Enum(name.clone()));
+                }
+            }
+            match infer_expression(ctx, base) {
+                Some(Type::Vec(_) | Type::Array(..)) if field == "length" => Some(Type::Uint(256)),
+                Some(Type::Custom(name) | Type::Generic(name, _)) => {
+                    let fields = ctx.structs.get(&name)?;
+                    let ty = fields.iter().find(|(f, _)| f == field).map(|(_, ty)| ty.clone());
+                    if ty.is_none() {
+                        ctx.err(TypeError::UndefinedField { ty: name, field: field.clone() });
+                    }
+                    ty
+                }
+                _ => None,
+            }
+        }
+        Expression::Index(base, key) => {
+            let base_ty = infer_expression(ctx, base);
+            let key_ty = infer_expression(ctx, key);
+            match base_ty {
+                Some(Type::Map(_, v)) => Some(*v),
+                Some(Type::Bytes | Type::FixedBytes(_)) => {
+                    if let Some(t) = key_ty.filter(|t| !is_numeric(t)) {
+                        ctx.err(TypeError::Mismatch {
+                            expected: "uint256".into(),
+                            got: fmt_type(&t),
+                        });
+                    }
+                    Some(Type::Uint(8))
+                }
+                Some(Type::Vec(elem)) => {
+                    check_array_index(ctx, &key_ty, key, None);
+                    Some(*elem)
+                }
+                Some(Type::Array(elem, len)) => {
+                    check_array_index(ctx, &key_ty, key, Some(len));
+                    Some(*elem)
+                }
+                _ => None,
+            }
+        }
+        Expression::Slice(base, start, end) => {
+            let base_ty = infer_expression(ctx, base);
+            if let Some(t) = base_ty.filter(|t| !matches!(t, Type::Bytes | Type::FixedBytes(_))) {
+                ctx.err(TypeError::Mismatch {
+                    expected: "bytes".into(),
+                    got: fmt_type(&t),
+                });
+            }
+            if slice_bounds(start, end).is_none() {
+                ctx.err(TypeError::InvalidSlice);
+            }
+            Some(Type::Bytes)
+        }
+        Expression::Binary(op, left, right) => {
+            let lt = infer_expression(ctx, left);
+            let rt = infer_expression(ctx, right);
+            infer_binary_op(ctx, op, &lt, &rt)
+        }
+        Expression::Unary(op, operand) => {
+            let t = infer_expression(ctx, operand);
+            match op {
+                UnaryOp::Not => Some(Type::Bool),
+                UnaryOp::Minus => t,
+            }
+        }
+        Expression::Call(callee, args) => {
+            if let Expression::Identifier(name) = callee.as_ref() {
+                if let Some((params, ret)) = ctx.functions.get(name).cloned() {
+                    check_args(ctx, name, &params, args);
+                    if let Some(ty @ (Type::String | Type::Bytes)) = &ret {
+                        ctx.err(TypeError::DynamicInternalReturn { name: name.clone(), ty: fmt_type(ty) });
+                    }
+                    return ret;
+                }
+                if name == "is_contract" || ctx.interfaces.contains_key(name) {
+                    if args.len() != 1 {
+                        ctx.err(TypeError::CallArity {
+                            name: name.clone(),
+                            expected: 1,
+                            got: args.len(),
+                        });
+                    }
+                    for arg in args {
+                        if let Some(t) = infer_expression(ctx, arg).filter(|t| *t != Type::Address) {
+                            ctx.err(TypeError::Mismatch {
+                                expected: "address".into(),
+                                got: fmt_type(&t),
+                            });
+                        }
+                    }
+                    if name == "is_contract" {
+                        return Some(Type::Bool);
+                    }
+                    return Some(Type::Custom(name.clone()));
+                }
+            }
+            if let Expression::Member(target, method) = callee.as_ref() {
+                let target_ty = infer_expression(ctx, target);
+                if let (Some(Type::Vec(elem)), "push") = (&target_ty, method.as_str()) {
+                    ctx.state_change();
+                    check_args(ctx, method, &[*elem.clone()], args);
+                    return None;
+                }
+                if let Some(Type::Custom(iface)) = &target_ty {
+                    if let Some(def) = ctx.interfaces.get(iface) {
+                        let Some(f) = def.function(method).cloned() else {
+                            ctx.err(TypeError::UndefinedInterfaceFunction {
+                                interface: iface.clone(),
+                                name: method.clone(),
+                            });
+                            for arg in args {
+                                infer_expression(ctx, arg);
+                            }
+                            return None;
+                        };
+                        if !f.is_read_only() {
+                            ctx.state_change();
+                        }
+                        let params: Vec<Type> = f.params.iter().map(|p| p.type_.clone()).collect();
+                        check_args(ctx, method, &params, args);
+                        for (param, arg) in params.iter().zip(args) {
+                            // A string constant is already reported as `StringConstUse`.
+                            let dynamic = matches!(param, Type::String | Type::Bytes);
+                            if dynamic && !has_known_contents(ctx, arg) && !is_string_const(ctx, arg) {
+                                ctx.err(TypeError::UnencodableArgument(fmt_type(param)));
+                            }
+                        }
+                        return f.return_type;
+                    }
+                }
+                // A generic parameter's methods come from its bound, which
+                // is not checked yet.
+                let generic = matches!(&target_ty, Some(Type::Custom(name))
+                    if !ctx.structs.contains_key(name) && !ctx.enums.contains_key(name));
+                if let Some(t) = target_ty.filter(|_| !generic) {
+                    ctx.err(TypeError::UndefinedMethod { ty: fmt_type(&t), name: method.clone() });
+                }
+                for arg in args {
+                    infer_expression(ctx, arg);
+                }
+                return None;
+            }
+            infer_expression(ctx, callee);
+            for arg in args {
+                infer_expression(ctx, arg);
+            }
+            None
+        }
+        Expression::Tuple(items) => {
+            let types: Vec<Option<Type>> = items.iter().map(|e| infer_expression(ctx, e)).collect();
+            types.into_iter().collect::<Option<Vec<_>>>().map(Type::Tuple)
+        }
+        Expression::StructInit(name, fields) => {
+            let declared = ctx.structs.get(name).cloned();
+            if declared.is_none() {
+                ctx.err(TypeError::Undefined(name.clone()));
+            }
+            for (field, val) in fields {
+                infer_expression(ctx, val);
+                let Some(declared) = &declared else { continue };
+                match declared.iter().find(|(f, _)| f == field) {
+                    Some((_, expected)) => check_literal_range(ctx, expected, val),
+                    None => ctx.err(TypeError::UndefinedField {
+                        ty: name.clone(),
+                        field: field.clone(),
+                    }),
+                }
+            }
+            Some(Type::Custom(name.clone()))
+        }
+    }
+}
+
+fn check_args(ctx: &mut CheckCtx, name: &str, params: &[Type], args: &[Expression]) {
+    if params.len() != args.len() {
+        ctx.err(TypeError::CallArity {
+            name: name.to_string(),
+            expected: params.len(),
+            got: args.len(),
+        });
+    }
+    for (arg, param) in args.iter().zip(params.iter().map(Some).chain(std::iter::repeat(None))) {
+        if let Some(expected) = param {
+            check_literal_range(ctx, expected, arg);
+        }
+        let arg_ty = infer_expression(ctx, arg);
+        if let (Some(expected), Some(got)) = (param, &arg_ty) {
+            if !accepts(expected, arg, got) {
+                ctx.err(TypeError::Mismatch {
+                    expected: fmt_type(expected),
+                    got: fmt_type(got),
+                });
+            }
+        }
+    }
+}
+
+fn check_array_index(ctx: &mut CheckCtx, key_ty: &Option<Type>, key: &Expression, len: Option<usize>) {
+    if let Some(t) = key_ty.as_ref().filter(|t| !is_numeric(t)) {
+        ctx.err(TypeError::Mismatch {
+            expected: "uint256".into(),
+            got: fmt_type(t),
+        });
+    }
+    if let (Some(len), Some((negative, index))) = (len, signed_constant(key)) {
+        if negative || index >= BigUint::from(len) {
+            ctx.err(TypeError::IndexOutOfBounds {
+                index: format!("{}{index}", if negative { "-" } else { "" }),
+                len,
+            });
+        }
+    }
+}
+
+/// Flags a constant that cannot be represented in the integer type it is
+/// given to. Negative constants are only accepted by signed types and
+/// `uint256`, which wraps them.
+fn check_literal_range(ctx: &mut CheckCtx, expected: &Type, value: &Expression) {
+    let Some((negative, n)) = signed_constant(value).or_else(|| match value {
+        Expression::Address(digits) => Some((false, BigUint::parse_bytes(digits.as_bytes(), 16)?)),
+        _ => None,
+    }) else {
+        return;
+    };
+    if n.bits() > 256 && matches!(value, Expression::Number(_) | Expression::HexNumber(_)) {
+        // Too wide for any type; `infer_expression` reports it.
+        return;
+    }
+    let fits = match *expected {
+        Type::Uint(bits) => (!negative || bits == 256) && n.bits() <= u64::from(bits),
+        Type::Int(bits) if negative => n <= BigUint::from(1u8) << (bits - 1),
+        Type::Int(bits) => n.bits() < u64::from(bits),
+        _ => true,
+    };
+    if !fits {
+        ctx.err(TypeError::LiteralOutOfRange {
+            value: format!("{}{n}", if negative { "-" } else { "" }),
+            ty: fmt_type(expected),
+        });
+    }
+}
+
+fn infer_binary_op(
     ctx: &mut CheckCtx,
     op: &BinaryOp,
     left: &Option<Type>,
@@ -298,20 +1721,41 @@ fn infer_binary_op(
                 if is_numeric(l) && is_numeric(r) {
                     return Some(wider_numeric(l, r));
                 }
+                match (l, r) {
+                    (Type::String, Type::String) if *op == BinaryOp::Add => {
+                        return Some(Type::String);
+                    }
+                    (Type::String, other) | (other, Type::String) if is_numeric(other) => {
+                        ctx.err(TypeError::MixedStringOperand(fmt_type(other), format!("{:?}", op)));
+                        return Some(Type::String);
+                    }
+                    _ => {}
+                }
                 ctx.err(TypeError::BinaryOp {
                     op: format!("{:?}", op),
                     left: fmt_type(l),
                     right: fmt_type(r),
                 });
             }
-            Some(Type::Uint256)
+            Some(Type::Uint(256))
+        }
+        BinaryOp::In => {
+            if let Some(r) = right {
+                if !matches!(r, Type::Map(_, _)) {
+                    ctx.err(TypeError::NotAContainer(fmt_type(r)));
+                }
+            }
+            Some(Type::Bool)
         }
         BinaryOp::Equal | BinaryOp::NotEqual => Some(Type::Bool),
         BinaryOp::Less | BinaryOp::Greater | BinaryOp::LessEqual | BinaryOp::GreaterEqual => {
             Some(Type::Bool)
         }
         BinaryOp::And | BinaryOp::Or => {
-            if let (Some(l), Some(r)) = (left, right) {
+            let context = if *op == BinaryOp::And { "`and` operand" } else { "`or` operand" };
+            if let Some(t) = [left, right].into_iter().flatten().find(|t| is_numeric(t)) {
+                ctx.err(non_bool_condition(context, t));
+            } else if let (Some(l), Some(r)) = (left, right) {
                 if !matches!(l, Type::Bool) || !matches!(r, Type::Bool) {
                     ctx.err(TypeError::BinaryOp {
                         op: format!("{:?}", op),
@@ -323,60 +1767,789 @@ fn infer_binary_op(
             Some(Type::Bool)
         }
     }
-}
+}
+
+fn is_numeric(ty: &Type) -> bool {
+    matches!(ty, Type::Uint(_) | Type::Int(_))
+}
+
+fn wider_numeric(a: &Type, b: &Type) -> Type {
+    match (a, b) {
+        (Type::Uint(x), Type::Uint(y)) => Type::Uint(*x.max(y)),
+        (Type::Int(x), Type::Int(y)) => Type::Int(*x.max(y)),
+        (Type::Uint(256), _) | (_, Type::Uint(256)) => Type::Uint(256),
+        (Type::Int(x), Type::Uint(y)) | (Type::Uint(y), Type::Int(x)) => Type::Int(*x.max(y)),
+        _ => a.clone(),
+    }
+}
+
+fn types_compatible(expected: &Type, got: &Type) -> bool {
+    if expected == got {
+        return true;
+    }
+    if is_numeric(expected) && is_numeric(got) {
+        return true;
+    }
+    if let (Type::Tuple(e), Type::Tuple(g)) = (expected, got) {
+        return e.len() == g.len() && e.iter().zip(g).all(|(e, g)| types_compatible(e, g));
+    }
+    false
+}
+
+/// Whether `value`, of type `got`, can be used where `expected` is: the
+/// types are compatible, or it is a hex literal that fits a `bytesN`.
+fn accepts(expected: &Type, value: &Expression, got: &Type) -> bool {
+    match (expected, value, got) {
+        (Type::Tuple(e), Expression::Tuple(v), Type::Tuple(g)) if e.len() == v.len() && v.len() == g.len() => {
+            e.iter().zip(v).zip(g).all(|((e, v), g)| accepts(e, v, g))
+        }
+        // 40 hex digits read as an address, but are still a number.
+        (expected, Expression::Address(_), _) if is_numeric(expected) => true,
+        _ => align_fixed_bytes(expected, value).is_some() || types_compatible(expected, got),
+    }
+}
+
+fn fmt_type(ty: &Type) -> String {
+    match ty {
+        Type::Uint(n) => format!("uint{n}"),
+        Type::Int(n) => format!("int{n}"),
+        Type::Bool => "bool".into(),
+        Type::Address => "address".into(),
+        Type::Bytes => "bytes".into(),
+        Type::FixedBytes(n) => format!("bytes{n}"),
+        Type::String => "string".into(),
+        Type::Vec(inner) => format!("{}[]", fmt_type(inner)),
+        Type::Array(inner, len) => format!("{}[{len}]", fmt_type(inner)),
+        Type::Map(k, v) => format!("Map<{},{}>", fmt_type(k), fmt_type(v)),
+        Type::Custom(name) | Type::Enum(name) => name.clone(),
+        Type::Generic(name, args) => {
+            let args_str: Vec<String> = args.iter().map(fmt_type).collect();
+            format!("{}<{}>", name, args_str.join(","))
+        }
+        Type::Tuple(items) => {
+            let items_str: Vec<String> = items.iter().map(fmt_type).collect();
+            format!("({})", items_str.join(", "))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::parse_from_source;
+
+    #[test]
+    fn errors_point_at_the_offending_statement() {
+        let src = "def f(a: uint256) -> uint256:\n    let b: bool = a\n    return a\n";
+        let errors = super::check_program(&parse_from_source(src).unwrap());
+        let span = errors[0].span().unwrap();
+        assert_eq!(&src[span.start..span.end], "let b: bool = a");
+        assert!(matches!(errors[0].kind(), TypeError::Mismatch { .. }));
+        assert_eq!(errors[0].to_string(), "type mismatch: expected bool, got uint256");
+    }
+
+    #[test]
+    fn declaration_errors_point_at_the_name() {
+        let src = "modifier guarded:\n    require msg.sender != 0x0\n";
+        let errors = check_program(&parse_from_source(src).unwrap());
+        let span = errors[0].span().unwrap();
+        assert_eq!(&src[span.start..span.end], "guarded");
+    }
+
+    #[test]
+    fn accepts_valid_function() {
+        let src = "def t(a: uint256) -> uint256: return a";
+        let program = parse_from_source(src).unwrap();
+        let errors = check_program(&program);
+        assert!(errors.is_empty());
+    }
+
+    #[test]
+    fn infers_nested_mapping_value_type() {
+        let src = "struct Book {\n    approved: mapping[address => mapping[address => bool]]\n}\n\nlet book: Book = Book {}\n\ndef ok(a: address, b: address) -> bool: return book.approved[a][b]\n\ndef bad(a: address, b: address) -> address: return book.approved[a][b]\n";
+        let errors = check_program(&parse_from_source(src).unwrap());
+        let kinds: Vec<_> = errors.iter().map(TypeError::kind).collect();
+        assert!(matches!(&kinds[..], [TypeError::ReturnMismatch { got, .. }] if got == "bool"), "{errors:?}");
+    }
+
+    #[test]
+    fn types_declared_state_variables() {
+        let src = "owner: address\napproved: mapping[address => bool]\n\ndef ok(a: address) -> bool: return approved[a]\n\ndef bad() -> uint256: return owner\n";
+        let errors = check_program(&parse_from_source(src).unwrap());
+        let kinds: Vec<_> = errors.iter().map(TypeError::kind).collect();
+        assert!(matches!(&kinds[..], [TypeError::ReturnMismatch { got, .. }] if got == "address"), "{errors:?}");
+    }
+
+    #[test]
+    fn catches_local_shadowing_state() {
+        let src = "owner: address\n\ndef t(owner: address) -> address: return owner\n\ndef u():\n    let owner = msg.sender\n";
+        let errors = check_program(&parse_from_source(src).unwrap());
+        let names: Vec<_> = errors
+            .iter()
+            .map(|e| match e.kind() {
+                TypeError::ShadowsState(n) => n.as_str(),
+                other => panic!("{other:?}"),
+            })
+            .collect();
+        assert_eq!(names, ["owner", "owner"]);
+    }
+
+    #[test]
+    fn rejects_string_returns_that_cannot_be_encoded() {
+        let src = "const NAME: string = \"pyra\"\n\nname: string\n\n\
+                   def a(s: string, b: bytes) -> string:\n    if b == \"\":\n        return NAME\n    return s\n\n\
+                   def b(data: bytes) -> bytes: return data[0:4]\n\n\
+                   def c() -> string:\n    let g = \"hi\"\n    return g\n\n\
+                   def d() -> string: return self.name\n\n\
+                   def e() -> (s: string):\n    s = \"hi\"\n";
+        let errors = check_program(&parse_from_source(src).unwrap());
+        assert_eq!(errors.len(), 3, "{errors:?}");
+        assert!(errors.iter().all(|e| matches!(e.kind(), TypeError::UnencodableReturn(ty) if ty == "string")));
+    }
+
+    #[test]
+    fn rejects_string_returns_internal_calls_cannot_carry() {
+        let src = "def greet() -> string: return \"hi\"\n\n\
+                   def tag() -> bytes: return b'01'\n\n\
+                   def both() -> (uint256, string): return 1, \"x\"\n\n\
+                   def t() -> bool:\n    let g = greet()\n    return tag() == b'01'\n";
+        let errors = check_program(&parse_from_source(src).unwrap());
+        let kinds: Vec<_> = errors.iter().map(|e| e.kind().to_string()).collect();
+        assert_eq!(
+            kinds,
+            [
+                "a returned tuple cannot hold string yet",
+                "`greet` returns string, which an internal call cannot pass back yet",
+                "`tag` returns bytes, which an internal call cannot pass back yet",
+            ],
+            "{errors:?}"
+        );
+    }
+
+    #[test]
+    fn rejects_indexed_dynamic_fields_that_cannot_be_hashed() {
+        let src = "name: string\n\nevent Named(who: indexed(string), tag: bytes)\n\n\
+                   def t(s: string):\n    emit Named(s, b'')\n    emit Named(\"x\", b'')\n    emit Named(self.name, b'')\n";
+        let errors = check_program(&parse_from_source(src).unwrap());
+        assert!(
+            matches!(&errors[..], [e] if matches!(e.kind(), TypeError::UnhashableTopic(ty) if ty == "string")),
+            "{errors:?}"
+        );
+    }
+
+    #[test]
+    fn rejects_external_call_arguments_that_cannot_be_encoded() {
+        let src = "interface INamed:\n    def rename(name: string, tag: bytes)\n\n\
+                   name: string\n\n\
+                   def t(s: string, b: bytes, who: address):\n    INamed(who).rename(s, b)\n    \
+                   INamed(who).rename(\"x\", b'01')\n    INamed(who).rename(self.name, b)\n";
+        let errors = check_program(&parse_from_source(src).unwrap());
+        assert!(
+            matches!(&errors[..], [e] if matches!(e.kind(), TypeError::UnencodableArgument(ty) if ty == "string")),
+            "{errors:?}"
+        );
+    }
+
+    #[test]
+    fn rejects_writes_to_calldata_array_elements() {
+        let src = "ids: uint256[]\n\n\
+                   def t(xs: uint256[], pair: uint256[2]):\n    xs[0] = 1\n    pair[1] += 2\n    ids.push(xs[0])\n    ids[0] = pair[0]\n";
+        let errors = check_program(&parse_from_source(src).unwrap());
+        let names: Vec<_> = errors
+            .iter()
+            .filter_map(|e| match e.kind() {
+                TypeError::CalldataWrite(name) => Some(name.as_str()),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(names, ["xs", "pair"], "{errors:?}");
+        assert_eq!(errors.len(), 2, "{errors:?}");
+    }
+
+    #[test]
+    fn rejects_unknown_method_calls() {
+        let src = "ids: uint256[]\n\ndef t(to: address):\n    to.frobnicate(1, 2)\n    ids.pop()\n    ids.push(1)\n";
+        let errors = check_program(&parse_from_source(src).unwrap());
+        let methods: Vec<_> = errors
+            .iter()
+            .filter_map(|e| match e.kind() {
+                TypeError::UndefinedMethod { ty, name } => Some((ty.as_str(), name.as_str())),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(methods, [("address", "frobnicate"), ("uint256[]", "pop")], "{errors:?}");
+    }
+
+    #[test]
+    fn rejects_string_const_outside_a_direct_return() {
+        let src = "const NAME: string = \"pyra\"\nconst COPY: string = NAME\n\n\
+                   def f(s: string) -> bool:\n    let n = NAME\n    return s == NAME\n\n\
+                   def g() -> string: return NAME\n\n\
+                   def h() -> string:\n    let NAME = 1\n    return \"x\"\n";
+        let errors = check_program(&parse_from_source(src).unwrap());
+        assert_eq!(errors.len(), 3, "{errors:?}");
+        assert!(errors.iter().all(|e| matches!(e.kind(), TypeError::StringConstUse(name) if name == "NAME")));
+    }
+
+    #[test]
+    fn accepts_hex_literal_that_fits_fixed_bytes() {
+        let src = "def f() -> uint8:\n    let sel: bytes4 = 0xa9059cbb\n    let wide: bytes2 = 0x123456\n    return sel[0]\n";
+        let errors = check_program(&parse_from_source(src).unwrap());
+        let kinds: Vec<_> = errors.iter().map(TypeError::kind).collect();
+        assert!(
+            matches!(&kinds[..], [TypeError::Mismatch { expected, .. }] if expected == "bytes2"),
+            "{errors:?}"
+        );
+    }
+
+    #[test]
+    fn hex_literals_coerce_to_fixed_bytes_in_arguments_and_returns() {
+        let src = "def pick(s: bytes4) -> bytes4: return s\n\n\
+                   def f() -> (bytes4, bytes2):\n    let s = pick(0xa9059cbb)\n    return s, 0x1234\n\n\
+                   def g() -> bytes2: return pick(0x1234)\n\n\
+                   def h() -> bytes2: return 0x123456\n";
+        let errors = check_program(&parse_from_source(src).unwrap());
+        let kinds: Vec<_> = errors.iter().map(TypeError::kind).collect();
+        assert!(
+            matches!(&kinds[..], [TypeError::ReturnMismatch { expected: a, .. }, TypeError::ReturnMismatch { expected: b, .. }]
+                if a == "bytes2" && b == "bytes2"),
+            "{errors:?}"
+        );
+    }
+
+    #[test]
+    fn checks_array_element_types_and_bounds() {
+        let src = "slots: uint256[4]\nusers: address[]\n\ndef ok(i: uint256) -> address: return users[i]\n\ndef bad():\n    slots[4] = 1\n    users.push(7)\n";
+        let errors = check_program(&parse_from_source(src).unwrap());
+        let kinds: Vec<_> = errors.iter().map(TypeError::kind).collect();
+        assert!(
+            matches!(&kinds[..], [
+                TypeError::IndexOutOfBounds { index, len: 4 },
+                TypeError::Mismatch { expected, got },
+            ] if index == "4" && expected == "address" && got == "uint256"),
+            "{errors:?}"
+        );
+    }
+
+    #[test]
+    fn rejects_arrays_outside_storage_and_calldata() {
+        let src = "lists: mapping[address => uint256[]]\n\ndef f(xs: uint256[]) -> uint256[]:\n    let ys = xs\n";
+        let errors = check_program(&parse_from_source(src).unwrap());
+        let reasons: Vec<_> = errors
+            .iter()
+            .map(|e| match e.kind() {
+                TypeError::UnsupportedArray { reason, .. } => *reason,
+                other => panic!("{other:?}"),
+            })
+            .collect();
+        assert_eq!(
+            reasons,
+            [
+                "arrays cannot be mapping values",
+                "functions cannot return arrays yet",
+                "arrays live in storage or calldata and are read by index",
+            ]
+        );
+    }
 
-fn is_numeric(ty: &Type) -> bool {
-    matches!(ty, Type::Uint256 | Type::Uint8 | Type::Int256)
-}
+    #[test]
+    fn catches_literal_out_of_range() {
+        let src = "def g(x: uint16):\n    let y = x\n\ndef f() -> int8:\n    let a: uint8 = 255\n    let b: uint8 = 256\n    let c: int8 = -128\n    g(70000)\n    return 128\n";
+        let errors = check_program(&parse_from_source(src).unwrap());
+        let out: Vec<_> = errors
+            .iter()
+            .map(|e| match e.kind() {
+                TypeError::LiteralOutOfRange { value, ty } => format!("{value}:{ty}"),
+                other => panic!("{other:?}"),
+            })
+            .collect();
+        assert_eq!(out, ["256:uint8", "70000:uint16", "128:int8"]);
+    }
 
-fn wider_numeric(a: &Type, b: &Type) -> Type {
-    match (a, b) {
-        (Type::Uint256, _) | (_, Type::Uint256) => Type::Uint256,
-        (Type::Int256, _) | (_, Type::Int256) => Type::Int256,
-        _ => a.clone(),
+    #[test]
+    fn rejects_literals_wider_than_256_bits() {
+        let src = "const BIG: uint256 = 2e77\n\ndef f() -> uint8:\n    let a: uint8 = 1e77\n    return 0\n\n\
+                   def g() -> uint256: return 1e77\n";
+        let errors = check_program(&parse_from_source(src).unwrap());
+        let out: Vec<_> = errors
+            .iter()
+            .map(|e| match e.kind() {
+                TypeError::LiteralOutOfRange { ty, .. } => ty.as_str(),
+                other => panic!("{other:?}"),
+            })
+            .collect();
+        assert_eq!(out, ["uint256", "uint8"]);
     }
-}
 
-fn types_compatible(expected: &Type, got: &Type) -> bool {
-    if expected == got {
-        return true;
+    #[test]
+    fn catches_bytes_mapping_key() {
+        let src = "struct State {\n    names: mapping[bytes => uint256]\n}\n\ndef t(k: bytes) -> uint256: return names[k]\n";
+        let program = parse_from_source(src).unwrap();
+        let errors = check_program(&program);
+        assert_eq!(errors.len(), 1);
+        assert!(matches!(errors[0].kind(), TypeError::DynamicMappingKey(k) if k == "bytes"));
     }
-    if is_numeric(expected) && is_numeric(got) {
-        return true;
+
+    #[test]
+    fn accepts_value_typed_mapping_key() {
+        let src = "struct State {\n    balances: mapping[address => uint256]\n}\n\ndef t(a: address) -> uint256: return balances[a]\n";
+        let program = parse_from_source(src).unwrap();
+        let errors = check_program(&program);
+        assert!(errors.is_empty(), "{errors:?}");
     }
-    false
-}
 
-fn fmt_type(ty: &Type) -> String {
-    match ty {
-        Type::Uint8 => "uint8".into(),
-        Type::Uint256 => "uint256".into(),
-        Type::Int256 => "int256".into(),
-        Type::Bool => "bool".into(),
-        Type::Address => "address".into(),
-        Type::Bytes => "bytes".into(),
-        Type::String => "string".into(),
-        Type::Vec(inner) => format!("Vec<{}>", fmt_type(inner)),
-        Type::Map(k, v) => format!("Map<{},{}>", fmt_type(k), fmt_type(v)),
-        Type::Custom(name) => name.clone(),
-        Type::Generic(name, args) => {
-            let args_str: Vec<String> = args.iter().map(|a| fmt_type(a)).collect();
-            format!("{}<{}>", name, args_str.join(","))
-        }
+    #[test]
+    fn checks_emit_against_event_declaration() {
+        let event = "event Transfer(from: address, to: address, amount: uint256)\n\n";
+        let ok = format!("{event}def t(to: address):\n    emit Transfer(msg.sender, to, 100)\n");
+        assert!(check_program(&parse_from_source(&ok).unwrap()).is_empty());
+
+        let short = format!("{event}def t(to: address):\n    emit Transfer(msg.sender, to)\n");
+        let errors = check_program(&parse_from_source(&short).unwrap());
+        let kinds: Vec<_> = errors.iter().map(TypeError::kind).collect();
+        assert!(matches!(&kinds[..], [TypeError::CallArity { expected: 3, got: 2, .. }]));
+
+        let wrong = format!("{event}def t(to: address):\n    emit Transfer(msg.sender, to, true)\n");
+        let errors = check_program(&parse_from_source(&wrong).unwrap());
+        let kinds: Vec<_> = errors.iter().map(TypeError::kind).collect();
+        assert!(matches!(&kinds[..], [TypeError::Mismatch { .. }]), "{errors:?}");
+
+        let unknown = "def t():\n    emit Approval(1)\n";
+        let errors = check_program(&parse_from_source(unknown).unwrap());
+        let kinds: Vec<_> = errors.iter().map(TypeError::kind).collect();
+        assert!(matches!(&kinds[..], [TypeError::UndefinedEvent(n)] if n == "Approval"));
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use crate::parser::parse_from_source;
+    #[test]
+    fn enum_assignments_use_declared_variants() {
+        let decls = "enum Status: Pending, Active\n\nstatus: Status\n\n";
+        let ok = format!("{decls}def t():\n    let s: Status = Status.Active\n    status = s\n");
+        assert!(check_program(&parse_from_source(&ok).unwrap()).is_empty());
+
+        let number = format!("{decls}def t():\n    status = 1\n");
+        let errors = check_program(&parse_from_source(&number).unwrap());
+        let kinds: Vec<_> = errors.iter().map(TypeError::kind).collect();
+        assert!(matches!(&kinds[..], [TypeError::Mismatch { .. }]), "{errors:?}");
+
+        let unknown = format!("{decls}def t():\n    status = Status.Closed\n");
+        let errors = check_program(&parse_from_source(&unknown).unwrap());
+        let kinds: Vec<_> = errors.iter().map(TypeError::kind).collect();
+        assert!(
+            matches!(&kinds[..], [TypeError::UndefinedVariant { variant, .. }] if variant == "Closed"),
+            "{errors:?}"
+        );
+    }
 
     #[test]
-    fn accepts_valid_function() {
-        let src = "def t(a: uint256) -> uint256: return a";
+    fn string_returns_cannot_resume_a_modifier() {
+        let src = "calls: uint256\n\n\
+                   modifier guarded:\n    require msg.value == 0\n    _\n\n\
+                   modifier counted:\n    if calls < 10:\n        _\n    calls += 1\n\n\
+                   @guarded\ndef a() -> string: return \"a\"\n\n\
+                   @guarded\n@counted\ndef b() -> bytes: return b'01'\n\n\
+                   @counted\ndef c() -> uint256: return 1\n";
+        let errors = check_program(&parse_from_source(src).unwrap());
+        assert!(
+            matches!(&errors[..], [e] if matches!(e.kind(), TypeError::CodeAfterDynamicReturn { modifier, ty } if modifier == "counted" && ty == "bytes")),
+            "{errors:?}"
+        );
+    }
+
+    #[test]
+    fn modifier_needs_a_placeholder() {
+        let src = "modifier guarded:\n    require msg.value == 0\n\n@guarded\ndef t():\n    return\n";
+        let errors = check_program(&parse_from_source(src).unwrap());
+        let kinds: Vec<_> = errors.iter().map(TypeError::kind).collect();
+        assert!(matches!(&kinds[..], [TypeError::MissingPlaceholder(n)] if n == "guarded"), "{errors:?}");
+    }
+
+    #[test]
+    fn checks_revert_against_error_declaration() {
+        let error = "error Unauthorized(caller: address)\n\n";
+        let ok = format!("{error}@view\ndef t():\n    revert Unauthorized(msg.sender)\n");
+        assert!(check_program(&parse_from_source(&ok).unwrap()).is_empty());
+
+        let wrong = format!("{error}def t():\n    revert Unauthorized(true)\n");
+        let errors = check_program(&parse_from_source(&wrong).unwrap());
+        let kinds: Vec<_> = errors.iter().map(TypeError::kind).collect();
+        assert!(matches!(&kinds[..], [TypeError::Mismatch { .. }]), "{errors:?}");
+
+        let unknown = "def t():\n    revert Missing()\n";
+        let errors = check_program(&parse_from_source(unknown).unwrap());
+        let kinds: Vec<_> = errors.iter().map(TypeError::kind).collect();
+        assert!(matches!(&kinds[..], [TypeError::UndefinedError(n)] if n == "Missing"));
+    }
+
+    #[test]
+    fn checks_interface_calls() {
+        let iface = "interface IERC20:\n    def transfer(to: address, amount: uint256) -> bool\n\n";
+        let ok = format!("{iface}def t(token: address) -> bool:\n    return IERC20(token).transfer(msg.sender, 1)\n");
+        assert!(check_program(&parse_from_source(&ok).unwrap()).is_empty());
+
+        let wrong = format!("{iface}def t(token: address) -> bool:\n    return IERC20(token).transfer(1, 1)\n");
+        let errors = check_program(&parse_from_source(&wrong).unwrap());
+        let kinds: Vec<_> = errors.iter().map(TypeError::kind).collect();
+        assert!(matches!(&kinds[..], [TypeError::Mismatch { .. }]), "{errors:?}");
+
+        let missing = format!("{iface}def t(token: address) -> bool:\n    return IERC20(token).approve(msg.sender, 1)\n");
+        let errors = check_program(&parse_from_source(&missing).unwrap());
+        let kinds: Vec<_> = errors.iter().map(TypeError::kind).collect();
+        assert!(matches!(&kinds[..], [TypeError::UndefinedInterfaceFunction { name, .. }] if name == "approve"));
+    }
+
+    #[test]
+    fn catches_break_outside_loop() {
+        let src = "def t():\n    if true:\n        break\n    while true:\n        continue\n";
+        let errors = check_program(&parse_from_source(src).unwrap());
+        let kinds: Vec<_> = errors.iter().map(TypeError::kind).collect();
+        assert!(matches!(&kinds[..], [TypeError::OutsideLoop("break")]), "{errors:?}");
+        let span = errors[0].span().unwrap();
+        assert_eq!(&src[span.start..span.end], "break");
+    }
+
+    #[test]
+    fn catches_too_many_indexed_fields() {
+        let src = "event E(a: indexed(uint256), b: indexed(uint256), c: indexed(uint256), d: indexed(uint256))\n";
+        let errors = check_program(&parse_from_source(src).unwrap());
+        let kinds: Vec<_> = errors.iter().map(TypeError::kind).collect();
+        assert!(matches!(&kinds[..], [TypeError::TooManyIndexed(n)] if n == "E"));
+    }
+
+    #[test]
+    fn catches_state_changes_in_view_and_pure() {
+        let src = "event Ping(n: uint256)\n\n@view\ndef bump(n: uint256) -> uint256:\n    let mut local = n\n    local += 1\n    total = local\n    return local\n\n@pure\ndef ping():\n    emit Ping(1)\n";
+        let errors = check_program(&parse_from_source(src).unwrap());
+        let names: Vec<(&str, &str)> = errors
+            .iter()
+            .map(|e| match e.kind() {
+                TypeError::StateChangeInReadOnly { name, mutability } => (name.as_str(), mutability.as_str()),
+                other => panic!("unexpected {other:?}"),
+            })
+            .collect();
+        assert_eq!(names, [("bump", "view"), ("ping", "pure")]);
+
+        let src = "@view\ndef f(a: uint256) -> uint256:\n    let mut x = a\n    for i in range(3):\n        x += i\n    return x\n";
+        assert!(check_program(&parse_from_source(src).unwrap()).is_empty());
+    }
+
+    #[test]
+    fn catches_conflicting_mutability() {
+        let src = "@view\n@payable\ndef t() -> uint256: return 1\n";
         let program = parse_from_source(src).unwrap();
         let errors = check_program(&program);
-        assert!(errors.is_empty());
+        assert_eq!(errors.len(), 1, "{errors:?}");
+        assert!(matches!(
+            errors[0].kind(),
+            TypeError::ConflictingMutability { first, second, .. } if first == "view" && second == "payable"
+        ));
+    }
+
+    #[test]
+    fn catches_string_mixed_with_number() {
+        let src = "const GREETING: string = \"hello\" + 1\n";
+        let program = parse_from_source(src).unwrap();
+        let errors = check_program(&program);
+        assert_eq!(errors.len(), 1, "{errors:?}");
+        assert!(matches!(errors[0].kind(), TypeError::MixedStringOperand(t, _) if t == "uint256"));
+    }
+
+    const DIVMOD: &str = "def divmod(a: uint256, b: uint256) -> (uint256, uint256):\n    return a / b, a % b\n\n";
+
+    #[test]
+    fn accepts_two_value_internal_call() {
+        let src = format!("{DIVMOD}def t(a: uint256):\n    (q, r) = divmod(a, 7)\n");
+        let program = parse_from_source(&src).unwrap();
+        let errors = check_program(&program);
+        assert!(errors.is_empty(), "{errors:?}");
+    }
+
+    #[test]
+    fn catches_tuple_destructuring_arity() {
+        let src = format!("{DIVMOD}def t(a: uint256):\n    (q, r, s) = divmod(a, 7)\n");
+        let program = parse_from_source(&src).unwrap();
+        let errors = check_program(&program);
+        assert_eq!(errors.len(), 1);
+        assert!(matches!(errors[0].kind(), TypeError::TupleArity { expected: 3, got: 2 }));
+    }
+
+    #[test]
+    fn catches_tuple_assigned_to_single_target() {
+        let src = format!("{DIVMOD}def t(a: uint256):\n    q = divmod(a, 7)\n");
+        let program = parse_from_source(&src).unwrap();
+        let errors = check_program(&program);
+        assert!(matches!(errors[0].kind(), TypeError::TupleArity { expected: 1, got: 2 }));
+    }
+
+    #[test]
+    fn catches_internal_call_arity() {
+        let src = format!("{DIVMOD}def t(a: uint256):\n    (q, r) = divmod(a)\n");
+        let program = parse_from_source(&src).unwrap();
+        let errors = check_program(&program);
+        assert!(matches!(errors[0].kind(), TypeError::CallArity { expected: 2, got: 1, .. }));
+    }
+
+    #[test]
+    fn catches_internal_call_argument_type() {
+        let src = format!("{DIVMOD}def t(a: uint256):\n    (q, r) = divmod(a, true)\n");
+        let program = parse_from_source(&src).unwrap();
+        let errors = check_program(&program);
+        let kinds: Vec<_> = errors.iter().map(TypeError::kind).collect();
+        assert!(matches!(&kinds[..], [TypeError::Mismatch { .. }]), "{errors:?}");
+    }
+
+    #[test]
+    fn catches_recursive_internal_call() {
+        let src = "def f(a: uint256) -> uint256: return g(a)\n\ndef g(a: uint256) -> uint256: return f(a)\n";
+        let program = parse_from_source(src).unwrap();
+        let errors = check_program(&program);
+        assert!(errors.iter().any(|e| matches!(e.kind(), TypeError::RecursiveCall(_))));
+    }
+
+    #[test]
+    fn accepts_require_membership() {
+        let src = "struct State {\n    admins: mapping[address => bool]\n}\n\ndef t():\n    require msg.sender in admins\n";
+        let program = parse_from_source(src).unwrap();
+        let errors = check_program(&program);
+        assert!(errors.is_empty(), "{errors:?}");
+    }
+
+    #[test]
+    fn catches_membership_in_non_container() {
+        let src = "def t(k: uint256, n: uint256) -> bool: return k in n";
+        let program = parse_from_source(src).unwrap();
+        let errors = check_program(&program);
+        assert_eq!(errors.len(), 1);
+        assert!(matches!(errors[0].kind(), TypeError::NotAContainer(t) if t == "uint256"));
+    }
+
+    #[test]
+    fn catches_const_forward_reference() {
+        let src = "const a: uint256 = b + 1\nconst b: uint256 = 1\n\ndef t() -> uint256: return a\n";
+        let program = parse_from_source(src).unwrap();
+        let errors = check_program(&program);
+        assert_eq!(errors.len(), 1);
+        assert!(matches!(
+            errors[0].kind(),
+            TypeError::ConstUsedBeforeInit { name, dependency } if name == "a" && dependency == "b"
+        ));
+    }
+
+    #[test]
+    fn accepts_backward_const_reference() {
+        let src = "const b: uint256 = 1\nconst a: uint256 = b + 1\nconst c: uint256 = 7\n\ndef t() -> uint256: return a + c\n";
+        let program = parse_from_source(src).unwrap();
+        let errors = check_program(&program);
+        assert!(errors.is_empty(), "{errors:?}");
+    }
+
+    #[test]
+    fn accepts_named_return_assignment() {
+        let src = "def f() -> (result: uint256):\n    result = 5\n";
+        let program = parse_from_source(src).unwrap();
+        let errors = check_program(&program);
+        assert!(errors.is_empty(), "{errors:?}");
+    }
+
+    #[test]
+    fn catches_named_return_type_mismatch() {
+        let src = "def f() -> (ok: bool):\n    let x: uint256 = ok\n";
+        let program = parse_from_source(src).unwrap();
+        let errors = check_program(&program);
+        assert!(matches!(errors[0].kind(), TypeError::Mismatch { expected, got } if expected == "uint256" && got == "bool"));
+    }
+
+    #[test]
+    fn types_bytes_index_and_slice() {
+        let src = "def t(data: bytes) -> bytes:\n    let b: uint8 = data[31]\n    return data[0:4]\n";
+        let program = parse_from_source(src).unwrap();
+        let errors = check_program(&program);
+        assert!(errors.is_empty(), "{errors:?}");
+    }
+
+    #[test]
+    fn catches_invalid_bytes_slice() {
+        let src = "def t(data: bytes, n: uint256) -> bytes:\n    let a = data[0:n]\n    return data[4:40]\n";
+        let program = parse_from_source(src).unwrap();
+        let errors = check_program(&program);
+        assert_eq!(errors.iter().filter(|e| matches!(e.kind(), TypeError::InvalidSlice)).count(), 2);
+    }
+
+    #[test]
+    fn types_is_contract_builtin() {
+        let src = "def t(to: address) -> bool: return is_contract(to)";
+        let program = parse_from_source(src).unwrap();
+        assert!(check_program(&program).is_empty());
+
+        let src = "def t(n: uint256) -> bool: return is_contract(n)";
+        let program = parse_from_source(src).unwrap();
+        let errors = check_program(&program);
+        assert!(matches!(errors[0].kind(), TypeError::Mismatch { expected, got } if expected == "address" && got == "uint256"));
+    }
+
+    #[test]
+    fn warns_on_require_without_message() {
+        let src = "def t(x: uint256):\n    require x > 0\n";
+        let program = parse_from_source(src).unwrap();
+        let warnings = check_warnings(&program);
+        assert!(matches!(&warnings[..], [TypeWarning::BareRevert { span }] if &src[span.start..span.end] == "require x > 0"));
+
+        let src = "def t(x: uint256):\n    require x > 0, \"x must be positive\"\n";
+        let program = parse_from_source(src).unwrap();
+        assert!(check_warnings(&program).is_empty());
+    }
+
+    #[test]
+    fn allow_attribute_suppresses_bare_revert() {
+        let src = "@allow(bare_revert)\ndef t(x: uint256):\n    if x > 1:\n        require x > 2\n";
+        let program = parse_from_source(src).unwrap();
+        assert!(check_warnings(&program).is_empty());
+    }
+
+    #[test]
+    fn warns_on_unused_variable() {
+        let src = "def t(a: uint256) -> uint256:\n    let x = a\n    let _y = a\n    let z = 1\n    z += 1\n    return z\n";
+        let program = parse_from_source(src).unwrap();
+        let warnings = check_warnings(&program);
+        assert!(matches!(&warnings[..], [TypeWarning::UnusedVariable { name, span }] if name == "x" && &src[span.start..span.end] == "let x = a"));
+    }
+
+    #[test]
+    fn warns_on_unchecked_external_call() {
+        let src = "interface IToken:\n    def transfer(to: address, amount: uint256) -> bool\n    def burn(amount: uint256)\n\n\
+                   def t(token: address, to: address):\n    IToken(token).transfer(to, 1)\n    IToken(token).burn(1)\n    \
+                   require IToken(token).transfer(to, 1), \"transfer failed\"\n";
+        let program = parse_from_source(src).unwrap();
+        let warnings = check_warnings(&program);
+        assert!(matches!(&warnings[..], [TypeWarning::UncheckedCall { span }] if &src[span.start..span.end] == "IToken(token).transfer(to, 1)"), "{warnings:?}");
+    }
+
+    #[test]
+    fn checked_external_call_does_not_warn() {
+        let src = "ids: uint256[]\n\ndef add(id: uint256):\n    ids.push(id)\n";
+        let program = parse_from_source(src).unwrap();
+        assert!(check_warnings(&program).is_empty());
+        let src = "interface IToken:\n    def transfer(to: address, amount: uint256) -> bool\n\n\
+                   @allow(unchecked_call)\ndef t(token: address, to: address):\n    IToken(token).transfer(to, 1)\n";
+        let program = parse_from_source(src).unwrap();
+        assert!(check_warnings(&program).is_empty());
+    }
+
+    #[test]
+    fn warns_on_msg_value_in_nonpayable_function() {
+        let src = "def t() -> uint256:\n    if msg.value > 0:\n        return 1\n    return 0\n\n@payable\ndef deposit() -> uint256: return msg.value\n";
+        let program = parse_from_source(src).unwrap();
+        let warnings = check_warnings(&program);
+        assert!(matches!(&warnings[..], [TypeWarning::NonPayableValue { name, .. }] if name == "t"));
+        let src = "@allow(nonpayable_value)\ndef t() -> uint256: return msg.value\n";
+        assert!(check_warnings(&parse_from_source(src).unwrap()).is_empty());
+    }
+
+    #[test]
+    fn warns_on_bare_storage_write() {
+        let src = "count: uint256\n\ndef t(a: uint256):\n    let x = a\n    x += 1\n    count = x\n    self.count = x\n";
+        let program = parse_from_source(src).unwrap();
+        let warnings = check_warnings(&program);
+        assert!(
+            matches!(&warnings[..], [TypeWarning::ImplicitStorageWrite { name, span }] if name == "count" && &src[span.start..span.end] == "count = x"),
+            "{warnings:?}"
+        );
+        let src = "@allow(implicit_storage)\ndef t(a: uint256):\n    count = a\n";
+        assert!(check_warnings(&parse_from_source(src).unwrap()).is_empty());
+    }
+
+    #[test]
+    fn address_literal_types_as_address_and_checks_checksum() {
+        assert_eq!(
+            checksum_address("5aaeb6053f3e94c9b9a09f33669435e7ef1beaed"),
+            "5aAeb6053F3E94C9b9A09f33669435E7Ef1BeAed"
+        );
+        let src = "const OWNER: address = 0x5aAeb6053F3E94C9b9A09f33669435E7Ef1BeAed\n\ndef t() -> bool: return msg.sender == OWNER\n";
+        let program = parse_from_source(src).unwrap();
+        assert!(check_program(&program).is_empty());
+        assert!(check_warnings(&program).is_empty());
+
+        let src = "def t(to: address) -> bool: return to != 0x5AAEB6053f3e94c9b9a09f33669435e7ef1beaed\n";
+        let program = parse_from_source(src).unwrap();
+        let warnings = check_warnings(&program);
+        assert!(
+            matches!(&warnings[..], [TypeWarning::AddressChecksum { expected, span, .. }] if expected == "5aAeb6053F3E94C9b9A09f33669435E7Ef1BeAed" && &src[span.start..span.end] == "t"),
+            "{warnings:?}"
+        );
+        let allowed = format!("@allow(address_checksum)\n{src}");
+        assert!(check_warnings(&parse_from_source(&allowed).unwrap()).is_empty());
+    }
+
+    #[test]
+    fn forty_digit_hex_literals_coerce_to_numbers() {
+        let src = "def t() -> uint256:\n    let x: uint256 = 0xffffffffffffffffffffffffffffffffffffffff\n    \
+                   let y: uint160 = 0x00000000000000000000000000000000000000aa\n    return x + y\n\n\
+                   def small() -> uint8: return 0x0000000000000000000000000000000000000100\n";
+        let program = parse_from_source(src).unwrap();
+        let errors = check_program(&program);
+        assert!(
+            matches!(&errors[..], [e] if matches!(e.kind(), TypeError::LiteralOutOfRange { ty, .. } if ty == "uint8")),
+            "{errors:?}"
+        );
+        assert!(check_warnings(&program).is_empty());
+    }
+
+    #[test]
+    fn strict_promotes_warnings_to_errors() {
+        let src = "def t(a: uint256):\n    let x = a\n";
+        let program = parse_from_source(src).unwrap();
+        assert!(check_program(&program).is_empty());
+        let errors = check_program_with_options(&program, &CheckOptions { strict: true });
+        assert!(matches!(&errors[..], [TypeError::Warning(TypeWarning::UnusedVariable { .. })]));
+    }
+
+    #[test]
+    fn loop_variable_does_not_leak() {
+        let src = "def t() -> uint256:\n    for i in range(3):\n        require i < 3\n    return i\n";
+        let program = parse_from_source(src).unwrap();
+        let errors = check_program(&program);
+        let kinds: Vec<_> = errors.iter().map(TypeError::kind).collect();
+        assert!(matches!(&kinds[..], [TypeError::Undefined(name)] if name == "i"));
+    }
+
+    #[test]
+    fn catches_struct_instance_used_as_variable() {
+        let src = "struct State {\n    paused: bool\n}\n\nlet state: State = State { paused: false }\n\ndef t():\n    state = 1\n";
+        let program = parse_from_source(src).unwrap();
+        let errors = check_program(&program);
+        assert!(matches!(&errors[..], [TypeError::StorageCollision(name)] if name == "state"));
+    }
+
+    #[test]
+    fn types_struct_instance_fields() {
+        let src = "struct State {\n    owner: address\n}\n\nlet state: State = State { owner: 0 }\n\ndef t() -> bool: return state.owner == msg.sender\n";
+        let program = parse_from_source(src).unwrap();
+        assert!(check_program(&program).is_empty());
+    }
+
+    #[test]
+    fn catches_undefined_struct_field() {
+        let src = "struct User {\n    balance: uint256\n}\n\nusers: mapping[address => User]\n\ndef t(a: address) -> uint256:\n    users[a] = User { balance: 1, age: 2 }\n    return users[a].balanse\n";
+        let errors = check_program(&parse_from_source(src).unwrap());
+        let fields: Vec<_> = errors
+            .iter()
+            .map(|e| match e.kind() {
+                TypeError::UndefinedField { ty, field } => format!("{ty}.{field}"),
+                other => panic!("{other:?}"),
+            })
+            .collect();
+        assert_eq!(fields, ["User.age", "User.balanse"]);
+    }
+
+    #[test]
+    fn catches_unsupported_asm() {
+        let src = "def t():\n    asm: PUSH1 0x1ff JUMP SSTORE\n";
+        let program = parse_from_source(src).unwrap();
+        let errors = check_program(&program);
+        let msgs: Vec<String> = errors.iter().map(|e| e.to_string()).collect();
+        assert_eq!(
+            msgs,
+            ["unsupported asm instruction `PUSH1 0x1ff`", "unsupported asm instruction `JUMP`"]
+        );
     }
 
     #[test]
@@ -386,6 +2559,8 @@ mod tests {
         let errors = check_program(&program);
         assert!(!errors.is_empty());
         assert!(errors[0].to_string().contains("return type mismatch"));
+        let span = errors[0].span().unwrap();
+        assert_eq!(&src[span.start..span.end], "return a");
     }
 
     #[test]
@@ -397,6 +2572,69 @@ mod tests {
         assert!(errors[0].to_string().contains("require"));
     }
 
+    #[test]
+    fn catches_if_numeric_literal_with_hint() {
+        let src = "def t():\n    if 1:\n        let x = 1\n";
+        let program = parse_from_source(src).unwrap();
+        let errors = check_program(&program);
+        assert_eq!(errors.len(), 1);
+        assert!(matches!(errors[0].kind(), TypeError::NonBoolCondition { .. }));
+        let msg = errors[0].to_string();
+        assert!(msg.contains("if condition"));
+        assert!(msg.contains("!= 0"));
+    }
+
+    #[test]
+    fn catches_require_numeric_with_hint() {
+        let src = "def t():\n    require 1\n";
+        let program = parse_from_source(src).unwrap();
+        let errors = check_program(&program);
+        assert!(matches!(errors[0].kind(), TypeError::NonBoolCondition { .. }));
+    }
+
+    #[test]
+    fn catches_and_numeric_with_hint() {
+        let src = "def t(a: bool) -> bool: return a and 1";
+        let program = parse_from_source(src).unwrap();
+        let errors = check_program(&program);
+        assert_eq!(errors.len(), 1);
+        assert!(errors[0].to_string().contains("`and` operand"));
+    }
+
+    #[test]
+    fn accepts_range_with_negative_step() {
+        let src = "def t():\n    for i in range(10, 0, -2):\n        x = i\n";
+        let program = parse_from_source(src).unwrap();
+        assert!(check_program(&program).is_empty());
+    }
+
+    #[test]
+    fn catches_zero_range_step() {
+        let src = "def t():\n    for i in range(0, 10, 0):\n        x = i\n";
+        let program = parse_from_source(src).unwrap();
+        let errors = check_program(&program);
+        let kinds: Vec<_> = errors.iter().map(TypeError::kind).collect();
+        assert!(matches!(kinds[..], [TypeError::ZeroRangeStep]));
+    }
+
+    #[test]
+    fn catches_non_constant_range_step() {
+        let src = "def t(s: uint256):\n    for i in range(0, 10, s):\n        x = i\n";
+        let program = parse_from_source(src).unwrap();
+        let errors = check_program(&program);
+        let kinds: Vec<_> = errors.iter().map(TypeError::kind).collect();
+        assert!(matches!(kinds[..], [TypeError::NonConstantRangeStep]));
+    }
+
+    #[test]
+    fn catches_non_range_iterable() {
+        let src = "def t():\n    for i in items:\n        x = i\n";
+        let program = parse_from_source(src).unwrap();
+        let errors = check_program(&program);
+        let kinds: Vec<_> = errors.iter().map(TypeError::kind).collect();
+        assert!(matches!(kinds[..], [TypeError::UnsupportedIterable]));
+    }
+
     #[test]
     fn catches_undefined_variable() {
         let src = "def t() -> uint256: return x";
@@ -1,5 +1,5 @@
 use crate::ir::{IrModule, IrOp};
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
 
 #[derive(Debug, Clone, PartialEq)]
 pub enum VerifyError {
@@ -7,6 +7,23 @@ pub enum VerifyError {
     OrphanJumpI(usize),
     DuplicateLabel(usize),
     UnreachableCode,
+    SelectorCollision {
+        selector: [u8; 4],
+        first: String,
+        second: String,
+    },
+}
+
+impl VerifyError {
+    pub fn code(&self) -> &'static str {
+        match self {
+            Self::OrphanJump(_) => "E4001",
+            Self::OrphanJumpI(_) => "E4002",
+            Self::DuplicateLabel(_) => "E4003",
+            Self::UnreachableCode => "E4004",
+            Self::SelectorCollision { .. } => "E4005",
+        }
+    }
 }
 
 impl std::fmt::Display for VerifyError {
@@ -16,6 +33,11 @@ impl std::fmt::Display for VerifyError {
             Self::OrphanJumpI(l) => write!(f, "conditional jump to undefined label {l}"),
             Self::DuplicateLabel(l) => write!(f, "duplicate label {l}"),
             Self::UnreachableCode => write!(f, "unreachable code after terminal instruction"),
+            Self::SelectorCollision { selector, first, second } => write!(
+                f,
+                "functions `{first}` and `{second}` share selector 0x{}",
+                hex::encode(selector)
+            ),
         }
     }
 }
@@ -26,21 +48,33 @@ pub fn verify_module(module: &IrModule) -> Vec<VerifyError> {
         verify_ops(&func.ops, &mut errors);
     }
     verify_ops(&module.constructor_ops, &mut errors);
+    verify_selectors(module, &mut errors);
     errors
 }
 
+fn verify_selectors(module: &IrModule, errors: &mut Vec<VerifyError>) {
+    let mut seen: HashMap<[u8; 4], &str> = HashMap::new();
+    for func in &module.functions {
+        if let Some(first) = seen.insert(func.selector, &func.name) {
+            errors.push(VerifyError::SelectorCollision {
+                selector: func.selector,
+                first: first.to_string(),
+                second: func.name.clone(),
+            });
+        }
+    }
+}
+
 fn verify_ops(ops: &[IrOp], errors: &mut Vec<VerifyError>) {
     let mut defined_labels = HashSet::new();
     let mut referenced_labels = Vec::new();
 
     for op in ops {
         match op {
-            IrOp::JumpDest(l) => {
-                if !defined_labels.insert(*l) {
-                    errors.push(VerifyError::DuplicateLabel(*l));
-                }
+            IrOp::JumpDest(l) if !defined_labels.insert(*l) => {
+                errors.push(VerifyError::DuplicateLabel(*l));
             }
-            IrOp::Jump(l) => referenced_labels.push((*l, false)),
+            IrOp::Jump(l) | IrOp::PushLabel(l) => referenced_labels.push((*l, false)),
             IrOp::JumpI(l) => referenced_labels.push((*l, true)),
             _ => {}
         }
@@ -69,6 +103,10 @@ mod tests {
                 selector: [0; 4],
                 ops,
                 label: 0,
+                guarded: false,
+                payable: false,
+                read_only: false,
+                calldata_size: 4,
             }],
             constructor_ops: vec![],
             label_count: 1,
@@ -134,6 +172,25 @@ mod tests {
         assert!(matches!(errors[0], VerifyError::OrphanJump(42)));
     }
 
+    #[test]
+    fn selector_collision() {
+        let mut module = make_module(vec![IrOp::Stop]);
+        module.functions.push(IrFunction {
+            name: "other".into(),
+            selector: [0; 4],
+            ops: vec![IrOp::Stop],
+            label: 1,
+            guarded: false,
+            payable: false,
+            read_only: false,
+            calldata_size: 4,
+        });
+        let errors = verify_module(&module);
+        assert_eq!(errors.len(), 1);
+        assert!(matches!(&errors[0], VerifyError::SelectorCollision { first, second, .. }
+            if first == "test" && second == "other"));
+    }
+
     #[test]
     fn complex_valid_module() {
         let module = make_module(vec![
@@ -0,0 +1,1283 @@
+//! A small EVM interpreter: enough of the instruction set to run what the
+//! code generator emits, with gas metered as of Cancun. It backs `pyra
+//! test` and the crate's own execution tests.
+
+#[cfg(test)]
+use crate::ir::compute_selector;
+#[cfg(test)]
+use crate::{parse_from_source, program_to_deploy_bytecode, Item, Program};
+use num_bigint::BigUint;
+use std::collections::{HashMap, HashSet};
+use tiny_keccak::{Hasher, Keccak};
+
+const STEP_LIMIT: usize = 1_000_000;
+const MEMORY_LIMIT: usize = 1 << 20;
+
+const TX_GAS: u64 = 21_000;
+const CREATE_GAS: u64 = 32_000;
+const CODE_DEPOSIT_GAS: u64 = 200;
+const COLD_SLOAD_GAS: u64 = 2_100;
+const COLD_ACCOUNT_GAS: u64 = 2_600;
+const WARM_GAS: u64 = 100;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Outcome {
+    Return(Vec<u8>),
+    Stop,
+    Revert(Vec<u8>),
+    Fault(String),
+}
+
+impl Outcome {
+    pub fn is_success(&self) -> bool {
+        matches!(self, Outcome::Return(_) | Outcome::Stop)
+    }
+
+    pub fn output(&self) -> &[u8] {
+        match self {
+            Outcome::Return(data) | Outcome::Revert(data) => data,
+            _ => &[],
+        }
+    }
+
+    pub fn word(&self, index: usize) -> BigUint {
+        let out = self.output();
+        let mut word = [0u8; 32];
+        let start = (32 * index).min(out.len());
+        let end = (start + 32).min(out.len());
+        word[..end - start].copy_from_slice(&out[start..end]);
+        BigUint::from_bytes_be(&word)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Log {
+    pub topics: Vec<BigUint>,
+    pub data: Vec<u8>,
+}
+
+pub struct Vm {
+    pub code: Vec<u8>,
+    pub storage: HashMap<BigUint, BigUint>,
+    pub caller: BigUint,
+    pub value: BigUint,
+    pub timestamp: BigUint,
+    pub block_number: BigUint,
+    pub logs: Vec<Log>,
+    pub accounts: HashMap<BigUint, Vec<u8>>,
+    /// Gas used by the last transaction: the intrinsic cost plus every
+    /// instruction run, without refunds.
+    pub gas_used: u64,
+    transient: HashMap<BigUint, BigUint>,
+    account_storage: HashMap<BigUint, HashMap<BigUint, BigUint>>,
+    /// Storage as the transaction found it, which `SSTORE` is priced against.
+    original: HashMap<BigUint, BigUint>,
+    warm_slots: HashSet<BigUint>,
+    warm_accounts: HashSet<BigUint>,
+    /// Inside a STATICCALL, where any state change faults.
+    is_static: bool,
+    #[cfg(test)]
+    program: Option<Program>,
+}
+
+impl Vm {
+    #[cfg(test)]
+    pub fn deploy(init_code: &[u8]) -> Self {
+        Self::try_deploy(init_code).unwrap_or_else(|(other, _)| panic!("deployment failed: {other:?}"))
+    }
+
+    /// Runs `init_code` as a contract creation and keeps the code it
+    /// returns. Any other outcome is handed back with the gas used.
+    pub fn try_deploy(init_code: &[u8]) -> Result<Self, (Outcome, u64)> {
+        let mut vm = Self {
+            code: Vec::new(),
+            storage: HashMap::new(),
+            caller: BigUint::from(0xc0ffeeu32),
+            value: BigUint::default(),
+            timestamp: BigUint::from(1_700_000_000u32),
+            block_number: BigUint::from(1u8),
+            logs: Vec::new(),
+            accounts: HashMap::new(),
+            gas_used: 0,
+            transient: HashMap::new(),
+            account_storage: HashMap::new(),
+            original: HashMap::new(),
+            warm_slots: HashSet::new(),
+            warm_accounts: HashSet::new(),
+            is_static: false,
+            #[cfg(test)]
+            program: None,
+        };
+        vm.begin_transaction(CREATE_GAS, init_code);
+        match vm.execute(init_code, &[]) {
+            Outcome::Return(runtime) => {
+                vm.gas_used += CODE_DEPOSIT_GAS * runtime.len() as u64;
+                vm.code = runtime;
+                Ok(vm)
+            }
+            other => Err((other, vm.gas_used)),
+        }
+    }
+
+    #[cfg(test)]
+    pub fn from_source(source: &str) -> Self {
+        Self::from_source_with_args(source, &[])
+    }
+
+    /// Deploys `source` with ABI-encoded constructor arguments appended to
+    /// the init code.
+    #[cfg(test)]
+    pub fn from_source_with_args(source: &str, args: &[BigUint]) -> Self {
+        let program = parse_from_source(source).expect("source parses");
+        let errors = crate::check_program(&program);
+        assert!(errors.is_empty(), "type errors: {errors:?}");
+        let mut init = program_to_deploy_bytecode(&program).expect("codegen succeeds");
+        init.extend(args.iter().flat_map(to_word));
+        let mut vm = Self::deploy(&init);
+        vm.program = Some(program);
+        vm
+    }
+
+    /// Deploys `source` built with the given checks instead of the defaults.
+    #[cfg(test)]
+    pub fn from_source_with_config(source: &str, config: &crate::security::HardenConfig) -> Self {
+        let program = parse_from_source(source).expect("source parses");
+        let errors = crate::check_program(&program);
+        assert!(errors.is_empty(), "type errors: {errors:?}");
+        let init = crate::codegen::program_to_deploy_bytecode_with_config(&program, config)
+            .expect("codegen succeeds");
+        let mut vm = Self::deploy(&init);
+        vm.program = Some(program);
+        vm
+    }
+
+    /// Runs one transaction against the deployed code; transient storage
+    /// starts empty.
+    pub fn call(&mut self, calldata: &[u8]) -> Outcome {
+        self.begin_transaction(0, calldata);
+        let code = std::mem::take(&mut self.code);
+        let outcome = self.execute(&code, calldata);
+        self.code = code;
+        outcome
+    }
+
+    #[cfg(test)]
+    pub fn call_function(&mut self, name: &str, args: &[BigUint]) -> Outcome {
+        let selector = self
+            .program
+            .as_ref()
+            .and_then(|p| {
+                p.items.iter().find_map(|item| match item {
+                    Item::Function(f) if f.name == name => Some(compute_selector(f)),
+                    _ => None,
+                })
+            })
+            .unwrap_or_else(|| panic!("no function `{name}`"));
+        self.call(&calldata(selector, args))
+    }
+
+    /// Starts a transaction: transient storage and the warm sets are
+    /// cleared, and the intrinsic gas is charged for `data`.
+    fn begin_transaction(&mut self, extra_gas: u64, data: &[u8]) {
+        self.transient.clear();
+        self.warm_slots.clear();
+        self.warm_accounts.clear();
+        self.original = self.storage.clone();
+        let data_gas: u64 = data.iter().map(|&b| if b == 0 { 4 } else { 16 }).sum();
+        self.gas_used = TX_GAS + extra_gas + data_gas;
+    }
+
+    /// Runs another account's code against its own storage. Calls to
+    /// addresses without code succeed with no output, as on mainnet. A
+    /// static call, and every call made under it, cannot change state.
+    fn call_account(&mut self, addr: &BigUint, value: BigUint, input: &[u8], is_static: bool) -> Outcome {
+        let Some(code) = self.accounts.get(addr).cloned() else {
+            return Outcome::Stop;
+        };
+        let saved_static = self.is_static;
+        self.is_static |= is_static;
+        let storage = self.account_storage.remove(addr).unwrap_or_default();
+        let saved_original = std::mem::replace(&mut self.original, storage.clone());
+        let saved_warm = std::mem::take(&mut self.warm_slots);
+        let saved_storage = std::mem::replace(&mut self.storage, storage);
+        let saved_value = std::mem::replace(&mut self.value, value);
+        let outcome = self.execute(&code, input);
+        self.is_static = saved_static;
+        self.value = saved_value;
+        self.warm_slots = saved_warm;
+        self.original = saved_original;
+        let storage = std::mem::replace(&mut self.storage, saved_storage);
+        self.account_storage.insert(addr.clone(), storage);
+        outcome
+    }
+
+    fn execute(&mut self, code: &[u8], calldata: &[u8]) -> Outcome {
+        let saved_storage = self.storage.clone();
+        let saved_transient = self.transient.clone();
+        let saved_logs = self.logs.len();
+        let mut frame = Frame::new(code, calldata);
+        let outcome = frame.run(self).unwrap_or_else(Outcome::Fault);
+        self.gas_used += frame.gas;
+        if !outcome.is_success() {
+            self.storage = saved_storage;
+            self.transient = saved_transient;
+            self.logs.truncate(saved_logs);
+        }
+        outcome
+    }
+}
+
+pub fn calldata(selector: [u8; 4], args: &[BigUint]) -> Vec<u8> {
+    let mut data = selector.to_vec();
+    for arg in args {
+        data.extend_from_slice(&to_word(arg));
+    }
+    data
+}
+
+/// Encodes a `bytesN` argument: the bytes are left-aligned in the word and
+/// zero-padded on the right, unlike integers.
+#[cfg(test)]
+pub fn fixed_bytes(data: &[u8]) -> BigUint {
+    let mut word = [0u8; 32];
+    let n = data.len().min(32);
+    word[..n].copy_from_slice(&data[..n]);
+    BigUint::from_bytes_be(&word)
+}
+
+fn modulus() -> BigUint {
+    BigUint::from(1u8) << 256
+}
+
+fn mask(value: BigUint) -> BigUint {
+    value % modulus()
+}
+
+fn to_word(value: &BigUint) -> [u8; 32] {
+    let bytes = value.to_bytes_be();
+    let mut word = [0u8; 32];
+    let n = bytes.len().min(32);
+    word[32 - n..].copy_from_slice(&bytes[bytes.len() - n..]);
+    word
+}
+
+fn to_usize(value: &BigUint) -> Result<usize, String> {
+    usize::try_from(value).map_err(|_| format!("value {value} out of range"))
+}
+
+fn is_negative(value: &BigUint) -> bool {
+    value.bit(255)
+}
+
+fn negate(value: &BigUint) -> BigUint {
+    mask(modulus() - value)
+}
+
+fn magnitude(value: &BigUint) -> BigUint {
+    if is_negative(value) {
+        negate(value)
+    } else {
+        value.clone()
+    }
+}
+
+fn from_bool(b: bool) -> BigUint {
+    BigUint::from(u8::from(b))
+}
+
+struct Frame<'a> {
+    code: &'a [u8],
+    calldata: &'a [u8],
+    pc: usize,
+    stack: Vec<BigUint>,
+    memory: Vec<u8>,
+    return_data: Vec<u8>,
+    jumpdests: HashSet<usize>,
+    /// Gas used by this frame alone; calls it makes are charged to the
+    /// `Vm` directly.
+    gas: u64,
+}
+
+impl<'a> Frame<'a> {
+    fn new(code: &'a [u8], calldata: &'a [u8]) -> Self {
+        let mut jumpdests = HashSet::new();
+        let mut pc = 0;
+        while pc < code.len() {
+            match code[pc] {
+                0x5b => {
+                    jumpdests.insert(pc);
+                }
+                op @ 0x60..=0x7f => pc += usize::from(op - 0x5f),
+                _ => {}
+            }
+            pc += 1;
+        }
+        Self {
+            code,
+            calldata,
+            pc: 0,
+            stack: Vec::with_capacity(64),
+            memory: Vec::new(),
+            return_data: Vec::new(),
+            jumpdests,
+            gas: 0,
+        }
+    }
+
+    fn pop(&mut self) -> Result<BigUint, String> {
+        self.stack.pop().ok_or_else(|| format!("stack underflow at pc {}", self.pc))
+    }
+
+    fn push(&mut self, value: BigUint) -> Result<(), String> {
+        if self.stack.len() >= 1024 {
+            return Err("stack overflow".into());
+        }
+        self.stack.push(value);
+        Ok(())
+    }
+
+    fn touch(&mut self, offset: usize, size: usize) -> Result<(), String> {
+        if size == 0 {
+            return Ok(());
+        }
+        let end = offset.checked_add(size).ok_or("memory offset overflow")?;
+        if end > MEMORY_LIMIT {
+            return Err(format!("memory access at {end} exceeds limit"));
+        }
+        let rounded = end.div_ceil(32) * 32;
+        if rounded > self.memory.len() {
+            self.gas += memory_gas(rounded) - memory_gas(self.memory.len());
+            self.memory.resize(rounded, 0);
+        }
+        Ok(())
+    }
+
+    fn read_memory(&mut self, offset: &BigUint, size: &BigUint) -> Result<Vec<u8>, String> {
+        let size = to_usize(size)?;
+        if size == 0 {
+            return Ok(Vec::new());
+        }
+        let offset = to_usize(offset)?;
+        self.touch(offset, size)?;
+        Ok(self.memory[offset..offset + size].to_vec())
+    }
+
+    fn copy_in(&mut self, source: &[u8], dest: &BigUint, offset: &BigUint, size: &BigUint) -> Result<(), String> {
+        let size = to_usize(size)?;
+        if size == 0 {
+            return Ok(());
+        }
+        let dest = to_usize(dest)?;
+        self.touch(dest, size)?;
+        let offset = to_usize(offset).unwrap_or(usize::MAX);
+        for i in 0..size {
+            self.memory[dest + i] = offset
+                .checked_add(i)
+                .and_then(|at| source.get(at))
+                .copied()
+                .unwrap_or(0);
+        }
+        Ok(())
+    }
+
+    fn jump(&mut self, dest: &BigUint) -> Result<(), String> {
+        let dest = to_usize(dest)?;
+        if !self.jumpdests.contains(&dest) {
+            return Err(format!("invalid jump destination {dest}"));
+        }
+        self.pc = dest;
+        Ok(())
+    }
+
+    fn run(&mut self, vm: &mut Vm) -> Result<Outcome, String> {
+        for _ in 0..STEP_LIMIT {
+            let Some(&op) = self.code.get(self.pc) else {
+                return Ok(Outcome::Stop);
+            };
+            self.pc += 1;
+            self.gas += base_gas(op);
+            if vm.is_static && writes_state(op, &self.stack) {
+                return Err(format!("state change 0x{op:02x} in a static call"));
+            }
+            match op {
+                0x00 => return Ok(Outcome::Stop),
+                0x01..=0x07 | 0x0a | 0x0b | 0x10..=0x14 | 0x16..=0x18 | 0x1a..=0x1c => {
+                    let a = self.pop()?;
+                    let b = self.pop()?;
+                    if op == 0x0a {
+                        self.gas += 50 * b.bits().div_ceil(8);
+                    }
+                    let result = binary(op, a, b);
+                    self.push(result)?;
+                }
+                0x15 => {
+                    let a = self.pop()?;
+                    self.push(from_bool(a.bits() == 0))?;
+                }
+                0x19 => {
+                    let a = self.pop()?;
+                    self.push(modulus() - 1u8 - a)?;
+                }
+                0x20 => {
+                    let offset = self.pop()?;
+                    let size = self.pop()?;
+                    self.gas += 6 * word_count(&size);
+                    let data = self.read_memory(&offset, &size)?;
+                    let mut hash = [0u8; 32];
+                    let mut keccak = Keccak::v256();
+                    keccak.update(&data);
+                    keccak.finalize(&mut hash);
+                    self.push(BigUint::from_bytes_be(&hash))?;
+                }
+                0x33 => self.push(vm.caller.clone())?,
+                0x34 => self.push(vm.value.clone())?,
+                0x42 => self.push(vm.timestamp.clone())?,
+                0x43 => self.push(vm.block_number.clone())?,
+                0x35 => {
+                    let offset = to_usize(&self.pop()?).unwrap_or(usize::MAX);
+                    let mut word = [0u8; 32];
+                    for (i, byte) in word.iter_mut().enumerate() {
+                        *byte = offset
+                            .checked_add(i)
+                            .and_then(|at| self.calldata.get(at))
+                            .copied()
+                            .unwrap_or(0);
+                    }
+                    self.push(BigUint::from_bytes_be(&word))?;
+                }
+                0x36 => self.push(BigUint::from(self.calldata.len()))?,
+                0x37 | 0x39 => {
+                    let dest = self.pop()?;
+                    let offset = self.pop()?;
+                    let size = self.pop()?;
+                    let source = if op == 0x37 { self.calldata } else { self.code };
+                    self.gas += 3 * word_count(&size);
+                    self.copy_in(source, &dest, &offset, &size)?;
+                }
+                0x38 => self.push(BigUint::from(self.code.len()))?,
+                0x3b => {
+                    let addr = self.pop()?;
+                    self.gas += account_gas(vm, &addr);
+                    let size = vm.accounts.get(&addr).map_or(0, Vec::len);
+                    self.push(BigUint::from(size))?;
+                }
+                0x3d => self.push(BigUint::from(self.return_data.len()))?,
+                0x3e => {
+                    let dest = self.pop()?;
+                    let offset = self.pop()?;
+                    let size = self.pop()?;
+                    if to_usize(&offset)?.saturating_add(to_usize(&size)?) > self.return_data.len() {
+                        return Err("return data out of bounds".into());
+                    }
+                    self.gas += 3 * word_count(&size);
+                    let data = std::mem::take(&mut self.return_data);
+                    self.copy_in(&data, &dest, &offset, &size)?;
+                    self.return_data = data;
+                }
+                0x50 => {
+                    self.pop()?;
+                }
+                0x5a => self.push(BigUint::from(STEP_LIMIT))?,
+                0x51 => {
+                    let offset = to_usize(&self.pop()?)?;
+                    self.touch(offset, 32)?;
+                    let word = BigUint::from_bytes_be(&self.memory[offset..offset + 32]);
+                    self.push(word)?;
+                }
+                0x52 => {
+                    let offset = to_usize(&self.pop()?)?;
+                    let value = self.pop()?;
+                    self.touch(offset, 32)?;
+                    self.memory[offset..offset + 32].copy_from_slice(&to_word(&value));
+                }
+                0x53 => {
+                    let offset = to_usize(&self.pop()?)?;
+                    let value = self.pop()?;
+                    self.touch(offset, 1)?;
+                    self.memory[offset] = to_word(&value)[31];
+                }
+                0x54 => {
+                    let key = self.pop()?;
+                    self.gas += if vm.warm_slots.insert(key.clone()) { COLD_SLOAD_GAS } else { WARM_GAS };
+                    let value = vm.storage.get(&key).cloned().unwrap_or_default();
+                    self.push(value)?;
+                }
+                0x55 => {
+                    let key = self.pop()?;
+                    let value = self.pop()?;
+                    if vm.warm_slots.insert(key.clone()) {
+                        self.gas += COLD_SLOAD_GAS;
+                    }
+                    let zero = BigUint::default();
+                    let current = vm.storage.get(&key).unwrap_or(&zero);
+                    let original = vm.original.get(&key).unwrap_or(&zero);
+                    self.gas += if *current == value || original != current {
+                        WARM_GAS
+                    } else if original.bits() == 0 {
+                        20_000
+                    } else {
+                        2_900
+                    };
+                    if value.bits() == 0 {
+                        vm.storage.remove(&key);
+                    } else {
+                        vm.storage.insert(key, value);
+                    }
+                }
+                0x5c => {
+                    let key = self.pop()?;
+                    let value = vm.transient.get(&key).cloned().unwrap_or_default();
+                    self.push(value)?;
+                }
+                0x5d => {
+                    let key = self.pop()?;
+                    let value = self.pop()?;
+                    if value.bits() == 0 {
+                        vm.transient.remove(&key);
+                    } else {
+                        vm.transient.insert(key, value);
+                    }
+                }
+                0x5e => {
+                    let dest = self.pop()?;
+                    let offset = self.pop()?;
+                    let size = self.pop()?;
+                    if to_usize(&size)? > 0 {
+                        self.touch(to_usize(&offset)?, to_usize(&size)?)?;
+                    }
+                    self.gas += 3 * word_count(&size);
+                    let memory = self.memory.clone();
+                    self.copy_in(&memory, &dest, &offset, &size)?;
+                }
+                0x56 => {
+                    let dest = self.pop()?;
+                    self.jump(&dest)?;
+                }
+                0x57 => {
+                    let dest = self.pop()?;
+                    let cond = self.pop()?;
+                    if cond.bits() != 0 {
+                        self.jump(&dest)?;
+                    }
+                }
+                0x5b => {}
+                0x5f..=0x7f => {
+                    let n = usize::from(op - 0x5f);
+                    let mut data = [0u8; 32];
+                    for i in 0..n {
+                        data[32 - n + i] = self.code.get(self.pc + i).copied().unwrap_or(0);
+                    }
+                    self.pc += n;
+                    self.push(BigUint::from_bytes_be(&data))?;
+                }
+                0x80..=0x8f => {
+                    let depth = usize::from(op - 0x7f);
+                    let len = self.stack.len();
+                    if depth > len {
+                        return Err(format!("stack underflow at pc {}", self.pc - 1));
+                    }
+                    let value = self.stack[len - depth].clone();
+                    self.push(value)?;
+                }
+                0x90..=0x9f => {
+                    let depth = usize::from(op - 0x8f);
+                    let len = self.stack.len();
+                    if depth >= len {
+                        return Err(format!("stack underflow at pc {}", self.pc - 1));
+                    }
+                    self.stack.swap(len - 1, len - 1 - depth);
+                }
+                0xa0..=0xa4 => {
+                    let offset = self.pop()?;
+                    let size = self.pop()?;
+                    let mut topics = Vec::new();
+                    for _ in 0..(op - 0xa0) {
+                        topics.push(self.pop()?);
+                    }
+                    let data = self.read_memory(&offset, &size)?;
+                    self.gas += 8 * data.len() as u64;
+                    vm.logs.push(Log { topics, data });
+                }
+                0xf3 | 0xfd => {
+                    let offset = self.pop()?;
+                    let size = self.pop()?;
+                    let data = self.read_memory(&offset, &size)?;
+                    return Ok(if op == 0xf3 {
+                        Outcome::Return(data)
+                    } else {
+                        Outcome::Revert(data)
+                    });
+                }
+                0xf1 | 0xfa => {
+                    let _gas = self.pop()?;
+                    let addr = self.pop()?;
+                    let value = if op == 0xf1 { self.pop()? } else { BigUint::default() };
+                    let args_offset = self.pop()?;
+                    let args_size = self.pop()?;
+                    let ret_offset = self.pop()?;
+                    let ret_size = self.pop()?;
+                    let input = self.read_memory(&args_offset, &args_size)?;
+                    self.gas += account_gas(vm, &addr);
+                    if value.bits() != 0 {
+                        self.gas += 9_000;
+                    }
+                    let outcome = vm.call_account(&addr, value, &input, op == 0xfa);
+                    self.return_data = outcome.output().to_vec();
+                    let copied = to_usize(&ret_size)?.min(self.return_data.len());
+                    let data = std::mem::take(&mut self.return_data);
+                    self.copy_in(&data, &ret_offset, &BigUint::default(), &BigUint::from(copied))?;
+                    self.return_data = data;
+                    self.push(from_bool(outcome.is_success()))?;
+                }
+                0xfe => return Err("invalid instruction".into()),
+                other => return Err(format!("unsupported opcode 0x{other:02x}")),
+            }
+        }
+        Err("step limit exceeded".into())
+    }
+}
+
+/// Whether `op` changes state, which a static call forbids: storage
+/// writes, logs, contract creation, self-destruct and calls sending value.
+fn writes_state(op: u8, stack: &[BigUint]) -> bool {
+    match op {
+        0x55 | 0x5d | 0xa0..=0xa4 | 0xf0 | 0xf5 | 0xff => true,
+        0xf1 => stack.len() >= 3 && stack[stack.len() - 3].bits() != 0,
+        _ => false,
+    }
+}
+
+/// The fixed part of an instruction's cost. Storage and account access,
+/// copies, hashing, logs and memory growth are charged where they run.
+fn base_gas(op: u8) -> u64 {
+    match op {
+        0x5b => 1,
+        0x30 | 0x32..=0x34 | 0x36 | 0x38 | 0x3a | 0x3d | 0x41..=0x48 | 0x4a | 0x50 | 0x58..=0x5a | 0x5f => 2,
+        0x01 | 0x03 | 0x10..=0x1d | 0x35 | 0x37 | 0x39 | 0x3e | 0x49 | 0x51..=0x53 | 0x5e | 0x60..=0x9f => 3,
+        0x02 | 0x04..=0x07 | 0x0b => 5,
+        0x08 | 0x09 | 0x56 => 8,
+        0x0a | 0x57 => 10,
+        0x20 => 30,
+        0x5c | 0x5d => WARM_GAS,
+        0xa0..=0xa4 => 375 * (1 + u64::from(op - 0xa0)),
+        _ => 0,
+    }
+}
+
+/// Words of memory or data `size` bytes cover.
+fn word_count(size: &BigUint) -> u64 {
+    u64::try_from(size).map_or(u64::MAX / 64, |n| n.div_ceil(32))
+}
+
+/// The total cost of `bytes` bytes of memory.
+fn memory_gas(bytes: usize) -> u64 {
+    let words = (bytes / 32) as u64;
+    3 * words + words * words / 512
+}
+
+/// What touching `addr` costs: cold the first time in a transaction.
+fn account_gas(vm: &mut Vm, addr: &BigUint) -> u64 {
+    if vm.warm_accounts.insert(addr.clone()) {
+        COLD_ACCOUNT_GAS
+    } else {
+        WARM_GAS
+    }
+}
+
+fn binary(op: u8, a: BigUint, b: BigUint) -> BigUint {
+    let zero = BigUint::default();
+    match op {
+        0x01 => mask(a + b),
+        0x02 => mask(a * b),
+        0x03 => mask(a + modulus() - b),
+        0x04 if b == zero => zero,
+        0x04 => a / b,
+        0x05 if b == zero => zero,
+        0x05 => {
+            let q = magnitude(&a) / magnitude(&b);
+            if is_negative(&a) != is_negative(&b) {
+                negate(&q)
+            } else {
+                q
+            }
+        }
+        0x06 if b == zero => zero,
+        0x06 => a % b,
+        0x07 if b == zero => zero,
+        0x07 => {
+            let r = magnitude(&a) % magnitude(&b);
+            if is_negative(&a) {
+                negate(&r)
+            } else {
+                r
+            }
+        }
+        0x0a => a.modpow(&b, &modulus()),
+        0x0b => match usize::try_from(&a) {
+            Ok(i) if i < 31 => {
+                let bits = 8 * (i + 1);
+                let low = b & ((BigUint::from(1u8) << bits) - 1u8);
+                if low.bit(bits as u64 - 1) {
+                    low | (modulus() - (BigUint::from(1u8) << bits))
+                } else {
+                    low
+                }
+            }
+            _ => b,
+        },
+        0x10 => from_bool(a < b),
+        0x11 => from_bool(a > b),
+        0x12 | 0x13 => {
+            let less = match (is_negative(&a), is_negative(&b)) {
+                (true, false) => true,
+                (false, true) => false,
+                _ => a < b,
+            };
+            let greater = a != b && !less;
+            from_bool(if op == 0x12 { less } else { greater })
+        }
+        0x14 => from_bool(a == b),
+        0x16 => a & b,
+        0x17 => a | b,
+        0x18 => a ^ b,
+        0x1a => match usize::try_from(&a) {
+            Ok(i) if i < 32 => BigUint::from(to_word(&b)[i]),
+            _ => zero,
+        },
+        0x1b => match usize::try_from(&a) {
+            Ok(shift) if shift < 256 => mask(b << shift),
+            _ => zero,
+        },
+        0x1c => match usize::try_from(&a) {
+            Ok(shift) if shift < 256 => b >> shift,
+            _ => zero,
+        },
+        _ => unreachable!("not a binary opcode: 0x{op:02x}"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run(code: &[u8]) -> Outcome {
+        let mut vm = Vm::deploy(&[0x60, 0x00, 0x60, 0x00, 0xf3]);
+        vm.code = code.to_vec();
+        vm.call(&[])
+    }
+
+    #[test]
+    fn wraps_arithmetic() {
+        let out = run(&[0x60, 0x01, 0x60, 0x00, 0x03, 0x60, 0x00, 0x52, 0x60, 0x20, 0x60, 0x00, 0xf3]);
+        assert_eq!(out.word(0), modulus() - 1u8);
+    }
+
+    #[test]
+    fn meters_storage_writes() {
+        // SSTORE 1 then 2 at slot 0, STOP
+        let code = [0x60, 0x01, 0x60, 0x00, 0x55, 0x60, 0x02, 0x60, 0x00, 0x55, 0x00];
+        let mut vm = Vm::deploy(&[0x60, 0x00, 0x60, 0x00, 0xf3]);
+        vm.code = code.to_vec();
+        assert!(vm.call(&[]).is_success());
+        // intrinsic, four pushes, a cold zero-to-nonzero write, a dirty write
+        assert_eq!(vm.gas_used, 21_000 + 12 + 2_100 + 20_000 + 100);
+        assert!(vm.call(&[]).is_success());
+        assert_eq!(vm.gas_used, 21_000 + 12 + 2_100 + 2_900 + 100);
+        // MSTORE expands memory by one word; calldata bytes are charged
+        vm.code = vec![0x60, 0x01, 0x60, 0x00, 0x52, 0x00];
+        vm.call(&[0, 1]);
+        assert_eq!(vm.gas_used, 21_000 + 4 + 16 + 6 + 3 + 3);
+    }
+
+    #[test]
+    fn reverts_roll_back_storage() {
+        let mut vm = Vm::deploy(&[0x60, 0x00, 0x60, 0x00, 0xf3]);
+        vm.code = vec![0x60, 0x01, 0x60, 0x00, 0x55, 0x60, 0x00, 0x60, 0x00, 0xfd];
+        assert!(matches!(vm.call(&[]), Outcome::Revert(_)));
+        assert!(vm.storage.is_empty());
+    }
+
+    #[test]
+    fn transient_storage_and_mcopy() {
+        // TSTORE 0x2a at key 1, TLOAD it to memory 0, MCOPY 0..32 to 32, return 32..64
+        let code = [
+            0x60, 0x2a, 0x60, 0x01, 0x5d, 0x60, 0x01, 0x5c, 0x5f, 0x52, 0x60, 0x20, 0x5f, 0x60,
+            0x20, 0x5e, 0x60, 0x20, 0x60, 0x20, 0xf3,
+        ];
+        let mut vm = Vm::deploy(&[0x60, 0x00, 0x60, 0x00, 0xf3]);
+        vm.code = code.to_vec();
+        assert_eq!(vm.call(&[]).word(0), 0x2au8.into());
+        assert!(vm.storage.is_empty());
+        vm.code = vec![0x60, 0x01, 0x5c, 0x5f, 0x52, 0x60, 0x20, 0x5f, 0xf3];
+        assert_eq!(vm.call(&[]).word(0), 0u8.into());
+    }
+
+    #[test]
+    fn rejects_jump_into_push_data() {
+        let out = run(&[0x60, 0x5b, 0x60, 0x01, 0x56]);
+        assert!(matches!(out, Outcome::Fault(msg) if msg.contains("invalid jump")));
+    }
+
+    #[test]
+    fn runs_compiled_function() {
+        let mut vm = Vm::from_source("def pick(a: uint256, b: uint256) -> uint256: return b");
+        let out = vm.call_function("pick", &[2u8.into(), 40u8.into()]);
+        assert!(out.is_success(), "{out:?}");
+        assert_eq!(out.word(0), BigUint::from(40u8));
+    }
+
+    #[test]
+    fn fixed_bytes_literal_is_left_aligned() {
+        let src = "def f() -> (bytes4, uint8):\n    let sel: bytes4 = 0xa9059cbb\n    return sel, sel[1]\n";
+        let mut vm = Vm::from_source(src);
+        let out = vm.call_function("f", &[]);
+        assert!(out.is_success(), "{out:?}");
+        assert_eq!(out.word(0), fixed_bytes(b"\xa9\x05\x9c\xbb"));
+        assert_eq!(out.word(1), 0x05u8.into());
+    }
+
+    #[test]
+    fn fixed_bytes_literals_are_aligned_wherever_they_meet_bytes_n() {
+        let src = "sig: bytes4\n\nevent Seen(s: indexed(bytes4), t: bytes4)\n\n\
+                   def pick(s: bytes4) -> bytes4: return s\n\n\
+                   def is_transfer(s: bytes4) -> bool: return s == 0xa9059cbb\n\n\
+                   def differs(s: bytes4) -> bool: return 0xa9059cbb != s\n\n\
+                   def store() -> bytes4:\n    sig = 0x095ea7b3\n    let local: bytes4 = 0x00000001\n    local = 0xdeadbeef\n    \
+                   require sig == 0x095ea7b3\n    return local\n\n\
+                   def pass_on() -> bytes4: return pick(0x23b872dd)\n\n\
+                   def ping():\n    emit Seen(0x12345678, 0x9abcdef0)\n\n\
+                   def pair() -> (uint256, bytes4): return 1, 0x70a08231\n";
+        let mut vm = Vm::from_source(src);
+        let transfer = fixed_bytes(b"\xa9\x05\x9c\xbb");
+        assert_eq!(vm.call_function("differs", std::slice::from_ref(&transfer)).word(0), 0u8.into());
+        assert_eq!(vm.call_function("is_transfer", &[transfer]).word(0), 1u8.into());
+        assert_eq!(vm.call_function("is_transfer", &[0xa9059cbbu32.into()]).word(0), 0u8.into());
+
+        let out = vm.call_function("store", &[]);
+        assert!(out.is_success(), "{out:?}");
+        assert_eq!(out.word(0), fixed_bytes(b"\xde\xad\xbe\xef"));
+        assert_eq!(vm.call_function("pass_on", &[]).word(0), fixed_bytes(b"\x23\xb8\x72\xdd"));
+        assert_eq!(vm.call_function("pair", &[]).word(1), fixed_bytes(b"\x70\xa0\x82\x31"));
+
+        vm.call_function("ping", &[]);
+        assert_eq!(vm.logs[0].topics[1], fixed_bytes(b"\x12\x34\x56\x78"));
+        assert_eq!(vm.logs[0].data, b"\x9a\xbc\xde\xf0".iter().copied().chain([0; 28]).collect::<Vec<u8>>());
+    }
+
+    #[test]
+    fn narrow_arithmetic_stays_within_the_type() {
+        let src = "small: uint8\n\n\
+                   def add(x: uint8, y: uint8) -> uint8: return x + y\n\n\
+                   def neg(x: int8, y: int8) -> int8: return x * y\n\n\
+                   def bump(n: uint8) -> uint8:\n    small = 200\n    small += n\n    return small\n\n\
+                   def wide(x: uint8, y: uint256) -> uint256: return x + y\n";
+        let mut vm = Vm::from_source(src);
+        assert_eq!(vm.call_function("add", &[100u8.into(), 55u8.into()]).word(0), 155u8.into());
+        assert!(matches!(vm.call_function("add", &[200u8.into(), 100u8.into()]), Outcome::Revert(_)));
+        assert_eq!(vm.call_function("neg", &[10u8.into(), 12u8.into()]).word(0), 120u8.into());
+        assert!(matches!(vm.call_function("neg", &[10u8.into(), 13u8.into()]), Outcome::Revert(_)));
+        assert_eq!(vm.call_function("bump", &[55u8.into()]).word(0), 255u8.into());
+        assert!(matches!(vm.call_function("bump", &[56u8.into()]), Outcome::Revert(_)));
+        assert_eq!(vm.call_function("wide", &[200u8.into(), 100u8.into()]).word(0), 300u16.into());
+
+        let unchecked = crate::security::HardenConfig { add: false, ..Default::default() };
+        let mut vm = Vm::from_source_with_config(src, &unchecked);
+        assert_eq!(vm.call_function("add", &[200u8.into(), 100u8.into()]).word(0), 44u8.into());
+    }
+
+    #[test]
+    fn forty_digit_hex_literals_work_as_numbers() {
+        let src = "def a() -> uint256:\n    let x: uint256 = 0xffffffffffffffffffffffffffffffffffffffff\n    return x + 1\n\n\
+                   def b() -> uint256:\n    \
+                   asm: PUSH20 0x00000000000000000000000000000000000001ff PUSH1 0x00 MSTORE PUSH1 0x20 PUSH1 0x00 RETURN\n";
+        let mut vm = Vm::from_source(src);
+        assert_eq!(vm.call_function("a", &[]).word(0), BigUint::from(1u8) << 160);
+        assert_eq!(vm.call_function("b", &[]).word(0), 0x1ffu16.into());
+    }
+
+    #[test]
+    fn narrow_params_drop_dirty_bits() {
+        let src = "def u(x: uint8) -> uint256: return x\n\ndef s(x: int16) -> int256: return x\n";
+        let mut vm = Vm::from_source(src);
+        let out = vm.call_function("u", &[0x1ffu32.into()]);
+        assert_eq!(out.word(0), 0xffu8.into());
+        let out = vm.call_function("s", &[0x1_8000u32.into()]);
+        assert_eq!(out.word(0), modulus() - 0x8000u32);
+        let out = vm.call_function("s", &[0x7fffu32.into()]);
+        assert_eq!(out.word(0), 0x7fffu32.into());
+    }
+
+    #[test]
+    fn address_bool_and_fixed_bytes_params_are_cleaned() {
+        let src = "struct T {\n    balances: mapping[address => uint256]\n}\n\ndef set(who: address, amount: uint256):\n    balances[who] = amount\n\ndef get(who: address) -> uint256: return balances[who]\n\ndef same(a: address, b: address) -> bool: return a == b\n\ndef flag(b: bool) -> uint256:\n    if b == true:\n        return 1\n    return 0\n\ndef head(x: bytes2) -> bytes2: return x\n\ndef pick(xs: address[2]) -> address: return xs[1]\n";
+        let mut vm = Vm::from_source(src);
+        let dirty = (BigUint::from(0xdeadu32) << 160u32) + 0x70u8;
+        assert!(vm.call_function("set", &[dirty.clone(), 9u8.into()]).is_success());
+        assert_eq!(vm.call_function("get", &[0x70u8.into()]).word(0), 9u8.into());
+        assert_eq!(vm.call_function("same", &[dirty.clone(), 0x70u8.into()]).word(0), 1u8.into());
+        assert_eq!(vm.call_function("flag", &[2u8.into()]).word(0), 1u8.into());
+        let out = vm.call_function("head", &[fixed_bytes(b"\xab\xcd\xef")]);
+        assert_eq!(out.word(0), fixed_bytes(b"\xab\xcd"));
+        let out = vm.call_function("pick", &[1u8.into(), dirty]);
+        assert_eq!(out.word(0), 0x70u8.into());
+    }
+
+    #[test]
+    fn compares_against_address_literal() {
+        let src = "const OWNER: address = 0x0000000000000000000000000000000000C0FfeE\n\ndef owner() -> address: return OWNER\n\ndef is_owner() -> bool: return msg.sender == 0x0000000000000000000000000000000000C0FfeE\n";
+        let mut vm = Vm::from_source(src);
+        assert_eq!(vm.call_function("owner", &[]).word(0), 0xc0ffeeu32.into());
+        assert_eq!(vm.call_function("is_owner", &[]).word(0), 1u8.into());
+        vm.caller = 0xbeefu32.into();
+        assert_eq!(vm.call_function("is_owner", &[]).word(0), 0u8.into());
+    }
+
+    #[test]
+    fn compares_time_units_against_block_timestamp() {
+        let src = "const LOCK: uint256 = 3 days\n\ndef unlocked(start: uint256) -> bool: return block.timestamp >= start + LOCK\n\ndef height() -> uint256: return block.number\n";
+        let mut vm = Vm::from_source(src);
+        let start = BigUint::from(1_000_000u32);
+        vm.timestamp = &start + 259_199u32;
+        assert_eq!(vm.call_function("unlocked", std::slice::from_ref(&start)).word(0), 0u8.into());
+        vm.timestamp = &start + 259_200u32;
+        assert_eq!(vm.call_function("unlocked", &[start]).word(0), 1u8.into());
+        vm.block_number = 42u8.into();
+        assert_eq!(vm.call_function("height", &[]).word(0), 42u8.into());
+    }
+
+    #[test]
+    fn round_trips_bytes32_param() {
+        let mut vm = Vm::from_source("def f(h: bytes32) -> bytes32: return h");
+        let hash: Vec<u8> = (1..=32).collect();
+        let out = vm.call_function("f", &[fixed_bytes(&hash)]);
+        assert!(out.is_success(), "{out:?}");
+        assert_eq!(out.output(), hash.as_slice());
+
+        let out = vm.call_function("f", &[fixed_bytes(b"\xab\xcd")]);
+        assert_eq!(&out.output()[..2], b"\xab\xcd");
+        assert!(out.output()[2..].iter().all(|&b| b == 0));
+    }
+
+    #[test]
+    fn calls_other_contract_through_interface() {
+        let token = Vm::from_source(
+            "struct Token {\n    balances: mapping[address => uint256]\n}\n\ndef mint(to: address, amount: uint256) -> bool:\n    balances[to] += amount\n    return true\n\n@view\ndef balanceOf(owner: address) -> uint256: return balances[owner]\n\ndef fail() -> bool:\n    require false, \"nope\"\n    return true\n",
+        );
+        let src = "interface IToken:\n    def mint(to: address, amount: uint256) -> bool\n    @view\n    def balanceOf(owner: address) -> uint256\n    def fail() -> bool\n\ndef pay(token: address, to: address) -> uint256:\n    require IToken(token).mint(to, 5), \"mint failed\"\n    require IToken(token).mint(to, 7)\n    return IToken(token).balanceOf(to)\n\ndef broken(token: address) -> bool:\n    return IToken(token).fail()\n";
+        let mut vm = Vm::from_source(src);
+        vm.accounts.insert(0x70u8.into(), token.code.clone());
+
+        let out = vm.call_function("pay", &[0x70u8.into(), 9u8.into()]);
+        assert!(out.is_success(), "{out:?}");
+        assert_eq!(out.word(0), 12u8.into());
+        assert_eq!(vm.call_function("pay", &[0x70u8.into(), 9u8.into()]).word(0), 24u8.into());
+
+        let out = vm.call_function("broken", &[0x70u8.into()]);
+        assert!(matches!(&out, Outcome::Revert(data) if data.windows(4).any(|w| w == b"nope")), "{out:?}");
+        assert!(!vm.call_function("broken", &[0x71u8.into()]).is_success());
+    }
+
+    #[test]
+    fn static_context_rejects_state_changes() {
+        let mut vm = Vm::deploy(&[0x60, 0x00, 0x60, 0x00, 0xf3]);
+        vm.is_static = true;
+        let mut run_static = |code: &[u8]| {
+            vm.code = code.to_vec();
+            vm.call(&[])
+        };
+        // SSTORE, LOG0 and a CALL sending one wei fault; a CALL without value runs.
+        let sstore = [0x60, 0x01, 0x60, 0x00, 0x55, 0x00];
+        let log = [0x60, 0x00, 0x60, 0x00, 0xa0, 0x00];
+        let call = |value: u8| [0x60, 0x00, 0x60, 0x00, 0x60, 0x00, 0x60, 0x00, 0x60, value, 0x60, 0x70, 0x5a, 0xf1, 0x00];
+        assert!(matches!(run_static(&sstore), Outcome::Fault(msg) if msg.contains("static call")));
+        assert!(matches!(run_static(&log), Outcome::Fault(_)));
+        assert!(matches!(run_static(&call(1)), Outcome::Fault(_)));
+        assert!(run_static(&call(0)).is_success());
+    }
+
+    #[test]
+    fn view_interface_calls_cannot_change_state() {
+        let counter = "count: uint256\n\nevent Ping(n: uint256)\n\n\
+                       @view\ndef peek() -> uint256: return count\n\n\
+                       def bump() -> uint256:\n    count += 1\n    return count\n\n\
+                       def ping() -> uint256:\n    emit Ping(1)\n    return 1\n";
+        let explicit = crate::security::HardenConfig {
+            guard_mode: crate::security::GuardMode::Explicit,
+            ..Default::default()
+        };
+        let unlocked = Vm::from_source_with_config(counter, &explicit);
+        let locked = Vm::from_source(counter);
+        let src = "interface ICounter:\n    @view\n    def peek() -> uint256\n    @view\n    def bump() -> uint256\n    \
+                   @view\n    def ping() -> uint256\n\n\
+                   def read(c: address) -> uint256: return ICounter(c).peek()\n\n\
+                   def sneak(c: address) -> uint256: return ICounter(c).bump()\n\n\
+                   def shout(c: address) -> uint256: return ICounter(c).ping()\n";
+        let mut vm = Vm::from_source(src);
+        vm.accounts.insert(0x70u8.into(), unlocked.code.clone());
+        vm.accounts.insert(0x71u8.into(), locked.code.clone());
+
+        assert!(vm.call_function("read", &[0x70u8.into()]).is_success());
+        assert!(vm.call_function("read", &[0x71u8.into()]).is_success());
+        assert!(matches!(vm.call_function("sneak", &[0x70u8.into()]), Outcome::Revert(_)));
+        assert!(matches!(vm.call_function("shout", &[0x70u8.into()]), Outcome::Revert(_)));
+        assert!(vm.logs.is_empty());
+    }
+
+    #[test]
+    fn external_calls_encode_dynamic_args_and_decode_tuples() {
+        let receiver = Vm::from_source(
+            "event Got(name: indexed(string), tag: indexed(bytes))\n\n\
+             def take(name: string, n: uint256, tag: bytes) -> (uint256, uint256):\n    \
+             emit Got(name, tag)\n    return n + 1, n * 2\n",
+        );
+        let src = "interface IRecv:\n    def take(name: string, n: uint256, tag: bytes) -> (uint256, uint256)\n\n\
+                   def relay(to: address, name: string) -> uint256:\n    let a = 0\n    let b = 0\n    \
+                   (a, b) = IRecv(to).take(name, 20, b'abcd')\n    return a * 1000 + b\n\n\
+                   def fixed(to: address) -> uint256:\n    let a = 0\n    let b = 0\n    \
+                   (a, b) = IRecv(to).take(\"a name that takes up more than one word\", 3, b'')\n    return a * 1000 + b\n";
+        let mut vm = Vm::from_source(src);
+        vm.accounts.insert(0x70u8.into(), receiver.code.clone());
+        let hash = |data: &[u8]| {
+            let mut out = [0u8; 32];
+            let mut hasher = Keccak::v256();
+            hasher.update(data);
+            hasher.finalize(&mut out);
+            BigUint::from_bytes_be(&out)
+        };
+
+        let args = [0x70u8.into(), 0x40u8.into(), 5u8.into(), fixed_bytes(b"alice")];
+        let out = vm.call_function("relay", &args);
+        assert!(out.is_success(), "{out:?}");
+        assert_eq!(out.word(0), 21040u32.into());
+        assert_eq!(vm.logs[0].topics[1], hash(b"alice"));
+        assert_eq!(vm.logs[0].topics[2], hash(b"\xab\xcd"));
+
+        let out = vm.call_function("fixed", &[0x70u8.into()]);
+        assert!(out.is_success(), "{out:?}");
+        assert_eq!(out.word(0), 4006u32.into());
+        assert_eq!(vm.logs[1].topics[1], hash(b"a name that takes up more than one word"));
+        assert_eq!(vm.logs[1].topics[2], hash(b""));
+    }
+
+    #[test]
+    fn constructor_reads_appended_args() {
+        let src = "def init(supply: uint256, admin: address):\n    total = supply * 2\n    boss = admin\n\ndef info() -> (uint256, uint256): return total, boss\n";
+        let mut vm = Vm::from_source_with_args(src, &[21u8.into(), 0xb055u32.into()]);
+        let out = vm.call_function("info", &[]);
+        assert_eq!(out.word(0), 42u8.into());
+        assert_eq!(out.word(1), 0xb055u32.into());
+    }
+
+    #[test]
+    fn storage_arrays_push_index_and_check_bounds() {
+        let src = "owners: address[2]\nids: uint256[]\n\ndef add(id: uint256):\n    ids.push(id)\n\ndef set(i: uint256, who: address):\n    owners[i] = who\n    ids[i] = ids[i] + 1\n\ndef get(i: uint256) -> (uint256, address, uint256): return ids.length, owners[i], ids[i]\n";
+        let mut vm = Vm::from_source(src);
+        assert!(matches!(vm.call_function("get", &[0u8.into()]), Outcome::Revert(_)));
+        vm.call_function("add", &[10u8.into()]);
+        vm.call_function("add", &[20u8.into()]);
+        assert!(vm.call_function("set", &[1u8.into(), 0xb0u8.into()]).is_success());
+        let out = vm.call_function("get", &[1u8.into()]);
+        assert_eq!(out.word(0), 2u8.into());
+        assert_eq!(out.word(1), 0xb0u8.into());
+        assert_eq!(out.word(2), 21u8.into());
+        assert!(matches!(vm.call_function("set", &[2u8.into(), 0xb0u8.into()]), Outcome::Revert(_)));
+    }
+
+    #[test]
+    fn calldata_array_params() {
+        let src = "def sum(pair: uint256[2], xs: uint256[], k: uint256) -> uint256: return pair[1] + xs[k] + xs.length\n";
+        let mut vm = Vm::from_source(src);
+        let args: [BigUint; 8] = [3u8, 4, 0x80, 1, 3, 100, 200, 255].map(BigUint::from);
+        let out = vm.call_function("sum", &args);
+        assert!(out.is_success(), "{out:?}");
+        assert_eq!(out.word(0), (4u32 + 200 + 3).into());
+        let mut args = args;
+        args[3] = 3u8.into();
+        assert!(matches!(vm.call_function("sum", &args), Outcome::Revert(_)));
+    }
+
+    #[test]
+    fn struct_mapping_entries_store_fields() {
+        let src = "struct User {\n    balance: uint256,\n    age: uint256\n}\n\nusers: mapping[address => User]\n\ndef join(age: uint256):\n    users[msg.sender] = User { age: age, balance: 10 }\n\ndef earn(amount: uint256):\n    users[msg.sender].balance = users[msg.sender].balance + amount\n\ndef reset():\n    users[msg.sender] = User { age: 1 }\n\ndef info(who: address) -> (uint256, uint256): return users[who].balance, users[who].age\n";
+        let mut vm = Vm::from_source(src);
+        vm.call_function("join", &[30u8.into()]);
+        vm.call_function("earn", &[5u8.into()]);
+        let caller = vm.caller.clone();
+        let out = vm.call_function("info", std::slice::from_ref(&caller));
+        assert_eq!((out.word(0), out.word(1)), (15u8.into(), 30u8.into()));
+        let out = vm.call_function("info", &[7u8.into()]);
+        assert_eq!((out.word(0), out.word(1)), (0u8.into(), 0u8.into()));
+        vm.call_function("reset", &[]);
+        let out = vm.call_function("info", &[caller]);
+        assert_eq!((out.word(0), out.word(1)), (0u8.into(), 1u8.into()));
+    }
+
+    #[test]
+    fn struct_instance_assigned_from_literal() {
+        let src = "struct Config {\n    fee: uint256,\n    paused: bool\n}\n\nconfig: Config\n\ndef set(fee: uint256):\n    config = Config { fee: fee }\n\ndef pause():\n    config.paused = true\n\ndef get() -> (uint256, bool): return config.fee, config.paused\n";
+        let mut vm = Vm::from_source(src);
+        vm.call_function("pause", &[]);
+        vm.call_function("set", &[25u8.into()]);
+        let out = vm.call_function("get", &[]);
+        assert_eq!((out.word(0), out.word(1)), (25u8.into(), 0u8.into()));
+    }
+
+    #[test]
+    fn declared_state_persists_between_calls() {
+        let src = "owner: address\nbalances: mapping[address => uint256]\n\ndef init():\n    owner = msg.sender\n\ndef credit(to: address, amount: uint256):\n    balances[to] = balances[to] + amount\n\ndef info(who: address) -> (address, uint256): return owner, balances[who]\n";
+        let mut vm = Vm::from_source(src);
+        vm.call_function("credit", &[9u8.into(), 40u8.into()]);
+        vm.call_function("credit", &[9u8.into(), 2u8.into()]);
+        let out = vm.call_function("info", &[9u8.into()]);
+        assert_eq!(out.word(0), vm.caller.clone());
+        assert_eq!(out.word(1), 42u8.into());
+    }
+
+    #[test]
+    fn emit_writes_log_with_event_topic() {
+        let src = "event Transfer(from: address, to: address, amount: uint256)\n\ndef send(to: address, amount: uint256):\n    emit Transfer(msg.sender, to, amount)\n";
+        let mut vm = Vm::from_source(src);
+        let out = vm.call_function("send", &[7u8.into(), 500u32.into()]);
+        assert!(out.is_success(), "{out:?}");
+        assert_eq!(vm.logs.len(), 1);
+
+        let log = &vm.logs[0];
+        let program = crate::parser::parse_from_source(src).unwrap();
+        let Some(crate::Item::Event(event)) = program.items.first() else { unreachable!() };
+        let topic = crate::ir::compute_event_topic(event);
+        assert_eq!(log.topics, [BigUint::from_bytes_be(&topic)]);
+        let words: Vec<BigUint> = log.data.chunks(32).map(BigUint::from_bytes_be).collect();
+        assert_eq!(words, [vm.caller.clone(), 7u8.into(), 500u32.into()]);
+    }
+
+    #[test]
+    fn indexed_event_fields_become_topics() {
+        let src = "event Transfer(from: indexed(address), to: indexed(address), amount: uint256)\n\ndef send(to: address, amount: uint256):\n    emit Transfer(msg.sender, to, amount)\n";
+        let mut vm = Vm::from_source(src);
+        let out = vm.call_function("send", &[7u8.into(), 500u32.into()]);
+        assert!(out.is_success(), "{out:?}");
+
+        let log = &vm.logs[0];
+        let program = crate::parser::parse_from_source(src).unwrap();
+        let Some(crate::Item::Event(event)) = program.items.first() else { unreachable!() };
+        let topic0 = BigUint::from_bytes_be(&crate::ir::compute_event_topic(event));
+        assert_eq!(log.topics, [topic0, vm.caller.clone(), 7u8.into()]);
+        assert_eq!(BigUint::from_bytes_be(&log.data), BigUint::from(500u32));
+        assert_eq!(log.data.len(), 32);
+    }
+
+    #[test]
+    fn event_arguments_run_in_source_order() {
+        let src = "count: uint256\n\nevent E(a: indexed(uint256), b: uint256, c: indexed(uint256), d: indexed(uint256))\n\n\
+                   def next() -> uint256:\n    self.count += 1\n    return self.count\n\n\
+                   def fire():\n    emit E(next(), next(), next(), next())\n";
+        let mut vm = Vm::from_source(src);
+        assert!(vm.call_function("fire", &[]).is_success());
+        let log = &vm.logs[0];
+        assert_eq!(log.topics[1..], [1u8.into(), 3u8.into(), 4u8.into()]);
+        assert_eq!(BigUint::from_bytes_be(&log.data), BigUint::from(2u8));
+    }
+
+    #[test]
+    fn indexed_string_and_bytes_fields_are_hashed() {
+        use crate::vm::fixed_bytes;
+        let src = "event Named(name: indexed(string), tag: indexed(bytes), n: uint256)\n\n\
+                   def fire(name: string):\n    emit Named(name, b'cafe', 7)\n\n\
+                   def lit():\n    emit Named(\"pyra\", b'', 8)\n";
+        let keccak = |data: &[u8]| {
+            let mut hasher = Keccak::v256();
+            hasher.update(data);
+            let mut out = [0u8; 32];
+            hasher.finalize(&mut out);
+            BigUint::from_bytes_be(&out)
+        };
+        let mut vm = Vm::from_source(src);
+        let out = vm.call_function("fire", &[0x20u8.into(), 4u8.into(), fixed_bytes(b"pyra")]);
+        assert!(out.is_success(), "{out:?}");
+        assert_eq!(vm.logs[0].topics[1..], [keccak(b"pyra"), keccak(&[0xca, 0xfe])]);
+        assert!(vm.call_function("lit", &[]).is_success());
+        assert_eq!(vm.logs[1].topics[1..], [keccak(b"pyra"), keccak(b"")]);
+    }
+
+    #[test]
+    fn enum_values_are_variant_indexes() {
+        let src = "enum Status: Pending, Active, Closed\n\nstatus: Status\n\ndef close():\n    status = Status.Closed\n\ndef set(s: Status):\n    status = s\n\n@view\ndef get() -> Status: return status\n";
+        let mut vm = Vm::from_source(src);
+        assert_eq!(vm.call_function("get", &[]).word(0), 0u8.into());
+        assert!(vm.call_function("close", &[]).is_success());
+        assert_eq!(vm.call_function("get", &[]).word(0), 2u8.into());
+        assert!(vm.call_function("set", &[1u8.into()]).is_success());
+        assert_eq!(vm.call_function("get", &[]).word(0), 1u8.into());
+        assert!(matches!(vm.call_function("set", &[3u8.into()]), Outcome::Revert(_)));
+    }
+
+    #[test]
+    fn modifier_guards_function_body() {
+        let src = "owner: address\npaused: bool\ncount: uint256\n\nmodifier only_owner:\n    require msg.sender == owner, \"not owner\"\n    _\n\nmodifier when_active:\n    require not paused, \"paused\"\n    _\n\ndef init():\n    owner = msg.sender\n\n@only_owner\ndef pause():\n    paused = true\n\n@when_active\ndef bump():\n    count += 1\n\n@view\ndef get() -> uint256: return count\n";
+        let mut vm = Vm::from_source(src);
+        assert!(vm.call_function("bump", &[]).is_success());
+        assert_eq!(vm.call_function("get", &[]).word(0), 1u8.into());
+
+        let owner = vm.caller.clone();
+        vm.caller = 7u8.into();
+        assert!(matches!(vm.call_function("pause", &[]), Outcome::Revert(_)));
+        vm.caller = owner;
+        assert!(vm.call_function("pause", &[]).is_success());
+        assert!(matches!(vm.call_function("bump", &[]), Outcome::Revert(_)));
+    }
+
+    #[test]
+    fn modifier_code_after_the_body_runs_when_it_returns() {
+        let src = "calls: uint256\ntrail: uint256\n\n\
+                   modifier counted:\n    _\n    calls += 1\n\n\
+                   modifier outer:\n    _\n    trail = trail * 10 + 1\n\n\
+                   modifier inner:\n    _\n    trail = trail * 10 + 2\n\n\
+                   @counted\ndef pick(x: uint256) -> uint256:\n    if x > 5:\n        return 1\n    return 2\n\n\
+                   @outer\n@inner\ndef mark() -> (done: bool):\n    done = true\n    return\n\n\
+                   def via(x: uint256) -> uint256: return pick(x) + calls\n\n\
+                   @view\ndef get() -> (uint256, uint256): return calls, trail\n";
+        let mut vm = Vm::from_source(src);
+        assert_eq!(vm.call_function("pick", &[9u8.into()]).word(0), 1u8.into());
+        assert_eq!(vm.call_function("pick", &[1u8.into()]).word(0), 2u8.into());
+        assert_eq!(vm.call_function("get", &[]).word(0), 2u8.into());
+        assert_eq!(vm.call_function("via", &[9u8.into()]).word(0), 4u8.into());
+        assert_eq!(vm.call_function("mark", &[]).word(0), 1u8.into());
+        let out = vm.call_function("get", &[]);
+        assert_eq!([out.word(0), out.word(1)], [3u8.into(), 21u8.into()]);
+    }
+
+    #[test]
+    fn self_prefix_reaches_storage() {
+        let src = "struct Pool {\n    total: uint256\n}\n\npool: Pool\ncount: uint256\nbalances: mapping[address => uint256]\nitems: uint256[]\n\ndef add(n: uint256):\n    self.count += n\n    self.balances[msg.sender] += n\n    self.pool.total = self.count + 1\n    self.items.push(n)\n\n@view\ndef get() -> (uint256, uint256, uint256, uint256):\n    return count, balances[msg.sender], pool.total, self.items.length\n";
+        let mut vm = Vm::from_source(src);
+        assert!(vm.call_function("add", &[5u8.into()]).is_success());
+        assert!(vm.call_function("add", &[2u8.into()]).is_success());
+        let out = vm.call_function("get", &[]);
+        assert_eq!(
+            [out.word(0), out.word(1), out.word(2), out.word(3)],
+            [7u8.into(), 7u8.into(), 8u8.into(), 2u8.into()]
+        );
+    }
+
+    #[test]
+    fn folded_constants_keep_overflow_checks() {
+        let src = "def small() -> uint256: return 2 ** 8 - 1\n\ndef big() -> uint256: return 2 ** 255 * 2\n";
+        let mut vm = Vm::from_source(src);
+        assert_eq!(vm.call_function("small", &[]).word(0), 255u8.into());
+        assert!(matches!(vm.call_function("big", &[]), Outcome::Revert(_)));
+    }
+
+    #[test]
+    fn revert_encodes_custom_error() {
+        let src = "error InsufficientBalance(needed: uint256, available: uint256)\n\ndef withdraw(amount: uint256):\n    if amount > 10:\n        revert InsufficientBalance(amount, 10)\n";
+        let mut vm = Vm::from_source(src);
+        assert!(vm.call_function("withdraw", &[5u8.into()]).is_success());
+
+        let out = vm.call_function("withdraw", &[500u32.into()]);
+        let Outcome::Revert(data) = &out else { panic!("{out:?}") };
+        assert_eq!(data.len(), 4 + 64);
+        assert_eq!(data[..4], [0xcf, 0x47, 0x91, 0x81]);
+        let words: Vec<BigUint> = data[4..].chunks(32).map(BigUint::from_bytes_be).collect();
+        assert_eq!(words, [500u32.into(), 10u8.into()]);
+    }
+}
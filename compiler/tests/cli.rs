@@ -59,6 +59,21 @@ fn pyra_build_fails_on_parse_error() {
         .stderr(contains("parse failed"));
 }
 
+#[test]
+fn pyra_build_reports_every_parse_error() {
+    let mut file = NamedTempFile::new().unwrap();
+    write!(file, "def a() -> :\n    return 1\n\ndef b():\n    x = = 1\n").unwrap();
+
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("pyra"));
+    cmd.arg("build")
+        .arg(file.path())
+        .arg("--no-color")
+        .assert()
+        .failure()
+        .stderr(contains(":1:12"))
+        .stderr(contains(":5:7"));
+}
+
 #[test]
 fn pyra_build_parses_multiline_require() {
     let mut file = NamedTempFile::new().unwrap();
@@ -84,6 +99,52 @@ fn pyra_build_parses_multiline_require() {
     assert!(out_dir.path().join(format!("{stem}.bin")).exists());
 }
 
+#[test]
+fn pyra_build_warns_on_bare_require() {
+    let mut file = NamedTempFile::new().unwrap();
+    write!(file, "def t(x: uint256):\n    require x > 0\n").unwrap();
+    let out_dir = TempDir::new().unwrap();
+
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("pyra"));
+    cmd.arg("build")
+        .arg(file.path())
+        .arg("--out-dir")
+        .arg(out_dir.path())
+        .arg("--no-color")
+        .assert()
+        .success()
+        .stderr(contains("warning[W2001]: `require` without a message"))
+        .stderr(contains(":2:5"))
+        .stderr(contains("= note: silence this with `@allow(bare_revert)` on the function"));
+}
+
+#[test]
+fn pyra_build_strict_fails_on_warning() {
+    let mut file = NamedTempFile::new().unwrap();
+    write!(file, "def t(a: uint256):\n    let unused = a\n").unwrap();
+    let out_dir = TempDir::new().unwrap();
+
+    let build = |strict: bool| {
+        let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("pyra"));
+        cmd.arg("build")
+            .arg(file.path())
+            .arg("--out-dir")
+            .arg(out_dir.path())
+            .arg("--no-color");
+        if strict {
+            cmd.arg("--strict");
+        }
+        cmd.assert()
+    };
+    build(false)
+        .success()
+        .stderr(contains("warning[W2002]: unused variable `unused`"));
+    build(true)
+        .failure()
+        .stderr(contains("error[W2002]: unused variable `unused`"))
+        .stderr(contains(":2:5"));
+}
+
 #[test]
 fn pyra_build_erc20_contract() {
     let out_dir = TempDir::new().unwrap();
@@ -100,6 +161,72 @@ fn pyra_build_erc20_contract() {
     assert!(out_dir.path().join("ERC20.bin").exists());
 }
 
+#[test]
+fn pyra_build_appends_constructor_args() {
+    let plain_dir = TempDir::new().unwrap();
+    let args_dir = TempDir::new().unwrap();
+    let args = format!("{:064x}{:064x}", 1000, 18);
+
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("pyra"));
+    cmd.arg("build")
+        .arg("../contracts/ERC20.pyra")
+        .arg("--out-dir")
+        .arg(plain_dir.path())
+        .assert()
+        .success();
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("pyra"));
+    cmd.arg("build")
+        .arg("../contracts/ERC20.pyra")
+        .arg("--out-dir")
+        .arg(args_dir.path())
+        .arg("--constructor-args")
+        .arg(format!("0x{args}"))
+        .assert()
+        .success();
+
+    let plain = std::fs::read_to_string(plain_dir.path().join("ERC20.bin")).unwrap();
+    let with_args = std::fs::read_to_string(args_dir.path().join("ERC20.bin")).unwrap();
+    assert!(with_args.ends_with(&args));
+    assert_eq!(with_args, format!("{plain}{args}"));
+}
+
+#[test]
+fn pyra_build_rejects_misaligned_constructor_args() {
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("pyra"));
+    cmd.arg("build")
+        .arg("../contracts/ERC20.pyra")
+        .arg("--constructor-args")
+        .arg("0x1234")
+        .assert()
+        .failure()
+        .stderr(contains("multiple of 32 bytes"));
+}
+
+#[test]
+fn pyra_build_summary_json_for_erc20() {
+    let out_dir = TempDir::new().unwrap();
+
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("pyra"));
+    let output = cmd
+        .arg("build")
+        .arg("../contracts/ERC20.pyra")
+        .arg("--out-dir")
+        .arg(out_dir.path())
+        .arg("--summary-json")
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    let summary: serde_json::Value = serde_json::from_str(stdout.lines().last().unwrap()).unwrap();
+    assert_eq!(summary["contract"], "ERC20");
+    assert_eq!(summary["functions"], 2);
+    let runtime_size = summary["runtime_size"].as_u64().unwrap();
+    assert!(runtime_size > 0);
+    let deploy = std::fs::read_to_string(out_dir.path().join("ERC20.bin")).unwrap();
+    assert_eq!(summary["deploy_size"].as_u64().unwrap() as usize, deploy.trim().len() / 2);
+}
+
 #[test]
 fn pyra_build_vault_contract() {
     let out_dir = TempDir::new().unwrap();
@@ -135,3 +262,962 @@ fn pyra_build_gas_report() {
         .stdout(contains("Gas Report"))
         .stdout(contains("gas"));
 }
+
+#[test]
+fn pyra_build_resolves_relative_import() {
+    let dir = TempDir::new().unwrap();
+    std::fs::write(dir.path().join("common.pyra"), "const FEE: uint256 = 5\n").unwrap();
+    let main = dir.path().join("main.pyra");
+    std::fs::write(
+        &main,
+        "import \"common.pyra\"\n\ndef fee() -> uint256: return FEE\n",
+    )
+    .unwrap();
+
+    let out_dir = TempDir::new().unwrap();
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("pyra"));
+    cmd.arg("build")
+        .arg(&main)
+        .arg("--out-dir")
+        .arg(out_dir.path())
+        .assert()
+        .success();
+
+    assert!(out_dir.path().join("main.bin").exists());
+}
+
+#[test]
+fn pyra_build_uses_include_path() {
+    let lib_dir = TempDir::new().unwrap();
+    std::fs::write(lib_dir.path().join("common.pyra"), "const FEE: uint256 = 5\n").unwrap();
+
+    let src_dir = TempDir::new().unwrap();
+    let main = src_dir.path().join("main.pyra");
+    std::fs::write(
+        &main,
+        "import \"common.pyra\"\n\ndef fee() -> uint256: return FEE\n",
+    )
+    .unwrap();
+
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("pyra"));
+    cmd.arg("build")
+        .arg(&main)
+        .arg("--out-dir")
+        .arg(src_dir.path())
+        .assert()
+        .failure()
+        .stderr(contains("cannot resolve import"))
+        .stderr(contains("main.pyra:1:1"));
+
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("pyra"));
+    cmd.arg("build")
+        .arg(&main)
+        .arg("--out-dir")
+        .arg(src_dir.path())
+        .arg("--include")
+        .arg(lib_dir.path())
+        .assert()
+        .success();
+}
+
+#[test]
+fn pyra_build_rejects_import_cycle() {
+    let dir = TempDir::new().unwrap();
+    std::fs::write(dir.path().join("a.pyra"), "import \"b.pyra\"\n").unwrap();
+    std::fs::write(dir.path().join("b.pyra"), "import \"a.pyra\"\n").unwrap();
+
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("pyra"));
+    cmd.arg("build")
+        .arg(dir.path().join("a.pyra"))
+        .assert()
+        .failure()
+        .stderr(contains("import cycle"));
+}
+
+#[test]
+fn pyra_build_rejects_duplicate_imported_definition() {
+    let dir = TempDir::new().unwrap();
+    std::fs::write(dir.path().join("common.pyra"), "const FEE: uint256 = 5\n").unwrap();
+    let main = dir.path().join("main.pyra");
+    std::fs::write(
+        &main,
+        "import \"common.pyra\"\nconst FEE: uint256 = 6\n\ndef fee() -> uint256: return FEE\n",
+    )
+    .unwrap();
+
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("pyra"));
+    cmd.arg("build")
+        .arg(&main)
+        .assert()
+        .failure()
+        .stderr(contains("duplicate definition `FEE`"));
+}
+
+#[test]
+fn pyra_build_op_histogram() {
+    let mut file = NamedTempFile::new().unwrap();
+    write!(file, "def t():\n    x = 1\n    y = 2\n").unwrap();
+    let path = file.path().to_path_buf();
+
+    let out_dir = TempDir::new().unwrap();
+
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("pyra"));
+    cmd.arg("build")
+        .arg(&path)
+        .arg("--out-dir")
+        .arg(out_dir.path())
+        .arg("--op-histogram")
+        .assert()
+        .success()
+        .stdout(contains("Opcode Histogram"))
+        .stdout(contains("SSTORE"));
+}
+
+#[test]
+fn pyra_build_no_color_has_no_escape_codes() {
+    let mut file = NamedTempFile::new().unwrap();
+    write!(file, "def t( -> bool: return true").unwrap();
+
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("pyra"));
+    let out = cmd
+        .arg("build")
+        .arg(file.path())
+        .arg("--no-color")
+        .env("CLICOLOR_FORCE", "1")
+        .assert()
+        .failure()
+        .get_output()
+        .stderr
+        .clone();
+    let stderr = String::from_utf8(out).unwrap();
+    assert!(!stderr.contains('\x1b'));
+    assert!(stderr.contains("error[E1001]: parse failed"));
+    assert!(stderr.contains(":1:8"));
+    assert!(stderr.contains("^^"));
+}
+
+#[test]
+fn pyra_build_forced_color_uses_escape_codes() {
+    let mut file = NamedTempFile::new().unwrap();
+    write!(file, "def t( -> bool: return true").unwrap();
+
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("pyra"));
+    cmd.arg("build")
+        .arg(file.path())
+        .env("CLICOLOR_FORCE", "1")
+        .env_remove("NO_COLOR")
+        .assert()
+        .failure()
+        .stderr(contains("\x1b[31m"));
+}
+
+#[test]
+fn pyra_build_sort_abi_orders_alphabetically() {
+    let dir = TempDir::new().unwrap();
+    let main = dir.path().join("main.pyra");
+    std::fs::write(
+        &main,
+        "def zeta() -> bool: return true\n\ndef alpha() -> bool: return true\n",
+    )
+    .unwrap();
+
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("pyra"));
+    cmd.arg("build").arg(&main).assert().success();
+    let abi = std::fs::read_to_string(dir.path().join("main.abi")).unwrap();
+    assert!(abi.find("zeta").unwrap() < abi.find("alpha").unwrap());
+
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("pyra"));
+    cmd.arg("build").arg(&main).arg("--sort-abi").assert().success();
+    let abi = std::fs::read_to_string(dir.path().join("main.abi")).unwrap();
+    assert!(abi.find("alpha").unwrap() < abi.find("zeta").unwrap());
+}
+
+#[test]
+fn pyra_build_print_slot_count() {
+    let mut file = NamedTempFile::new().unwrap();
+    write!(
+        file,
+        "const supply: uint256 = 100\n\nstruct State {{\n    owner: address,\n    paused: bool\n}}\n\nlet state: State = State {{ paused: false }}\n\ndef t() -> uint256: return supply\n"
+    )
+    .unwrap();
+    let out_dir = TempDir::new().unwrap();
+
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("pyra"));
+    cmd.arg("build")
+        .arg(file.path())
+        .arg("--out-dir")
+        .arg(out_dir.path())
+        .arg("--print-slot-count")
+        .assert()
+        .success()
+        .stdout(contains("supply                 slot 0"))
+        .stdout(contains("state.paused           slot 2"))
+        .stdout(contains("slot count             3"));
+}
+
+#[test]
+fn pyra_build_format_eof() {
+    let mut file = NamedTempFile::new().unwrap();
+    write!(file, "def t() -> bool: return true").unwrap();
+    let path = file.path().to_path_buf();
+    let out_dir = TempDir::new().unwrap();
+
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("pyra"));
+    cmd.arg("build")
+        .arg(&path)
+        .arg("--out-dir")
+        .arg(out_dir.path())
+        .arg("--format")
+        .arg("eof")
+        .assert()
+        .success();
+
+    let stem = path.file_stem().unwrap().to_str().unwrap();
+    let bin_hex = std::fs::read_to_string(out_dir.path().join(format!("{stem}.bin"))).unwrap();
+    assert!(bin_hex.starts_with("ef0001010004020001"));
+}
+
+#[test]
+fn pyra_check_accepts_valid_file() {
+    let mut file = NamedTempFile::new().unwrap();
+    write!(file, "def t(a: uint256):\n    let unused = a\n").unwrap();
+
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("pyra"));
+    cmd.arg("check")
+        .arg(file.path())
+        .arg("--no-color")
+        .assert()
+        .success()
+        .stdout(contains(": ok"))
+        .stderr(contains("warning[W2002]"));
+}
+
+#[test]
+fn pyra_check_reports_every_type_error() {
+    let mut file = NamedTempFile::new().unwrap();
+    write!(file, "def t(a: uint256) -> bool:\n    let b: bool = a\n    return a\n\ndef u():\n    x = y\n").unwrap();
+
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("pyra"));
+    cmd.arg("check")
+        .arg(file.path())
+        .arg("--no-color")
+        .assert()
+        .failure()
+        .stderr(contains("error[E2002]: type mismatch"))
+        .stderr(contains(":2:5"))
+        .stderr(contains("error[E2005]: return type mismatch"))
+        .stderr(contains("error[E2001]: undefined variable `y`"))
+        .stderr(contains(":6:5"));
+}
+
+#[test]
+fn pyra_check_locates_diagnostics_in_imported_files() {
+    let dir = TempDir::new().unwrap();
+    let main = dir.path().join("main.pyra");
+    let lib = dir.path().join("lib.pyra");
+    std::fs::write(&main, "import \"lib.pyra\"\n\ndef main_fn() -> uint256:\n    return helper()\n").unwrap();
+    let check = || {
+        let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("pyra"));
+        cmd.arg("check").arg(&main).arg("--no-color").assert()
+    };
+
+    std::fs::write(&lib, "def helper() -> uint256:\n    let a: bool = 5\n    return 1\n").unwrap();
+    check()
+        .failure()
+        .stderr(contains("error[E2002]"))
+        .stderr(contains("lib.pyra:2:5"))
+        .stderr(contains("let a: bool = 5"));
+
+    std::fs::write(&lib, "def helper() -> uint256:\n    let unused = 1\n    return 2\n").unwrap();
+    check()
+        .success()
+        .stderr(contains("warning[W2002]"))
+        .stderr(contains("lib.pyra:2:5"))
+        .stderr(contains("let unused = 1"));
+}
+
+#[test]
+fn pyra_check_strict_fails_on_warning() {
+    let mut file = NamedTempFile::new().unwrap();
+    write!(file, "def t(a: uint256):\n    let unused = a\n").unwrap();
+
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("pyra"));
+    cmd.arg("check")
+        .arg(file.path())
+        .arg("--strict")
+        .arg("--no-color")
+        .assert()
+        .failure()
+        .stderr(contains("error[W2002]"));
+}
+
+#[test]
+fn pyra_verify_accepts_valid_file() {
+    let mut file = NamedTempFile::new().unwrap();
+    write!(file, "def t() -> bool: return true").unwrap();
+
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("pyra"));
+    cmd.arg("verify")
+        .arg(file.path())
+        .assert()
+        .success()
+        .stdout(contains(": ok"));
+}
+
+#[test]
+fn pyra_verify_fails_on_type_error() {
+    let mut file = NamedTempFile::new().unwrap();
+    write!(file, "def t() -> bool: return 1").unwrap();
+
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("pyra"));
+    cmd.arg("verify")
+        .arg(file.path())
+        .arg("--no-color")
+        .assert()
+        .failure()
+        .stderr(contains("return type mismatch"));
+}
+
+#[test]
+fn pyra_type_error_points_at_source() {
+    let mut file = NamedTempFile::new().unwrap();
+    write!(file, "def t(a: uint256):\n    let b: bool = a\n").unwrap();
+
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("pyra"));
+    cmd.arg("verify")
+        .arg(file.path())
+        .arg("--no-color")
+        .assert()
+        .failure()
+        .stderr(contains("error[E2002]: type mismatch: expected bool, got uint256"))
+        .stderr(contains(":2:5"))
+        .stderr(contains("^^^^^^^^^^^^^^^"));
+}
+
+#[test]
+fn pyra_build_rejects_literal_past_256_bits() {
+    let dir = TempDir::new().unwrap();
+    let main = dir.path().join("main.pyra");
+    std::fs::write(&main, "def t() -> uint256: return 2e77\n").unwrap();
+
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("pyra"));
+    cmd.arg("build")
+        .arg(&main)
+        .arg("--no-color")
+        .assert()
+        .failure()
+        .stderr(contains("error[E2034]: literal `200000"))
+        .stderr(contains("does not fit in uint256"));
+}
+
+#[test]
+fn pyra_verify_fails_on_orphan_jump() {
+    let mut file = NamedTempFile::new().unwrap();
+    write!(file, "def t():\n    asm: JUMP 7\n").unwrap();
+
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("pyra"));
+    cmd.arg("verify")
+        .arg(file.path())
+        .arg("--no-color")
+        .assert()
+        .failure()
+        .stderr(contains("jump to undefined label"));
+}
+
+#[test]
+fn pyra_build_no_check_mul_drops_mul_guard() {
+    let dir = TempDir::new().unwrap();
+    let main = dir.path().join("main.pyra");
+    std::fs::write(&main, "def t(a: uint256, b: uint256) -> uint256: return a * b\n").unwrap();
+
+    let build = |extra: &[&str]| {
+        let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("pyra"));
+        cmd.arg("build").arg(&main).args(extra).assert().success();
+        std::fs::read_to_string(dir.path().join("main.bin")).unwrap()
+    };
+    let checked = build(&[]);
+    let unchecked = build(&["--no-check-mul"]);
+    assert!(unchecked.len() < checked.len());
+}
+
+#[test]
+fn pyra_build_strict_calldata_adds_size_check() {
+    let dir = TempDir::new().unwrap();
+    let main = dir.path().join("main.pyra");
+    std::fs::write(&main, "def t(a: uint256) -> uint256: return a\n").unwrap();
+
+    let build = |extra: &[&str]| {
+        let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("pyra"));
+        cmd.arg("build").arg(&main).args(extra).assert().success();
+        std::fs::read_to_string(dir.path().join("main.bin")).unwrap()
+    };
+    let lenient = build(&[]);
+    let strict = build(&["--strict-calldata"]);
+    assert!(strict.len() > lenient.len());
+    assert!(strict.contains("602436"));
+}
+
+#[test]
+fn pyra_build_opt_level_is_recorded() {
+    let dir = TempDir::new().unwrap();
+    let main = dir.path().join("main.pyra");
+    std::fs::write(&main, "def t() -> uint256: return 1 + 2\n").unwrap();
+
+    let build = |extra: &[&str]| {
+        let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("pyra"));
+        cmd.arg("build").arg(&main).args(extra).assert().success();
+        let bin = std::fs::read_to_string(dir.path().join("main.bin")).unwrap();
+        let meta = std::fs::read_to_string(dir.path().join("main.meta.json")).unwrap();
+        (bin, meta)
+    };
+    let (optimized, meta) = build(&[]);
+    assert!(meta.contains("\"opt_level\":\"O2\""));
+    let (unoptimized, meta) = build(&["-O0"]);
+    assert!(meta.contains("\"opt_level\":\"O0\""));
+    assert!(optimized.len() < unoptimized.len());
+
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("pyra"));
+    cmd.arg("build")
+        .arg(&main)
+        .args(["--opt-level", "1", "--summary-json"])
+        .assert()
+        .success()
+        .stdout(contains("\"opt_level\":\"O1\""));
+}
+
+#[test]
+fn pyra_build_writes_source_maps() {
+    let dir = TempDir::new().unwrap();
+    let main = dir.path().join("main.pyra");
+    std::fs::write(&main, "x: uint256\n\ndef set(v: uint256):\n    x = v\n").unwrap();
+
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("pyra"));
+    cmd.arg("build").arg(&main).assert().success();
+    assert!(!dir.path().join("main.srcmap").exists());
+
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("pyra"));
+    cmd.arg("build").arg(&main).arg("--source-map").assert().success();
+    let runtime = std::fs::read_to_string(dir.path().join("main.runtime.srcmap")).unwrap();
+    assert!(runtime.starts_with("-1:-1:-1:-;"));
+    assert!(runtime.contains(";37:5:0;"));
+    assert!(dir.path().join("main.srcmap").exists());
+}
+
+#[test]
+fn pyra_build_emits_ast_json() {
+    let dir = TempDir::new().unwrap();
+    let main = dir.path().join("main.pyra");
+    std::fs::write(&main, "def t(x: uint256) -> uint256:\n    return x + 1\n").unwrap();
+
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("pyra"));
+    cmd.arg("build").arg(&main).args(["--emit", "ast-json"]).assert().success();
+    let json = std::fs::read_to_string(dir.path().join("main.ast.json")).unwrap();
+    assert!(json.contains("\"Function\""));
+    assert!(json.contains("\"name\": \"t\""));
+    assert!(json.contains("\"Add\""));
+}
+
+#[test]
+fn pyra_build_emits_ir_listing() {
+    let dir = TempDir::new().unwrap();
+    let main = dir.path().join("main.pyra");
+    std::fs::write(&main, "def t(x: uint256) -> uint256:\n    return x * (2 + 3)\n").unwrap();
+
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("pyra"));
+    cmd.arg("build").arg(&main).args(["--emit", "ir"]).assert().success();
+    let ir = std::fs::read_to_string(dir.path().join("main.ir")).unwrap();
+    assert!(ir.starts_with("constructor\n"));
+    assert!(ir.contains("function t selector 0x"));
+    assert!(ir.contains("CALLVALUE"));
+    assert!(!ir.contains("; ===="));
+
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("pyra"));
+    cmd.arg("build").arg(&main).args(["--emit", "ir-stages"]).assert().success();
+    let ir = std::fs::read_to_string(dir.path().join("main.ir")).unwrap();
+    let unoptimized = ir.find("; ==== unoptimized ====").unwrap();
+    let optimized = ir.find("; ==== optimized ====").unwrap();
+    let hardened = ir.find("; ==== hardened ====").unwrap();
+    assert!(unoptimized < optimized && optimized < hardened);
+    assert!(ir[unoptimized..optimized].contains("PUSH 0x03"));
+    assert!(ir[optimized..hardened].contains("PUSH 0x05"));
+}
+
+#[test]
+fn pyra_disasm_lists_bytecode() {
+    let dir = TempDir::new().unwrap();
+    let main = dir.path().join("main.pyra");
+    std::fs::write(&main, "def t(x: uint256) -> uint256:\n    return x\n").unwrap();
+
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("pyra"));
+    cmd.arg("build").arg(&main).assert().success();
+
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("pyra"));
+    let out = cmd.arg("disasm").arg(dir.path().join("main.bin")).output().unwrap();
+    assert!(out.status.success());
+    let listing = String::from_utf8(out.stdout).unwrap();
+    let (deploy, runtime) = listing.split_once("; ==== runtime (at 0x").unwrap();
+    assert!(deploy.contains("CODECOPY"));
+    assert!(runtime.contains("CALLDATALOAD"));
+    assert!(runtime.contains("JUMPDEST"));
+    assert!(!runtime.contains("(not a JUMPDEST)"));
+
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("pyra"));
+    cmd.arg("disasm")
+        .write_stdin("0x600456fe5b\n")
+        .assert()
+        .success()
+        .stdout(predicates::str::contains("; -> 0004\n"))
+        .stdout(predicates::str::contains("0004  JUMPDEST"));
+
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("pyra"));
+    cmd.args(["disasm", "-"])
+        .write_stdin("60zz")
+        .assert()
+        .failure()
+        .stderr(predicates::str::contains("invalid hex bytecode"));
+}
+
+#[test]
+fn pyra_standard_json_reads_stdin() {
+    let input = r#"{
+        "language": "Pyra",
+        "sources": { "Token.pyra": { "content": "def total() -> uint256:\n    return 1\n" } },
+        "settings": { "outputSelection": { "*": { "*": ["abi", "evm.bytecode.object"] } } }
+    }"#;
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("pyra"));
+    cmd.arg("--standard-json")
+        .write_stdin(input)
+        .assert()
+        .success()
+        .stdout(contains(r#""contracts":{"Token.pyra":{"Token":{"abi":[{"#))
+        .stdout(contains(r#""errors":[]"#));
+
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("pyra"));
+    cmd.arg("--standard-json")
+        .write_stdin("not json")
+        .assert()
+        .success()
+        .stdout(contains(r#""type":"JSONError""#));
+
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("pyra"));
+    cmd.assert().failure().stdout(contains("Usage"));
+}
+
+#[test]
+fn pyra_build_writes_foundry_artifact() {
+    let dir = TempDir::new().unwrap();
+    let main = dir.path().join("Token.pyra");
+    std::fs::write(&main, "def total() -> uint256:\n    return 1\n").unwrap();
+
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("pyra"));
+    cmd.current_dir(dir.path())
+        .args(["build", "Token.pyra", "--artifact-format", "foundry", "--emit", "ir"])
+        .assert()
+        .success();
+    let artifact = dir.path().join("out/Token.pyra/Token.json");
+    let json = std::fs::read_to_string(&artifact).unwrap();
+    assert!(json.contains("\"methodIdentifiers\": {\n    \"total()\": \"2ddbd13a\"\n  }"));
+    assert!(json.contains("\"object\": \"0x60"));
+    assert!(json.contains("\"immutableReferences\": {}"));
+    assert!(dir.path().join("out/Token.pyra/Token.ir").exists());
+    assert!(!dir.path().join("Token.bin").exists());
+
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("pyra"));
+    cmd.arg("build")
+        .arg(&main)
+        .args(["--artifact-format", "foundry", "-o"])
+        .arg(dir.path().join("build"))
+        .assert()
+        .success();
+    assert!(dir.path().join("build/Token.pyra/Token.json").exists());
+
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("pyra"));
+    cmd.arg("build")
+        .arg(&main)
+        .args(["--artifact-format", "foundry", "--constructor-args", "0x"])
+        .assert()
+        .failure()
+        .stderr(contains("cannot be used with"));
+}
+
+#[test]
+fn pyra_build_writes_hardhat_artifact() {
+    let dir = TempDir::new().unwrap();
+    std::fs::create_dir(dir.path().join("contracts")).unwrap();
+    std::fs::write(
+        dir.path().join("contracts/Token.pyra"),
+        "def total() -> uint256:\n    return 1\n",
+    )
+    .unwrap();
+
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("pyra"));
+    cmd.current_dir(dir.path())
+        .args(["build", "./contracts/Token.pyra", "--artifact-format", "hardhat"])
+        .assert()
+        .success();
+    let artifact = dir.path().join("artifacts/contracts/Token.pyra/Token.json");
+    let json = std::fs::read_to_string(&artifact).unwrap();
+    assert!(json.contains("\"_format\": \"hh-sol-artifact-1\""));
+    assert!(json.contains("\"contractName\": \"Token\""));
+    assert!(json.contains("\"sourceName\": \"contracts/Token.pyra\""));
+    assert!(json.contains("\"bytecode\": \"0x60"));
+    assert!(json.contains("\"deployedLinkReferences\": {}"));
+    assert!(json.contains("\"name\": \"total\""));
+
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("pyra"));
+    cmd.arg("build")
+        .arg(dir.path().join("contracts/Token.pyra"))
+        .args(["--artifact-format", "hardhat", "-o"])
+        .arg(dir.path().join("build"))
+        .assert()
+        .success();
+    let json = std::fs::read_to_string(dir.path().join("build/Token.pyra/Token.json")).unwrap();
+    assert!(json.contains("\"sourceName\": \"Token.pyra\""));
+}
+
+#[test]
+fn pyra_init_scaffolds_a_project() {
+    let dir = TempDir::new().unwrap();
+    let project = dir.path().join("my-token");
+
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("pyra"));
+    cmd.arg("init")
+        .arg(&project)
+        .assert()
+        .success()
+        .stdout(contains("pyra.toml"))
+        .stdout(contains("Counter.pyra"));
+    let manifest = std::fs::read_to_string(project.join("pyra.toml")).unwrap();
+    assert!(manifest.contains("name = \"my-token\""));
+    assert!(project.join("tests/test_counter.pyra").exists());
+    assert!(project.join(".gitignore").exists());
+
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("pyra"));
+    cmd.arg("build")
+        .arg(project.join("contracts/Counter.pyra"))
+        .assert()
+        .success()
+        .stderr("");
+
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("pyra"));
+    cmd.current_dir(&project)
+        .arg("init")
+        .assert()
+        .failure()
+        .stderr(contains("pyra.toml already exists"));
+}
+
+#[test]
+fn pyra_build_resolves_from_imports() {
+    let dir = TempDir::new().unwrap();
+    let lib = dir.path().join("lib/math");
+    std::fs::create_dir_all(&lib).unwrap();
+    std::fs::write(
+        lib.join("wad.pyra"),
+        "const WAD: uint256 = 1e18\n\ndef scale(a: uint256) -> uint256:\n    return a * WAD\n\ndef unused() -> uint256:\n    return 0\n",
+    )
+    .unwrap();
+    let main = dir.path().join("main.pyra");
+    std::fs::write(
+        &main,
+        "from math.wad import scale\n\ndef unused() -> uint256:\n    return scale(2)\n",
+    )
+    .unwrap();
+
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("pyra"));
+    cmd.arg("build").arg(&main).arg("-I").arg(dir.path().join("lib")).assert().success();
+    let abi = std::fs::read_to_string(dir.path().join("main.abi")).unwrap();
+    assert!(abi.contains("\"name\":\"unused\""));
+    assert!(!abi.contains("\"name\":\"scale\""));
+
+    std::fs::write(&main, "const WAD: uint256 = 1\nimport \"math/wad.pyra\"\n").unwrap();
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("pyra"));
+    cmd.arg("build")
+        .arg(&main)
+        .arg("-I")
+        .arg(dir.path().join("lib"))
+        .assert()
+        .failure()
+        .stderr(contains("error[E2007]"))
+        .stderr(contains("wad.pyra:1:7"));
+
+    std::fs::write(&main, "from math.wad import nope\n").unwrap();
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("pyra"));
+    cmd.arg("build")
+        .arg(&main)
+        .arg("-I")
+        .arg(dir.path().join("lib"))
+        .assert()
+        .failure()
+        .stderr(contains("error[E5005]"))
+        .stderr(contains("has no item named `nope`"));
+}
+
+#[test]
+fn pyra_build_evm_version() {
+    let dir = TempDir::new().unwrap();
+    let main = dir.path().join("main.pyra");
+    std::fs::write(&main, "def t(x: uint256) -> uint256: return x\n").unwrap();
+
+    let build = |extra: &[&str]| {
+        let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("pyra"));
+        cmd.arg("build").arg(&main).args(extra).assert().success();
+        let bin = std::fs::read_to_string(dir.path().join("main.bin")).unwrap();
+        let meta = std::fs::read_to_string(dir.path().join("main.meta.json")).unwrap();
+        (bin, meta)
+    };
+    let (paris, meta) = build(&[]);
+    assert!(meta.contains("\"evm_version\":\"paris\""));
+    let (shanghai, meta) = build(&["--evm-version", "shanghai"]);
+    assert!(meta.contains("\"evm_version\":\"shanghai\""));
+    assert!(shanghai.len() < paris.len());
+
+    std::fs::write(&main, "def t():\n    asm: PUSH1 0x00 TLOAD POP\n").unwrap();
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("pyra"));
+    cmd.arg("build").arg(&main).assert().failure().stderr(contains("TLOAD requires evm version cancun"));
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("pyra"));
+    cmd.arg("build").arg(&main).args(["--evm-version", "cancun"]).assert().success();
+}
+
+#[test]
+fn pyra_fmt_rewrites_file() {
+    let mut file = NamedTempFile::new().unwrap();
+    write!(file, "def t( a:uint256 )->uint256:\n  return a*2\n").unwrap();
+
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("pyra"));
+    cmd.arg("fmt").arg(file.path()).assert().success();
+    let formatted = std::fs::read_to_string(file.path()).unwrap();
+    assert_eq!(formatted, "def t(a: uint256) -> uint256:\n    return a * 2\n");
+}
+
+#[test]
+fn pyra_fmt_check_lists_unformatted_files() {
+    let mut messy = NamedTempFile::new().unwrap();
+    write!(messy, "def t():\n  x = 1\n").unwrap();
+    let mut clean = NamedTempFile::new().unwrap();
+    write!(clean, "def t():\n    x = 1\n").unwrap();
+
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("pyra"));
+    cmd.arg("fmt")
+        .arg("--check")
+        .arg(messy.path())
+        .assert()
+        .failure()
+        .stdout(contains("would reformat"));
+    assert_eq!(std::fs::read_to_string(messy.path()).unwrap(), "def t():\n  x = 1\n");
+
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("pyra"));
+    cmd.arg("fmt").arg("--check").arg(clean.path()).assert().success();
+}
+
+#[test]
+fn pyra_fmt_leaves_broken_file_alone() {
+    let mut file = NamedTempFile::new().unwrap();
+    file.write_all(b"def t( -> :\n").unwrap();
+
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("pyra"));
+    cmd.arg("fmt")
+        .arg(file.path())
+        .arg("--no-color")
+        .assert()
+        .failure()
+        .stderr(contains("error[E1001]"));
+    assert_eq!(std::fs::read_to_string(file.path()).unwrap(), "def t( -> :\n");
+}
+
+#[test]
+fn pyra_build_compiles_the_project() {
+    let dir = TempDir::new().unwrap();
+    let project = dir.path().join("app");
+    let math = dir.path().join("math");
+    std::fs::create_dir_all(&math).unwrap();
+    std::fs::write(
+        math.join("fixed.pyra"),
+        "def double(x: uint256) -> uint256:\n    return x * 2\n",
+    )
+    .unwrap();
+
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("pyra"));
+    cmd.arg("init").arg(&project).assert().success();
+    let manifest = std::fs::read_to_string(project.join("pyra.toml")).unwrap();
+    std::fs::write(
+        project.join("pyra.toml"),
+        format!("{manifest}math = {{ path = \"../math\" }}\n"),
+    )
+    .unwrap();
+    std::fs::create_dir_all(project.join("contracts/vaults")).unwrap();
+    std::fs::write(
+        project.join("contracts/vaults/Quad.pyra"),
+        "from math.fixed import double\n\n@view\ndef quad(x: uint256) -> uint256:\n    return double(double(x))\n",
+    )
+    .unwrap();
+
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("pyra"));
+    cmd.current_dir(project.join("contracts"))
+        .arg("build")
+        .assert()
+        .success()
+        .stderr("");
+    assert!(project.join("out/Counter.bin").exists());
+    assert!(project.join("out/Quad.bin").exists());
+
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("pyra"));
+    cmd.current_dir(&project)
+        .args(["build", "--artifact-format", "foundry"])
+        .assert()
+        .success();
+    assert!(project.join("out/Quad.pyra/Quad.json").exists());
+
+    std::fs::write(project.join("pyra.toml"), "[build]\nopt_level = \"fast\"\n").unwrap();
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("pyra"));
+    cmd.current_dir(&project)
+        .arg("build")
+        .assert()
+        .failure()
+        .stderr(contains("error[E5006]"))
+        .stderr(contains("pyra.toml:2:13"));
+}
+
+#[test]
+fn pyra_build_reuses_unchanged_contracts() {
+    let dir = TempDir::new().unwrap();
+    let project = dir.path();
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("pyra"));
+    cmd.arg("init").arg(project).assert().success();
+    std::fs::write(project.join("contracts/helpers.pyra"), "const STEP: uint256 = 1\n").unwrap();
+    std::fs::write(
+        project.join("contracts/Stepper.pyra"),
+        "import \"helpers.pyra\"\n\ncount: uint256\n\ndef step():\n    self.count += STEP\n",
+    )
+    .unwrap();
+
+    let build = || {
+        let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("pyra"));
+        let output = cmd.current_dir(project).arg("build").output().unwrap();
+        assert!(output.status.success());
+        String::from_utf8(output.stdout).unwrap()
+    };
+    let first = build();
+    assert!(first.contains("compiled contracts/Counter.pyra"), "{first}");
+    assert!(first.contains("compiled contracts/Stepper.pyra"), "{first}");
+    assert!(project.join(".pyra-cache").is_dir());
+
+    assert_eq!(build(), "");
+    std::fs::remove_file(project.join("out/Counter.bin")).unwrap();
+    assert_eq!(build(), "");
+    assert!(project.join("out/Counter.bin").exists());
+
+    std::fs::write(project.join("contracts/helpers.pyra"), "const STEP: uint256 = 2\n").unwrap();
+    let rebuilt = build();
+    assert!(rebuilt.contains("compiled contracts/Stepper.pyra"), "{rebuilt}");
+    assert!(!rebuilt.contains("Counter"), "{rebuilt}");
+}
+
+#[test]
+fn pyra_build_compiles_many_inputs_in_order() {
+    let dir = TempDir::new().unwrap();
+    let src = dir.path().join("src");
+    std::fs::create_dir_all(src.join("nested")).unwrap();
+    std::fs::write(src.join("a_broken.pyra"), "def f() -> uint256:\n    return missing\n").unwrap();
+    std::fs::write(src.join("b_ok.pyra"), "x: uint256\n").unwrap();
+    std::fs::write(src.join("nested/c_broken.pyra"), "def g():\n    return unknown\n").unwrap();
+    let extra = dir.path().join("d_ok.pyra");
+    std::fs::write(&extra, "y: uint256\n").unwrap();
+    let out = dir.path().join("out");
+
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("pyra"));
+    let output = cmd
+        .args(["build", "--no-color", "-j", "4"])
+        .arg(&src)
+        .arg(&extra)
+        .arg(src.join("b_ok.pyra"))
+        .arg("-o")
+        .arg(&out)
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(1));
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    let first = stderr.find("a_broken.pyra").expect(&stderr);
+    let second = stderr.find("c_broken.pyra").expect(&stderr);
+    assert!(first < second, "{stderr}");
+    assert!(stderr.contains("`missing`") && stderr.contains("`unknown`"), "{stderr}");
+    assert!(out.join("b_ok.bin").exists());
+    assert!(out.join("d_ok.bin").exists());
+}
+
+#[test]
+fn pyra_build_watch_rebuilds_on_save() {
+    let dir = TempDir::new().unwrap();
+    let input = dir.path().join("Counter.pyra");
+    let bin = dir.path().join("Counter.bin");
+    std::fs::write(&input, "count: uint256\n\ndef bump():\n    self.count += 1\n").unwrap();
+
+    let mut child = std::process::Command::new(assert_cmd::cargo::cargo_bin!("pyra"))
+        .args(["build", "--watch"])
+        .arg(&input)
+        .stdout(std::process::Stdio::null())
+        .stderr(std::process::Stdio::null())
+        .spawn()
+        .unwrap();
+    let wait_for = |done: &dyn Fn() -> bool| {
+        let deadline = std::time::Instant::now() + std::time::Duration::from_secs(20);
+        while !done() && std::time::Instant::now() < deadline {
+            std::thread::sleep(std::time::Duration::from_millis(50));
+        }
+        done()
+    };
+
+    let built = wait_for(&|| bin.exists());
+    let first = std::fs::read_to_string(&bin).unwrap_or_default();
+    std::fs::write(&input, "count: uint256\n\ndef bump():\n    self.count += 10\n").unwrap();
+    let rebuilt = wait_for(&|| std::fs::read_to_string(&bin).is_ok_and(|code| code != first));
+    child.kill().unwrap();
+    child.wait().unwrap();
+    assert!(built, "first build never finished");
+    assert!(rebuilt, "no rebuild after the source changed");
+}
+
+#[test]
+fn pyra_test_runs_the_project_tests() {
+    let dir = TempDir::new().unwrap();
+    let project = dir.path().join("app");
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("pyra"));
+    cmd.arg("init").arg(&project).assert().success();
+
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("pyra"));
+    cmd.current_dir(&project)
+        .arg("test")
+        .assert()
+        .success()
+        .stdout(contains("test test_increment ... ok ("))
+        .stdout(contains("test result: ok. 1 passed; 0 failed"));
+
+    std::fs::write(
+        project.join("tests/test_limits.pyra"),
+        "import \"../contracts/Counter.pyra\"\n\n\
+         error TooHigh(count: uint256)\n\n\
+         def test_revert_cap():\n    increment()\n    revert TooHigh(get())\n\n\
+         def test_cap():\n    increment()\n    revert TooHigh(get())\n\n\
+         def test_count():\n    require get() == 1, \"count starts at zero\"\n",
+    )
+    .unwrap();
+    let out = Command::new(assert_cmd::cargo::cargo_bin!("pyra"))
+        .current_dir(&project)
+        .arg("test")
+        .output()
+        .unwrap();
+    assert!(!out.status.success());
+    let stdout = String::from_utf8(out.stdout).unwrap();
+    assert!(stdout.contains("running 3 tests in tests/test_limits.pyra"), "{stdout}");
+    assert!(stdout.contains("test test_revert_cap ... ok ("), "{stdout}");
+    assert!(stdout.contains("test test_cap ... FAILED (") && stdout.contains("    TooHigh(1)"), "{stdout}");
+    assert!(stdout.contains("    count starts at zero"), "{stdout}");
+    assert!(stdout.contains("test result: FAILED. 2 passed; 2 failed"), "{stdout}");
+
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("pyra"));
+    cmd.arg("test")
+        .arg(project.join("tests/test_counter.pyra"))
+        .assert()
+        .success()
+        .stdout(contains("1 passed; 0 failed"));
+}
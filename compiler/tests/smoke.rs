@@ -16,3 +16,23 @@ fn parses_minimal_function() {
     let program = parse_from_source(source).unwrap();
     assert_eq!(program.items.len(), 1);
 }
+
+#[test]
+fn requires_and_ifs_get_unique_labels() {
+    use pyra_compiler::{hardened_module, verify_module, HardenConfig, IrOp};
+    use std::collections::HashSet;
+
+    let source = "def t(a: uint256, b: uint256) -> uint256:\n    require a > 0, \"a\"\n    if a > b:\n        require a - b < 100, \"gap\"\n        return a + b\n    if b > 10:\n        return b * 2\n    require b != a, \"eq\"\n    return a\n";
+    let program = parse_from_source(source).unwrap();
+    let module = hardened_module(&program, &HardenConfig::default());
+    assert!(verify_module(&module).is_empty());
+
+    let mut seen = HashSet::new();
+    let ops = module.functions.iter().flat_map(|f| &f.ops).chain(&module.constructor_ops);
+    for op in ops {
+        if let IrOp::JumpDest(label) = op {
+            assert!(seen.insert(*label), "label {label} defined twice");
+        }
+    }
+    assert!(seen.len() >= 5);
+}